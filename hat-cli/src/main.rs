@@ -1,15 +1,11 @@
-use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    path::PathBuf,
-};
+use std::{net::SocketAddr, path::PathBuf};
 
 use anyhow::Context;
 use clap::Parser;
-use hat::integrations::dummy::DummyIntegration;
-use hat::integrations::home_assistant::HassIntegration;
+use hat::config::{Config, IntegrationConfig};
 use hat::runtime::HatRuntime;
 use hat::server;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 #[derive(clap::Parser, Debug)]
@@ -31,17 +27,18 @@ struct CliArguments {
     #[arg(
         short,
         long,
-        default_value_t = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 5000)),
-        help = "address of the HTTP server"
+        help = "address of the HTTP server, overrides the configuration file"
     )]
-    address: SocketAddr,
+    address: Option<SocketAddr>,
+
+    #[arg(short, long, help = "the TOML configuration file")]
+    config: Option<PathBuf>,
 
     #[arg(help = "the HAT source file")]
     file: PathBuf,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     read_env_files();
 
     let args = CliArguments::parse();
@@ -51,6 +48,24 @@ async fn main() -> anyhow::Result<()> {
         .with(EnvFilter::from_default_env())
         .init();
 
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => default_config(),
+    };
+
+    if let Some(timezone) = config.timezone() {
+        // SAFETY: no other thread exists yet, the tokio runtime is only started below
+        unsafe { std::env::set_var("TZ", timezone) };
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to start the tokio runtime")?
+        .block_on(run(args, config))
+}
+
+async fn run(args: CliArguments, config: Config) -> anyhow::Result<()> {
     let path_string = args
         .file
         .to_str()
@@ -60,31 +75,27 @@ async fn main() -> anyhow::Result<()> {
     let source = std::fs::read_to_string(&args.file)
         .with_context(|| format!("failed to read file: {:?}", args.file))?;
 
-    let runtime = HatRuntime::new().await;
+    let runtime = HatRuntime::with_settings(config.runtime_settings()).await?;
 
     runtime.parse(path_string, &source).await?;
 
-    // The runtime can integrate with any implementation of the Integration trait
-    // Here is an example of a Dummy integration
-    runtime.integrate(DummyIntegration::new()).await;
-    // Here is the default integration with HomeAssistant
-    runtime
-        .integrate(
-            HassIntegration::new(
-                &std::env::var("HA_URL").context("missing environment variable HA_URL")?,
-                &std::env::var("HA_TOKEN").context("missing environment variable HA_TOKEN")?,
-            )
-            .await?,
-        )
-        .await;
-
-    let router = server::make_router(runtime);
-
-    let listener = tokio::net::TcpListener::bind(&args.address)
+    for integration in &config.integrations {
+        integration.integrate(&runtime).await?;
+    }
+
+    if !args.serve && !config.features.server {
+        runtime.join().await;
+        return Ok(());
+    }
+
+    let router = server::make_router_with_config(runtime, &config.server, &config.features);
+
+    let address = args.address.unwrap_or(config.server.address);
+    let listener = tokio::net::TcpListener::bind(&address)
         .await
-        .with_context(|| format!("failed to bind address: {}", args.address))?;
+        .with_context(|| format!("failed to bind address: {address}"))?;
 
-    info!("Starting HTTP server at {}", args.address);
+    info!("Starting HTTP server at {address}");
 
     axum::serve(listener, router)
         .await
@@ -93,6 +104,29 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Configuration used without `--config`: a dummy integration, plus Home Assistant when
+/// `HA_URL` and `HA_TOKEN` are set.
+fn default_config() -> Config {
+    // Aliases are the IDs generated by older versions, still used by existing source files
+    let mut integrations = vec![IntegrationConfig::Dummy {
+        id: "dummy".into(),
+        aliases: vec!["DummyIntegration0".into()],
+    }];
+    match (std::env::var("HA_URL"), std::env::var("HA_TOKEN")) {
+        (Ok(url), Ok(token)) => integrations.push(IntegrationConfig::HomeAssistant {
+            id: "home".into(),
+            aliases: vec!["HassIntegration0".into()],
+            url,
+            token,
+        }),
+        _ => warn!("HA_URL or HA_TOKEN is not set, not connecting to home assistant"),
+    }
+    Config {
+        integrations,
+        ..Default::default()
+    }
+}
+
 fn read_env_files() {
     let mut env: &str;
     let env_var = std::env::var("RUST_ENV").unwrap_or("".into());
@@ -21,7 +21,7 @@ pub fn benchmark_runtime_throughput(c: &mut Criterion) {
     let hat_rt = tokio_rt.block_on(async {
         let hat_rt = HatRuntime::new().await;
         hat_rt
-            .parse("bench.hat".into(), &include_str!("../src/test/bench.hat"))
+            .parse("bench.hat".into(), include_str!("../src/test/bench.hat"))
             .await
             .unwrap();
         hat_rt
@@ -51,6 +51,9 @@ pub fn benchmark_runtime_throughput(c: &mut Criterion) {
                                 id: "benchmark".into(),
                                 name: None,
                                 typ: DeviceType::Dummy,
+                                capabilities: Vec::new(),
+                                area: None,
+                                floor: None,
                                 state: None,
                                 attributes: Map::new(),
                             },
@@ -0,0 +1,953 @@
+//! Declarative configuration of a Hat instance, read from a TOML file:
+//!
+//! ```toml
+//! [server]
+//! address = "0.0.0.0:5000"
+//!
+//! [server.webhooks]
+//! doorbell = "${DOORBELL_WEBHOOK_SECRET}"
+//!
+//! [runtime]
+//! event_channel_size = 128
+//! integration_channel_size = 128
+//! helpers_file = "/var/lib/hat/helpers.json"
+//!
+//! [http]
+//! allowed_urls = ["https://api.open-meteo.com/v1/"]
+//!
+//! [http.webhooks.doorbell_alert]
+//! url = "https://hooks.example.com/doorbell"
+//! headers = { Authorization = "Bearer ${HOOK_TOKEN}" }
+//!
+//! [location]
+//! timezone = "America/Sao_Paulo"
+//!
+//! [notifiers.phone]
+//! type = "home_assistant"
+//! integration = "home"
+//! service = "mobile_app_phone"
+//!
+//! [notifiers.family]
+//! type = "ntfy"
+//! topic = "${NTFY_TOPIC}"
+//!
+//! [notifiers.email]
+//! type = "smtp"
+//! host = "smtp.example.com"
+//! username = "hat@example.com"
+//! password = "${SMTP_PASSWORD}"
+//! from = "hat@example.com"
+//! to = ["me@example.com"]
+//!
+//! [features]
+//! server = true
+//! update_code = false
+//!
+//! [[integrations]]
+//! type = "home_assistant"
+//! id = "home"
+//! aliases = ["HassIntegration0"]
+//! url = "http://homeassistant.local:8123"
+//! token = "${HA_TOKEN}"
+//!
+//! [[integrations]]
+//! type = "hue"
+//! id = "hue"
+//! url = "https://192.168.1.10"
+//! application_key = "${HUE_APPLICATION_KEY}"
+//! insecure = true
+//!
+//! [[integrations]]
+//! type = "hat"
+//! id = "garage"
+//! url = "http://garage.local:5000"
+//! token = "${GARAGE_TOKEN}"
+//!
+//! [[integrations]]
+//! type = "mqtt"
+//! id = "zigbee"
+//! host = "localhost"
+//! username = "hat"
+//! password = "${MQTT_PASSWORD}"
+//!
+//! [[integrations]]
+//! type = "rest"
+//! id = "gadgets"
+//! poll_interval = 30
+//!
+//! [[integrations.devices]]
+//! id = "inverter"
+//! type = "Sensor"
+//! url = "http://192.168.1.30/status"
+//! state = "/power/current"
+//!
+//! [[integrations]]
+//! type = "calendar"
+//! id = "calendar"
+//! calendars = { family = "/var/lib/hat/family.ics" }
+//!
+//! [[integrations]]
+//! type = "simulation"
+//! id = "demo"
+//! scenario = "demo-home.toml"
+//! speed = 60
+//! ```
+//!
+//! `${NAME}` inside any string is replaced by the environment variable `NAME`, so secrets do
+//! not need to be written in the file. `$$` is a literal `$`.
+
+use crate::integrations::calendar::CalendarIntegration;
+use crate::integrations::clock::CLOCK_INTEGRATION_ID;
+use crate::integrations::dummy::DummyIntegration;
+use crate::integrations::helpers::HELPERS_INTEGRATION_ID;
+use crate::integrations::home_assistant::HassIntegration;
+use crate::integrations::hue::HueIntegration;
+use crate::integrations::is_valid_integration_id;
+use crate::integrations::mqtt::{MqttIntegration, MqttSettings};
+use crate::integrations::remote::RemoteHatIntegration;
+use crate::integrations::rest::{RestDeviceSettings, RestIntegration};
+use crate::integrations::simulation::SimulationIntegration;
+use crate::notifiers::{
+    HassNotifier, Notifier, NtfyNotifier, SmtpNotifier, SmtpSecurity, WebhookNotifier,
+};
+use crate::runtime::event::{RUNTIME_INTEGRATION_ID, WEBHOOK_INTEGRATION_ID};
+use crate::runtime::function::http::{HttpSettings, Webhook};
+use crate::runtime::value::Value;
+use crate::runtime::{HatRuntime, RuntimeSettings};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub runtime: RuntimeConfig,
+    pub location: Option<LocationConfig>,
+    pub http: HttpConfig,
+    /// Targets of `notify`, by name
+    pub notifiers: BTreeMap<String, NotifierConfig>,
+    pub features: Features,
+    pub integrations: Vec<IntegrationConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server listens on
+    pub address: SocketAddr,
+    /// Token required from clients, as `Authorization: Bearer {TOKEN}`
+    pub token: Option<String>,
+    /// Secrets of the webhooks accepted at `POST /webhook/:name`, by name. Webhooks do not
+    /// need the token, only their secret.
+    pub webhooks: BTreeMap<String, String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 5000)),
+            token: None,
+            webhooks: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// See [`RuntimeSettings::event_channel_size`]
+    pub event_channel_size: usize,
+    /// See [`RuntimeSettings::integration_channel_size`]
+    pub integration_channel_size: usize,
+    /// See [`RuntimeSettings::helpers_file`]
+    pub helpers_file: Option<PathBuf>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            event_channel_size: RuntimeSettings::default().event_channel_size,
+            integration_channel_size: RuntimeSettings::default().integration_channel_size,
+            helpers_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocationConfig {
+    /// IANA name of the timezone used by schedules and clock events, like `Europe/Lisbon`.
+    /// When missing, the timezone of the system is used.
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Seconds to wait for a response, see [`HttpSettings::timeout`]
+    pub timeout: u64,
+    /// See [`HttpSettings::allowed_urls`]
+    pub allowed_urls: Vec<String>,
+    /// Requests called by name with `webhook`
+    pub webhooks: BTreeMap<String, WebhookConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: HttpSettings::default().timeout.as_secs(),
+            allowed_urls: Vec::new(),
+            webhooks: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// `POST` by default
+    #[serde(default = "default_webhook_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_webhook_method() -> String {
+    "POST".to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NotifierConfig {
+    /// A `notify.*` service of a Home Assistant integration
+    HomeAssistant {
+        /// ID or alias of the integration
+        integration: String,
+        /// Name of the service, like `mobile_app_phone` for `notify.mobile_app_phone`
+        service: String,
+        #[serde(default)]
+        data: BTreeMap<String, serde_json::Value>,
+    },
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    Ntfy {
+        /// `https://ntfy.sh` by default
+        #[serde(default = "default_ntfy_url")]
+        url: String,
+        topic: String,
+        token: Option<String>,
+        priority: Option<u8>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    Smtp {
+        host: String,
+        /// 25, 587 or 465 by default, depending on `security`
+        port: Option<u16>,
+        /// `starttls` by default
+        #[serde(default = "default_smtp_security")]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+fn default_ntfy_url() -> String {
+    "https://ntfy.sh".to_owned()
+}
+
+fn default_smtp_security() -> SmtpSecurity {
+    SmtpSecurity::StartTls
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Serve the HTTP API used by the visual editor
+    pub server: bool,
+    /// Allow replacing the automations through `POST /update_code`
+    pub update_code: bool,
+    /// Allow dispatching events through `POST /events/:name`
+    pub emit_events: bool,
+    /// Allow other Hat instances to follow events through `GET /event_stream` and to command
+    /// devices through `POST /device/command`
+    pub federation: bool,
+    /// Allow creating, changing and deleting helpers through `/helpers`
+    pub helpers: bool,
+    /// Accept the webhooks of `server.webhooks` at `POST /webhook/:name`
+    pub webhooks: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            server: true,
+            update_code: true,
+            emit_events: true,
+            federation: true,
+            helpers: true,
+            webhooks: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum IntegrationConfig {
+    Dummy {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+    },
+    HomeAssistant {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// Base URL of Home Assistant, like `http://homeassistant.local:8123`
+        url: String,
+        /// Long-lived access token
+        token: String,
+    },
+    Hue {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// Base URL of the bridge, like `https://192.168.1.10`
+        url: String,
+        /// Key created by pressing the link button of the bridge
+        application_key: String,
+        /// Accept any certificate, see [`HueIntegration::new`]
+        #[serde(default)]
+        insecure: bool,
+    },
+    /// Another Hat instance
+    Hat {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// Base URL of its HTTP server, like `http://garage.local:5000`
+        url: String,
+        /// Token of its HTTP server, if it requires one
+        token: Option<String>,
+    },
+    Mqtt {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+        client_id: Option<String>,
+        /// Base topic of Zigbee2MQTT, empty to ignore Zigbee2MQTT devices
+        #[serde(default = "default_zigbee2mqtt_topic")]
+        zigbee2mqtt_topic: String,
+        /// Discovery topic of Tasmota, empty to ignore Tasmota devices
+        #[serde(default = "default_tasmota_discovery_topic")]
+        tasmota_discovery_topic: String,
+    },
+    Rest {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// Seconds between polls, for devices that do not override it
+        #[serde(default = "default_poll_interval")]
+        poll_interval: u64,
+        #[serde(default)]
+        devices: Vec<RestDeviceSettings>,
+    },
+    /// iCalendar files, each one a calendar
+    Calendar {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// Path of the `.ics` file of each calendar, by name
+        calendars: BTreeMap<String, PathBuf>,
+        /// Seconds between checks of the files for changes
+        #[serde(default = "default_calendar_poll_interval")]
+        poll_interval: u64,
+    },
+    /// A simulated home, for demos and tests without real devices
+    Simulation {
+        id: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+        /// TOML file describing the home, see [`Scenario`](crate::integrations::simulation::Scenario)
+        scenario: PathBuf,
+        /// Overrides the speed of the scenario
+        speed: Option<f64>,
+    },
+}
+
+fn default_poll_interval() -> u64 {
+    30
+}
+
+fn default_calendar_poll_interval() -> u64 {
+    60
+}
+
+fn default_mqtt_port() -> u16 {
+    MqttSettings::default().port
+}
+
+fn default_zigbee2mqtt_topic() -> String {
+    MqttSettings::default()
+        .zigbee2mqtt_topic
+        .unwrap_or_default()
+}
+
+fn default_tasmota_discovery_topic() -> String {
+    MqttSettings::default()
+        .tasmota_discovery_topic
+        .unwrap_or_default()
+}
+
+impl IntegrationConfig {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Dummy { id, .. }
+            | Self::HomeAssistant { id, .. }
+            | Self::Hue { id, .. }
+            | Self::Hat { id, .. }
+            | Self::Mqtt { id, .. }
+            | Self::Rest { id, .. }
+            | Self::Calendar { id, .. }
+            | Self::Simulation { id, .. } => id,
+        }
+    }
+
+    pub fn aliases(&self) -> &[String] {
+        match self {
+            Self::Dummy { aliases, .. }
+            | Self::HomeAssistant { aliases, .. }
+            | Self::Hue { aliases, .. }
+            | Self::Hat { aliases, .. }
+            | Self::Mqtt { aliases, .. }
+            | Self::Rest { aliases, .. }
+            | Self::Calendar { aliases, .. }
+            | Self::Simulation { aliases, .. } => aliases,
+        }
+    }
+
+    /// Connects the integration and adds it, with its aliases, to the runtime.
+    pub async fn integrate(&self, runtime: &HatRuntime) -> Result<()> {
+        match self {
+            Self::Dummy { id, .. } => runtime.integrate(DummyIntegration::new(id)?).await,
+            Self::HomeAssistant { id, url, token, .. } => {
+                let integration = HassIntegration::new(id, url, token)
+                    .await
+                    .with_context(|| format!("failed to connect to home assistant {id}"))?;
+                runtime.integrate(integration).await
+            }
+            Self::Hue {
+                id,
+                url,
+                application_key,
+                insecure,
+                ..
+            } => {
+                let integration = HueIntegration::new(id, url, application_key, *insecure)
+                    .await
+                    .with_context(|| format!("failed to connect to hue bridge {id}"))?;
+                runtime.integrate(integration).await
+            }
+            Self::Hat { id, url, token, .. } => {
+                let integration = RemoteHatIntegration::new(id, url, token.as_deref())
+                    .await
+                    .with_context(|| format!("failed to connect to hat {id}"))?;
+                runtime.integrate(integration).await
+            }
+            Self::Mqtt {
+                id,
+                host,
+                port,
+                username,
+                password,
+                client_id,
+                zigbee2mqtt_topic,
+                tasmota_discovery_topic,
+                ..
+            } => {
+                let settings = MqttSettings {
+                    host: host.clone(),
+                    port: *port,
+                    username: username.clone(),
+                    password: password.clone(),
+                    client_id: client_id.clone(),
+                    zigbee2mqtt_topic: Some(zigbee2mqtt_topic.clone()).filter(|t| !t.is_empty()),
+                    tasmota_discovery_topic: Some(tasmota_discovery_topic.clone())
+                        .filter(|t| !t.is_empty()),
+                };
+                runtime
+                    .integrate(MqttIntegration::new(id, settings).await?)
+                    .await
+            }
+            Self::Rest {
+                id,
+                poll_interval,
+                devices,
+                ..
+            } => {
+                let poll_interval = Duration::from_secs(*poll_interval);
+                let integration = RestIntegration::new(id, poll_interval, devices.clone()).await?;
+                runtime.integrate(integration).await
+            }
+            Self::Calendar {
+                id,
+                calendars,
+                poll_interval,
+                ..
+            } => {
+                let poll_interval = Duration::from_secs(*poll_interval);
+                let integration = CalendarIntegration::new(id, calendars.clone(), poll_interval)?;
+                runtime.integrate(integration).await
+            }
+            Self::Simulation {
+                id,
+                scenario,
+                speed,
+                ..
+            } => {
+                let integration = SimulationIntegration::from_file(id, scenario, *speed)
+                    .with_context(|| format!("failed to start simulation {id}"))?;
+                runtime.integrate(integration).await
+            }
+        }?;
+        for alias in self.aliases() {
+            runtime.add_integration_alias(alias, self.id());
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read configuration file {path:?}"))?;
+        Self::parse(&source).with_context(|| format!("invalid configuration file {path:?}"))
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_with_env(source, |name| std::env::var(name).ok())
+    }
+
+    /// Parses and validates a configuration, looking up `${NAME}` substitutions with `env`.
+    pub fn parse_with_env(source: &str, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(source).map_err(|e| anyhow!("{e}"))?;
+        for (key, value) in table.iter_mut() {
+            substitute_env(value, key, &env)?;
+        }
+        let config: Config = serde_path_to_error::deserialize(toml::Value::Table(table))
+            .map_err(|e| anyhow!("{}: {}", e.path(), e.inner().message()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks everything that the types alone do not, reporting every problem at once.
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self
+            .server
+            .token
+            .as_ref()
+            .is_some_and(|t| t.trim().is_empty())
+        {
+            errors.push("server.token: must not be empty".to_owned());
+        }
+        for (name, secret) in &self.server.webhooks {
+            if secret.trim().is_empty() {
+                errors.push(format!("server.webhooks.{name}: secret must not be empty"));
+            }
+        }
+        if self.runtime.event_channel_size == 0 {
+            errors.push("runtime.event_channel_size: must be greater than zero".to_owned());
+        }
+        if self.runtime.integration_channel_size == 0 {
+            errors.push("runtime.integration_channel_size: must be greater than zero".to_owned());
+        }
+
+        if let Some(location) = &self.location {
+            if let Some(timezone) = &location.timezone {
+                if timezone.parse::<Tz>().is_err() {
+                    errors.push(format!(
+                        "location.timezone: {timezone:?} is not in the tz database, use a name \
+                        like \"Europe/Lisbon\""
+                    ));
+                }
+            }
+        }
+
+        if self.http.timeout == 0 {
+            errors.push("http.timeout: must be greater than zero".to_owned());
+        }
+        for (i, url) in self.http.allowed_urls.iter().enumerate() {
+            if let Some(error) = check_http_url(url) {
+                errors.push(format!("http.allowed_urls[{i}]: {error}"));
+            }
+        }
+        for (name, webhook) in &self.http.webhooks {
+            if let Some(error) = check_http_url(&webhook.url) {
+                errors.push(format!("http.webhooks.{name}.url: {error}"));
+            }
+            let method = webhook.method.to_uppercase();
+            if !["GET", "POST", "PUT", "PATCH", "DELETE"].contains(&method.as_str()) {
+                errors.push(format!(
+                    "http.webhooks.{name}.method: {:?} is not GET, POST, PUT, PATCH or DELETE",
+                    webhook.method
+                ));
+            }
+        }
+
+        for (name, notifier) in &self.notifiers {
+            errors.extend(
+                self.validate_notifier(notifier)
+                    .into_iter()
+                    .map(|error| format!("notifiers.{name}.{error}")),
+            );
+        }
+
+        let mut names = HashSet::from([
+            CLOCK_INTEGRATION_ID,
+            HELPERS_INTEGRATION_ID,
+            RUNTIME_INTEGRATION_ID,
+            WEBHOOK_INTEGRATION_ID,
+        ]);
+        for (i, integration) in self.integrations.iter().enumerate() {
+            let ids = std::iter::once(("id", integration.id())).chain(
+                integration
+                    .aliases()
+                    .iter()
+                    .map(|a| ("aliases", a.as_str())),
+            );
+            for (field, name) in ids {
+                if !is_valid_integration_id(name) {
+                    errors.push(format!(
+                        "integrations[{i}].{field}: {name:?} must start with a letter and \
+                        contain only letters, digits and `_`"
+                    ));
+                } else if !names.insert(name) {
+                    errors.push(format!(
+                        "integrations[{i}].{field}: {name:?} is already used by another integration"
+                    ));
+                }
+            }
+
+            let (url, required) = match integration {
+                IntegrationConfig::HomeAssistant { url, token, .. } => {
+                    (Some(url), vec![("token", token)])
+                }
+                IntegrationConfig::Hue {
+                    url,
+                    application_key,
+                    ..
+                } => (Some(url), vec![("application_key", application_key)]),
+                IntegrationConfig::Hat { url, token, .. } => (
+                    Some(url),
+                    token.iter().map(|token| ("token", token)).collect(),
+                ),
+                IntegrationConfig::Mqtt { host, .. } => (None, vec![("host", host)]),
+                IntegrationConfig::Rest {
+                    poll_interval,
+                    devices,
+                    ..
+                } => {
+                    if *poll_interval == 0 {
+                        errors.push(format!(
+                            "integrations[{i}].poll_interval: must be greater than zero"
+                        ));
+                    }
+                    let mut device_ids = HashSet::new();
+                    for (j, device) in devices.iter().enumerate() {
+                        for error in device.validate() {
+                            errors.push(format!("integrations[{i}].devices[{j}].{error}"));
+                        }
+                        if !device_ids.insert(&device.id) {
+                            errors.push(format!(
+                                "integrations[{i}].devices[{j}].id: {:?} is already used by another device",
+                                device.id
+                            ));
+                        }
+                    }
+                    (None, vec![])
+                }
+                IntegrationConfig::Calendar {
+                    calendars,
+                    poll_interval,
+                    ..
+                } => {
+                    if *poll_interval == 0 {
+                        errors.push(format!(
+                            "integrations[{i}].poll_interval: must be greater than zero"
+                        ));
+                    }
+                    for name in calendars.keys() {
+                        if name.trim().is_empty() || name.contains('@') {
+                            errors.push(format!(
+                                "integrations[{i}].calendars: {name:?} must not be empty or contain `@`"
+                            ));
+                        }
+                    }
+                    (None, vec![])
+                }
+                IntegrationConfig::Simulation { speed, .. } => {
+                    if speed.is_some_and(|s| s <= 0.0) {
+                        errors.push(format!(
+                            "integrations[{i}].speed: must be greater than zero"
+                        ));
+                    }
+                    (None, vec![])
+                }
+                IntegrationConfig::Dummy { .. } => (None, vec![]),
+            };
+            if let Some(error) = url.and_then(|url| check_http_url(url)) {
+                errors.push(format!("integrations[{i}].url: {error}"));
+            }
+            for (field, value) in required {
+                if value.trim().is_empty() {
+                    errors.push(format!("integrations[{i}].{field}: must not be empty"));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// The problems of a notifier, starting with the field they are about.
+    fn validate_notifier(&self, notifier: &NotifierConfig) -> Vec<String> {
+        let mut errors = Vec::new();
+        match notifier {
+            NotifierConfig::HomeAssistant {
+                integration,
+                service,
+                ..
+            } => {
+                if self.home_assistant_id(integration).is_none() {
+                    errors.push(format!(
+                        "integration: {integration:?} is not a home_assistant integration"
+                    ));
+                }
+                if service.trim().is_empty() {
+                    errors.push("service: must not be empty".to_owned());
+                }
+            }
+            NotifierConfig::Webhook { url, .. } => {
+                errors.extend(check_http_url(url).map(|e| format!("url: {e}")))
+            }
+            NotifierConfig::Ntfy {
+                url,
+                topic,
+                priority,
+                ..
+            } => {
+                errors.extend(check_http_url(url).map(|e| format!("url: {e}")));
+                if topic.trim().is_empty() || topic.contains('/') {
+                    errors.push(format!("topic: {topic:?} must not be empty or contain `/`"));
+                }
+                if priority.is_some_and(|p| !(1..=5).contains(&p)) {
+                    errors.push("priority: must be between 1 and 5".to_owned());
+                }
+            }
+            NotifierConfig::Smtp {
+                host,
+                username,
+                password,
+                from,
+                to,
+                ..
+            } => {
+                for (field, value) in [("host", host), ("from", from)] {
+                    if value.trim().is_empty() {
+                        errors.push(format!("{field}: must not be empty"));
+                    }
+                }
+                if to.is_empty() {
+                    errors.push("to: must have at least one address".to_owned());
+                }
+                if password.is_some() && username.is_none() {
+                    errors.push("username: is required with a password".to_owned());
+                }
+            }
+        }
+        errors
+    }
+
+    /// The ID of the Home Assistant integration with this ID or alias.
+    fn home_assistant_id(&self, id_or_alias: &str) -> Option<&str> {
+        self.integrations
+            .iter()
+            .filter(|i| matches!(i, IntegrationConfig::HomeAssistant { .. }))
+            .find(|i| i.id() == id_or_alias || i.aliases().iter().any(|a| a == id_or_alias))
+            .map(IntegrationConfig::id)
+    }
+
+    fn notifier(&self, notifier: &NotifierConfig) -> Arc<dyn Notifier> {
+        match notifier {
+            NotifierConfig::HomeAssistant {
+                integration,
+                service,
+                data,
+            } => Arc::new(HassNotifier {
+                integration: self
+                    .home_assistant_id(integration)
+                    .unwrap_or(integration)
+                    .to_owned(),
+                service: service.clone(),
+                data: data
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::from_json(value.clone())))
+                    .collect(),
+            }),
+            NotifierConfig::Webhook { url, headers } => Arc::new(WebhookNotifier {
+                url: url.clone(),
+                headers: headers.clone(),
+            }),
+            NotifierConfig::Ntfy {
+                url,
+                topic,
+                token,
+                priority,
+                tags,
+            } => Arc::new(NtfyNotifier {
+                url: url.clone(),
+                topic: topic.clone(),
+                token: token.clone(),
+                priority: *priority,
+                tags: tags.clone(),
+            }),
+            NotifierConfig::Smtp {
+                host,
+                port,
+                security,
+                username,
+                password,
+                from,
+                to,
+            } => Arc::new(SmtpNotifier {
+                host: host.clone(),
+                port: port.unwrap_or(match security {
+                    SmtpSecurity::None => 25,
+                    SmtpSecurity::StartTls => 587,
+                    SmtpSecurity::Tls => 465,
+                }),
+                security: *security,
+                username: username.clone(),
+                password: password.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+        }
+    }
+
+    pub fn runtime_settings(&self) -> RuntimeSettings {
+        RuntimeSettings {
+            event_channel_size: self.runtime.event_channel_size,
+            integration_channel_size: self.runtime.integration_channel_size,
+            helpers_file: self.runtime.helpers_file.clone(),
+            http: HttpSettings {
+                allowed_urls: self.http.allowed_urls.clone(),
+                timeout: Duration::from_secs(self.http.timeout),
+                webhooks: self
+                    .http
+                    .webhooks
+                    .iter()
+                    .map(|(name, webhook)| {
+                        let webhook = Webhook {
+                            url: webhook.url.clone(),
+                            // Validated when the file is parsed
+                            method: webhook.method.to_uppercase().parse().unwrap_or_default(),
+                            headers: webhook.headers.clone(),
+                        };
+                        (name.clone(), webhook)
+                    })
+                    .collect(),
+            },
+            notifiers: self
+                .notifiers
+                .iter()
+                .map(|(name, notifier)| (name.clone(), self.notifier(notifier)))
+                .collect(),
+        }
+    }
+
+    pub fn timezone(&self) -> Option<&str> {
+        self.location.as_ref()?.timezone.as_deref()
+    }
+}
+
+/// Replaces `${NAME}` in every string of `value` by the environment variable `NAME`. `path` is
+/// where `value` is in the file, used in error messages.
+fn substitute_env(
+    value: &mut toml::Value,
+    path: &str,
+    env: &impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = substitute_string(s, env).context(path.to_owned())?,
+        toml::Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                substitute_env(value, &format!("{path}[{i}]"), env)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                substitute_env(value, &format!("{path}.{key}"), env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute_string(s: &str, env: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let Some(end) = after.find('}') else {
+                bail!("unclosed `${{` in {s:?}");
+            };
+            let name = &after[..end];
+            let value =
+                env(name).ok_or_else(|| anyhow!("environment variable {name} is not set"))?;
+            result.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The problem with a URL that must be an http or https one, if any.
+fn check_http_url(url: &str) -> Option<String> {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => None,
+        Ok(_) => Some(format!("{url:?} must be an http or https URL")),
+        Err(e) => Some(format!("{url:?} is {e}")),
+    }
+}
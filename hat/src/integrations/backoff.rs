@@ -0,0 +1,37 @@
+//! Delays between reconnection attempts.
+
+use std::time::Duration;
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Doubles the delay after every failed attempt, up to a minute.
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: INITIAL_RECONNECT_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    /// How long the next [`Backoff::wait`] sleeps.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Sleeps for the current delay, then doubles it.
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+
+    /// Goes back to the initial delay, after connecting.
+    pub fn reset(&mut self) {
+        self.delay = INITIAL_RECONNECT_DELAY;
+    }
+}
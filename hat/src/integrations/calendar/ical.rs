@@ -0,0 +1,551 @@
+//! A small iCalendar (RFC 5545) reader, enough for the `.ics` files exported by common calendar
+//! applications. Times with a `TZID` are read in that IANA timezone, like `Europe/Lisbon`, and
+//! times without one or with an unknown one in the timezone of Hat.
+
+use anyhow::{bail, Context, Result};
+use chrono::{
+    DateTime, Datelike, Days, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta,
+    TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Recurrences are expanded at most this many times, so broken rules cannot hang Hat.
+const MAX_OCCURRENCES: usize = 100_000;
+
+/// One occurrence of an event of a calendar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub summary: String,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub all_day: bool,
+}
+
+/// The events of an `.ics` file.
+#[derive(Debug, Clone, Default)]
+pub struct Calendar {
+    events: Vec<EventSpec>,
+}
+
+/// A time as written in the file. UTC times keep their instant when recurring, the other ones
+/// keep their wall clock time in their timezone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Moment {
+    naive: NaiveDateTime,
+    zone: Zone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zone {
+    /// Without a timezone, read in the timezone of Hat
+    Floating,
+    Utc,
+    Named(Tz),
+}
+
+impl Moment {
+    fn resolve(&self) -> DateTime<Local> {
+        match self.zone {
+            Zone::Floating => resolve_wall_clock(&Local, self.naive),
+            Zone::Utc => Utc.from_utc_datetime(&self.naive).with_timezone(&Local),
+            Zone::Named(tz) => resolve_wall_clock(&tz, self.naive).with_timezone(&Local),
+        }
+    }
+
+    fn with_naive(&self, naive: NaiveDateTime) -> Self {
+        Self {
+            naive,
+            zone: self.zone,
+        }
+    }
+}
+
+fn resolve_wall_clock<Z: TimeZone>(zone: &Z, naive: NaiveDateTime) -> DateTime<Z> {
+    // Wall clock times that do not exist, skipped by a DST change, move forward an hour
+    zone.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            zone.from_local_datetime(&(naive + TimeDelta::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| zone.from_utc_datetime(&naive))
+}
+
+/// The timezone of a `TZID`. Some applications add a prefix to the IANA name, like
+/// `/mozilla.org/20070129_1/Europe/Berlin`, so the longest known suffix is used.
+fn parse_zone(tzid: &str) -> Zone {
+    let known = tzid
+        .char_indices()
+        .filter(|(i, c)| *i == 0 || *c == '/')
+        .find_map(|(i, _)| tzid[i..].trim_start_matches('/').parse::<Tz>().ok());
+    match known {
+        Some(tz) => Zone::Named(tz),
+        None => {
+            warn!("Unknown timezone {tzid:?}, reading its times in the timezone of Hat");
+            Zone::Floating
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct EventSpec {
+    uid: String,
+    summary: String,
+    location: Option<String>,
+    description: Option<String>,
+    start: Moment,
+    length: TimeDelta,
+    all_day: bool,
+    rule: Option<Rule>,
+    excluded: Vec<DateTime<Local>>,
+    /// Set on events that replace one occurrence of a recurring event
+    recurrence_id: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<DateTime<Local>>,
+    /// Days of the week of daily and weekly rules
+    weekdays: Vec<Weekday>,
+}
+
+impl Calendar {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        let mut properties: Option<Vec<Property>> = None;
+        // Components inside events, like alarms, whose properties are ignored
+        let mut nested = 0;
+        let mut began = false;
+        for (i, line) in unfold(source).iter().enumerate() {
+            let property = Property::parse(line).with_context(|| format!("line {}", i + 1))?;
+            match (property.name.as_str(), property.value.as_str()) {
+                ("BEGIN", "VCALENDAR") => began = true,
+                ("BEGIN", "VEVENT") if properties.is_none() => properties = Some(Vec::new()),
+                ("BEGIN", _) if properties.is_some() => nested += 1,
+                ("END", "VEVENT") if nested == 0 => {
+                    if let Some(properties) = properties.take() {
+                        if let Some(event) = EventSpec::from_properties(&properties)
+                            .with_context(|| format!("event ending at line {}", i + 1))?
+                        {
+                            events.push(event);
+                        }
+                    }
+                }
+                ("END", _) if properties.is_some() => nested -= 1,
+                _ => {
+                    if let (Some(properties), 0) = (&mut properties, nested) {
+                        properties.push(property);
+                    }
+                }
+            }
+        }
+        if !began {
+            bail!("not an iCalendar file, BEGIN:VCALENDAR is missing");
+        }
+
+        // Occurrences moved or changed are replaced by their own events
+        let replaced = events
+            .iter()
+            .filter_map(|e| Some((e.uid.clone(), e.recurrence_id?)))
+            .fold(HashMap::<_, Vec<_>>::new(), |mut map, (uid, at)| {
+                map.entry(uid).or_default().push(at);
+                map
+            });
+        for event in events.iter_mut() {
+            if let (None, Some(replaced)) = (event.recurrence_id, replaced.get(&event.uid)) {
+                event.excluded.extend(replaced);
+            }
+        }
+        Ok(Self { events })
+    }
+
+    /// Occurrences that overlap `from..to`, sorted by start.
+    pub fn occurrences(&self, from: DateTime<Local>, to: DateTime<Local>) -> Vec<CalendarEvent> {
+        let mut occurrences = self
+            .events
+            .iter()
+            .flat_map(|event| event.occurrences(from, to))
+            .collect::<Vec<_>>();
+        occurrences.sort_by_key(|o| (o.start, o.end));
+        occurrences
+    }
+}
+
+impl EventSpec {
+    /// Reads an event, or nothing when it was cancelled.
+    fn from_properties(properties: &[Property]) -> Result<Option<Self>> {
+        let find = |name: &str| properties.iter().find(|p| p.name == name);
+        let text = |name: &str| find(name).map(|p| unescape(&p.value));
+        if text("STATUS").is_some_and(|s| s.eq_ignore_ascii_case("CANCELLED")) {
+            return Ok(None);
+        }
+
+        let start = find("DTSTART").context("DTSTART is missing")?;
+        let all_day = start.is_date();
+        let start = start.moment()?;
+        let length = match (find("DTEND"), find("DURATION")) {
+            (Some(end), _) if all_day => end.moment()?.naive - start.naive,
+            (Some(end), _) => end.moment()?.resolve() - start.resolve(),
+            (None, Some(duration)) => parse_duration(&duration.value)?,
+            (None, None) if all_day => TimeDelta::days(1),
+            (None, None) => TimeDelta::zero(),
+        };
+        let excluded = properties
+            .iter()
+            .filter(|p| p.name == "EXDATE")
+            .map(|p| p.moments())
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .map(|m| m.resolve())
+            .collect();
+        let rule = match find("RRULE").map(|p| Rule::parse(&p.value)).transpose() {
+            Ok(rule) => rule,
+            Err(e) => {
+                warn!("Ignoring the recurrence of a calendar event: {e:#}");
+                None
+            }
+        };
+
+        Ok(Some(Self {
+            uid: text("UID").unwrap_or_default(),
+            summary: text("SUMMARY").unwrap_or_default(),
+            location: text("LOCATION").filter(|l| !l.is_empty()),
+            description: text("DESCRIPTION").filter(|d| !d.is_empty()),
+            start,
+            length: length.max(TimeDelta::zero()),
+            all_day,
+            rule,
+            excluded,
+            recurrence_id: find("RECURRENCE-ID")
+                .map(|p| p.moment())
+                .transpose()?
+                .map(|m| m.resolve()),
+        }))
+    }
+
+    fn occurrences(&self, from: DateTime<Local>, to: DateTime<Local>) -> Vec<CalendarEvent> {
+        let starts: Box<dyn Iterator<Item = Moment>> = match &self.rule {
+            Some(rule) => Box::new(rule.starts(self.start)),
+            None => Box::new(std::iter::once(self.start)),
+        };
+        let mut occurrences = Vec::new();
+        for start in starts {
+            let (start, end) = if self.all_day {
+                // All-day events follow the calendar days, even across DST changes
+                let end = start.with_naive(start.naive + self.length);
+                (start.resolve(), end.resolve())
+            } else {
+                let start = start.resolve();
+                (start, start + self.length)
+            };
+            if start >= to {
+                break;
+            }
+            if end > from || (end == start && start >= from) {
+                if self.excluded.contains(&start) {
+                    continue;
+                }
+                occurrences.push(CalendarEvent {
+                    uid: self.uid.clone(),
+                    summary: self.summary.clone(),
+                    location: self.location.clone(),
+                    description: self.description.clone(),
+                    start,
+                    end,
+                    all_day: self.all_day,
+                });
+            }
+        }
+        occurrences
+    }
+}
+
+impl Rule {
+    fn parse(source: &str) -> Result<Self> {
+        let mut frequency = None;
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            weekdays: Vec::new(),
+        };
+        for part in source.split(';').filter(|p| !p.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .with_context(|| format!("invalid rule part {part:?}"))?;
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => bail!("unsupported frequency {value:?}"),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().context("invalid INTERVAL")?,
+                "COUNT" => rule.count = Some(value.parse().context("invalid COUNT")?),
+                "UNTIL" => rule.until = Some(parse_moment(value)?.resolve()),
+                "BYDAY" => {
+                    rule.weekdays = value.split(',').map(parse_weekday).collect::<Result<_>>()?
+                }
+                "WKST" => {}
+                _ => bail!("unsupported rule part {part:?}"),
+            }
+        }
+        rule.frequency = frequency.context("FREQ is missing")?;
+        if rule.interval == 0 {
+            bail!("INTERVAL must be greater than zero");
+        }
+        if !rule.weekdays.is_empty()
+            && !matches!(rule.frequency, Frequency::Daily | Frequency::Weekly)
+        {
+            bail!("BYDAY is only supported in daily and weekly rules");
+        }
+        Ok(rule)
+    }
+
+    /// Starts of the occurrences of an event that starts at `start`, in order.
+    fn starts(&self, start: Moment) -> impl Iterator<Item = Moment> + '_ {
+        let date = start.naive.date();
+        let time = start.naive.time();
+        let interval = self.interval;
+        let dates: Box<dyn Iterator<Item = NaiveDate>> = match self.frequency {
+            Frequency::Daily => Box::new(
+                (0..)
+                    .map_while(move |n| date.checked_add_days(Days::new(n * u64::from(interval))))
+                    .filter(|d| self.weekdays.is_empty() || self.weekdays.contains(&d.weekday())),
+            ),
+            Frequency::Weekly => {
+                let weekdays = if self.weekdays.is_empty() {
+                    vec![date.weekday()]
+                } else {
+                    self.weekdays.clone()
+                };
+                let monday = date - Days::new(date.weekday().num_days_from_monday().into());
+                Box::new(
+                    (0..)
+                        .map_while(move |n| {
+                            monday.checked_add_days(Days::new(7 * n * u64::from(interval)))
+                        })
+                        .flat_map(move |week| {
+                            let mut days = weekdays
+                                .iter()
+                                .map(|d| week + Days::new(d.num_days_from_monday().into()))
+                                .collect::<Vec<_>>();
+                            days.sort();
+                            days
+                        })
+                        .filter(move |d| *d >= date),
+                )
+            }
+            // Months without the day of the event are skipped, like February 30
+            Frequency::Monthly | Frequency::Yearly => {
+                let months = if self.frequency == Frequency::Monthly {
+                    interval
+                } else {
+                    12 * interval
+                };
+                let first = date.with_day(1).unwrap_or(date);
+                Box::new(
+                    (0..)
+                        .map_while(move |n| first.checked_add_months(Months::new(n * months)))
+                        .filter_map(move |month| month.with_day(date.day())),
+                )
+            }
+        };
+        dates
+            .take(self.count.unwrap_or(MAX_OCCURRENCES).min(MAX_OCCURRENCES))
+            .map(move |d| start.with_naive(d.and_time(time)))
+            .take_while(|m| self.until.is_none_or(|until| m.resolve() <= until))
+    }
+}
+
+/// A content line, like `DTSTART;TZID=Europe/Lisbon:20250101T100000`.
+#[derive(Debug)]
+struct Property {
+    name: String,
+    parameters: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Result<Self> {
+        // The value starts at the first colon that is not inside a quoted parameter value
+        let mut quoted = false;
+        let colon = line
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    quoted = !quoted;
+                }
+                *c == ':' && !quoted
+            })
+            .map(|(i, _)| i)
+            .with_context(|| format!("invalid line {line:?}"))?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let parameters = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_owned()))
+            .collect();
+        Ok(Self {
+            name,
+            parameters,
+            value: value.to_owned(),
+        })
+    }
+
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_date(&self) -> bool {
+        self.parameter("VALUE")
+            .is_some_and(|v| v.eq_ignore_ascii_case("DATE"))
+            || self.value.len() == 8
+    }
+
+    fn moment(&self) -> Result<Moment> {
+        self.parse_moment(&self.value)
+    }
+
+    fn moments(&self) -> Result<Vec<Moment>> {
+        self.value
+            .split(',')
+            .map(|v| self.parse_moment(v))
+            .collect()
+    }
+
+    fn parse_moment(&self, value: &str) -> Result<Moment> {
+        let mut moment = parse_moment(value).with_context(|| format!("invalid {}", self.name))?;
+        // Dates of all-day events are the same day everywhere
+        let is_date = value.len() == 8;
+        if let (Zone::Floating, Some(tzid), false) = (moment.zone, self.parameter("TZID"), is_date)
+        {
+            moment.zone = parse_zone(tzid);
+        }
+        Ok(moment)
+    }
+}
+
+/// Joins the lines that were folded, which continue with a space or a tab.
+fn unfold(source: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in source.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n' | 'N')) => {
+                chars.next();
+                unescaped.push('\n');
+            }
+            ('\\', Some(escaped @ ('\\' | ',' | ';'))) => {
+                chars.next();
+                unescaped.push(escaped);
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Reads `20250101`, `20250101T100000` or `20250101T100000Z`.
+fn parse_moment(value: &str) -> Result<Moment> {
+    let (value, zone) = match value.strip_suffix(['Z', 'z']) {
+        Some(value) => (value, Zone::Utc),
+        None => (value, Zone::Floating),
+    };
+    let naive = if value.len() == 8 {
+        NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map(|d| d.and_time(NaiveTime::MIN))
+            .with_context(|| format!("invalid date {value:?}"))?
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .with_context(|| format!("invalid date and time {value:?}"))?
+    };
+    Ok(Moment { naive, zone })
+}
+
+/// Reads durations like `PT1H30M`, `P1D` or `P2W`.
+fn parse_duration(value: &str) -> Result<TimeDelta> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut rest = value
+        .strip_prefix('P')
+        .with_context(|| format!("invalid duration {value:?}"))?;
+    let mut total = TimeDelta::zero();
+    let mut in_time = false;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = after;
+            continue;
+        }
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount: i64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("invalid duration {value:?}"))?;
+        let unit = rest[digits..].chars().next();
+        total += match (unit, in_time) {
+            (Some('W'), false) => TimeDelta::weeks(amount),
+            (Some('D'), false) => TimeDelta::days(amount),
+            (Some('H'), true) => TimeDelta::hours(amount),
+            (Some('M'), true) => TimeDelta::minutes(amount),
+            (Some('S'), true) => TimeDelta::seconds(amount),
+            _ => bail!("invalid duration {value:?}"),
+        };
+        rest = &rest[digits + 1..];
+    }
+    Ok(if negative { -total } else { total })
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday> {
+    Ok(match value.trim().to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => bail!("unsupported day {value:?}"),
+    })
+}
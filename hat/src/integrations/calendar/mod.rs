@@ -0,0 +1,387 @@
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Device, DeviceType};
+use crate::runtime::event::{Event, EventDescriptor, EventType};
+use crate::runtime::function::{Function, FunctionCategory, FunctionParameter};
+use crate::runtime::value::{Value, ValueType};
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, TimeDelta};
+use serde_json::Map;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+mod ical;
+
+pub use ical::{Calendar, CalendarEvent};
+
+/// Emitted when an event of a calendar starts
+pub const CALENDAR_EVENT_STARTED: &str = "CalendarEventStarted";
+/// Emitted when an event of a calendar ends
+pub const CALENDAR_EVENT_ENDED: &str = "CalendarEventEnded";
+
+/// How far ahead `next_calendar_event` looks
+const LOOKAHEAD: TimeDelta = TimeDelta::days(366);
+
+/// Calendars read from iCalendar (`.ics`) files, each one a device named after the calendar.
+/// The files are read again when they change, so they can be kept in sync by other tools.
+///
+/// [`CALENDAR_EVENT_STARTED`] and [`CALENDAR_EVENT_ENDED`] are emitted with the parameters
+/// `calendar`, `summary`, `location`, `description`, `start`, `end` and `all_day`.
+pub struct CalendarIntegration {
+    inner: Arc<Inner>,
+    task: JoinHandle<()>,
+}
+
+struct Inner {
+    id: String,
+    files: BTreeMap<String, PathBuf>,
+    /// The calendars and the source they were read from
+    calendars: RwLock<BTreeMap<String, (String, Calendar)>>,
+    subscribers: Subscribers,
+}
+
+impl CalendarIntegration {
+    /// Reads the calendars, named by the keys of `files`, and checks the files for changes every
+    /// `poll_interval`.
+    pub fn new(
+        id: &str,
+        files: BTreeMap<String, PathBuf>,
+        poll_interval: Duration,
+    ) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let mut calendars = BTreeMap::new();
+        for (name, path) in &files {
+            let source = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read calendar {name} from {path:?}"))?;
+            let calendar = Calendar::parse(&source)
+                .with_context(|| format!("invalid calendar {name} in {path:?}"))?;
+            calendars.insert(name.clone(), (source, calendar));
+        }
+        let inner = Arc::new(Inner {
+            id: id.to_owned(),
+            files,
+            calendars: RwLock::new(calendars),
+            subscribers: Default::default(),
+        });
+        let task = tokio::spawn(Arc::clone(&inner).run(poll_interval));
+        Ok(Self { inner, task })
+    }
+
+    /// Whether an event of the calendar is happening now.
+    pub fn is_busy(&self, calendar: &str) -> Result<bool> {
+        self.inner.is_busy(calendar)
+    }
+
+    /// The first event of the calendar that starts after now, within a year.
+    pub fn next_event(&self, calendar: &str) -> Result<Option<CalendarEvent>> {
+        self.inner.next_event(calendar)
+    }
+}
+
+impl Drop for CalendarIntegration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Inner {
+    fn is_busy(&self, calendar: &str) -> Result<bool> {
+        let now = Local::now();
+        Ok(!self.occurrences(calendar, now, now)?.is_empty())
+    }
+
+    fn next_event(&self, calendar: &str) -> Result<Option<CalendarEvent>> {
+        let now = Local::now();
+        let occurrences = self.occurrences(calendar, now, now + LOOKAHEAD)?;
+        Ok(occurrences.into_iter().find(|o| o.start > now))
+    }
+
+    /// Occurrences of a calendar that overlap `from..=to`.
+    fn occurrences(
+        &self,
+        calendar: &str,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> Result<Vec<CalendarEvent>> {
+        let calendars = self.calendars.read().unwrap();
+        let (_, calendar) = calendars
+            .get(calendar)
+            .with_context(|| format!("calendar {calendar} not found"))?;
+        Ok(calendar.occurrences(from, to + TimeDelta::nanoseconds(1)))
+    }
+
+    async fn run(self: Arc<Self>, poll_interval: Duration) {
+        let mut last = Local::now();
+        loop {
+            let wait = self
+                .next_change(last, poll_interval)
+                .and_then(|at| (at - Local::now()).to_std().ok())
+                .map_or(poll_interval, |wait| wait.min(poll_interval));
+            tokio::time::sleep(wait).await;
+            self.reload().await;
+            let now = Local::now();
+            self.emit_changes(last, now);
+            last = now;
+        }
+    }
+
+    /// When the next event starts or ends, if it is before `poll_interval` from `after`.
+    fn next_change(
+        &self,
+        after: DateTime<Local>,
+        poll_interval: Duration,
+    ) -> Option<DateTime<Local>> {
+        let until = after + TimeDelta::from_std(poll_interval).ok()?;
+        let calendars = self.calendars.read().unwrap();
+        calendars
+            .values()
+            .flat_map(|(_, calendar)| calendar.occurrences(after, until))
+            .flat_map(|o| [o.start, o.end])
+            .filter(|at| *at > after)
+            .min()
+    }
+
+    /// Reads the files that changed. Files that cannot be read keep their last calendar.
+    async fn reload(&self) {
+        for (name, path) in &self.files {
+            let source = match tokio::fs::read_to_string(path).await {
+                Ok(source) => source,
+                Err(e) => {
+                    warn!("Failed to read calendar {name} from {path:?}: {e}");
+                    continue;
+                }
+            };
+            let unchanged = self
+                .calendars
+                .read()
+                .unwrap()
+                .get(name)
+                .is_some_and(|(last, _)| *last == source);
+            if unchanged {
+                continue;
+            }
+            match Calendar::parse(&source) {
+                Ok(calendar) => {
+                    let mut calendars = self.calendars.write().unwrap();
+                    calendars.insert(name.clone(), (source, calendar));
+                }
+                Err(e) => warn!("Invalid calendar {name} in {path:?}: {e:#}"),
+            }
+        }
+    }
+
+    /// Emits the events that started or ended after `last` and until `now`, in order.
+    fn emit_changes(&self, last: DateTime<Local>, now: DateTime<Local>) {
+        let mut changes = Vec::new();
+        for name in self.files.keys() {
+            let Ok(occurrences) = self.occurrences(name, last, now) else {
+                continue;
+            };
+            for occurrence in occurrences {
+                // At the same time, events that end come before the ones that start, except
+                // the end of an event without length, which comes after its own start
+                if occurrence.start > last && occurrence.start <= now {
+                    changes.push((
+                        occurrence.start,
+                        1,
+                        CALENDAR_EVENT_STARTED,
+                        name,
+                        occurrence.clone(),
+                    ));
+                }
+                if occurrence.end > last && occurrence.end <= now {
+                    let rank = if occurrence.end == occurrence.start {
+                        2
+                    } else {
+                        0
+                    };
+                    changes.push((occurrence.end, rank, CALENDAR_EVENT_ENDED, name, occurrence));
+                }
+            }
+        }
+        changes.sort_by_key(|(at, rank, ..)| (*at, *rank));
+        for (_, _, typ, name, occurrence) in changes {
+            let Ok(device) = self.device(name, now) else {
+                continue;
+            };
+            self.subscribers.send(Event {
+                typ: EventType::custom(typ),
+                datetime: Local::now(),
+                device,
+                parameters: event_parameters(name, &occurrence),
+            });
+        }
+    }
+
+    /// The device of a calendar, `on` while one of its events is happening.
+    fn device(&self, name: &str, now: DateTime<Local>) -> Result<Device> {
+        let occurrences = self.occurrences(name, now, now + LOOKAHEAD)?;
+        let current = occurrences.iter().find(|o| o.start <= now);
+        let mut attributes = Map::new();
+        if let Some(shown) = current.or_else(|| occurrences.first()) {
+            attributes.insert("message".into(), shown.summary.clone().into());
+            attributes.insert("location".into(), shown.location.clone().into());
+            attributes.insert("start_time".into(), shown.start.to_rfc3339().into());
+            attributes.insert("end_time".into(), shown.end.to_rfc3339().into());
+            attributes.insert("all_day".into(), shown.all_day.into());
+        }
+        Ok(Device {
+            integration: self.id.clone(),
+            id: name.to_owned(),
+            name: Some(name.to_owned()),
+            typ: DeviceType::Unknown,
+            capabilities: Vec::new(),
+            area: None,
+            floor: None,
+            state: Some(if current.is_some() { "on" } else { "off" }.to_owned()),
+            attributes,
+        })
+    }
+}
+
+fn event_parameters(calendar: &str, occurrence: &CalendarEvent) -> HashMap<String, Value> {
+    event_fields(occurrence)
+        .into_iter()
+        .chain([("calendar".to_owned(), Value::String(calendar.to_owned()))])
+        .collect()
+}
+
+/// The fields of an event, as returned by `next_calendar_event`.
+fn event_fields(occurrence: &CalendarEvent) -> BTreeMap<String, Value> {
+    let text = |text: &Option<String>| text.clone().map_or(Value::Null, Value::String);
+    BTreeMap::from([
+        (
+            "summary".to_owned(),
+            Value::String(occurrence.summary.clone()),
+        ),
+        ("location".to_owned(), text(&occurrence.location)),
+        ("description".to_owned(), text(&occurrence.description)),
+        (
+            "start".to_owned(),
+            Value::String(occurrence.start.to_rfc3339()),
+        ),
+        ("end".to_owned(), Value::String(occurrence.end.to_rfc3339())),
+        ("all_day".to_owned(), Value::Boolean(occurrence.all_day)),
+    ])
+}
+
+fn calendar_argument(args: &[Value]) -> Result<&str> {
+    match args.first() {
+        Some(Value::String(calendar)) => Ok(calendar),
+        _ => bail!("first argument must be the name of the calendar"),
+    }
+}
+
+#[async_trait]
+impl Integration for CalendarIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        let now = Local::now();
+        self.inner
+            .files
+            .keys()
+            .map(|name| self.inner.device(name, now))
+            .collect()
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        if !self.inner.files.contains_key(id) {
+            return Ok(None);
+        }
+        self.inner.device(id, Local::now()).map(Some)
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        bail!("calendar {device_id} cannot be turned on")
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        bail!("calendar {device_id} cannot be turned off")
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, _color: [u8; 3]) -> Result<()> {
+        bail!("calendar {device_id} is not a light")
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, _brightness: u8) -> Result<()> {
+        bail!("calendar {device_id} is not a light")
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.inner.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.inner.id
+    }
+
+    fn get_functions(&self) -> Vec<Function> {
+        let busy = Arc::clone(&self.inner);
+        let next = Arc::clone(&self.inner);
+        vec![
+            Function {
+                name: "calendar_busy".to_owned(),
+                description: "Verifica se há um evento acontecendo agora em uma agenda",
+                category: FunctionCategory::Condition,
+                parameters: vec![FunctionParameter::required("calendar", ValueType::String)],
+                returns: ValueType::Boolean,
+                fun: Arc::new(move |_ctx, args| {
+                    let inner = Arc::clone(&busy);
+                    Box::pin(async move {
+                        Ok(Value::Boolean(inner.is_busy(calendar_argument(&args)?)?))
+                    })
+                }),
+            },
+            Function {
+                name: "next_calendar_event".to_owned(),
+                description: "Próximo evento de uma agenda, ou um campo dele, como \"summary\" \
+                    ou \"start\"",
+                category: FunctionCategory::Device,
+                parameters: vec![
+                    FunctionParameter::required("calendar", ValueType::String),
+                    FunctionParameter::optional("field", ValueType::String),
+                ],
+                returns: ValueType::Any,
+                fun: Arc::new(move |_ctx, args| {
+                    let inner = Arc::clone(&next);
+                    Box::pin(async move {
+                        let Some(next) = inner.next_event(calendar_argument(&args)?)? else {
+                            return Ok(Value::Null);
+                        };
+                        let mut fields = event_fields(&next);
+                        match args.get(1) {
+                            Some(Value::String(field)) => fields
+                                .remove(field)
+                                .with_context(|| format!("calendar events have no {field:?}")),
+                            Some(Value::Null) | None => Ok(Value::Map(fields)),
+                            Some(_) => bail!("second argument must be the name of a field"),
+                        }
+                    })
+                }),
+            },
+        ]
+    }
+
+    fn get_event_descriptors(&self) -> Vec<EventDescriptor> {
+        vec![
+            EventDescriptor {
+                event: EventType::custom(CALENDAR_EVENT_STARTED),
+                description: "Evento da agenda começou",
+                related_device_type: None,
+            },
+            EventDescriptor {
+                event: EventType::custom(CALENDAR_EVENT_ENDED),
+                description: "Evento da agenda terminou",
+                related_device_type: None,
+            },
+        ]
+    }
+}
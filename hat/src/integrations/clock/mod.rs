@@ -10,6 +10,9 @@ use tokio::sync::mpsc;
 
 use super::Integration;
 
+/// ID of the clock integration, which every runtime integrates on creation
+pub(crate) const CLOCK_INTEGRATION_ID: &str = "ClockIntegration";
+
 pub struct ClockIntegration;
 
 #[async_trait::async_trait]
@@ -38,8 +41,8 @@ impl Integration for ClockIntegration {
         bail!("ClockIntegration has no light to configure");
     }
 
-    fn subscribe(&self) -> mpsc::UnboundedReceiver<Event> {
-        let (tx, rx) = mpsc::unbounded_channel();
+    fn subscribe(&self, capacity: usize) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel(capacity);
 
         let integration = self.get_id().to_owned();
         tokio::spawn(async move {
@@ -49,6 +52,9 @@ impl Integration for ClockIntegration {
                 id: "Clock".to_owned(),
                 name: None,
                 typ: DeviceType::Unknown,
+                capabilities: Vec::new(),
+                area: None,
+                floor: None,
                 state: None,
                 attributes: serde_json::Map::new(),
             };
@@ -62,7 +68,7 @@ impl Integration for ClockIntegration {
                     parameters: HashMap::new(),
                 };
 
-                if tx.send(event).is_err() {
+                if tx.send(event).await.is_err() {
                     break;
                 }
             }
@@ -72,6 +78,6 @@ impl Integration for ClockIntegration {
     }
 
     fn get_id(&self) -> &str {
-        "ClockIntegration"
+        CLOCK_INTEGRATION_ID
     }
 }
@@ -1,33 +1,27 @@
-use crate::integrations::Integration;
-use crate::runtime::device::{Device, DeviceType};
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Capability, Device, DeviceType};
 use crate::runtime::event::{Event, EventType};
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use async_trait::async_trait;
 use chrono::Utc;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::Receiver;
 use tokio::time::sleep;
 use tracing::error;
 
-lazy_static::lazy_static! {
-    static ref ID_COUNTER: AtomicU64 = {
-        AtomicU64::default()
-    };
-}
-
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct DummyIntegration {
     id: String,
 }
 
 impl DummyIntegration {
-    pub fn new() -> Self {
-        let new_id = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-        Self {
-            id: format!("DummyIntegration{new_id}"),
-        }
+    pub fn new(id: &str) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        Ok(Self { id: id.to_owned() })
     }
 }
 
@@ -40,6 +34,9 @@ impl Integration for DummyIntegration {
             name: Some("Dummy Device".into()),
             state: Some("dummy-state".into()),
             typ: DeviceType::Dummy,
+            capabilities: vec![Capability::OnOff],
+            area: None,
+            floor: None,
             attributes: Default::default(),
         }]
         .into())
@@ -51,6 +48,9 @@ impl Integration for DummyIntegration {
                 integration: self.get_id().to_owned(),
                 id: "dummy-device-2707".into(),
                 typ: DeviceType::Dummy,
+                capabilities: vec![Capability::OnOff],
+                area: None,
+                floor: None,
                 state: Some("dummy-state".into()),
                 name: Some("Dummy Device".into()),
                 attributes: Default::default(),
@@ -68,26 +68,31 @@ impl Integration for DummyIntegration {
         Ok(())
     }
 
-    fn subscribe(&self) -> UnboundedReceiver<Event> {
-        let (tx, rx) = mpsc::unbounded_channel();
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel(capacity);
 
         let integration_name = self.get_id().to_owned();
 
         tokio::spawn(async move {
             loop {
-                let result = tx.send(Event {
-                    typ: EventType::Dummy,
-                    datetime: Utc::now().into(),
-                    device: Device {
-                        integration: integration_name.to_string(),
-                        id: "dummy-device-2707".into(),
-                        name: Some("Dummy Device".into()),
-                        state: Some("dummy-state".into()),
-                        typ: DeviceType::Dummy,
-                        attributes: Default::default(),
-                    },
-                    parameters: Default::default(),
-                });
+                let result = tx
+                    .send(Event {
+                        typ: EventType::Dummy,
+                        datetime: Utc::now().into(),
+                        device: Device {
+                            integration: integration_name.to_string(),
+                            id: "dummy-device-2707".into(),
+                            name: Some("Dummy Device".into()),
+                            state: Some("dummy-state".into()),
+                            typ: DeviceType::Dummy,
+                            capabilities: vec![Capability::OnOff],
+                            area: None,
+                            floor: None,
+                            attributes: Default::default(),
+                        },
+                        parameters: Default::default(),
+                    })
+                    .await;
                 if result.is_err() {
                     error!("Failed to send event to runtime!");
                     break;
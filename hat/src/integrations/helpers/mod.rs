@@ -0,0 +1,910 @@
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Device, DeviceCommand, DeviceType};
+use crate::runtime::event::Event;
+use crate::runtime::function::{Function, FunctionCategory, FunctionParameter};
+use crate::runtime::value::{Value, ValueType};
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// ID of the helper integration, which every runtime integrates on creation
+pub const HELPERS_INTEGRATION_ID: &str = "helpers";
+/// Parameter of the `SwitchTurnedOffEvent` of timers, true when the timer reached zero and
+/// false when it was cancelled
+pub const FINISHED_PARAMETER: &str = "finished";
+
+/// A helper, as declared in the source code (`helper guest_mode toggle`) or created through
+/// `POST /helpers`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelperSettings {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub kind: HelperKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HelperKind {
+    /// On or off, like "guest mode"
+    Toggle {
+        #[serde(default)]
+        initial: bool,
+    },
+    Number {
+        min: f64,
+        max: f64,
+        /// Granularity offered by editors
+        #[serde(default = "default_step")]
+        step: f64,
+        /// `min` by default
+        initial: Option<f64>,
+        unit: Option<String>,
+    },
+    /// One of a list of options, like the mode of the house
+    Select {
+        options: Vec<String>,
+        /// The first option by default
+        initial: Option<String>,
+    },
+    Text {
+        #[serde(default)]
+        initial: String,
+        #[serde(default = "default_max_length")]
+        max_length: usize,
+    },
+    /// Countdown timer, `on` while running
+    Timer {
+        /// Seconds it runs for when started without a duration
+        duration: u64,
+    },
+}
+
+fn default_step() -> f64 {
+    1.0
+}
+
+fn default_max_length() -> usize {
+    255
+}
+
+impl HelperSettings {
+    /// Problems with these settings, as `field: message`.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if !is_valid_integration_id(&self.id) {
+            errors.push(format!(
+                "id: {:?} must start with a letter and contain only letters, digits and `_`",
+                self.id
+            ));
+        }
+        match &self.kind {
+            HelperKind::Toggle { .. } => {}
+            HelperKind::Number {
+                min,
+                max,
+                step,
+                initial,
+                ..
+            } => {
+                if min >= max {
+                    errors.push(format!("max: {max} must be greater than min {min}"));
+                }
+                if *step <= 0.0 {
+                    errors.push("step: must be greater than zero".to_owned());
+                }
+                if let Some(initial) = initial {
+                    if !(min..=max).contains(&initial) {
+                        errors.push(format!("initial: {initial} is not between {min} and {max}"));
+                    }
+                }
+            }
+            HelperKind::Select { options, initial } => {
+                if options.is_empty() {
+                    errors.push("options: must not be empty".to_owned());
+                }
+                let mut seen = HashSet::new();
+                for option in options {
+                    if !seen.insert(option) {
+                        errors.push(format!("options: {option:?} is repeated"));
+                    }
+                }
+                if let Some(initial) = initial {
+                    if !options.contains(initial) {
+                        errors.push(format!("initial: {initial:?} is not one of the options"));
+                    }
+                }
+            }
+            HelperKind::Text {
+                initial,
+                max_length,
+            } => {
+                if initial.chars().count() > *max_length {
+                    errors.push(format!("initial: is longer than {max_length} characters"));
+                }
+            }
+            HelperKind::Timer { duration } => {
+                if *duration == 0 {
+                    errors.push("duration: must be greater than zero".to_owned());
+                }
+            }
+        }
+        errors
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            HelperKind::Toggle { .. } => "toggle",
+            HelperKind::Number { .. } => "number",
+            HelperKind::Select { .. } => "select",
+            HelperKind::Text { .. } => "text",
+            HelperKind::Timer { .. } => "timer",
+        }
+    }
+
+    fn initial_state(&self) -> String {
+        match &self.kind {
+            HelperKind::Toggle { initial: true } => "on".to_owned(),
+            HelperKind::Toggle { initial: false } | HelperKind::Timer { .. } => "off".to_owned(),
+            HelperKind::Number { min, initial, .. } => initial.unwrap_or(*min).to_string(),
+            HelperKind::Select { options, initial } => initial
+                .as_ref()
+                .or(options.first())
+                .cloned()
+                .unwrap_or_default(),
+            HelperKind::Text { initial, .. } => initial.clone(),
+        }
+    }
+
+    /// Converts a value into the state of this helper, failing when the helper cannot hold it.
+    /// Timers only accept on and off, see [`HelperIntegration::set_value`] for starting them.
+    fn parse_value(&self, value: &Value) -> Result<String> {
+        match (&self.kind, value) {
+            (HelperKind::Toggle { .. } | HelperKind::Timer { .. }, Value::Boolean(on)) => {
+                Ok(if *on { "on" } else { "off" }.to_owned())
+            }
+            (HelperKind::Toggle { .. } | HelperKind::Timer { .. }, Value::String(s))
+                if s == "on" || s == "off" =>
+            {
+                Ok(s.clone())
+            }
+            (HelperKind::Toggle { .. } | HelperKind::Timer { .. }, _) => {
+                bail!("{value} is not on or off")
+            }
+            (HelperKind::Number { min, max, .. }, value) => {
+                let number = match value {
+                    Value::Number(number) => *number,
+                    Value::String(s) => s
+                        .parse::<f64>()
+                        .with_context(|| format!("{value} is not a number"))?,
+                    _ => bail!("{value} is not a number"),
+                };
+                ensure!(
+                    (min..=max).contains(&&number),
+                    "{number} is not between {min} and {max}"
+                );
+                Ok(number.to_string())
+            }
+            (HelperKind::Select { options, .. }, Value::String(s)) => {
+                ensure!(options.contains(s), "{value} is not one of {options:?}");
+                Ok(s.clone())
+            }
+            (HelperKind::Text { max_length, .. }, Value::String(s)) => {
+                ensure!(
+                    s.chars().count() <= *max_length,
+                    "text is longer than {max_length} characters"
+                );
+                Ok(s.clone())
+            }
+            (HelperKind::Select { .. } | HelperKind::Text { .. }, _) => {
+                bail!("{value} is not a string")
+            }
+        }
+    }
+}
+
+/// Where a helper was declared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HelperOrigin {
+    /// Declared with `helper` in the source code, removed together with it
+    Code,
+    /// Created through the API, kept until deleted
+    Api,
+}
+
+/// A helper with its current state, as listed by `GET /helpers` and saved to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelperEntry {
+    #[serde(flatten)]
+    pub settings: HelperSettings,
+    pub origin: HelperOrigin,
+    pub state: String,
+    /// When a running timer reaches zero
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finishes_at: Option<DateTime<Local>>,
+}
+
+impl HelperEntry {
+    /// The state as a runtime value: a boolean for toggles and timers, a number for numbers
+    /// and a string otherwise.
+    pub fn value(&self) -> Value {
+        match &self.settings.kind {
+            HelperKind::Toggle { .. } | HelperKind::Timer { .. } => {
+                Value::Boolean(self.state == "on")
+            }
+            HelperKind::Number { .. } => self
+                .state
+                .parse::<f64>()
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+            HelperKind::Select { .. } | HelperKind::Text { .. } => {
+                Value::String(self.state.clone())
+            }
+        }
+    }
+
+    fn to_device(&self) -> Device {
+        let settings = &self.settings;
+        let mut attributes = Map::new();
+        attributes.insert("kind".into(), settings.kind_name().into());
+        match &settings.kind {
+            HelperKind::Toggle { .. } => {}
+            HelperKind::Number {
+                min,
+                max,
+                step,
+                unit,
+                ..
+            } => {
+                attributes.insert("min".into(), (*min).into());
+                attributes.insert("max".into(), (*max).into());
+                attributes.insert("step".into(), (*step).into());
+                if let Some(unit) = unit {
+                    attributes.insert("unit_of_measurement".into(), unit.clone().into());
+                }
+            }
+            HelperKind::Select { options, .. } => {
+                attributes.insert("options".into(), options.clone().into());
+            }
+            HelperKind::Text { max_length, .. } => {
+                attributes.insert("max_length".into(), (*max_length).into());
+            }
+            HelperKind::Timer { duration } => {
+                attributes.insert("duration".into(), (*duration).into());
+                if let Some(finishes_at) = self.finishes_at {
+                    attributes.insert("finishes_at".into(), finishes_at.to_rfc3339().into());
+                }
+            }
+        }
+        let typ = match settings.kind {
+            HelperKind::Toggle { .. } | HelperKind::Timer { .. } => DeviceType::Switch,
+            _ => DeviceType::Sensor,
+        };
+        Device {
+            integration: HELPERS_INTEGRATION_ID.to_owned(),
+            id: settings.id.clone(),
+            name: settings.name.clone(),
+            typ,
+            capabilities: typ.default_capabilities().to_vec(),
+            area: None,
+            floor: None,
+            state: Some(self.state.clone()),
+            attributes,
+        }
+    }
+
+    /// Changes the state, returning the event for the change.
+    fn set_state(&mut self, state: String) -> Option<Event> {
+        let old_state = std::mem::replace(&mut self.state, state);
+        let device = self.to_device();
+        Event::state_change(device, &old_state, &self.state)
+    }
+}
+
+/// Virtual devices owned by Hat, like a "guest mode" toggle or the mode of the house, for
+/// state that no real device holds. Toggles and timers are switches, the other helpers are
+/// sensors, so they emit the usual events when they change. Helpers and their states are
+/// saved to a file, when there is one, and restored on start.
+#[derive(Clone)]
+pub struct HelperIntegration {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    saver: Option<Saver>,
+    state: Mutex<Helpers>,
+    subscribers: Subscribers,
+}
+
+#[derive(Default)]
+struct Helpers {
+    active: BTreeMap<String, Helper>,
+    /// Helpers of the source code saved by a previous run, waiting to be declared again
+    remembered: BTreeMap<String, HelperEntry>,
+}
+
+impl Helpers {
+    /// Contents of the helpers file
+    fn to_json(&self) -> Result<Vec<u8>> {
+        let entries = self
+            .active
+            .values()
+            .map(|h| &h.entry)
+            .chain(self.remembered.values())
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_vec_pretty(&entries)?)
+    }
+}
+
+/// Writes the helpers file in a background task, so changes never wait for the disk. Only
+/// the latest contents are written when several changes happen during a write.
+struct Saver {
+    latest: watch::Sender<Snapshot>,
+    written: watch::Receiver<u64>,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    version: u64,
+    json: Vec<u8>,
+}
+
+impl Saver {
+    fn spawn(path: PathBuf) -> Self {
+        let (latest, mut snapshots) = watch::channel(Snapshot::default());
+        let (written_tx, written) = watch::channel(0);
+        tokio::spawn(async move {
+            while snapshots.changed().await.is_ok() {
+                let (version, json) = {
+                    let snapshot = snapshots.borrow_and_update();
+                    (snapshot.version, snapshot.json.clone())
+                };
+                if let Err(e) = write(&path, json).await {
+                    error!("Failed to save helpers to {path:?}: {e:#}");
+                }
+                written_tx.send_replace(version);
+            }
+        });
+        Self { latest, written }
+    }
+
+    /// Sets the contents read from the file, without writing them back.
+    fn loaded(&self, json: Vec<u8>) {
+        self.latest.send_if_modified(|snapshot| {
+            snapshot.json = json;
+            false
+        });
+    }
+
+    /// Queues `json` to be written, unless the file already has it.
+    fn save(&self, json: Vec<u8>) {
+        self.latest.send_if_modified(|snapshot| {
+            if snapshot.json == json {
+                return false;
+            }
+            snapshot.version += 1;
+            snapshot.json = json;
+            true
+        });
+    }
+
+    async fn wait(&self) {
+        let version = self.latest.borrow().version;
+        let mut written = self.written.clone();
+        written.wait_for(|written| *written >= version).await.ok();
+    }
+}
+
+/// Writes next to the file and renames, so a crash never leaves half a file.
+async fn write(path: &Path, json: Vec<u8>) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    tokio::fs::write(&temporary, json).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    Ok(())
+}
+
+struct Helper {
+    entry: HelperEntry,
+    timer: Option<JoinHandle<()>>,
+}
+
+impl Helper {
+    fn stop_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+        self.entry.finishes_at = None;
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        self.stop_timer();
+    }
+}
+
+impl HelperIntegration {
+    /// Restores the helpers saved in `file`. Without a file, helpers are kept only in memory.
+    /// Fails if the file cannot be read, so it is never overwritten with the defaults.
+    pub fn new(file: Option<PathBuf>) -> Result<Self> {
+        let entries = match &file {
+            Some(path) => load(path)?,
+            None => Vec::new(),
+        };
+        let integration = Self {
+            inner: Arc::new(Inner {
+                saver: file.map(Saver::spawn),
+                state: Default::default(),
+                subscribers: Default::default(),
+            }),
+        };
+        let mut helpers = integration.inner.state.lock().unwrap();
+        for entry in entries {
+            let id = entry.settings.id.clone();
+            match entry.origin {
+                HelperOrigin::Code => {
+                    helpers.remembered.insert(id, entry);
+                }
+                HelperOrigin::Api => {
+                    helpers.active.insert(id, integration.restore(entry));
+                }
+            }
+        }
+        if let Some(saver) = &integration.inner.saver {
+            saver.loaded(helpers.to_json()?);
+        }
+        drop(helpers);
+        Ok(integration)
+    }
+
+    /// Waits until the file has the latest changes, or failed to be written.
+    pub async fn saved(&self) {
+        if let Some(saver) = &self.inner.saver {
+            saver.wait().await;
+        }
+    }
+
+    /// Lists every helper, sorted by ID.
+    pub fn list(&self) -> Vec<HelperEntry> {
+        let helpers = self.inner.state.lock().unwrap();
+        helpers.active.values().map(|h| h.entry.clone()).collect()
+    }
+
+    /// Returns a helper by its ID, which can also be its full device ID.
+    pub fn get(&self, id: &str) -> Option<HelperEntry> {
+        let helpers = self.inner.state.lock().unwrap();
+        helpers.active.get(local_id(id)).map(|h| h.entry.clone())
+    }
+
+    /// Creates a helper that is kept until [`Self::remove`].
+    pub fn create(&self, settings: HelperSettings) -> Result<()> {
+        let errors = settings.validate();
+        ensure!(errors.is_empty(), "{}", errors.join(", "));
+        self.update(|helpers| {
+            ensure!(
+                !helpers.active.contains_key(&settings.id),
+                "helper {} already exists",
+                settings.id
+            );
+            let entry = HelperEntry {
+                state: settings.initial_state(),
+                settings,
+                origin: HelperOrigin::Api,
+                finishes_at: None,
+            };
+            helpers
+                .active
+                .insert(entry.settings.id.clone(), self.restore(entry));
+            Ok(Vec::new())
+        })
+    }
+
+    /// Removes a helper created through [`Self::create`].
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let id = local_id(id);
+        self.update(|helpers| {
+            let helper = helpers
+                .active
+                .get(id)
+                .with_context(|| format!("helper {id} not found"))?;
+            ensure!(
+                helper.entry.origin == HelperOrigin::Api,
+                "helper {id} is declared in the source code"
+            );
+            helpers.active.remove(id);
+            Ok(Vec::new())
+        })
+    }
+
+    /// Changes the value of a helper. Timers are started by `true` or a number of seconds,
+    /// and cancelled by `false`.
+    pub fn set_value(&self, id: &str, value: &Value) -> Result<()> {
+        let id = local_id(id);
+        self.update(|helpers| {
+            let helper = helpers
+                .active
+                .get_mut(id)
+                .with_context(|| format!("helper {id} not found"))?;
+            if let HelperKind::Timer { .. } = helper.entry.settings.kind {
+                return match value {
+                    Value::Number(seconds) => {
+                        ensure!(*seconds > 0.0, "timer duration must be positive");
+                        let duration = Duration::try_from_secs_f64(*seconds)?;
+                        self.start(helper, Some(duration))
+                    }
+                    value => match helper.entry.settings.parse_value(value)?.as_str() {
+                        "on" => self.start(helper, None),
+                        _ => Ok(Self::cancel(helper)),
+                    },
+                };
+            }
+            let state = helper.entry.settings.parse_value(value)?;
+            Ok(helper.entry.set_state(state).into_iter().collect())
+        })
+    }
+
+    /// Starts a timer, or restarts it when it is running. Without a duration, it runs for the
+    /// duration in its settings.
+    pub fn start_timer(&self, id: &str, duration: Option<Duration>) -> Result<()> {
+        let id = local_id(id);
+        self.update(|helpers| {
+            let helper = helpers
+                .active
+                .get_mut(id)
+                .with_context(|| format!("helper {id} not found"))?;
+            self.start(helper, duration)
+        })
+    }
+
+    pub fn cancel_timer(&self, id: &str) -> Result<()> {
+        let id = local_id(id);
+        self.update(|helpers| {
+            let helper = helpers
+                .active
+                .get_mut(id)
+                .with_context(|| format!("helper {id} not found"))?;
+            ensure!(
+                matches!(helper.entry.settings.kind, HelperKind::Timer { .. }),
+                "helper {id} is not a timer"
+            );
+            Ok(Self::cancel(helper))
+        })
+    }
+
+    /// Declares the helpers of the source code. Helpers that were declared before keep their
+    /// state, unless it does not fit their new settings. When `replace` is true, the helpers
+    /// of the source code that are not in `declared` are removed.
+    pub(crate) fn declare(&self, declared: Vec<HelperSettings>, replace: bool) -> Result<()> {
+        let mut ids = HashSet::new();
+        for settings in &declared {
+            let errors = settings.validate();
+            ensure!(
+                errors.is_empty(),
+                "invalid helper {}: {}",
+                settings.id,
+                errors.join(", ")
+            );
+            ensure!(
+                ids.insert(settings.id.clone()),
+                "helper {} is declared twice",
+                settings.id
+            );
+        }
+        self.update(|helpers| {
+            for id in &ids {
+                if let Some(helper) = helpers.active.get(id) {
+                    ensure!(
+                        helper.entry.origin == HelperOrigin::Code,
+                        "helper {id} was already created through the API"
+                    );
+                }
+            }
+            if replace {
+                helpers
+                    .active
+                    .retain(|id, h| h.entry.origin == HelperOrigin::Api || ids.contains(id));
+            }
+            for settings in declared {
+                let id = settings.id.clone();
+                if let Some(helper) = helpers.active.get_mut(&id) {
+                    if !is_same_kind(&helper.entry.settings, &settings) {
+                        helper.stop_timer();
+                    }
+                    helper.entry.settings = settings;
+                    keep_state_if_valid(&mut helper.entry);
+                    continue;
+                }
+                let entry = match helpers.remembered.remove(&id) {
+                    Some(mut entry) if is_same_kind(&entry.settings, &settings) => {
+                        entry.settings = settings;
+                        keep_state_if_valid(&mut entry);
+                        entry
+                    }
+                    _ => HelperEntry {
+                        state: settings.initial_state(),
+                        settings,
+                        origin: HelperOrigin::Code,
+                        finishes_at: None,
+                    },
+                };
+                helpers.active.insert(id, self.restore(entry));
+            }
+            Ok(Vec::new())
+        })
+    }
+
+    /// Wraps an entry, resuming its timer if it is running.
+    fn restore(&self, entry: HelperEntry) -> Helper {
+        let timer = match entry.finishes_at {
+            Some(finishes_at) if entry.state == "on" => {
+                Some(self.spawn_timer(entry.settings.id.clone(), finishes_at))
+            }
+            _ => None,
+        };
+        Helper { entry, timer }
+    }
+
+    fn start(&self, helper: &mut Helper, duration: Option<Duration>) -> Result<Vec<Event>> {
+        let HelperKind::Timer {
+            duration: default_duration,
+        } = helper.entry.settings.kind
+        else {
+            bail!("helper {} is not a timer", helper.entry.settings.id);
+        };
+        let duration = duration.unwrap_or(Duration::from_secs(default_duration));
+        let finishes_at = Local::now() + chrono::Duration::from_std(duration)?;
+        helper.stop_timer();
+        helper.entry.finishes_at = Some(finishes_at);
+        helper.timer = Some(self.spawn_timer(helper.entry.settings.id.clone(), finishes_at));
+        Ok(helper
+            .entry
+            .set_state("on".to_owned())
+            .into_iter()
+            .collect())
+    }
+
+    fn cancel(helper: &mut Helper) -> Vec<Event> {
+        helper.stop_timer();
+        Self::stop(helper, false)
+    }
+
+    fn stop(helper: &mut Helper, finished: bool) -> Vec<Event> {
+        let event = helper.entry.set_state("off".to_owned());
+        event
+            .map(|mut event| {
+                event
+                    .parameters
+                    .insert(FINISHED_PARAMETER.to_owned(), finished.into());
+                event
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn spawn_timer(&self, id: String, finishes_at: DateTime<Local>) -> JoinHandle<()> {
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let delay = (finishes_at - Local::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(delay).await;
+            if let Some(inner) = inner.upgrade() {
+                HelperIntegration { inner }.finish_timer(&id, finishes_at);
+            }
+        })
+    }
+
+    fn finish_timer(&self, id: &str, finishes_at: DateTime<Local>) {
+        let result = self.update(|helpers| {
+            let Some(helper) = helpers.active.get_mut(id) else {
+                return Ok(Vec::new());
+            };
+            if helper.entry.finishes_at != Some(finishes_at) {
+                return Ok(Vec::new());
+            }
+            // The timer is this task, which is about to end
+            helper.timer = None;
+            helper.entry.finishes_at = None;
+            Ok(Self::stop(helper, true))
+        });
+        if let Err(e) = result {
+            error!("Failed to finish timer {id}: {e:#}");
+        }
+    }
+
+    /// Changes the helpers, then saves them and sends the events of the change.
+    fn update(&self, change: impl FnOnce(&mut Helpers) -> Result<Vec<Event>>) -> Result<()> {
+        let events = {
+            let mut helpers = self.inner.state.lock().unwrap();
+            let events = change(&mut helpers)?;
+            if let Some(saver) = &self.inner.saver {
+                match helpers.to_json() {
+                    Ok(json) => saver.save(json),
+                    Err(e) => error!("Failed to save helpers: {e:#}"),
+                }
+            }
+            events
+        };
+        for event in events {
+            self.inner.subscribers.send(event);
+        }
+        Ok(())
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<HelperEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let json = std::fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+    serde_json::from_slice(&json).with_context(|| format!("invalid helpers file {path:?}"))
+}
+
+/// Accepts both `guest_mode` and `helpers@guest_mode`.
+fn local_id(id: &str) -> &str {
+    id.strip_prefix(HELPERS_INTEGRATION_ID)
+        .and_then(|id| id.strip_prefix('@'))
+        .unwrap_or(id)
+}
+
+fn is_same_kind(a: &HelperSettings, b: &HelperSettings) -> bool {
+    std::mem::discriminant(&a.kind) == std::mem::discriminant(&b.kind)
+}
+
+fn keep_state_if_valid(entry: &mut HelperEntry) {
+    let value = Value::String(entry.state.clone());
+    if entry.settings.parse_value(&value).is_err() {
+        entry.state = entry.settings.initial_state();
+    }
+}
+
+/// The helper ID in the first argument of a function.
+fn helper_argument(args: &[Value]) -> Result<&str> {
+    match args.first() {
+        Some(Value::String(id)) => Ok(id),
+        _ => bail!("first argument must be the helper id"),
+    }
+}
+
+#[async_trait]
+impl Integration for HelperIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(self.list().iter().map(HelperEntry::to_device).collect())
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.get(id).as_ref().map(HelperEntry::to_device))
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        match command {
+            DeviceCommand::TurnOn => self.set_value(device_id, &Value::Boolean(true)),
+            DeviceCommand::TurnOff => self.set_value(device_id, &Value::Boolean(false)),
+            command => bail!("{device_id} does not support {command:?}"),
+        }
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.inner.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        HELPERS_INTEGRATION_ID
+    }
+
+    fn get_functions(&self) -> Vec<Function> {
+        let value = self.clone();
+        let set_value = self.clone();
+        let start_timer = self.clone();
+        let cancel_timer = self.clone();
+        vec![
+            Function {
+                name: "value".to_owned(),
+                description: "Valor atual de um auxiliar",
+                category: FunctionCategory::Device,
+                parameters: vec![FunctionParameter::required("helper", ValueType::String)],
+                returns: ValueType::Any,
+                fun: Arc::new(move |_ctx, args| {
+                    let helpers = value.clone();
+                    Box::pin(async move {
+                        let id = helper_argument(&args)?;
+                        let entry = helpers
+                            .get(id)
+                            .with_context(|| format!("helper {id} not found"))?;
+                        Ok(entry.value())
+                    })
+                }),
+            },
+            Function {
+                name: "set_value".to_owned(),
+                description: "Muda o valor de um auxiliar",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("helper", ValueType::String),
+                    FunctionParameter::required("value", ValueType::Any),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(move |_ctx, args| {
+                    let helpers = set_value.clone();
+                    Box::pin(async move {
+                        let id = helper_argument(&args)?;
+                        let value = args.get(1).context("second argument must be the value")?;
+                        helpers.set_value(id, value)?;
+                        Ok(Value::Null)
+                    })
+                }),
+            },
+            Function {
+                name: "start_timer".to_owned(),
+                description:
+                    "Inicia um temporizador auxiliar, opcionalmente com outra duração em segundos",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("helper", ValueType::String),
+                    FunctionParameter::optional("seconds", ValueType::Number),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(move |_ctx, args| {
+                    let helpers = start_timer.clone();
+                    Box::pin(async move {
+                        let id = helper_argument(&args)?;
+                        let duration = match args.get(1) {
+                            Some(Value::Number(seconds)) if *seconds > 0.0 => {
+                                Some(Duration::try_from_secs_f64(*seconds)?)
+                            }
+                            Some(Value::Null) | None => None,
+                            Some(_) => {
+                                bail!("second argument must be a positive number of seconds")
+                            }
+                        };
+                        helpers.start_timer(id, duration)?;
+                        Ok(Value::Null)
+                    })
+                }),
+            },
+            Function {
+                name: "cancel_timer".to_owned(),
+                description: "Cancela um temporizador auxiliar",
+                category: FunctionCategory::Action,
+                parameters: vec![FunctionParameter::required("helper", ValueType::String)],
+                returns: ValueType::Null,
+                fun: Arc::new(move |_ctx, args| {
+                    let helpers = cancel_timer.clone();
+                    Box::pin(async move {
+                        helpers.cancel_timer(helper_argument(&args)?)?;
+                        Ok(Value::Null)
+                    })
+                }),
+            },
+        ]
+    }
+}
@@ -1,6 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use futures_util::SinkExt;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message as WebSocketMessage;
 
@@ -13,10 +16,42 @@ pub(super) struct CommandMessage {
     pub message: Message,
 }
 
+/// Errors returned by Home Assistant for a command, by their error code.
+#[derive(Error, Debug)]
+pub enum HassError {
+    #[error("command timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("invalid format: {0}")]
+    InvalidFormat(String),
+    #[error("invalid service call: {0}")]
+    ServiceValidation(String),
+    #[error("home assistant error {code}: {message}")]
+    Other { code: String, message: String },
+}
+
+impl HassError {
+    fn from_code(code: &str, message: String) -> Self {
+        match code {
+            "not_found" => Self::NotFound(message),
+            "unauthorized" => Self::Unauthorized(message),
+            "invalid_format" => Self::InvalidFormat(message),
+            "service_validation_error" => Self::ServiceValidation(message),
+            code => Self::Other {
+                code: code.to_owned(),
+                message,
+            },
+        }
+    }
+}
+
 pub struct Command<'a> {
     pub(super) ws: &'a HAWebSocket,
     pub(super) id: usize,
-    pub(super) recv: mpsc::Receiver<Message>,
+    pub(super) recv: mpsc::UnboundedReceiver<Message>,
 }
 
 impl<'a> Command<'a> {
@@ -36,6 +71,30 @@ impl<'a> Command<'a> {
             .await
             .context("command channel already closed")
     }
+    /// Waits for the `result` message of this command and returns its `result` field. Failures
+    /// reported by Home Assistant become [`HassError`]s.
+    pub async fn receive_result(&mut self, timeout: Duration) -> Result<Value> {
+        let mut msg = tokio::time::timeout(timeout, self.receive_message())
+            .await
+            .map_err(|_| HassError::Timeout(timeout))??;
+        ensure!(
+            &msg.msg_type == "result",
+            "expected result message, got {}",
+            msg.msg_type
+        );
+        if msg.fields.get("success") != Some(&Value::Bool(true)) {
+            let error = msg.fields.get("error");
+            let field = |name| {
+                error
+                    .and_then(|e| e.get(name))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            return Err(HassError::from_code(&field("code"), field("message")).into());
+        }
+        Ok(msg.fields.remove("result").unwrap_or_default())
+    }
 }
 
 impl Drop for Command<'_> {
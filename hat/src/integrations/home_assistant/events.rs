@@ -35,8 +35,10 @@ impl<'a> Events<'a> {
 pub enum EventData {
     StateChanged {
         entity_id: String,
-        new_state: Map<String, Value>,
-        old_state: Map<String, Value>,
+        /// `None` when the entity was removed
+        new_state: Option<Map<String, Value>>,
+        /// `None` when the entity was added
+        old_state: Option<Map<String, Value>>,
     },
     Unknown {
         #[serde(flatten)]
@@ -1,38 +1,53 @@
-use super::HAWebSocket;
+use super::registry::AreaRegistry;
+use super::subscription::{index_states, Subscription};
+use super::{HAWebSocket, DEFAULT_COMMAND_TIMEOUT};
 use crate::integrations::home_assistant::events::{Event as HassEvent, EventData};
-use crate::runtime::device::DeviceType;
-use crate::runtime::event::{Event as RuntimeEvent, EventType};
-use crate::{integrations::Integration, runtime::device::Device};
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::Device;
+use crate::runtime::device::{Capability, DeviceCommand, DeviceState, DeviceType};
+use crate::runtime::event::{Event as RuntimeEvent, EventType, ZONE_PARAMETER};
+use crate::runtime::function::{Function, FunctionCategory, FunctionParameter};
+use crate::runtime::value::{Value as RuntimeValue, ValueType};
 use anyhow::{bail, ensure, Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Local, TimeZone, Utc};
-use reqwest::header::HeaderMap;
-use reqwest::StatusCode;
 use serde::Deserialize;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::{debug, error};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
 use url::Url;
 
-lazy_static::lazy_static! {
-    static ref ID_COUNTER: AtomicU64 = {
-        AtomicU64::default()
-    };
-}
+/// Domains whose entities are turned on and off with the `turn_on` and `turn_off` services,
+/// whatever their type
+const TURN_ON_DOMAINS: [&str; 6] = [
+    "switch",
+    "input_boolean",
+    "climate",
+    "humidifier",
+    "siren",
+    "remote",
+];
 
 pub struct HassIntegration {
-    http_client: reqwest::Client,
-    url: Url,
-    ws: Arc<HAWebSocket>,
+    ws_url: Url,
+    access_token: String,
+    /// Replaced by the subscription task whenever it reconnects
+    ws: Arc<RwLock<Arc<HAWebSocket>>>,
+    states: StateCache,
+    registry: SharedRegistry,
     id: String,
 }
 
 impl HassIntegration {
-    pub async fn new(hass_url: &str, access_token: &str) -> Result<Self> {
+    pub async fn new(id: &str, hass_url: &str, access_token: &str) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
         let url = Url::parse(hass_url)?;
         ensure!(
             url.scheme() == "http" || url.scheme() == "https",
@@ -51,27 +66,39 @@ impl HassIntegration {
             ws_url
         };
         let ws = HAWebSocket::connect(ws_url.to_string().as_ref(), access_token).await?;
-        let new_id = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-        let http_client = reqwest::Client::builder()
-            .default_headers(
-                HeaderMap::try_from(&HashMap::from([(
-                    "Authorization".to_owned(),
-                    format!("Bearer {access_token}"),
-                )]))
-                .unwrap(),
-            )
-            .build()?;
+        let states = index_states(ws.get_states().await?);
+        let registry = AreaRegistry::load(&ws).await.unwrap_or_else(|e| {
+            warn!("Failed to load home assistant areas: {e:#}");
+            AreaRegistry::default()
+        });
         Ok(Self {
-            http_client,
-            url,
-            ws: Arc::new(ws),
-            id: format!("HassIntegration{new_id}"),
+            ws_url,
+            access_token: access_token.to_owned(),
+            ws: Arc::new(RwLock::new(Arc::new(ws))),
+            states: Arc::new(std::sync::RwLock::new(states)),
+            registry: Arc::new(std::sync::RwLock::new(registry)),
+            id: id.to_owned(),
         })
     }
-    fn get_endpoint_from_api_route(&self, route: &str) -> Url {
-        let mut url = self.url.clone();
-        url.set_path(route);
-        url
+    /// Calls a Home Assistant service on the given entity, with `data` as the service data.
+    async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        entity_id: &str,
+        data: Value,
+    ) -> Result<()> {
+        let ws = Arc::clone(&*self.ws.read().await);
+        ws.call_service(
+            domain,
+            service,
+            entity_id.into(),
+            data,
+            false,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await?;
+        Ok(())
     }
     fn get_device_type_from_entity_id(entity_id: &str, device_class: Option<&str>) -> DeviceType {
         let typ = entity_id.split_once(".");
@@ -92,14 +119,106 @@ impl HassIntegration {
                 "input_boolean" => DeviceType::Switch,
                 "input_button" => DeviceType::Button,
                 "input_number" => DeviceType::Sensor,
+                "climate" => DeviceType::Thermostat,
+                "cover" => DeviceType::Cover,
+                "lock" => DeviceType::Lock,
+                "fan" => DeviceType::Fan,
+                "person" => DeviceType::Person,
                 _ => DeviceType::Unknown,
             }
         } else {
             DeviceType::Unknown
         }
     }
+    /// Capabilities of a Home Assistant entity. Lights advertise what they support through
+    /// the `supported_color_modes` attribute.
+    fn get_capabilities(
+        entity_id: &str,
+        typ: DeviceType,
+        attributes: &serde_json::Map<String, Value>,
+    ) -> Vec<Capability> {
+        let color_modes = attributes
+            .get("supported_color_modes")
+            .and_then(|modes| modes.as_array());
+
+        match (entity_id.split_once("."), color_modes) {
+            (Some(("light", _)), Some(color_modes)) => {
+                let mut capabilities = vec![Capability::OnOff];
+                for mode in color_modes.iter().filter_map(|mode| mode.as_str()) {
+                    let supported: &[Capability] = match mode {
+                        "brightness" | "white" => &[Capability::Brightness],
+                        "color_temp" => &[Capability::Brightness, Capability::ColorTemp],
+                        "hs" | "xy" | "rgb" | "rgbw" | "rgbww" => {
+                            &[Capability::Brightness, Capability::ColorRgb]
+                        }
+                        _ => &[],
+                    };
+                    for capability in supported {
+                        if !capabilities.contains(capability) {
+                            capabilities.push(*capability);
+                        }
+                    }
+                }
+                capabilities
+            }
+            // Trackers of phones, but also of routers and TVs, so they are not people
+            (Some(("device_tracker", _)), _) => vec![Capability::Presence],
+            (Some(("media_player", _)), _) => vec![Capability::OnOff, Capability::MediaPlayback],
+            (Some((domain, _)), _) if TURN_ON_DOMAINS.contains(&domain) => {
+                let mut capabilities = typ.default_capabilities().to_vec();
+                if !capabilities.contains(&Capability::OnOff) {
+                    capabilities.push(Capability::OnOff);
+                }
+                capabilities
+            }
+            _ => typ.default_capabilities().to_vec(),
+        }
+    }
+    fn device_from_state(&self, state: &Map<String, Value>) -> Option<Device> {
+        let entity = serde_json::from_value::<HassEntityState>(Value::Object(state.clone()))
+            .inspect_err(|e| warn!("Invalid home assistant state: {e:?}"))
+            .ok()?;
+        Some(Self::make_device(
+            self.get_id(),
+            &self.registry.read().unwrap(),
+            entity.entity_id,
+            state_string(&entity.state),
+            entity.attributes,
+        ))
+    }
+    fn make_device(
+        integration_id: &str,
+        registry: &AreaRegistry,
+        entity_id: String,
+        state: Option<String>,
+        attributes: serde_json::Map<String, Value>,
+    ) -> Device {
+        let typ = Self::get_device_type_from_entity_id(
+            &entity_id,
+            attributes.get("device_class").and_then(|c| c.as_str()),
+        );
+        let location = registry.get(&entity_id).cloned().unwrap_or_default();
+        Device {
+            integration: integration_id.to_owned(),
+            capabilities: Self::get_capabilities(&entity_id, typ, &attributes),
+            area: location.area,
+            floor: location.floor,
+            typ,
+            name: attributes
+                .get("friendly_name")
+                .and_then(|f| f.as_str().map(|s| s.to_owned())),
+            id: entity_id,
+            state,
+            attributes,
+        }
+    }
 }
 
+/// Entity states by entity ID, kept up to date by the [`Subscription`]
+pub(super) type StateCache = Arc<std::sync::RwLock<HashMap<String, Map<String, Value>>>>;
+/// Reloaded by the [`Subscription`] whenever it reconnects
+pub(super) type SharedRegistry = Arc<std::sync::RwLock<AreaRegistry>>;
+
 #[derive(Debug, Deserialize)]
 struct HassEntityState {
     attributes: serde_json::Map<String, serde_json::Value>,
@@ -110,53 +229,18 @@ struct HassEntityState {
 #[async_trait]
 impl Integration for HassIntegration {
     async fn list_devices(&self) -> Result<Vec<Device>> {
-        let res = self
-            .http_client
-            .get(self.get_endpoint_from_api_route("/api/states"))
-            .send()
-            .await?;
+        let states = self.states.read().unwrap();
 
-        ensure!(
-            res.status() == StatusCode::OK,
-            "failed to list devices on hass: {}, {}",
-            res.status(),
-            res.text().await?,
-        );
-
-        let devices = res
-            .json::<Vec<HassEntityState>>()
-            .await?
-            .into_iter()
-            .filter_map(|entity| {
-                let typ = Self::get_device_type_from_entity_id(
-                    &entity.entity_id,
-                    entity
-                        .attributes
-                        .get("device_class")
-                        .and_then(|c| c.as_str()),
-                );
+        let devices = states
+            .values()
+            .filter_map(|state| {
+                let device = self.device_from_state(state)?;
 
-                if matches!(typ, DeviceType::Unknown) {
+                if device.capabilities.is_empty() {
                     return None;
                 }
 
-                Some(Device {
-                    integration: self.get_id().to_owned(),
-                    typ,
-                    id: entity.entity_id,
-                    name: entity
-                        .attributes
-                        .get("friendly_name")
-                        .and_then(|f| f.as_str().map(|s| s.to_owned())),
-                    state: Some(
-                        entity
-                            .state
-                            .as_str()
-                            .expect("states that are not a string are not yet implemented")
-                            .to_owned(),
-                    ),
-                    attributes: entity.attributes,
-                })
+                Some(device)
             })
             .collect();
 
@@ -164,46 +248,10 @@ impl Integration for HassIntegration {
     }
 
     async fn get_device(&self, id: &str) -> Result<Option<Device>> {
-        let res = self
-            .http_client
-            .get(self.get_endpoint_from_api_route(&format!("/api/states/{id}")))
-            .send()
-            .await?;
-
-        if res.status() == StatusCode::NOT_FOUND {
-            return Ok(None);
-        }
-
-        ensure!(
-            res.status() == StatusCode::OK,
-            "failed to list devices on hass: {}, {}",
-            res.status(),
-            res.text().await?,
-        );
-
-        let res = res.json::<HassEntityState>().await?;
-
-        let device = Device {
-            integration: self.get_id().to_owned(),
-            typ: Self::get_device_type_from_entity_id(
-                &res.entity_id,
-                res.attributes.get("device_class").and_then(|c| c.as_str()),
-            ),
-            id: res.entity_id,
-            name: res
-                .attributes
-                .get("friendly_name")
-                .and_then(|f| f.as_str().map(|s| s.to_owned())),
-            state: Some(
-                res.state
-                    .as_str()
-                    .expect("states that are not a string are not yet implemented")
-                    .to_owned(),
-            ),
-            attributes: res.attributes,
-        };
-
-        Ok(Some(device))
+        let states = self.states.read().unwrap();
+        Ok(states
+            .get(id)
+            .and_then(|state| self.device_from_state(state)))
     }
 
     async fn turn_on_device(&self, device_id: &str) -> Result<()> {
@@ -211,33 +259,17 @@ impl Integration for HassIntegration {
             .get_device(device_id)
             .await?
             .context("device not found")?;
-        if device.state.as_deref() != Some("off") {
-            bail!(
-                "cannot turn on a device that is not off: {}.state = {:?}",
-                device.id,
-                device.state
-            );
-        }
-        let splitted = device_id.split_once(".");
-        if let Some((domain, _id)) = splitted {
-            let res = self
-                .http_client
-                .post(self.get_endpoint_from_api_route(&format!("/api/services/{domain}/turn_on")))
-                .json(&json!({
-                    "entity_id": device_id
-                }))
-                .send()
-                .await?;
-            ensure!(
-                res.status() == StatusCode::OK,
-                "turn on request failed: {}, {}",
-                res.status(),
-                res.text().await?
-            );
-            Ok(())
-        } else {
-            bail!("device id does not contain home assistant domain")
+        // Turning on a device that is already on does nothing. Devices in any other state,
+        // including unavailable or unknown ones, are left for Home Assistant to handle.
+        if device.typed_state() == DeviceState::On {
+            debug!("{device_id} is already on");
+            return Ok(());
         }
+        let (domain, _id) = device_id
+            .split_once(".")
+            .context("device id does not contain home assistant domain")?;
+        self.call_service(domain, "turn_on", device_id, json!({}))
+            .await
     }
 
     async fn turn_off_device(&self, device_id: &str) -> Result<()> {
@@ -245,127 +277,158 @@ impl Integration for HassIntegration {
             .get_device(device_id)
             .await?
             .context("device not found")?;
-        if device.state.as_deref() != Some("on") {
-            bail!("cannot turn off a device that is not on");
-        }
-        let splitted = device_id.split_once(".");
-        if let Some((domain, _id)) = splitted {
-            let res = self
-                .http_client
-                .post(self.get_endpoint_from_api_route(&format!("/api/services/{domain}/turn_off")))
-                .json(&json!({
-                    "entity_id": device_id
-                }))
-                .send()
-                .await?;
-            ensure!(
-                res.status() == StatusCode::OK,
-                "turn on request failed: {}, {}",
-                res.status(),
-                res.text().await?
-            );
-            Ok(())
-        } else {
-            bail!("device id does not contain home assistant domain")
+        if device.typed_state() == DeviceState::Off {
+            debug!("{device_id} is already off");
+            return Ok(());
         }
+        let (domain, _id) = device_id
+            .split_once(".")
+            .context("device id does not contain home assistant domain")?;
+        self.call_service(domain, "turn_off", device_id, json!({}))
+            .await
     }
 
-    fn subscribe(&self) -> UnboundedReceiver<RuntimeEvent> {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let api = Arc::clone(&self.ws);
-
-        let integration_name = self.get_id().to_owned();
-
-        tokio::spawn(async move {
-            let mut events = match api.subscribe_events(None).await {
-                Ok(events) => events,
-                Err(e) => {
-                    error!("Failed to subscribe to home assistant events: {e:#?}");
-                    return;
-                }
-            };
-
-            loop {
-                let hass_event = match events.next().await {
-                    Ok(event) => event,
-                    Err(e) => {
-                        error!("Failed to read event from home assistant: {e:#?}");
-                        break;
-                    }
-                };
-
-                let runtime_event = parse_event(&integration_name, &hass_event);
+    fn subscribe(&self, capacity: usize) -> Receiver<RuntimeEvent> {
+        let (tx, rx) = mpsc::channel(capacity);
+        let subscription = Subscription {
+            integration_id: self.get_id().to_owned(),
+            ws: Arc::clone(&self.ws),
+            ws_url: self.ws_url.clone(),
+            access_token: self.access_token.clone(),
+            states: Arc::clone(&self.states),
+            registry: Arc::clone(&self.registry),
+        };
 
-                if let Some(runtime_event) = runtime_event {
-                    tx.send(runtime_event).unwrap();
-                } else {
-                    debug!(
-                        "Event not recognized: {}",
-                        serde_json::to_string_pretty(&hass_event).unwrap_or_default()
-                    );
-                }
-            }
-        });
+        tokio::spawn(subscription.run(tx));
 
         rx
     }
 
     async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
-        let splitted = device_id.split_once(".");
-        if let Some((domain, _id)) = splitted {
-            ensure!(domain == "light", "device is not a light");
-            let res = self
-                .http_client
-                .post(self.get_endpoint_from_api_route("/api/services/light/turn_on"))
-                .json(&json!({
-                    "entity_id": device_id,
-                    "rgb_color": color,
-                }))
-                .send()
-                .await?;
-            ensure!(
-                res.status() == StatusCode::OK,
-                "turn on request failed: {}, {}",
-                res.status(),
-                res.text().await?
-            );
-            Ok(())
-        } else {
-            bail!("device id does not contain home assistant domain")
-        }
+        ensure!(device_id.starts_with("light."), "device is not a light");
+        self.call_service("light", "turn_on", device_id, json!({ "rgb_color": color }))
+            .await
     }
 
     async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
-        let splitted = device_id.split_once(".");
-        if let Some((domain, _id)) = splitted {
-            ensure!(domain == "light", "device is not a light");
-            let res = self
-                .http_client
-                .post(self.get_endpoint_from_api_route("/api/services/light/turn_on"))
-                .json(&json!({
-                    "entity_id": device_id,
-                    "brightness": brightness,
-                }))
-                .send()
-                .await?;
-            ensure!(
-                res.status() == StatusCode::OK,
-                "turn on request failed: {}, {}",
-                res.status(),
-                res.text().await?
-            );
-            Ok(())
-        } else {
-            bail!("device id does not contain home assistant domain")
-        }
+        ensure!(device_id.starts_with("light."), "device is not a light");
+        self.call_service(
+            "light",
+            "turn_on",
+            device_id,
+            json!({ "brightness": brightness }),
+        )
+        .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let (domain, service, data) = match command {
+            DeviceCommand::TurnOn => return self.turn_on_device(device_id).await,
+            DeviceCommand::TurnOff => return self.turn_off_device(device_id).await,
+            DeviceCommand::SetBrightness(brightness) => {
+                return self.set_light_brightness(device_id, brightness).await
+            }
+            DeviceCommand::SetColorRgb(color) => {
+                return self.set_light_color_rgb(device_id, color).await
+            }
+            DeviceCommand::SetColorTemp(kelvin) => {
+                ("light", "turn_on", json!({ "color_temp_kelvin": kelvin }))
+            }
+            DeviceCommand::SetPosition(position) => (
+                "cover",
+                "set_cover_position",
+                json!({ "position": position }),
+            ),
+            DeviceCommand::Open => ("cover", "open_cover", json!({})),
+            DeviceCommand::Close => ("cover", "close_cover", json!({})),
+            DeviceCommand::Lock => ("lock", "lock", json!({})),
+            DeviceCommand::Unlock => ("lock", "unlock", json!({})),
+            DeviceCommand::SetTargetTemperature(temperature) => (
+                "climate",
+                "set_temperature",
+                json!({ "temperature": temperature }),
+            ),
+            DeviceCommand::SetFanSpeed(percentage) => {
+                ("fan", "set_percentage", json!({ "percentage": percentage }))
+            }
+            DeviceCommand::MediaPlay => ("media_player", "media_play", json!({})),
+            DeviceCommand::MediaPause => ("media_player", "media_pause", json!({})),
+            DeviceCommand::MediaStop => ("media_player", "media_stop", json!({})),
+        };
+        self.call_service(domain, service, device_id, data).await
     }
 
     fn get_id(&self) -> &str {
         &self.id
     }
+
+    fn get_functions(&self) -> Vec<Function> {
+        let ws = Arc::clone(&self.ws);
+        vec![Function {
+            name: "ha_call_service".to_owned(),
+            description: "Chama um serviço do Home Assistant, como \"light.turn_on\". Com \
+                          return_response, retorna a resposta de serviços que retornam dados, \
+                          como \"weather.get_forecasts\"",
+            category: FunctionCategory::Action,
+            parameters: vec![
+                FunctionParameter::required("service", ValueType::String),
+                FunctionParameter::optional("target", ValueType::Any),
+                FunctionParameter::optional("data", ValueType::Map),
+                FunctionParameter::optional("return_response", ValueType::Boolean),
+            ],
+            returns: ValueType::Any,
+            fun: Arc::new(move |_ctx, args| {
+                let ws = Arc::clone(&ws);
+                Box::pin(async move {
+                    let mut args = args.into_iter();
+                    let (domain, service) = match args.next() {
+                        Some(RuntimeValue::String(name)) => name
+                            .split_once('.')
+                            .map(|(d, s)| (d.to_owned(), s.to_owned()))
+                            .context("service must have the format \"domain.service\"")?,
+                        _ => bail!("first argument must be the service name"),
+                    };
+                    let target = match args.next() {
+                        Some(target @ (RuntimeValue::String(_) | RuntimeValue::Map(_))) => {
+                            target.to_json()
+                        }
+                        Some(RuntimeValue::Null) | None => Value::Null,
+                        Some(_) => bail!("target must be an entity id or a map"),
+                    };
+                    let data = match args.next() {
+                        Some(data @ RuntimeValue::Map(_)) => data.to_json(),
+                        Some(RuntimeValue::Null) | None => json!({}),
+                        Some(_) => bail!("service data must be a map"),
+                    };
+                    let return_response = match args.next() {
+                        Some(RuntimeValue::Boolean(return_response)) => return_response,
+                        Some(RuntimeValue::Null) | None => false,
+                        Some(_) => bail!("return_response must be a boolean"),
+                    };
+
+                    let ws = Arc::clone(&*ws.read().await);
+                    let response = ws
+                        .call_service(
+                            &domain,
+                            &service,
+                            target,
+                            data,
+                            return_response,
+                            DEFAULT_COMMAND_TIMEOUT,
+                        )
+                        .await?;
+                    Ok(RuntimeValue::from_json(response))
+                })
+            }),
+        }]
+    }
 }
 
-fn parse_event(integration_name: &str, hass_event: &HassEvent) -> Option<RuntimeEvent> {
+pub(super) fn parse_event(
+    integration_name: &str,
+    registry: &AreaRegistry,
+    hass_event: &HassEvent,
+) -> Option<RuntimeEvent> {
     let time = DateTime::parse_from_rfc3339(&hass_event.time_fired).ok()?;
     let time: DateTime<Local> = Utc.from_utc_datetime(&time.naive_utc()).into();
     match &hass_event.data {
@@ -374,210 +437,81 @@ fn parse_event(integration_name: &str, hass_event: &HassEvent) -> Option<Runtime
             old_state: old_state_data,
             new_state: new_state_data,
         } => {
-            let entity_type = entity_id.split(".").next()?;
+            let (old_state_data, new_state_data) =
+                (old_state_data.as_ref()?, new_state_data.as_ref()?);
             let new_state = new_state_data.get("state")?;
             let old_state = old_state_data.get("state")?;
             let attribs = match new_state_data.get("attributes").cloned() {
                 Some(Value::Object(map)) => map,
                 _ => Default::default(),
             };
-            let name = attribs
-                .get("friendly_name")
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_owned());
+            let device = HassIntegration::make_device(
+                integration_name,
+                registry,
+                entity_id.to_owned(),
+                state_string(new_state),
+                attribs,
+            );
 
-            match entity_type {
-                "binary_sensor" => {
-                    let device_class = attribs.get("device_class").and_then(|v| v.as_str());
-                    if device_class == Some("door") {
-                        let device = Device {
-                            integration: integration_name.to_owned(),
-                            id: entity_id.to_owned(),
-                            name,
-                            state: Some(new_state.to_string()),
-                            typ: DeviceType::DoorSensor,
-                            attributes: attribs,
-                        };
-                        if old_state == "off" && new_state == "on" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::DoorOpenEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                        if old_state == "on" && new_state == "off" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::DoorCloseEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                    } else if device_class == Some("motion") {
-                        let device = Device {
-                            integration: integration_name.to_owned(),
-                            id: entity_id.to_owned(),
-                            name,
-                            state: Some(new_state.to_string()),
-                            typ: DeviceType::MotionSensor,
-                            attributes: attribs,
-                        };
-                        if old_state == "off" && new_state == "on" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::MotionSensorOnEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                        if old_state == "on" && new_state == "off" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::MotionSensorOffEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                    }
-                }
-                "light" => {
-                    let device = Device {
-                        integration: integration_name.to_owned(),
-                        id: entity_id.to_owned(),
-                        name,
-                        state: Some(new_state.to_string()),
-                        typ: DeviceType::Light,
-                        attributes: attribs,
-                    };
-                    if old_state == "off" && new_state == "on" {
-                        return Some(RuntimeEvent {
-                            typ: EventType::LightOnEvent,
-                            datetime: time,
-                            device,
-                            parameters: Default::default(),
-                        });
-                    }
-                    if old_state == "on" && new_state == "off" {
-                        return Some(RuntimeEvent {
-                            typ: EventType::LightOffEvent,
-                            datetime: time,
-                            device,
-                            parameters: Default::default(),
-                        });
-                    }
-                }
-                "sensor" => {
-                    let mut parameters = HashMap::new();
-                    let value = new_state.as_str();
-                    if let Some(value) = value {
-                        parameters.insert("value".into(), value.to_owned());
-                    }
-                    return Some(RuntimeEvent {
-                        typ: EventType::SensorValueChangeEvent,
-                        datetime: time,
-                        device: Device {
-                            integration: integration_name.to_owned(),
-                            id: entity_id.to_owned(),
-                            name,
-                            state: Some(new_state.to_string()),
-                            typ: DeviceType::Sensor,
-                            attributes: attribs,
-                        },
-                        parameters,
-                    });
-                }
-                "switch" => {
-                    let device_class = attribs.get("device_class").and_then(|v| v.as_str());
-                    if device_class == Some("outlet") {
-                        let device = Device {
-                            integration: integration_name.to_owned(),
-                            id: entity_id.to_owned(),
-                            name,
-                            state: Some(new_state.to_string()),
-                            typ: DeviceType::Switch,
-                            attributes: attribs,
-                        };
-                        if old_state == "off" && new_state == "on" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::SwitchTurnedOnEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                        if old_state == "on" && new_state == "off" {
-                            return Some(RuntimeEvent {
-                                typ: EventType::SwitchTurnedOffEvent,
-                                datetime: time,
-                                device,
-                                parameters: Default::default(),
-                            });
-                        }
-                    }
-                }
-                "input_boolean" => {
-                    let device = Device {
-                        integration: integration_name.to_owned(),
-                        id: entity_id.to_owned(),
-                        name,
-                        state: Some(new_state.to_string()),
-                        typ: DeviceType::Switch,
-                        attributes: attribs,
-                    };
-                    if old_state == "off" && new_state == "on" {
-                        return Some(RuntimeEvent {
-                            typ: EventType::SwitchTurnedOnEvent,
-                            datetime: time,
-                            device,
-                            parameters: Default::default(),
-                        });
+            let (typ, old_value, new_value) = match device
+                .get_state_change_event(&state_string(old_state)?, &state_string(new_state)?)
+            {
+                Some(typ) => (typ, old_state, new_state),
+                None if device.has_capability(Capability::TemperatureSetpoint) => {
+                    // Thermostats keep their mode as the state, so changes to the target
+                    // temperature only show up in the attributes.
+                    let old_target = old_state_data.get("attributes")?.get("temperature")?;
+                    let new_target = device.attributes.get("temperature")?;
+                    if old_target == new_target {
+                        return None;
                     }
-                    if old_state == "on" && new_state == "off" {
-                        return Some(RuntimeEvent {
-                            typ: EventType::SwitchTurnedOffEvent,
-                            datetime: time,
-                            device,
-                            parameters: Default::default(),
-                        });
-                    }
-                }
-                "input_number" => {
-                    let device = Device {
-                        integration: integration_name.to_owned(),
-                        id: entity_id.to_owned(),
-                        name,
-                        state: Some(new_state.to_string()),
-                        typ: DeviceType::Sensor,
-                        attributes: attribs,
-                    };
-                    return Some(RuntimeEvent {
-                        typ: EventType::SensorValueChangeEvent,
-                        datetime: time,
-                        device,
-                        parameters: Default::default(),
-                    });
+                    (
+                        EventType::TargetTemperatureChangedEvent,
+                        old_target,
+                        new_target,
+                    )
                 }
-                "input_button" => {
-                    let device = Device {
-                        integration: integration_name.to_owned(),
-                        id: entity_id.to_owned(),
-                        name,
-                        state: Some(new_state.to_string()),
-                        typ: DeviceType::Button,
-                        attributes: attribs,
-                    };
-                    return Some(RuntimeEvent {
-                        typ: EventType::ButtonPressedEvent,
-                        datetime: time,
-                        device,
-                        parameters: Default::default(),
-                    });
+                // Sensors also report changes to their attributes, like the battery level
+                None if device.has_capability(Capability::Measurement) => {
+                    (EventType::SensorValueChangeEvent, old_state, new_state)
                 }
-                _ => {}
+                None => return None,
+            };
+
+            let (old_value, new_value) = (state_value(old_value), state_value(new_value));
+            let mut event = RuntimeEvent::with_states(typ, device, old_value, new_value);
+            event.datetime = time;
+            let zone = match event.typ {
+                EventType::PersonArrivedEvent => Some(new_state),
+                EventType::PersonLeftEvent => Some(old_state),
+                _ => None,
+            };
+            if let Some(zone) = zone {
+                event
+                    .parameters
+                    .insert(ZONE_PARAMETER.to_owned(), state_value(zone));
             }
+            Some(event)
         }
-        EventData::Unknown { .. } => {}
-    };
-    None
+        EventData::Unknown { .. } => None,
+    }
+}
+
+/// Home Assistant states are strings, but other JSON values are accepted as their JSON text
+/// instead of panicking. A `null` state means the device has no state.
+fn state_string(state: &Value) -> Option<String> {
+    match state {
+        Value::String(state) => Some(state.clone()),
+        Value::Null => None,
+        state => Some(state.to_string()),
+    }
+}
+
+/// Converts a Home Assistant state into a runtime value. Numeric states, like the ones from
+/// sensors, become numbers so they can be compared and subtracted in Hat code.
+fn state_value(state: &Value) -> RuntimeValue {
+    match state_string(state) {
+        Some(state) => DeviceState::parse(&state).to_value(),
+        None => RuntimeValue::Null,
+    }
 }
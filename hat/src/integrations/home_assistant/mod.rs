@@ -1,6 +1,8 @@
 pub mod command;
 pub mod events;
 mod integration;
+mod registry;
+mod subscription;
 
 pub use integration::HassIntegration;
 
@@ -21,6 +23,9 @@ use tokio_tungstenite::tungstenite::Message as WebSocketMessage;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use tracing::{debug, error, warn};
 
+/// How long to wait for the result of a command before giving up
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Message {
     #[serde(rename = "type")]
@@ -33,13 +38,15 @@ pub struct HAWebSocket {
     pub ha_version: String,
     tx: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WebSocketMessage>>>,
     last_command_id: AtomicUsize,
-    command_channels: Arc<std::sync::Mutex<BTreeMap<usize, mpsc::Sender<Message>>>>,
+    /// Unbounded, so a subscription that is not being read never stalls the replies of other
+    /// commands
+    command_channels: Arc<std::sync::Mutex<BTreeMap<usize, mpsc::UnboundedSender<Message>>>>,
 }
 
 impl HAWebSocket {
-    pub async fn new_command(&self) -> Command {
+    pub async fn new_command(&self) -> Command<'_> {
         let id = self.generate_command_id();
-        let (tx, rx) = mpsc::channel(3);
+        let (tx, rx) = mpsc::unbounded_channel();
         let cmd = Command {
             ws: self,
             id,
@@ -113,7 +120,7 @@ impl HAWebSocket {
 
         let command_channels = Arc::new(std::sync::Mutex::new(BTreeMap::<
             usize,
-            mpsc::Sender<Message>,
+            mpsc::UnboundedSender<Message>,
         >::new()));
 
         let channels = Arc::clone(&command_channels);
@@ -149,7 +156,7 @@ impl HAWebSocket {
                             };
                             match channel {
                                 Some(c) => {
-                                    c.send(msg.message).await.ok();
+                                    c.send(msg.message).ok();
                                 }
                                 None => {
                                     warn!("Received message for unknown command channel {}", msg.id)
@@ -160,9 +167,12 @@ impl HAWebSocket {
                     },
                     Err(e) => {
                         error!("Failed to read message from home assistant: {e:?}");
+                        break;
                     }
                 }
             }
+            // Closes every pending command, so their owners notice the connection is gone
+            channels.lock().unwrap().clear();
         });
 
         Ok(Self {
@@ -172,6 +182,14 @@ impl HAWebSocket {
             command_channels,
         })
     }
+    /// Closes the connection, failing every pending command and subscription.
+    pub async fn close(&self) {
+        let mut tx = self.tx.lock().await;
+        if let Err(e) = tx.close().await {
+            debug!("Failed to close home assistant websocket: {e:?}");
+        }
+        self.command_channels.lock().unwrap().clear();
+    }
     pub async fn ping(&self, timeout: Duration) -> Result<()> {
         let mut command = self.new_command().await;
         command
@@ -186,6 +204,67 @@ impl HAWebSocket {
         ensure!(&res.msg_type == "pong");
         Ok(())
     }
+    /// Sends a command and waits for its result.
+    pub async fn send_command(
+        &self,
+        msg_type: &str,
+        fields: HashMap<String, Value>,
+        timeout: Duration,
+    ) -> Result<Value> {
+        let mut command = self.new_command().await;
+        command
+            .send_message(Message {
+                msg_type: msg_type.into(),
+                fields,
+            })
+            .await?;
+        command
+            .receive_result(timeout)
+            .await
+            .with_context(|| format!("{msg_type} command failed"))
+    }
+    /// Returns the current state objects of every entity.
+    pub async fn get_states(&self) -> Result<Vec<serde_json::Map<String, Value>>> {
+        let states = self
+            .send_command("get_states", HashMap::new(), DEFAULT_COMMAND_TIMEOUT)
+            .await?;
+        Ok(serde_json::from_value(states)?)
+    }
+    /// Calls `domain.service` on `target`, which may be an entity ID or a target object with
+    /// `entity_id`, `device_id`, `area_id`... With `return_response`, returns the response of
+    /// services that return data, like `weather.get_forecasts`. Home Assistant rejects it for
+    /// services that do not.
+    pub async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        target: Value,
+        data: Value,
+        return_response: bool,
+        timeout: Duration,
+    ) -> Result<Value> {
+        let target = match target {
+            Value::String(entity_id) => json!({ "entity_id": entity_id }),
+            target => target,
+        };
+        let mut fields = HashMap::from([
+            ("domain".to_owned(), Value::from(domain)),
+            ("service".to_owned(), Value::from(service)),
+            ("service_data".to_owned(), data),
+        ]);
+        if !target.is_null() {
+            fields.insert("target".to_owned(), target);
+        }
+        if return_response {
+            fields.insert("return_response".to_owned(), Value::Bool(true));
+        }
+        let result = self.send_command("call_service", fields, timeout).await;
+        let mut result = result.with_context(|| format!("failed to call {domain}.{service}"))?;
+        Ok(result
+            .get_mut("response")
+            .map(Value::take)
+            .unwrap_or_default())
+    }
     pub async fn subscribe_events(&self, event_type: Option<String>) -> Result<Events<'_>> {
         let mut command = self.new_command().await;
 
@@ -200,10 +279,10 @@ impl HAWebSocket {
                 fields: message_fields,
             })
             .await?;
-        let result = command.receive_message().await?;
-        ensure!(&result.msg_type == "result");
-        let success = result.fields.get("success");
-        ensure!(success == Some(&Value::Bool(true)));
+        command
+            .receive_result(DEFAULT_COMMAND_TIMEOUT)
+            .await
+            .context("failed to subscribe to events")?;
         Ok(Events { command })
     }
     fn generate_command_id(&self) -> usize {
@@ -0,0 +1,107 @@
+use super::{HAWebSocket, DEFAULT_COMMAND_TIMEOUT};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+
+#[derive(Debug, Deserialize)]
+struct AreaEntry {
+    area_id: String,
+    name: String,
+    #[serde(default)]
+    floor_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FloorEntry {
+    floor_id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceEntry {
+    id: String,
+    #[serde(default)]
+    area_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EntityEntry {
+    entity_id: String,
+    #[serde(default)]
+    device_id: Option<String>,
+    #[serde(default)]
+    area_id: Option<String>,
+}
+
+/// Where an entity is, by name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Location {
+    pub area: Option<String>,
+    pub floor: Option<String>,
+}
+
+/// Location of every entity, built from the area, floor, device and entity registries of Home
+/// Assistant. Entities without an area of their own inherit the area of their device.
+#[derive(Debug, Default)]
+pub(super) struct AreaRegistry {
+    entities: HashMap<String, Location>,
+}
+
+impl AreaRegistry {
+    pub async fn load(ws: &HAWebSocket) -> Result<Self> {
+        let areas: Vec<AreaEntry> = list(ws, "config/area_registry/list").await?;
+        let devices: Vec<DeviceEntry> = list(ws, "config/device_registry/list").await?;
+        let entities: Vec<EntityEntry> = list(ws, "config/entity_registry/list").await?;
+        // Floors were added in Home Assistant 2024.4
+        let floors: Vec<FloorEntry> =
+            list(ws, "config/floor_registry/list")
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to list home assistant floors: {e:#}");
+                    Vec::new()
+                });
+
+        let floors = floors
+            .into_iter()
+            .map(|f| (f.floor_id, f.name))
+            .collect::<HashMap<_, _>>();
+        let areas = areas
+            .into_iter()
+            .map(|a| {
+                let location = Location {
+                    floor: a.floor_id.and_then(|id| floors.get(&id).cloned()),
+                    area: Some(a.name),
+                };
+                (a.area_id, location)
+            })
+            .collect::<HashMap<_, _>>();
+        let device_areas = devices
+            .into_iter()
+            .filter_map(|d| Some((d.id, d.area_id?)))
+            .collect::<HashMap<_, _>>();
+
+        let entities = entities
+            .into_iter()
+            .filter_map(|e| {
+                let area_id = e
+                    .area_id
+                    .or_else(|| device_areas.get(e.device_id.as_ref()?).cloned())?;
+                Some((e.entity_id, areas.get(&area_id)?.clone()))
+            })
+            .collect();
+
+        Ok(Self { entities })
+    }
+
+    pub fn get(&self, entity_id: &str) -> Option<&Location> {
+        self.entities.get(entity_id)
+    }
+}
+
+async fn list<T: for<'de> Deserialize<'de>>(ws: &HAWebSocket, msg_type: &str) -> Result<Vec<T>> {
+    let result = ws
+        .send_command(msg_type, HashMap::new(), DEFAULT_COMMAND_TIMEOUT)
+        .await?;
+    Ok(serde_json::from_value(result)?)
+}
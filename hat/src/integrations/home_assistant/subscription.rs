@@ -0,0 +1,286 @@
+use super::events::{Event as HassEvent, EventData};
+use super::integration::parse_event;
+use super::integration::{SharedRegistry, StateCache};
+use super::registry::AreaRegistry;
+use super::HAWebSocket;
+use crate::integrations::backoff::Backoff;
+use crate::integrations::subscribers;
+use crate::runtime::event::{Event as RuntimeEvent, EventType};
+use crate::runtime::value::Value as RuntimeValue;
+use anyhow::{Error, Result};
+use chrono::Utc;
+use futures_util::future::select_all;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{Notify, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events of the registries the [`AreaRegistry`] is built from
+const REGISTRY_UPDATED_EVENTS: [&str; 4] = [
+    "area_registry_updated",
+    "device_registry_updated",
+    "entity_registry_updated",
+    "floor_registry_updated",
+];
+/// Time to wait for more registry updates before reloading
+const REGISTRY_RELOAD_DELAY: Duration = Duration::from_millis(500);
+
+/// Keeps the event subscription of a [`super::HassIntegration`] alive. When the websocket drops,
+/// it reconnects with exponential backoff, authenticates and subscribes again. After every
+/// subscription, it emits the state changes that happened before it. The areas of devices are
+/// reloaded when the registries of Home Assistant change.
+pub(super) struct Subscription {
+    pub integration_id: String,
+    pub ws: Arc<RwLock<Arc<HAWebSocket>>>,
+    pub ws_url: Url,
+    pub access_token: String,
+    pub states: StateCache,
+    pub registry: SharedRegistry,
+}
+
+impl Subscription {
+    pub async fn run(self, tx: Sender<RuntimeEvent>) {
+        let mut reconnected = false;
+
+        loop {
+            let ws = Arc::clone(&*self.ws.read().await);
+            let reason = self.forward_events(&ws, &tx, reconnected).await;
+            ws.close().await;
+            if tx.is_closed() {
+                return;
+            }
+
+            error!(
+                "Lost connection to home assistant {}: {reason:#}",
+                self.integration_id
+            );
+            let parameters = HashMap::from([(
+                "reason".to_owned(),
+                RuntimeValue::String(format!("{reason:#}")),
+            )]);
+            self.send_status(&tx, EventType::IntegrationDisconnectedEvent, parameters);
+
+            let Some(ws) = self.reconnect(&tx).await else {
+                return;
+            };
+            *self.ws.write().await = Arc::new(ws);
+            reconnected = true;
+        }
+    }
+
+    /// Subscribes to the events of `ws` and forwards them until the connection fails, returning
+    /// the reason.
+    async fn forward_events(
+        &self,
+        ws: &Arc<HAWebSocket>,
+        tx: &Sender<RuntimeEvent>,
+        reconnected: bool,
+    ) -> Error {
+        let mut registry_events = Vec::new();
+        for event_type in REGISTRY_UPDATED_EVENTS {
+            match ws.subscribe_events(Some(event_type.into())).await {
+                Ok(events) => registry_events.push(events),
+                Err(e) => return e.context("failed to subscribe to registry events"),
+            }
+        }
+        let mut events = match ws.subscribe_events(Some("state_changed".into())).await {
+            Ok(events) => events,
+            Err(e) => return e.context("failed to subscribe to events"),
+        };
+        let registry_updated = Arc::new(Notify::new());
+        let registry_reloader = self.reload_registry(ws, &registry_updated);
+
+        let heartbeat = {
+            let ws = Arc::clone(ws);
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                    if let Err(e) = ws.ping(HEARTBEAT_TIMEOUT).await {
+                        warn!("Home assistant did not answer ping: {e:#}");
+                        ws.close().await;
+                        break;
+                    }
+                }
+            })
+        };
+
+        if reconnected {
+            info!("Reconnected to home assistant {}", self.integration_id);
+            self.send_status(tx, EventType::IntegrationConnectedEvent, HashMap::new());
+        }
+        // The states are read after subscribing, so changes since the cache was filled are
+        // emitted, and later ones arrive as events
+        if let Err(e) = self.resync(ws, tx).await {
+            error!("Failed to resynchronize home assistant states: {e:#}");
+        }
+
+        let reason = loop {
+            let next_registry_event = select_all(
+                registry_events
+                    .iter_mut()
+                    .map(|events| Box::pin(events.next())),
+            );
+            let hass_event = tokio::select! {
+                event = events.next() => match event {
+                    Ok(event) => event,
+                    Err(e) => break e.context("failed to read event"),
+                },
+                (event, _, _) = next_registry_event => match event {
+                    Ok(_) => {
+                        registry_updated.notify_one();
+                        continue;
+                    }
+                    Err(e) => break e.context("failed to read registry event"),
+                },
+            };
+
+            if let EventData::StateChanged {
+                entity_id,
+                new_state,
+                ..
+            } = &hass_event.data
+            {
+                let mut states = self.states.write().unwrap();
+                let known = states.get(entity_id);
+                let last_updated = new_state.as_ref().and_then(|s| s.get("last_updated"));
+                if known.is_some() && known.and_then(|s| s.get("last_updated")) == last_updated {
+                    // Already emitted while resynchronizing
+                    continue;
+                }
+                match new_state {
+                    Some(new_state) => states.insert(entity_id.clone(), new_state.clone()),
+                    None => states.remove(entity_id),
+                };
+            }
+
+            if let Some(runtime_event) = parse_event(
+                &self.integration_id,
+                &self.registry.read().unwrap(),
+                &hass_event,
+            ) {
+                let mut events = runtime_event.split_zone_change().into_iter();
+                if !events.all(|event| subscribers::send(tx, event)) {
+                    break Error::msg("integration was removed");
+                }
+            } else {
+                debug!(
+                    "Event not recognized: {}",
+                    serde_json::to_string_pretty(&hass_event).unwrap_or_default()
+                );
+            }
+        };
+
+        heartbeat.abort();
+        registry_reloader.abort();
+        reason
+    }
+
+    /// Reloads the area registry whenever `updated` is notified, so areas and devices
+    /// reassigned in Home Assistant are seen without reconnecting. Updates that arrive together
+    /// cause a single reload.
+    fn reload_registry(&self, ws: &Arc<HAWebSocket>, updated: &Arc<Notify>) -> JoinHandle<()> {
+        let ws = Arc::clone(ws);
+        let updated = Arc::clone(updated);
+        let registry = Arc::clone(&self.registry);
+        tokio::spawn(async move {
+            loop {
+                updated.notified().await;
+                tokio::time::sleep(REGISTRY_RELOAD_DELAY).await;
+                match AreaRegistry::load(&ws).await {
+                    Ok(loaded) => *registry.write().unwrap() = loaded,
+                    Err(e) => warn!("Failed to reload home assistant areas: {e:#}"),
+                }
+            }
+        })
+    }
+
+    async fn reconnect(&self, tx: &Sender<RuntimeEvent>) -> Option<HAWebSocket> {
+        let mut backoff = Backoff::default();
+        loop {
+            backoff.wait().await;
+            if tx.is_closed() {
+                return None;
+            }
+            match HAWebSocket::connect(self.ws_url.as_str(), &self.access_token).await {
+                Ok(ws) => return Some(ws),
+                Err(e) => {
+                    let delay = backoff.delay();
+                    warn!("Failed to reconnect to home assistant, retrying in {delay:?}: {e:#}");
+                }
+            }
+        }
+    }
+
+    /// Replaces the state cache and emits state changes for every entity that changed since it
+    /// was filled, like while the websocket was offline.
+    async fn resync(&self, ws: &HAWebSocket, tx: &Sender<RuntimeEvent>) -> Result<()> {
+        match AreaRegistry::load(ws).await {
+            Ok(registry) => *self.registry.write().unwrap() = registry,
+            Err(e) => warn!("Failed to reload home assistant areas: {e:#}"),
+        }
+        let new_states = index_states(ws.get_states().await?);
+        let mut states = self.states.write().unwrap();
+        for (entity_id, new_state) in &new_states {
+            let Some(old_state) = states.get(entity_id) else {
+                continue;
+            };
+            if old_state.get("last_updated") == new_state.get("last_updated") {
+                continue;
+            }
+            let hass_event = HassEvent {
+                event_type: "state_changed".into(),
+                time_fired: new_state
+                    .get("last_updated")
+                    .and_then(|t| t.as_str())
+                    .map(|t| t.to_owned())
+                    .unwrap_or_else(|| Utc::now().to_rfc3339()),
+                origin: "LOCAL".into(),
+                context: Value::Null,
+                data: EventData::StateChanged {
+                    entity_id: entity_id.clone(),
+                    new_state: Some(new_state.clone()),
+                    old_state: Some(old_state.clone()),
+                },
+            };
+            if let Some(runtime_event) = parse_event(
+                &self.integration_id,
+                &self.registry.read().unwrap(),
+                &hass_event,
+            ) {
+                for event in runtime_event.split_zone_change() {
+                    subscribers::send(tx, event);
+                }
+            }
+        }
+        *states = new_states;
+        Ok(())
+    }
+
+    fn send_status(
+        &self,
+        tx: &Sender<RuntimeEvent>,
+        typ: EventType,
+        parameters: HashMap<String, RuntimeValue>,
+    ) {
+        let event = RuntimeEvent::from_integration(&self.integration_id, typ, parameters);
+        subscribers::send(tx, event);
+    }
+}
+
+/// Indexes state objects, as returned by `get_states`, by their entity ID.
+pub(super) fn index_states(states: Vec<Map<String, Value>>) -> HashMap<String, Map<String, Value>> {
+    states
+        .into_iter()
+        .filter_map(|state| {
+            let entity_id = state.get("entity_id")?.as_str()?.to_owned();
+            Some((entity_id, state))
+        })
+        .collect()
+}
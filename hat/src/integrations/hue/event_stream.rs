@@ -0,0 +1,101 @@
+use super::resources::Resources;
+use super::HueClient;
+use crate::integrations::backoff::Backoff;
+use crate::integrations::sse;
+use crate::integrations::subscribers::Subscribers;
+use crate::runtime::event::{Event, EventType};
+use crate::runtime::value::Value as RuntimeValue;
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, error, info, warn};
+
+/// Listens to the event stream of the bridge, keeping the [`Resources`] up to date and
+/// forwarding events to the subscribers. Loads every resource again after reconnecting, to
+/// catch up on the changes it missed.
+pub(super) struct EventStream {
+    pub integration_id: String,
+    pub client: HueClient,
+    pub resources: Arc<RwLock<Resources>>,
+    pub subscribers: Subscribers,
+}
+
+impl EventStream {
+    pub async fn run(self) {
+        let mut online = true;
+        let mut backoff = Backoff::default();
+
+        loop {
+            let Err(e) = self.listen(&mut online, &mut backoff).await else {
+                continue;
+            };
+            if online {
+                error!("Lost connection to hue bridge {}: {e}", self.integration_id);
+                let parameters =
+                    HashMap::from([("reason".to_owned(), RuntimeValue::String(e.to_string()))]);
+                self.send(Event::from_integration(
+                    &self.integration_id,
+                    EventType::IntegrationDisconnectedEvent,
+                    parameters,
+                ));
+                online = false;
+            } else {
+                let delay = backoff.delay();
+                warn!("Failed to reconnect to hue bridge, retrying in {delay:?}: {e}");
+            }
+            backoff.wait().await;
+        }
+    }
+
+    /// Connects to the event stream and handles its messages until it closes.
+    async fn listen(&self, online: &mut bool, backoff: &mut Backoff) -> Result<()> {
+        let response = self.client.event_stream().await?;
+        if !*online {
+            let resources = self.client.get_resources().await?;
+            let events = self
+                .resources
+                .write()
+                .unwrap()
+                .replace(&self.integration_id, resources);
+            info!("Reconnected to hue bridge {}", self.integration_id);
+            self.send(Event::from_integration(
+                &self.integration_id,
+                EventType::IntegrationConnectedEvent,
+                HashMap::new(),
+            ));
+            for event in events {
+                self.send(event);
+            }
+            *online = true;
+        }
+        backoff.reset();
+
+        let Err(e) = sse::read_events(response, None, |data| self.handle_message(data)).await;
+        Err(e)
+    }
+
+    /// Handles the data of one server-sent event, a list of events, each with the resources
+    /// it touches.
+    fn handle_message(&self, data: &str) {
+        let events = match serde_json::from_str::<Vec<Value>>(data) {
+            Ok(events) => events,
+            Err(e) => {
+                debug!("Ignoring invalid message from hue bridge: {e}");
+                return;
+            }
+        };
+        let events = self
+            .resources
+            .write()
+            .unwrap()
+            .apply(&self.integration_id, &events);
+        for event in events {
+            self.send(event);
+        }
+    }
+
+    fn send(&self, event: Event) {
+        self.subscribers.send(event);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::integrations::subscribers::Subscribers;
+mod event_stream;
+mod resources;
+
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Device, DeviceCommand};
+use crate::runtime::event::Event;
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use event_stream::EventStream;
+use resources::Resources;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use url::Url;
+
+/// Header with the application key, created by pressing the link button of the bridge
+const APPLICATION_KEY_HEADER: &str = "hue-application-key";
+/// Longest time to connect, and to wait for the answer of a request other than the event stream
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lights, rooms, buttons and motion sensors of a Philips Hue bridge, through its local v2 API.
+/// Lights are controlled through `PUT /clip/v2/resource`, and changes arrive through the
+/// event stream at `/eventstream/clip/v2`. Every room is also a light, controlling all the
+/// lights in it.
+pub struct HueIntegration {
+    id: String,
+    client: HueClient,
+    resources: Arc<RwLock<Resources>>,
+    subscribers: Subscribers,
+    task: JoinHandle<()>,
+}
+
+impl HueIntegration {
+    /// Connects to the bridge at `bridge_url`, like `https://192.168.1.10`, and loads its
+    /// resources. The bridge uses a certificate signed by Signify for its ID instead of its
+    /// address, so connecting over https only works with `insecure`, which skips verifying
+    /// the certificate. Anyone on the network can then impersonate the bridge and read the
+    /// application key.
+    pub async fn new(
+        id: &str,
+        bridge_url: &str,
+        application_key: &str,
+        insecure: bool,
+    ) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let url = Url::parse(bridge_url)?;
+        ensure!(
+            url.scheme() == "http" || url.scheme() == "https",
+            "unknown url scheme"
+        );
+        if insecure && url.scheme() == "https" {
+            warn!("The certificate of hue bridge {id} is not verified");
+        }
+        let client = HueClient {
+            http: reqwest::Client::builder()
+                .connect_timeout(REQUEST_TIMEOUT)
+                .danger_accept_invalid_certs(insecure)
+                .build()?,
+            url,
+            application_key: application_key.to_owned(),
+        };
+        let resources = Arc::new(RwLock::new(Resources::new(client.get_resources().await?)));
+        let subscribers = Subscribers::default();
+        let event_stream = EventStream {
+            integration_id: id.to_owned(),
+            client: client.clone(),
+            resources: Arc::clone(&resources),
+            subscribers: subscribers.clone(),
+        };
+        Ok(Self {
+            id: id.to_owned(),
+            client,
+            resources,
+            subscribers,
+            task: tokio::spawn(event_stream.run()),
+        })
+    }
+}
+
+impl Drop for HueIntegration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl Integration for HueIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(self.resources.read().unwrap().devices(&self.id))
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.resources.read().unwrap().device(&self.id, id))
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let resource_type = {
+            let resources = self.resources.read().unwrap();
+            let device = resources
+                .device(&self.id, device_id)
+                .context("device not found")?;
+            ensure!(
+                device.has_capability(command.required_capability()),
+                "{device_id} does not support {command:?}"
+            );
+            resources
+                .resource_type(device_id)
+                .context("device not found")?
+                .to_owned()
+        };
+        let body = match command {
+            DeviceCommand::TurnOn => json!({ "on": { "on": true } }),
+            DeviceCommand::TurnOff => json!({ "on": { "on": false } }),
+            // Hue brightness is a percentage
+            DeviceCommand::SetBrightness(brightness) => {
+                json!({ "dimming": { "brightness": f64::from(brightness) * 100.0 / 255.0 } })
+            }
+            DeviceCommand::SetColorRgb(color) => {
+                let (x, y) = rgb_to_xy(color);
+                json!({ "color": { "xy": { "x": x, "y": y } } })
+            }
+            // Hue lights go from 153 to 500 mireds
+            DeviceCommand::SetColorTemp(kelvin) => {
+                let mirek = (1_000_000 / u32::from(kelvin.max(1))).clamp(153, 500);
+                json!({ "color_temperature": { "mirek": mirek } })
+            }
+            command => bail!("hue lights do not support {command:?}"),
+        };
+        self.client
+            .put_resource(&resource_type, device_id, body)
+            .await
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Clone)]
+struct HueClient {
+    http: reqwest::Client,
+    url: Url,
+    application_key: String,
+}
+
+/// Body of every response of the v2 API
+#[derive(Debug, Deserialize)]
+struct HueResponse {
+    #[serde(default)]
+    errors: Vec<HueError>,
+    #[serde(default)]
+    data: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HueError {
+    description: String,
+}
+
+impl HueClient {
+    /// Every resource of the bridge: lights, devices, rooms, buttons and so on.
+    async fn get_resources(&self) -> Result<Vec<Value>> {
+        let response = self
+            .http
+            .get(self.url.join("/clip/v2/resource")?)
+            .header(APPLICATION_KEY_HEADER, &self.application_key)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        Self::parse_response(response).await
+    }
+
+    async fn put_resource(&self, resource_type: &str, id: &str, body: Value) -> Result<()> {
+        debug!("Updating hue {resource_type} {id} with {body}");
+        let response = self
+            .http
+            .put(
+                self.url
+                    .join(&format!("/clip/v2/resource/{resource_type}/{id}"))?,
+            )
+            .header(APPLICATION_KEY_HEADER, &self.application_key)
+            .json(&body)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        Self::parse_response(response).await?;
+        Ok(())
+    }
+
+    async fn event_stream(&self) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .get(self.url.join("/eventstream/clip/v2")?)
+            .header(APPLICATION_KEY_HEADER, &self.application_key)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        ensure!(
+            response.status().is_success(),
+            "hue bridge answered {}",
+            response.status()
+        );
+        Ok(response)
+    }
+
+    async fn parse_response(response: reqwest::Response) -> Result<Vec<Value>> {
+        let status = response.status();
+        let body: HueResponse = response
+            .json()
+            .await
+            .with_context(|| format!("invalid response from hue bridge ({status})"))?;
+        if !status.is_success() || !body.errors.is_empty() {
+            let errors = body
+                .errors
+                .into_iter()
+                .map(|e| e.description)
+                .collect::<Vec<_>>();
+            bail!("hue bridge answered {status}: {}", errors.join(", "));
+        }
+        Ok(body.data)
+    }
+}
+
+/// Converts an sRGB color into CIE 1931 xy coordinates, the color space of Hue lights.
+fn rgb_to_xy(color: [u8; 3]) -> (f64, f64) {
+    let [r, g, b] = color.map(|c| {
+        let c = f64::from(c) / 255.0;
+        if c > 0.04045 {
+            ((c + 0.055) / 1.055).powf(2.4)
+        } else {
+            c / 12.92
+        }
+    });
+    let x = r * 0.4124 + g * 0.3576 + b * 0.1805;
+    let y = r * 0.2126 + g * 0.7152 + b * 0.0722;
+    let z = r * 0.0193 + g * 0.1192 + b * 0.9505;
+    let sum = x + y + z;
+    if sum == 0.0 {
+        // Black has no chromaticity, use the white point
+        return (0.3127, 0.3290);
+    }
+    let round = |v: f64| (v * 10_000.0).round() / 10_000.0;
+    (round(x / sum), round(y / sum))
+}
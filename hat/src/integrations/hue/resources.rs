@@ -0,0 +1,287 @@
+use crate::runtime::device::{Capability, Device, DeviceType};
+use crate::runtime::event::Event;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Button events that count as a press. The others, like `initial_press` and `repeat`, come
+/// before or after them.
+const PRESS_EVENTS: &[&str] = &["short_release", "long_press"];
+
+/// Cache of every resource of the bridge, by id, kept up to date with the event stream.
+pub(super) struct Resources {
+    resources: HashMap<String, Value>,
+}
+
+impl Resources {
+    pub fn new(resources: Vec<Value>) -> Self {
+        let mut this = Self {
+            resources: HashMap::new(),
+        };
+        for resource in resources {
+            this.insert(resource);
+        }
+        this
+    }
+
+    /// Replaces every resource, after reconnecting, returning the events for what changed
+    /// while disconnected.
+    pub fn replace(&mut self, integration_id: &str, resources: Vec<Value>) -> Vec<Event> {
+        let before = self.states(integration_id);
+        *self = Self::new(resources);
+        self.state_events(integration_id, before, &[])
+    }
+
+    /// Applies one message of the event stream: a list of `add`, `update` and `delete`
+    /// events, each with the resources it touches.
+    pub fn apply(&mut self, integration_id: &str, events: &[Value]) -> Vec<Event> {
+        let before = self.states(integration_id);
+        let mut pressed = Vec::new();
+        for event in events {
+            let data = event["data"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+            for resource in data {
+                let Some(id) = resource["id"].as_str() else {
+                    continue;
+                };
+                match event["type"].as_str() {
+                    Some("add") => self.insert(resource.clone()),
+                    Some("update") => {
+                        if let Some(cached) = self.resources.get_mut(id) {
+                            merge(cached, resource);
+                        }
+                        if resource.pointer("/button/button_report").is_some()
+                            || resource.pointer("/button/last_event").is_some()
+                        {
+                            pressed.push(id.to_owned());
+                        }
+                    }
+                    Some("delete") => {
+                        self.resources.remove(id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.state_events(integration_id, before, &pressed)
+    }
+
+    pub fn devices(&self, integration_id: &str) -> Vec<Device> {
+        let mut devices = self
+            .resources
+            .keys()
+            .filter_map(|id| self.device(integration_id, id))
+            .collect::<Vec<_>>();
+        devices.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        devices
+    }
+
+    /// Type of the resource, like `light` or `grouped_light`, used in its url.
+    pub fn resource_type(&self, id: &str) -> Option<&str> {
+        self.resources.get(id)?["type"].as_str()
+    }
+
+    /// Maps a resource into a device. Lights, grouped lights of rooms and zones, buttons and
+    /// motion sensors are devices, the other resources only describe them.
+    pub fn device(&self, integration_id: &str, id: &str) -> Option<Device> {
+        let resource = self.resources.get(id)?;
+        let owner = self.owner(resource);
+        let product_name = owner.and_then(|o| o.pointer("/product_data/product_name"));
+        let mut attributes = Map::new();
+        if let Some(product_name) = product_name {
+            attributes.insert("model".into(), product_name.clone());
+        }
+
+        let (typ, capabilities, name, area, state) = match resource["type"].as_str()? {
+            "light" => {
+                let mut capabilities = vec![Capability::OnOff];
+                if let Some(brightness) = resource.pointer("/dimming/brightness") {
+                    capabilities.push(Capability::Brightness);
+                    attributes.insert("brightness".into(), to_brightness(brightness));
+                }
+                if resource.get("color_temperature").is_some() {
+                    capabilities.push(Capability::ColorTemp);
+                    if let Some(mirek) = resource
+                        .pointer("/color_temperature/mirek")
+                        .and_then(Value::as_u64)
+                        .filter(|mirek| *mirek > 0)
+                    {
+                        attributes.insert("color_temp_kelvin".into(), (1_000_000 / mirek).into());
+                    }
+                }
+                if resource.get("color").is_some() {
+                    capabilities.push(Capability::ColorRgb);
+                }
+                let area = owner.and_then(|o| self.room_of(o["id"].as_str()?));
+                let state = on_off(resource.pointer("/on/on"));
+                (DeviceType::Light, capabilities, name(resource), area, state)
+            }
+            "grouped_light" => {
+                let group =
+                    owner.filter(|o| matches!(o["type"].as_str(), Some("room" | "zone")))?;
+                let mut capabilities = vec![Capability::OnOff];
+                if let Some(brightness) = resource.pointer("/dimming/brightness") {
+                    capabilities.push(Capability::Brightness);
+                    attributes.insert("brightness".into(), to_brightness(brightness));
+                }
+                let area = (group["type"] == "room").then(|| name(group)).flatten();
+                let state = on_off(resource.pointer("/on/on"));
+                (DeviceType::Light, capabilities, name(group), area, state)
+            }
+            "button" => {
+                let owner = owner?;
+                let name = match (name(owner), resource.pointer("/metadata/control_id")) {
+                    (Some(name), Some(control_id)) => Some(format!("{name} {control_id}")),
+                    (name, _) => name,
+                };
+                let state = resource
+                    .pointer("/button/button_report/event")
+                    .or_else(|| resource.pointer("/button/last_event"))
+                    .and_then(Value::as_str)
+                    .map(str::to_owned);
+                let area = self.room_of(owner["id"].as_str()?);
+                let capabilities = vec![Capability::Press];
+                (DeviceType::Button, capabilities, name, area, state)
+            }
+            "motion" => {
+                let owner = owner?;
+                let state = on_off(
+                    resource
+                        .pointer("/motion/motion_report/motion")
+                        .or_else(|| resource.pointer("/motion/motion")),
+                );
+                let area = self.room_of(owner["id"].as_str()?);
+                let capabilities = vec![Capability::Motion];
+                (
+                    DeviceType::MotionSensor,
+                    capabilities,
+                    name(owner),
+                    area,
+                    state,
+                )
+            }
+            _ => return None,
+        };
+
+        let state = match owner {
+            Some(owner) if !self.is_connected(owner) => Some("unavailable".to_owned()),
+            _ => state,
+        };
+        Some(Device {
+            integration: integration_id.to_owned(),
+            id: id.to_owned(),
+            name,
+            typ,
+            capabilities,
+            area,
+            floor: None,
+            state,
+            attributes,
+        })
+    }
+
+    fn insert(&mut self, resource: Value) {
+        if let Some(id) = resource["id"].as_str() {
+            self.resources.insert(id.to_owned(), resource);
+        }
+    }
+
+    fn owner(&self, resource: &Value) -> Option<&Value> {
+        self.resources
+            .get(resource.pointer("/owner/rid")?.as_str()?)
+    }
+
+    /// Name of the room with the device, from the children of the rooms.
+    fn room_of(&self, device_id: &str) -> Option<String> {
+        self.resources
+            .values()
+            .filter(|r| r["type"] == "room")
+            .find(|room| {
+                room["children"]
+                    .as_array()
+                    .is_some_and(|children| children.iter().any(|c| c["rid"] == device_id))
+            })
+            .and_then(name)
+    }
+
+    /// Zigbee devices report whether the bridge can reach them through one of their services.
+    fn is_connected(&self, device: &Value) -> bool {
+        let services = device["services"].as_array().map(Vec::as_slice);
+        services.unwrap_or(&[]).iter().all(|service| {
+            service["rtype"] != "zigbee_connectivity"
+                || service["rid"]
+                    .as_str()
+                    .and_then(|rid| self.resources.get(rid))
+                    .and_then(|connectivity| connectivity["status"].as_str())
+                    .is_none_or(|status| status == "connected")
+        })
+    }
+
+    fn states(&self, integration_id: &str) -> HashMap<String, Device> {
+        self.devices(integration_id)
+            .into_iter()
+            .map(|device| (device.id.clone(), device))
+            .collect()
+    }
+
+    /// Events for the devices whose state changed since `before`. Buttons in `pressed`
+    /// emit an event even when they repeat the last one.
+    fn state_events(
+        &self,
+        integration_id: &str,
+        before: HashMap<String, Device>,
+        pressed: &[String],
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        for device in self.devices(integration_id) {
+            let new_state = device.state.clone().unwrap_or_default();
+            let old_state = if device.has_capability(Capability::Press) {
+                if !pressed.contains(&device.id) || !PRESS_EVENTS.contains(&new_state.as_str()) {
+                    continue;
+                }
+                String::new()
+            } else {
+                match before.get(&device.id) {
+                    Some(old) => old.state.clone().unwrap_or_default(),
+                    None => continue,
+                }
+            };
+            events.extend(Event::state_change(device, &old_state, &new_state));
+        }
+        events
+    }
+}
+
+fn name(resource: &Value) -> Option<String> {
+    resource
+        .pointer("/metadata/name")
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+}
+
+fn on_off(value: Option<&Value>) -> Option<String> {
+    value
+        .and_then(Value::as_bool)
+        .map(|on| if on { "on" } else { "off" }.to_owned())
+}
+
+/// Hue brightness is a percentage, Hat brightness goes up to 255.
+fn to_brightness(brightness: &Value) -> Value {
+    let percentage = brightness.as_f64().unwrap_or_default().clamp(0.0, 100.0);
+    ((percentage * 255.0 / 100.0).round() as u64).into()
+}
+
+/// Merges the fields of a partial update into a cached resource.
+fn merge(target: &mut Value, update: &Value) {
+    match (target, update) {
+        (Value::Object(target), Value::Object(update)) => {
+            for (key, value) in update {
+                match target.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, update) => *target = update.clone(),
+    }
+}
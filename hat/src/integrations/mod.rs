@@ -1,12 +1,31 @@
-use crate::runtime::device::Device;
-use crate::runtime::event::Event;
-use anyhow::Result;
+use crate::runtime::device::{Device, DeviceCommand};
+use crate::runtime::event::{Event, EventDescriptor};
+use crate::runtime::function::Function;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use tokio::sync::mpsc;
 
+pub(crate) mod backoff;
+pub mod calendar;
 pub(crate) mod clock;
 pub mod dummy;
+pub mod helpers;
 pub mod home_assistant;
+pub mod hue;
+pub mod mqtt;
+pub mod remote;
+pub mod rest;
+pub mod simulation;
+pub(crate) mod sse;
+pub(crate) mod subscribers;
+
+/// Integration IDs are used in full device IDs (`{INTEGRATION_ID}@{DEVICE}`) and to namespace
+/// functions (`{INTEGRATION_ID}.{NAME}`), so they must be identifiers of the grammar.
+pub fn is_valid_integration_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
 
 #[async_trait]
 pub trait Integration: Send + Sync {
@@ -16,6 +35,30 @@ pub trait Integration: Send + Sync {
     async fn turn_off_device(&self, device_id: &str) -> Result<()>;
     async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()>;
     async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()>;
-    fn subscribe(&self) -> mpsc::UnboundedReceiver<Event>;
+    /// Executes a command on a device. The default implementation supports the commands that
+    /// have a dedicated method in this trait.
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        match command {
+            DeviceCommand::TurnOn => self.turn_on_device(device_id).await,
+            DeviceCommand::TurnOff => self.turn_off_device(device_id).await,
+            DeviceCommand::SetBrightness(brightness) => {
+                self.set_light_brightness(device_id, brightness).await
+            }
+            DeviceCommand::SetColorRgb(color) => self.set_light_color_rgb(device_id, color).await,
+            command => bail!("integration {} does not support {command:?}", self.get_id()),
+        }
+    }
+    /// Starts sending the events of the integration through a channel of `capacity` events.
+    /// Events that do not fit while the runtime is busy are dropped.
+    fn subscribe(&self, capacity: usize) -> mpsc::Receiver<Event>;
     fn get_id(&self) -> &str;
+    /// Extra functions provided by this integration. The runtime registers them as
+    /// `{INTEGRATION_ID}.{NAME}` and removes them together with the integration.
+    fn get_functions(&self) -> Vec<Function> {
+        Vec::new()
+    }
+    /// Extra event types emitted by this integration, usually [`crate::runtime::event::EventType::Custom`].
+    fn get_event_descriptors(&self) -> Vec<EventDescriptor> {
+        Vec::new()
+    }
 }
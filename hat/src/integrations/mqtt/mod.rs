@@ -0,0 +1,564 @@
+use crate::integrations::backoff::Backoff;
+use crate::integrations::subscribers::Subscribers;
+mod tasmota;
+mod zigbee2mqtt;
+
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceState};
+use crate::runtime::event::{Event, EventType};
+use crate::runtime::value::Value as RuntimeValue;
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use rumqttc::{
+    AsyncClient, Event as MqttEvent, EventLoop, MqttOptions, Packet, QoS, SubscribeFilter,
+};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// `bridge/devices` of Zigbee2MQTT describes every device, so it can be large
+const MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+
+/// How to reach the broker and where to look for devices.
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Defaults to `hat-{INTEGRATION_ID}`
+    pub client_id: Option<String>,
+    /// Base topic of Zigbee2MQTT, or `None` to ignore Zigbee2MQTT devices
+    pub zigbee2mqtt_topic: Option<String>,
+    /// Discovery topic of Tasmota, or `None` to ignore Tasmota devices
+    pub tasmota_discovery_topic: Option<String>,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            host: "localhost".into(),
+            port: 1883,
+            username: None,
+            password: None,
+            client_id: None,
+            zigbee2mqtt_topic: Some("zigbee2mqtt".into()),
+            tasmota_discovery_topic: Some("tasmota/discovery".into()),
+        }
+    }
+}
+
+/// Devices published on an MQTT broker by Zigbee2MQTT and Tasmota. Zigbee2MQTT devices are
+/// discovered from `bridge/devices` and Tasmota devices from its native discovery
+/// (`SetOption19 0`). Devices are commanded by publishing to their command topics.
+pub struct MqttIntegration {
+    id: String,
+    client: AsyncClient,
+    registry: Arc<RwLock<Registry>>,
+    subscribers: Subscribers,
+    task: JoinHandle<()>,
+}
+
+impl MqttIntegration {
+    /// Connects to the broker. Fails if the first connection does not succeed, later
+    /// disconnections are retried with exponential backoff.
+    pub async fn new(id: &str, settings: MqttSettings) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let client_id = settings
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("hat-{id}"));
+        let mut options = MqttOptions::new(client_id, &settings.host, settings.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+        if let Some(username) = &settings.username {
+            options.set_credentials(username, settings.password.clone().unwrap_or_default());
+        }
+        let (client, eventloop) = AsyncClient::new(options, 64);
+
+        let registry = Arc::new(RwLock::new(Registry {
+            integration_id: id.to_owned(),
+            zigbee2mqtt_topic: settings.zigbee2mqtt_topic,
+            tasmota_discovery_topic: settings.tasmota_discovery_topic,
+            ..Default::default()
+        }));
+        let subscribers = Subscribers::default();
+        let (subscriptions, filters) = mpsc::unbounded_channel();
+        tokio::spawn(subscribe(client.clone(), filters));
+        let connection = Connection {
+            integration_id: id.to_owned(),
+            subscriptions,
+            registry: Arc::clone(&registry),
+            subscribers: subscribers.clone(),
+        };
+        let (connected_tx, connected_rx) = oneshot::channel();
+        let task = tokio::spawn(connection.run(eventloop, connected_tx));
+
+        let broker = format!("{}:{}", settings.host, settings.port);
+        match tokio::time::timeout(CONNECT_TIMEOUT, connected_rx).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => {
+                return Err(e).with_context(|| format!("failed to connect to mqtt broker {broker}"))
+            }
+            Ok(Err(_)) | Err(_) => {
+                task.abort();
+                bail!("timed out connecting to mqtt broker {broker}");
+            }
+        }
+
+        Ok(Self {
+            id: id.to_owned(),
+            client,
+            registry,
+            subscribers,
+            task,
+        })
+    }
+}
+
+impl Drop for MqttIntegration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl Integration for MqttIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        let registry = self.registry.read().unwrap();
+        Ok(registry
+            .devices
+            .values()
+            .map(|d| d.device.clone())
+            .collect())
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        let registry = self.registry.read().unwrap();
+        Ok(registry.devices.get(id).map(|d| d.device.clone()))
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let (topic, payload) = {
+            let registry = self.registry.read().unwrap();
+            let device = registry
+                .devices
+                .get(device_id)
+                .context("device not found")?;
+            ensure!(
+                device.device.has_capability(command.required_capability()),
+                "{device_id} does not support {command:?}"
+            );
+            device.control.message(&command)?
+        };
+        debug!("Publishing {payload} to {topic}");
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await?;
+        Ok(())
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// A device published on the broker, and how to command it.
+struct MqttDevice {
+    device: Device,
+    control: Control,
+}
+
+enum Control {
+    Zigbee2Mqtt(zigbee2mqtt::Control),
+    Tasmota(tasmota::Control),
+}
+
+impl Control {
+    /// Topic and payload to publish to execute `command`.
+    fn message(&self, command: &DeviceCommand) -> Result<(String, String)> {
+        match self {
+            Control::Zigbee2Mqtt(control) => control.message(command),
+            Control::Tasmota(control) => control.message(command),
+        }
+    }
+}
+
+/// New state of a device, read from a state message.
+struct StateUpdate {
+    device_id: String,
+    state: String,
+    /// Merged into the attributes of the device
+    attributes: Map<String, Value>,
+}
+
+/// Everything known about the devices on the broker, kept up to date by the [`Connection`].
+#[derive(Default)]
+struct Registry {
+    integration_id: String,
+    zigbee2mqtt_topic: Option<String>,
+    tasmota_discovery_topic: Option<String>,
+    devices: HashMap<String, MqttDevice>,
+    /// Last state published by each Zigbee2MQTT device, by friendly name. States can arrive
+    /// before `bridge/devices`, and are applied when the device is discovered.
+    zigbee2mqtt_states: HashMap<String, Map<String, Value>>,
+    /// Tasmota devices by MAC address
+    tasmota_nodes: HashMap<String, tasmota::Node>,
+    /// Last sensors message published by each Tasmota device, by MAC address. Applied when
+    /// the configuration of the device is known.
+    tasmota_sensors: HashMap<String, String>,
+    /// State topics of discovered devices, subscribed again after reconnecting
+    filters: HashSet<String>,
+}
+
+impl Registry {
+    /// Topic filters to subscribe after connecting.
+    fn filters(&self) -> Vec<String> {
+        let discovery = [&self.zigbee2mqtt_topic, &self.tasmota_discovery_topic]
+            .into_iter()
+            .flatten()
+            .map(|topic| format!("{topic}/#"));
+        discovery.chain(self.filters.iter().cloned()).collect()
+    }
+
+    /// Applies a message to the registry. Returns the resulting events and the topic filters
+    /// that must be subscribed.
+    fn handle_message(&mut self, topic: &str, payload: &[u8]) -> (Vec<Event>, Vec<String>) {
+        let payload = String::from_utf8_lossy(payload);
+        let subtopic = |base: &Option<String>| {
+            topic
+                .strip_prefix(base.as_deref()?)?
+                .strip_prefix('/')
+                .map(|s| s.to_owned())
+        };
+
+        let mut filters = Vec::new();
+        let updates = if let Some(subtopic) = subtopic(&self.zigbee2mqtt_topic) {
+            self.handle_zigbee2mqtt(&subtopic, &payload)
+        } else if let Some(subtopic) = subtopic(&self.tasmota_discovery_topic) {
+            filters = self.handle_tasmota_discovery(&subtopic, &payload);
+            Vec::new()
+        } else {
+            self.tasmota_nodes
+                .values()
+                .flat_map(|node| node.state_updates(topic, &payload))
+                .collect()
+        };
+
+        let events = updates
+            .into_iter()
+            .filter_map(|update| self.apply(update))
+            .collect();
+        filters.retain(|filter| self.filters.insert(filter.clone()));
+        (events, filters)
+    }
+
+    fn handle_zigbee2mqtt(&mut self, subtopic: &str, payload: &str) -> Vec<StateUpdate> {
+        if subtopic == "bridge/devices" {
+            let base_topic = self.zigbee2mqtt_topic.as_deref().unwrap_or_default();
+            match zigbee2mqtt::parse_devices(&self.integration_id, base_topic, payload) {
+                Ok(devices) => {
+                    self.devices
+                        .retain(|_, d| !matches!(d.control, Control::Zigbee2Mqtt(_)));
+                    for mut device in devices {
+                        if let (Some(state), Control::Zigbee2Mqtt(control)) = (
+                            self.zigbee2mqtt_states.get(&device.device.id),
+                            &device.control,
+                        ) {
+                            device.device.state = control.state(state);
+                            device.device.attributes.extend(state.clone());
+                        }
+                        self.devices.insert(device.device.id.clone(), device);
+                    }
+                }
+                Err(e) => warn!("Invalid zigbee2mqtt device list: {e:#}"),
+            }
+            return Vec::new();
+        }
+        if subtopic.starts_with("bridge/") {
+            return Vec::new();
+        }
+
+        if let Some(name) = subtopic.strip_suffix("/availability") {
+            let state = if zigbee2mqtt::is_online(payload) {
+                let stored = self.zigbee2mqtt_states.get(name);
+                match self.devices.get(name) {
+                    Some(MqttDevice {
+                        control: Control::Zigbee2Mqtt(control),
+                        ..
+                    }) => stored.and_then(|s| control.state(s)),
+                    _ => None,
+                }
+                .unwrap_or_else(|| "unknown".into())
+            } else {
+                "unavailable".into()
+            };
+            return vec![StateUpdate {
+                device_id: name.to_owned(),
+                state,
+                attributes: Map::new(),
+            }];
+        }
+
+        // Anything else that is not a JSON object is a command, like `{NAME}/set`
+        let Ok(Value::Object(state)) = serde_json::from_str::<Value>(payload) else {
+            return Vec::new();
+        };
+        self.zigbee2mqtt_states
+            .insert(subtopic.to_owned(), state.clone());
+        match self.devices.get_mut(subtopic) {
+            Some(MqttDevice {
+                control: Control::Zigbee2Mqtt(control),
+                device,
+            }) => match control.state(&state) {
+                Some(new_state) => vec![StateUpdate {
+                    device_id: subtopic.to_owned(),
+                    state: new_state,
+                    attributes: state,
+                }],
+                None => {
+                    // Updates of other properties, like the battery level
+                    device.attributes.extend(state);
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles `{DISCOVERY_TOPIC}/{MAC}/config` and `{DISCOVERY_TOPIC}/{MAC}/sensors`.
+    fn handle_tasmota_discovery(&mut self, subtopic: &str, payload: &str) -> Vec<String> {
+        let Some((mac, kind)) = subtopic.split_once('/') else {
+            return Vec::new();
+        };
+        match kind {
+            "config" if payload.is_empty() => {
+                // Retained configurations are cleared when a device is removed
+                self.remove_tasmota_node(mac);
+                Vec::new()
+            }
+            "config" => match tasmota::Node::parse(mac, payload) {
+                Ok(node) => {
+                    self.remove_tasmota_node(mac);
+                    let filters = node.filters();
+                    for device in node.devices(&self.integration_id) {
+                        self.devices.insert(device.device.id.clone(), device);
+                    }
+                    self.tasmota_nodes.insert(mac.to_owned(), node);
+                    self.add_tasmota_sensors(mac);
+                    filters
+                }
+                Err(e) => {
+                    warn!("Invalid tasmota configuration of {mac}: {e:#}");
+                    Vec::new()
+                }
+            },
+            "sensors" => {
+                self.tasmota_sensors
+                    .insert(mac.to_owned(), payload.to_owned());
+                self.add_tasmota_sensors(mac);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn add_tasmota_sensors(&mut self, mac: &str) {
+        let (Some(node), Some(sensors)) =
+            (self.tasmota_nodes.get(mac), self.tasmota_sensors.get(mac))
+        else {
+            return;
+        };
+        match node.sensor_devices(&self.integration_id, sensors) {
+            Ok(devices) => {
+                for device in devices {
+                    self.devices.insert(device.device.id.clone(), device);
+                }
+            }
+            Err(e) => warn!("Invalid tasmota sensors of {mac}: {e:#}"),
+        }
+    }
+
+    fn remove_tasmota_node(&mut self, mac: &str) {
+        if self.tasmota_nodes.remove(mac).is_some() {
+            self.devices.retain(|_, d| match &d.control {
+                Control::Tasmota(control) => control.mac != mac,
+                _ => true,
+            });
+        }
+    }
+
+    /// Updates the state of a device, returning the event that represents the change.
+    fn apply(&mut self, update: StateUpdate) -> Option<Event> {
+        let device = &mut self.devices.get_mut(&update.device_id)?.device;
+        device.attributes.extend(update.attributes);
+        let new_state = update.state;
+        let old_state = device.state.replace(new_state.clone());
+
+        let old_state = if device.has_capability(Capability::Press) {
+            // Every press is an event, even when it repeats the last action
+            if !DeviceState::parse(&new_state).is_known() {
+                return None;
+            }
+            String::new()
+        } else {
+            old_state?
+        };
+        let typ = match device.get_state_change_event(&old_state, &new_state) {
+            Some(typ) => typ,
+            None if device.has_capability(Capability::TemperatureSetpoint)
+                && old_state != new_state
+                && DeviceState::parse(&old_state).is_known()
+                && DeviceState::parse(&new_state).is_known() =>
+            {
+                EventType::TargetTemperatureChangedEvent
+            }
+            None => return None,
+        };
+
+        let old_value = DeviceState::parse(&old_state).to_value();
+        let new_value = DeviceState::parse(&new_state).to_value();
+        Some(Event::with_states(
+            typ,
+            device.clone(),
+            old_value,
+            new_value,
+        ))
+    }
+}
+
+/// Subscribes to the filters found by a [`Connection`]. The event loop cannot wait for space in
+/// the queue of requests, since it is the one emptying it, so a burst of discovery messages is
+/// subscribed to from this task instead.
+async fn subscribe(client: AsyncClient, mut filters: mpsc::UnboundedReceiver<Vec<String>>) {
+    while let Some(filters) = filters.recv().await {
+        let filters = filters
+            .into_iter()
+            .map(|filter| SubscribeFilter::new(filter, QoS::AtLeastOnce));
+        if let Err(e) = client.subscribe_many(filters).await {
+            error!("Failed to subscribe to mqtt topics: {e}");
+            return;
+        }
+    }
+}
+
+/// Polls the connection to the broker, keeping the [`Registry`] up to date and forwarding
+/// events to the subscribers. Subscribes again after reconnecting.
+struct Connection {
+    integration_id: String,
+    /// Filters to subscribe to, see [`subscribe`]
+    subscriptions: mpsc::UnboundedSender<Vec<String>>,
+    registry: Arc<RwLock<Registry>>,
+    subscribers: Subscribers,
+}
+
+impl Connection {
+    async fn run(self, mut eventloop: EventLoop, connected: oneshot::Sender<Result<()>>) {
+        let mut connected = Some(connected);
+        let mut online = false;
+        let mut backoff = Backoff::default();
+
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    let filters = self.registry.read().unwrap().filters();
+                    self.subscribe(filters);
+                    online = true;
+                    backoff.reset();
+                    match connected.take() {
+                        Some(connected) => {
+                            connected.send(Ok(())).ok();
+                        }
+                        None => {
+                            info!("Reconnected to mqtt broker {}", self.integration_id);
+                            self.send(Event::from_integration(
+                                &self.integration_id,
+                                EventType::IntegrationConnectedEvent,
+                                HashMap::new(),
+                            ));
+                        }
+                    }
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    let (events, filters) = self
+                        .registry
+                        .write()
+                        .unwrap()
+                        .handle_message(&publish.topic, &publish.payload);
+                    self.subscribe(filters);
+                    for event in events {
+                        self.send(event);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if let Some(connected) = connected.take() {
+                        connected.send(Err(e.into())).ok();
+                        return;
+                    }
+                    if online {
+                        error!(
+                            "Lost connection to mqtt broker {}: {e}",
+                            self.integration_id
+                        );
+                        let parameters = HashMap::from([(
+                            "reason".to_owned(),
+                            RuntimeValue::String(e.to_string()),
+                        )]);
+                        self.send(Event::from_integration(
+                            &self.integration_id,
+                            EventType::IntegrationDisconnectedEvent,
+                            parameters,
+                        ));
+                        online = false;
+                    } else {
+                        let delay = backoff.delay();
+                        warn!("Failed to reconnect to mqtt broker, retrying in {delay:?}: {e}");
+                    }
+                    backoff.wait().await;
+                }
+            }
+        }
+    }
+
+    fn subscribe(&self, filters: Vec<String>) {
+        if !filters.is_empty() {
+            self.subscriptions.send(filters).ok();
+        }
+    }
+
+    fn send(&self, event: Event) {
+        self.subscribers.send(event);
+    }
+}
@@ -0,0 +1,401 @@
+use super::{Control as MqttControl, MqttDevice, StateUpdate};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceType};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Relay types in the discovery message
+const RELAY: u8 = 1;
+const LIGHT: u8 = 2;
+/// Light attributes copied from state messages
+const LIGHT_ATTRIBUTES: &[&str] = &["Dimmer", "Color", "HSBColor", "CT", "White"];
+
+/// Message published by Tasmota on `{DISCOVERY_TOPIC}/{MAC}/config`
+#[derive(Debug, Deserialize)]
+struct DiscoveryConfig {
+    /// Device name
+    dn: String,
+    /// Friendly names of the relays
+    #[serde(rename = "fn", default)]
+    friendly_names: Vec<Option<String>>,
+    /// Hostname
+    hn: String,
+    mac: String,
+    /// Model
+    md: Option<String>,
+    /// Firmware version
+    sw: Option<String>,
+    ip: Option<String>,
+    /// Topic of the device
+    t: String,
+    /// Full topic format, like `%prefix%/%topic%/`
+    ft: String,
+    /// Prefixes of command, status and telemetry topics
+    tp: Vec<String>,
+    /// Type of each relay: 0 is none, 1 a relay, 2 a light and 3 a shutter
+    rl: Vec<u8>,
+    /// Light subtype: 1 is a dimmer, 2 has color temperature, 3 RGB, 4 RGBW and 5 RGBCW
+    #[serde(default)]
+    lt_st: u8,
+    /// Payloads of the LWT topic
+    onln: String,
+    ofln: String,
+    /// Texts for off, on, toggle and hold
+    state: Vec<String>,
+}
+
+/// A relay or light of a Tasmota device.
+#[derive(Debug)]
+struct Relay {
+    device_id: String,
+    name: String,
+    /// `POWER` when the device has a single relay, `POWER{N}` otherwise
+    power: String,
+    light: bool,
+}
+
+/// A Tasmota device, which contains one Hat device for each relay and sensor value.
+#[derive(Debug)]
+pub(super) struct Node {
+    mac: String,
+    topic: String,
+    model: Option<String>,
+    firmware: Option<String>,
+    ip: Option<String>,
+    command_topic: String,
+    stat_topic: String,
+    tele_topic: String,
+    relays: Vec<Relay>,
+    light_subtype: u8,
+    online: String,
+    offline: String,
+    state_on: String,
+    state_off: String,
+}
+
+impl Node {
+    pub fn parse(mac: &str, payload: &str) -> Result<Self> {
+        let config: DiscoveryConfig = serde_json::from_str(payload)?;
+        let full_topic = |i: usize| -> Result<String> {
+            let prefix = config.tp.get(i).context("missing topic prefix")?;
+            let topic = config
+                .ft
+                .replace("%prefix%", prefix)
+                .replace("%topic%", &config.t)
+                .replace("%hostname%", &config.hn)
+                .replace("%id%", &config.mac);
+            Ok(if topic.ends_with('/') {
+                topic
+            } else {
+                format!("{topic}/")
+            })
+        };
+
+        let relay_count = config.rl.iter().filter(|r| **r != 0).count();
+        let relays = config
+            .rl
+            .iter()
+            .enumerate()
+            .filter(|(_, typ)| **typ == RELAY || **typ == LIGHT)
+            .map(|(i, typ)| {
+                let name = config
+                    .friendly_names
+                    .get(i)
+                    .cloned()
+                    .flatten()
+                    .unwrap_or_else(|| config.dn.clone());
+                let (device_id, power) = if relay_count == 1 {
+                    (config.t.clone(), "POWER".to_owned())
+                } else {
+                    (format!("{}.{}", config.t, i + 1), format!("POWER{}", i + 1))
+                };
+                Relay {
+                    device_id,
+                    name,
+                    power,
+                    light: *typ == LIGHT,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            mac: mac.to_owned(),
+            command_topic: full_topic(0)?,
+            stat_topic: full_topic(1)?,
+            tele_topic: full_topic(2)?,
+            topic: config.t,
+            model: config.md,
+            firmware: config.sw,
+            ip: config.ip,
+            relays,
+            light_subtype: config.lt_st,
+            online: config.onln,
+            offline: config.ofln,
+            state_off: config
+                .state
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "OFF".into()),
+            state_on: config.state.get(1).cloned().unwrap_or_else(|| "ON".into()),
+        })
+    }
+
+    /// Topic filters of the state messages of this device.
+    pub fn filters(&self) -> Vec<String> {
+        vec![
+            format!("{}+", self.stat_topic),
+            format!("{}+", self.tele_topic),
+        ]
+    }
+
+    /// A device for each relay and light.
+    pub fn devices(&self, integration_id: &str) -> Vec<MqttDevice> {
+        self.relays
+            .iter()
+            .map(|relay| {
+                let (typ, capabilities) = if relay.light {
+                    (DeviceType::Light, self.light_capabilities())
+                } else {
+                    (DeviceType::Switch, vec![Capability::OnOff])
+                };
+                MqttDevice {
+                    device: Device {
+                        integration: integration_id.to_owned(),
+                        id: relay.device_id.clone(),
+                        name: Some(relay.name.clone()),
+                        typ,
+                        capabilities,
+                        area: None,
+                        floor: None,
+                        state: None,
+                        attributes: self.attributes(),
+                    },
+                    control: self.control(Some(relay.power.clone())),
+                }
+            })
+            .collect()
+    }
+
+    /// A device for each value of the message published on `{DISCOVERY_TOPIC}/{MAC}/sensors`,
+    /// like `{"sn": {"AM2301": {"Temperature": 21.5, "Humidity": 40}, "TempUnit": "C"}}`.
+    pub fn sensor_devices(&self, integration_id: &str, payload: &str) -> Result<Vec<MqttDevice>> {
+        let message: Map<String, Value> = serde_json::from_str(payload)?;
+        let sensors = match message.get("sn") {
+            Some(Value::Object(sensors)) => sensors,
+            _ => bail!("missing sensors"),
+        };
+        let temperature_unit = sensors.get("TempUnit").and_then(|u| u.as_str());
+
+        Ok(self
+            .readings(sensors)
+            .into_iter()
+            .map(|(sensor, quantity, value)| {
+                let mut attributes = self.attributes();
+                let unit = match quantity.as_str() {
+                    "Temperature" => temperature_unit.map(|u| format!("°{u}")),
+                    "Humidity" => Some("%".into()),
+                    _ => None,
+                };
+                if let Some(unit) = unit {
+                    attributes.insert("unit_of_measurement".into(), Value::String(unit));
+                }
+                MqttDevice {
+                    device: Device {
+                        integration: integration_id.to_owned(),
+                        id: self.sensor_id(&sensor, &quantity),
+                        name: Some(format!("{} {sensor} {quantity}", self.topic)),
+                        typ: DeviceType::Sensor,
+                        capabilities: vec![Capability::Measurement],
+                        area: None,
+                        floor: None,
+                        state: Some(value),
+                        attributes,
+                    },
+                    control: self.control(None),
+                }
+            })
+            .collect())
+    }
+
+    /// Reads the states of the devices of this node from a message on `topic`, if it is one
+    /// of its state topics.
+    pub fn state_updates(&self, topic: &str, payload: &str) -> Vec<StateUpdate> {
+        if let Some(suffix) = topic.strip_prefix(&self.stat_topic) {
+            if suffix == "RESULT" {
+                return self.json_updates(payload);
+            }
+            return self
+                .relays
+                .iter()
+                .filter(|relay| self.is_power_key(relay, suffix))
+                .filter_map(|relay| self.relay_update(relay, payload))
+                .collect();
+        }
+        match topic.strip_prefix(&self.tele_topic) {
+            Some("STATE") => self.json_updates(payload),
+            Some("SENSOR") => match serde_json::from_str::<Map<String, Value>>(payload) {
+                Ok(sensors) => self
+                    .readings(&sensors)
+                    .into_iter()
+                    .map(|(sensor, quantity, value)| StateUpdate {
+                        device_id: self.sensor_id(&sensor, &quantity),
+                        state: value,
+                        attributes: Map::new(),
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            Some("LWT") => {
+                let state = if payload == self.offline {
+                    "unavailable"
+                } else if payload == self.online {
+                    // The real state is published right after connecting
+                    "unknown"
+                } else {
+                    return Vec::new();
+                };
+                self.relays
+                    .iter()
+                    .map(|relay| StateUpdate {
+                        device_id: relay.device_id.clone(),
+                        state: state.to_owned(),
+                        attributes: Map::new(),
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reads `RESULT` and `STATE` messages, like `{"POWER": "ON", "Dimmer": 40}`.
+    fn json_updates(&self, payload: &str) -> Vec<StateUpdate> {
+        let Ok(message) = serde_json::from_str::<Map<String, Value>>(payload) else {
+            return Vec::new();
+        };
+        self.relays
+            .iter()
+            .filter_map(|relay| {
+                let (_, power) = message
+                    .iter()
+                    .find(|(key, _)| self.is_power_key(relay, key))?;
+                let mut update = self.relay_update(relay, power.as_str()?)?;
+                if relay.light {
+                    update.attributes = LIGHT_ATTRIBUTES
+                        .iter()
+                        .filter_map(|key| Some((key.to_string(), message.get(*key)?.clone())))
+                        .collect();
+                }
+                Some(update)
+            })
+            .collect()
+    }
+
+    fn relay_update(&self, relay: &Relay, power: &str) -> Option<StateUpdate> {
+        let state = if power.eq_ignore_ascii_case(&self.state_on) {
+            "on"
+        } else if power.eq_ignore_ascii_case(&self.state_off) {
+            "off"
+        } else {
+            return None;
+        };
+        Some(StateUpdate {
+            device_id: relay.device_id.clone(),
+            state: state.to_owned(),
+            attributes: Map::new(),
+        })
+    }
+
+    /// Devices with a single relay also accept `POWER1`.
+    fn is_power_key(&self, relay: &Relay, key: &str) -> bool {
+        key == relay.power || (relay.power == "POWER" && key == "POWER1")
+    }
+
+    /// Numeric values of sensor messages, as (sensor, quantity, value).
+    fn readings(&self, sensors: &Map<String, Value>) -> Vec<(String, String, String)> {
+        sensors
+            .iter()
+            .filter_map(|(sensor, values)| Some((sensor, values.as_object()?)))
+            .flat_map(|(sensor, values)| {
+                values.iter().filter_map(|(quantity, value)| {
+                    let value = value.as_number()?;
+                    Some((sensor.clone(), quantity.clone(), value.to_string()))
+                })
+            })
+            .collect()
+    }
+
+    fn sensor_id(&self, sensor: &str, quantity: &str) -> String {
+        format!("{}.{sensor}.{quantity}", self.topic)
+    }
+
+    fn light_capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = vec![Capability::OnOff];
+        if self.light_subtype >= 1 {
+            capabilities.push(Capability::Brightness);
+        }
+        if self.light_subtype == 2 || self.light_subtype == 5 {
+            capabilities.push(Capability::ColorTemp);
+        }
+        if self.light_subtype >= 3 {
+            capabilities.push(Capability::ColorRgb);
+        }
+        capabilities
+    }
+
+    fn attributes(&self) -> Map<String, Value> {
+        [
+            ("mac", Some(&self.mac)),
+            ("model", self.model.as_ref()),
+            ("firmware", self.firmware.as_ref()),
+            ("ip", self.ip.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_owned(), Value::String(value?.clone()))))
+        .collect()
+    }
+
+    fn control(&self, power: Option<String>) -> MqttControl {
+        MqttControl::Tasmota(Control {
+            mac: self.mac.clone(),
+            command_topic: self.command_topic.clone(),
+            power,
+        })
+    }
+}
+
+/// How to command a Tasmota device, through `cmnd/{TOPIC}/{COMMAND}`.
+#[derive(Debug)]
+pub(super) struct Control {
+    /// MAC address of the [`Node`] of the device
+    pub mac: String,
+    command_topic: String,
+    /// Power command of relays, `None` for sensors
+    power: Option<String>,
+}
+
+impl Control {
+    pub fn message(&self, command: &DeviceCommand) -> Result<(String, String)> {
+        let (command, payload) = match command {
+            DeviceCommand::TurnOn | DeviceCommand::TurnOff => {
+                let power = self.power.as_ref().context("device has no relay")?;
+                let on = *command == DeviceCommand::TurnOn;
+                (power.as_str(), if on { "ON" } else { "OFF" }.to_owned())
+            }
+            // Tasmota dimmers go from 0 to 100
+            DeviceCommand::SetBrightness(brightness) => (
+                "Dimmer",
+                ((u32::from(*brightness) * 100 + 127) / 255).to_string(),
+            ),
+            DeviceCommand::SetColorRgb([r, g, b]) => ("Color", format!("{r},{g},{b}")),
+            // CT is in mireds, from 153 to 500
+            DeviceCommand::SetColorTemp(kelvin) => (
+                "CT",
+                (1_000_000 / u32::from((*kelvin).max(1)))
+                    .clamp(153, 500)
+                    .to_string(),
+            ),
+            command => bail!("tasmota devices do not support {command:?}"),
+        };
+        Ok((format!("{}{command}", self.command_topic), payload))
+    }
+}
@@ -0,0 +1,363 @@
+use super::{Control as MqttControl, MqttDevice};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceType};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// Numeric properties reported as the state of sensors, in order of preference
+const MEASUREMENTS: &[&str] = &[
+    "temperature",
+    "humidity",
+    "pressure",
+    "illuminance_lux",
+    "illuminance",
+    "co2",
+    "voc",
+    "pm25",
+    "soil_moisture",
+    "power",
+    "energy",
+];
+
+/// Entry of `bridge/devices`
+#[derive(Debug, Deserialize)]
+struct BridgeDevice {
+    ieee_address: String,
+    friendly_name: String,
+    #[serde(rename = "type")]
+    typ: String,
+    #[serde(default)]
+    disabled: bool,
+    definition: Option<Definition>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Definition {
+    model: Option<String>,
+    vendor: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    exposes: Vec<Expose>,
+}
+
+/// What a device can report or be set to, see <https://www.zigbee2mqtt.io/guide/usage/exposes.html>
+#[derive(Debug, Deserialize)]
+struct Expose {
+    #[serde(rename = "type")]
+    typ: String,
+    name: Option<String>,
+    property: Option<String>,
+    unit: Option<String>,
+    value_on: Option<Value>,
+    value_off: Option<Value>,
+    #[serde(default)]
+    features: Vec<Expose>,
+}
+
+impl Expose {
+    fn feature(&self, name: &str) -> Option<&Expose> {
+        self.features
+            .iter()
+            .find(|f| f.name.as_deref() == Some(name))
+    }
+}
+
+/// How the state of a device is read from its state messages.
+#[derive(Debug, Clone)]
+pub(super) enum StateProperty {
+    /// A property with two values, reported as the two states of the capability
+    Binary {
+        property: String,
+        value_on: Value,
+        value_off: Value,
+        states: (&'static str, &'static str),
+    },
+    /// A property reported as is, like measurements and button actions
+    Raw { property: String },
+}
+
+impl StateProperty {
+    fn binary(
+        expose: Option<&Expose>,
+        default_values: (Value, Value),
+        states: (&'static str, &'static str),
+    ) -> Self {
+        Self::Binary {
+            property: expose
+                .and_then(|e| e.property.clone())
+                .unwrap_or_else(|| "state".into()),
+            value_on: expose
+                .and_then(|e| e.value_on.clone())
+                .unwrap_or(default_values.0),
+            value_off: expose
+                .and_then(|e| e.value_off.clone())
+                .unwrap_or(default_values.1),
+            states,
+        }
+    }
+}
+
+/// Main function of a device, picked by [`classify`].
+struct Kind {
+    typ: DeviceType,
+    capabilities: Vec<Capability>,
+    state: StateProperty,
+    setpoint_property: Option<String>,
+    unit: Option<String>,
+}
+
+impl Kind {
+    fn new(typ: DeviceType, capabilities: Vec<Capability>, state: StateProperty) -> Self {
+        Self {
+            typ,
+            capabilities,
+            state,
+            setpoint_property: None,
+            unit: None,
+        }
+    }
+}
+
+/// How to command a Zigbee2MQTT device, through `{BASE_TOPIC}/{FRIENDLY_NAME}/set`.
+#[derive(Debug)]
+pub(super) struct Control {
+    set_topic: String,
+    state: StateProperty,
+    /// Property with the target temperature of thermostats
+    setpoint_property: Option<String>,
+}
+
+impl Control {
+    /// Reads the state of the device from a state message. Returns `None` if the message
+    /// does not include it.
+    pub fn state(&self, payload: &Map<String, Value>) -> Option<String> {
+        match &self.state {
+            StateProperty::Binary {
+                property,
+                value_on,
+                value_off,
+                states,
+            } => {
+                let value = payload.get(property)?;
+                if value == value_on {
+                    Some(states.0.to_owned())
+                } else if value == value_off {
+                    Some(states.1.to_owned())
+                } else {
+                    None
+                }
+            }
+            StateProperty::Raw { property } => match payload.get(property)? {
+                Value::String(s) if s.is_empty() => None,
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                Value::Bool(true) => Some("on".into()),
+                Value::Bool(false) => Some("off".into()),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn message(&self, command: &DeviceCommand) -> Result<(String, String)> {
+        let payload = match command {
+            DeviceCommand::TurnOn | DeviceCommand::Open | DeviceCommand::Lock => {
+                self.binary_command(true)?
+            }
+            DeviceCommand::TurnOff | DeviceCommand::Close | DeviceCommand::Unlock => {
+                self.binary_command(false)?
+            }
+            // Zigbee brightness goes up to 254
+            DeviceCommand::SetBrightness(brightness) => {
+                json!({ "brightness": (*brightness).min(254) })
+            }
+            DeviceCommand::SetColorRgb([r, g, b]) => json!({ "color": { "r": r, "g": g, "b": b } }),
+            DeviceCommand::SetColorTemp(kelvin) => {
+                json!({ "color_temp": 1_000_000 / u32::from((*kelvin).max(1)) })
+            }
+            DeviceCommand::SetPosition(position) => json!({ "position": position }),
+            DeviceCommand::SetTargetTemperature(temperature) => {
+                let property = self
+                    .setpoint_property
+                    .as_ref()
+                    .context("device has no target temperature")?;
+                json!({ property: temperature })
+            }
+            command => bail!("zigbee2mqtt devices do not support {command:?}"),
+        };
+        Ok((self.set_topic.clone(), payload.to_string()))
+    }
+
+    fn binary_command(&self, on: bool) -> Result<Value> {
+        match &self.state {
+            StateProperty::Binary {
+                property,
+                value_on,
+                value_off,
+                ..
+            } => Ok(json!({ property: if on { value_on } else { value_off } })),
+            StateProperty::Raw { .. } => bail!("device does not have two states"),
+        }
+    }
+}
+
+/// Availability messages are `{"state": "online"}`, or just `online` in legacy mode.
+pub(super) fn is_online(payload: &str) -> bool {
+    match serde_json::from_str::<Value>(payload) {
+        Ok(Value::Object(availability)) => {
+            availability.get("state").and_then(|s| s.as_str()) == Some("online")
+        }
+        _ => payload == "online",
+    }
+}
+
+/// Parses the devices of `bridge/devices`, skipping the coordinator, disabled devices and
+/// devices that expose nothing Hat understands.
+pub(super) fn parse_devices(
+    integration_id: &str,
+    base_topic: &str,
+    payload: &str,
+) -> Result<Vec<MqttDevice>> {
+    let devices: Vec<BridgeDevice> = serde_json::from_str(payload)?;
+    Ok(devices
+        .into_iter()
+        .filter(|d| d.typ != "Coordinator" && !d.disabled)
+        .filter_map(|d| {
+            let definition = d.definition?;
+            let kind = classify(&definition.exposes)?;
+            let attributes = [
+                ("ieee_address", Some(d.ieee_address)),
+                ("model", definition.model),
+                ("vendor", definition.vendor),
+                ("description", definition.description),
+                ("unit_of_measurement", kind.unit),
+            ]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_owned(), Value::String(value?))))
+            .collect();
+            Some(MqttDevice {
+                control: MqttControl::Zigbee2Mqtt(Control {
+                    set_topic: format!("{base_topic}/{}/set", d.friendly_name),
+                    state: kind.state,
+                    setpoint_property: kind.setpoint_property,
+                }),
+                device: Device {
+                    integration: integration_id.to_owned(),
+                    id: d.friendly_name.clone(),
+                    name: Some(d.friendly_name),
+                    typ: kind.typ,
+                    capabilities: kind.capabilities,
+                    area: None,
+                    floor: None,
+                    state: None,
+                    attributes,
+                },
+            })
+        })
+        .collect())
+}
+
+/// Picks the main function of a device from what it exposes. Actuators come first, so a
+/// light that also reports its power consumption is a light.
+fn classify(exposes: &[Expose]) -> Option<Kind> {
+    let on_off = || (json!("ON"), json!("OFF"));
+    let find = |typ: &str| exposes.iter().find(|e| e.typ == typ);
+    let find_named = |name: &str| exposes.iter().find(|e| e.name.as_deref() == Some(name));
+
+    if let Some(light) = find("light") {
+        let mut capabilities = vec![Capability::OnOff];
+        if light.feature("brightness").is_some() {
+            capabilities.push(Capability::Brightness);
+        }
+        if light.feature("color_temp").is_some() {
+            capabilities.push(Capability::ColorTemp);
+        }
+        if light.feature("color_xy").is_some() || light.feature("color_hs").is_some() {
+            capabilities.push(Capability::ColorRgb);
+        }
+        let state = StateProperty::binary(light.feature("state"), on_off(), ("on", "off"));
+        return Some(Kind::new(DeviceType::Light, capabilities, state));
+    }
+    for (typ, device_type, capability) in [
+        ("switch", DeviceType::Switch, Capability::OnOff),
+        ("fan", DeviceType::Fan, Capability::OnOff),
+    ] {
+        if let Some(expose) = find(typ) {
+            let state = StateProperty::binary(expose.feature("state"), on_off(), ("on", "off"));
+            return Some(Kind::new(device_type, vec![capability], state));
+        }
+    }
+    if let Some(lock) = find("lock") {
+        let state = StateProperty::binary(
+            lock.feature("state"),
+            (json!("LOCK"), json!("UNLOCK")),
+            ("locked", "unlocked"),
+        );
+        return Some(Kind::new(DeviceType::Lock, vec![Capability::Lock], state));
+    }
+    if find("cover").is_some() {
+        // The state of covers is an enum with a STOP value, not a binary
+        let state =
+            StateProperty::binary(None, (json!("OPEN"), json!("CLOSE")), ("open", "closed"));
+        return Some(Kind::new(
+            DeviceType::Cover,
+            vec![Capability::Position],
+            state,
+        ));
+    }
+    if let Some(climate) = find("climate") {
+        let setpoint = climate.features.iter().find_map(|f| {
+            f.name
+                .as_deref()
+                .filter(|name| name.ends_with("_setpoint"))
+                .and(f.property.clone())
+        })?;
+        let state = StateProperty::Raw {
+            property: setpoint.clone(),
+        };
+        let capabilities = vec![Capability::TemperatureSetpoint];
+        return Some(Kind {
+            setpoint_property: Some(setpoint),
+            ..Kind::new(DeviceType::Thermostat, capabilities, state)
+        });
+    }
+
+    if let Some(occupancy) = find_named("occupancy").or_else(|| find_named("presence")) {
+        let state =
+            StateProperty::binary(Some(occupancy), (json!(true), json!(false)), ("on", "off"));
+        return Some(Kind::new(
+            DeviceType::MotionSensor,
+            vec![Capability::Motion],
+            state,
+        ));
+    }
+    if let Some(contact) = find_named("contact") {
+        // `contact` is true when the door is closed
+        let state =
+            StateProperty::binary(Some(contact), (json!(false), json!(true)), ("on", "off"));
+        return Some(Kind::new(
+            DeviceType::DoorSensor,
+            vec![Capability::Contact],
+            state,
+        ));
+    }
+    if let Some(action) = find_named("action") {
+        let state = StateProperty::Raw {
+            property: action.property.clone().unwrap_or_else(|| "action".into()),
+        };
+        return Some(Kind::new(
+            DeviceType::Button,
+            vec![Capability::Press],
+            state,
+        ));
+    }
+    MEASUREMENTS.iter().find_map(|name| {
+        let expose = find_named(name).filter(|e| e.typ == "numeric")?;
+        let state = StateProperty::Raw {
+            property: expose.property.clone()?,
+        };
+        Some(Kind {
+            unit: expose.unit.clone(),
+            ..Kind::new(DeviceType::Sensor, vec![Capability::Measurement], state)
+        })
+    })
+}
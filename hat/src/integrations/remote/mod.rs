@@ -0,0 +1,382 @@
+use crate::integrations::backoff::Backoff;
+use crate::integrations::clock::CLOCK_INTEGRATION_ID;
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, sse, Integration};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceState};
+use crate::runtime::event::{Event, EventType, RUNTIME_INTEGRATION_ID, WEBHOOK_INTEGRATION_ID};
+use crate::runtime::value::Value as RuntimeValue;
+use crate::server::KEEP_ALIVE_INTERVAL;
+use anyhow::{bail, ensure, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+/// Longest time to connect, and to wait for the answer of a request other than the event stream
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// The event stream is considered lost after missing this many keep-alives
+const MISSED_KEEP_ALIVES: u32 = 3;
+
+/// Devices of another Hat instance, through its HTTP API. A remote device `home@light.kitchen`
+/// becomes `{INTEGRATION_ID}@home@light.kitchen`, commands are forwarded to
+/// `POST /device/command` and events arrive through `GET /event_stream`.
+///
+/// Devices the remote instance imports from other instances are not imported again, so two
+/// instances can follow each other without echoing events back and forth.
+pub struct RemoteHatIntegration {
+    id: String,
+    client: RemoteClient,
+    devices: Arc<RwLock<BTreeMap<String, Device>>>,
+    subscribers: Subscribers,
+    task: JoinHandle<()>,
+}
+
+impl RemoteHatIntegration {
+    /// Connects to the Hat server at `url`, like `http://192.168.1.2:5000`, sending `token`
+    /// when the server requires one.
+    pub async fn new(id: &str, url: &str, token: Option<&str>) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let url = Url::parse(url)?;
+        ensure!(
+            url.scheme() == "http" || url.scheme() == "https",
+            "unknown url scheme"
+        );
+        let client = RemoteClient {
+            http: reqwest::Client::builder()
+                .connect_timeout(REQUEST_TIMEOUT)
+                .build()?,
+            url,
+            token: token.map(str::to_owned),
+        };
+        // Following the events before loading the devices, so no change is missed
+        let response = client.event_stream().await?;
+        let devices = Arc::new(RwLock::new(client.get_devices(id).await?));
+        let subscribers = Subscribers::default();
+        let event_stream = EventStream {
+            integration_id: id.to_owned(),
+            client: client.clone(),
+            devices: Arc::clone(&devices),
+            subscribers: subscribers.clone(),
+        };
+        Ok(Self {
+            id: id.to_owned(),
+            client,
+            devices,
+            subscribers,
+            task: tokio::spawn(event_stream.run(response)),
+        })
+    }
+}
+
+impl Drop for RemoteHatIntegration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl Integration for RemoteHatIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(self.devices.read().unwrap().values().cloned().collect())
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.devices.read().unwrap().get(id).cloned())
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        ensure!(
+            self.devices.read().unwrap().contains_key(device_id),
+            "device not found"
+        );
+        self.client.execute(device_id, command).await
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Clone)]
+struct RemoteClient {
+    http: reqwest::Client,
+    url: Url,
+    token: Option<String>,
+}
+
+/// Body of the errors of the Hat API
+#[derive(Debug, Deserialize)]
+struct ApiErrors {
+    errors: Vec<ApiErrorDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorDescription {
+    description: String,
+}
+
+impl RemoteClient {
+    fn request(&self, method: reqwest::Method, path: &str) -> Result<reqwest::RequestBuilder> {
+        let request = self.http.request(method, self.url.join(path)?);
+        Ok(match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    /// The devices of the remote instance, by local ID.
+    async fn get_devices(&self, integration_id: &str) -> Result<BTreeMap<String, Device>> {
+        let response = self
+            .request(reqwest::Method::GET, "/devices")?
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        let devices: Vec<Device> = Self::check(response).await?.json().await?;
+        Ok(devices
+            .into_iter()
+            .filter_map(|device| localize(integration_id, device))
+            .map(|device| (device.id.clone(), device))
+            .collect())
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let response = self
+            .request(reqwest::Method::POST, "/device/command")?
+            .json(&json!({ "id": device_id, "command": command }))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        Self::check(response).await?;
+        Ok(())
+    }
+
+    async fn event_stream(&self) -> Result<reqwest::Response> {
+        let response = self
+            .request(reqwest::Method::GET, "/event_stream")?
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        Self::check(response).await
+    }
+
+    /// Turns error responses into errors with the descriptions sent by the server.
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        match response.json::<ApiErrors>().await {
+            Ok(body) => {
+                let errors = body
+                    .errors
+                    .into_iter()
+                    .map(|e| e.description)
+                    .collect::<Vec<_>>();
+                bail!("hat server answered {status}: {}", errors.join(", "))
+            }
+            Err(_) => bail!("hat server answered {status}"),
+        }
+    }
+}
+
+/// Maps a remote device into a local one, or `None` if the remote instance imported it
+/// from another instance itself.
+fn localize(integration_id: &str, mut device: Device) -> Option<Device> {
+    if device.id.contains('@') {
+        return None;
+    }
+    device.id = device.full_id();
+    device.integration = integration_id.to_owned();
+    Some(device)
+}
+
+/// Follows the event stream of the remote instance, keeping the devices up to date and
+/// forwarding their events to the subscribers. Loads the devices again after reconnecting, to
+/// catch up on the changes it missed.
+struct EventStream {
+    integration_id: String,
+    client: RemoteClient,
+    devices: Arc<RwLock<BTreeMap<String, Device>>>,
+    subscribers: Subscribers,
+}
+
+impl EventStream {
+    async fn run(self, response: reqwest::Response) {
+        let mut online = true;
+        let mut backoff = Backoff::default();
+        let mut result = self.read(response).await;
+
+        loop {
+            let Err(e) = result;
+            if online {
+                error!("Lost connection to hat {}: {e}", self.integration_id);
+                let parameters =
+                    HashMap::from([("reason".to_owned(), RuntimeValue::String(e.to_string()))]);
+                self.send(Event::from_integration(
+                    &self.integration_id,
+                    EventType::IntegrationDisconnectedEvent,
+                    parameters,
+                ));
+                online = false;
+            } else {
+                let delay = backoff.delay();
+                warn!("Failed to reconnect to hat, retrying in {delay:?}: {e}");
+            }
+            backoff.wait().await;
+            result = self.reconnect(&mut online, &mut backoff).await;
+        }
+    }
+
+    /// Connects to the event stream again and handles its events until it closes.
+    async fn reconnect(&self, online: &mut bool, backoff: &mut Backoff) -> Result<Infallible> {
+        let response = self.client.event_stream().await?;
+        if !*online {
+            let devices = self.client.get_devices(&self.integration_id).await?;
+            let events = {
+                let mut current = self.devices.write().unwrap();
+                let before = std::mem::replace(&mut *current, devices);
+                missed_events(&before, &current)
+            };
+            info!("Reconnected to hat {}", self.integration_id);
+            self.send(Event::from_integration(
+                &self.integration_id,
+                EventType::IntegrationConnectedEvent,
+                HashMap::new(),
+            ));
+            for event in events {
+                self.send(event);
+            }
+            *online = true;
+        }
+        backoff.reset();
+        self.read(response).await
+    }
+
+    /// Handles the events of the stream until it closes or stops sending keep-alives.
+    async fn read(&self, response: reqwest::Response) -> Result<Infallible> {
+        let idle_timeout = KEEP_ALIVE_INTERVAL * MISSED_KEEP_ALIVES;
+        sse::read_events(response, Some(idle_timeout), |data| {
+            self.handle_message(data)
+        })
+        .await
+    }
+
+    /// Handles the data of one server-sent event, an [`Event`]. Events of the remote clock,
+    /// runtime and webhooks, and about its integrations, are not forwarded. Neither are events
+    /// of devices that are not known yet, which are loaded again instead.
+    fn handle_message(&self, data: &str) {
+        let mut event = match serde_json::from_str::<Event>(data) {
+            Ok(event) => event,
+            Err(e) => {
+                debug!(
+                    "Ignoring invalid event from hat {}: {e}",
+                    self.integration_id
+                );
+                return;
+            }
+        };
+        let forwarded = !matches!(
+            event.typ,
+            EventType::ClockTickEvent
+                | EventType::IntegrationConnectedEvent
+                | EventType::IntegrationDisconnectedEvent
+        ) && ![
+            CLOCK_INTEGRATION_ID,
+            RUNTIME_INTEGRATION_ID,
+            WEBHOOK_INTEGRATION_ID,
+        ]
+        .contains(&event.device.integration.as_str());
+        if !forwarded {
+            return;
+        }
+        let Some(device) = localize(&self.integration_id, event.device) else {
+            return;
+        };
+        {
+            let mut devices = self.devices.write().unwrap();
+            let Some(known) = devices.get_mut(&device.id) else {
+                drop(devices);
+                self.load_new_devices();
+                return;
+            };
+            *known = device.clone();
+        }
+        event.device = device;
+        self.send(event);
+    }
+
+    /// Adds the devices created in the remote instance after they were loaded.
+    fn load_new_devices(&self) {
+        let client = self.client.clone();
+        let integration_id = self.integration_id.clone();
+        let devices = Arc::clone(&self.devices);
+        tokio::spawn(async move {
+            match client.get_devices(&integration_id).await {
+                Ok(loaded) => {
+                    let mut devices = devices.write().unwrap();
+                    for (id, device) in loaded {
+                        devices.entry(id).or_insert(device);
+                    }
+                }
+                Err(e) => warn!("Failed to load the devices of hat {integration_id}: {e}"),
+            }
+        });
+    }
+
+    fn send(&self, event: Event) {
+        self.subscribers.send(event);
+    }
+}
+
+/// Events for the devices whose state changed while disconnected. Presses are not states,
+/// so missed presses are lost.
+fn missed_events(
+    before: &BTreeMap<String, Device>,
+    after: &BTreeMap<String, Device>,
+) -> Vec<Event> {
+    after
+        .values()
+        .filter(|device| !device.has_capability(Capability::Press))
+        .filter_map(|device| {
+            let old_state = before.get(&device.id)?.state.clone().unwrap_or_default();
+            let new_state = device.state.clone().unwrap_or_default();
+            if !DeviceState::parse(&new_state).is_known() {
+                return None;
+            }
+            Event::state_change(device.clone(), &old_state, &new_state)
+        })
+        .flat_map(Event::split_zone_change)
+        .collect()
+}
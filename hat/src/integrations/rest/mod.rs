@@ -0,0 +1,405 @@
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceState, DeviceType};
+use crate::runtime::event::Event;
+use anyhow::{bail, ensure, Context, Result};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tracing::warn;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A device read from a JSON HTTP endpoint, like the status page of a solar inverter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestDeviceSettings {
+    pub id: String,
+    pub name: Option<String>,
+    /// `Sensor` reports the polled value as is. `Switch`, `Light`, `Fan`, `DoorSensor` and
+    /// `MotionSensor` map it into `on` and `off`.
+    #[serde(rename = "type")]
+    pub typ: DeviceType,
+    /// Endpoint polled with `GET`
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// JSON pointer to the state in the response, like `/power/current`
+    pub state: String,
+    /// Attributes of the device, as JSON pointers into the response
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    pub unit_of_measurement: Option<String>,
+    /// Seconds between polls, overriding the default of the integration
+    pub poll_interval: Option<u64>,
+    /// Value of the state that means `on`. By default `true`, `1`, `"on"` and `"ON"`.
+    pub on_value: Option<Value>,
+    /// Value of the state that means `off`. By default `false`, `0`, `"off"` and `"OFF"`.
+    pub off_value: Option<Value>,
+    pub turn_on: Option<RestCommand>,
+    pub turn_off: Option<RestCommand>,
+}
+
+/// A request that commands a device.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestCommand {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+}
+
+fn default_method() -> String {
+    "POST".into()
+}
+
+impl RestDeviceSettings {
+    /// Problems with these settings, as `field: message`.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.id.trim().is_empty() {
+            errors.push("id: must not be empty".to_owned());
+        }
+        if self.capability().is_none() {
+            errors.push(format!("type: {:?} cannot be polled", self.typ));
+        }
+        validate_url("url", &self.url, &mut errors);
+        if !self.state.is_empty() && !self.state.starts_with('/') {
+            errors.push(format!(
+                "state: {:?} must be a JSON pointer, starting with `/`",
+                self.state
+            ));
+        }
+        for (name, pointer) in &self.attributes {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                errors.push(format!(
+                    "attributes.{name}: {pointer:?} must be a JSON pointer, starting with `/`"
+                ));
+            }
+        }
+        if self.poll_interval == Some(0) {
+            errors.push("poll_interval: must be greater than zero".to_owned());
+        }
+        for (field, command) in [("turn_on", &self.turn_on), ("turn_off", &self.turn_off)] {
+            let Some(command) = command else { continue };
+            if self.capability() != Some(Capability::OnOff) {
+                errors.push(format!("{field}: {:?} cannot be commanded", self.typ));
+            }
+            validate_url(&format!("{field}.url"), &command.url, &mut errors);
+            if Method::from_bytes(command.method.as_bytes()).is_err() {
+                errors.push(format!(
+                    "{field}.method: {:?} is not an HTTP method",
+                    command.method
+                ));
+            }
+        }
+        errors
+    }
+
+    /// The capability that reads the polled value.
+    fn capability(&self) -> Option<Capability> {
+        match self.typ {
+            DeviceType::Sensor => Some(Capability::Measurement),
+            DeviceType::Switch | DeviceType::Light | DeviceType::Fan => Some(Capability::OnOff),
+            DeviceType::DoorSensor => Some(Capability::Contact),
+            DeviceType::MotionSensor => Some(Capability::Motion),
+            _ => None,
+        }
+    }
+
+    /// Converts the polled value into the state of the device.
+    fn parse_state(&self, value: &Value) -> String {
+        if self.capability() == Some(Capability::Measurement) {
+            return match value {
+                Value::String(s) => s.clone(),
+                Value::Null => "unknown".into(),
+                value => value.to_string(),
+            };
+        }
+        let matches = |expected: &Option<Value>, defaults: &[Value]| match expected {
+            Some(expected) => value == expected,
+            None => defaults.contains(value),
+        };
+        let on = [true.into(), 1.into(), "on".into(), "ON".into()];
+        let off = [false.into(), 0.into(), "off".into(), "OFF".into()];
+        if matches(&self.on_value, &on) {
+            "on".into()
+        } else if matches(&self.off_value, &off) {
+            "off".into()
+        } else {
+            "unknown".into()
+        }
+    }
+}
+
+fn validate_url(field: &str, url: &str, errors: &mut Vec<String>) {
+    match Url::parse(url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+        Ok(_) => errors.push(format!("{field}: {url:?} must be an http or https URL")),
+        Err(e) => errors.push(format!("{field}: {url:?} is {e}")),
+    }
+}
+
+/// Devices that only expose a JSON HTTP endpoint, polled periodically. The state and the
+/// attributes of each device are read from the response with JSON pointers, and switches can
+/// be turned on and off with configured requests.
+pub struct RestIntegration {
+    id: String,
+    client: reqwest::Client,
+    devices: Arc<RwLock<BTreeMap<String, Device>>>,
+    settings: HashMap<String, Arc<RestDeviceSettings>>,
+    subscribers: Subscribers,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl RestIntegration {
+    /// Polls every device once and keeps polling them every `poll_interval`, unless they
+    /// override it. Devices that cannot be polled are `unavailable`.
+    pub async fn new(
+        id: &str,
+        poll_interval: Duration,
+        devices: Vec<RestDeviceSettings>,
+    ) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let mut ids = HashSet::new();
+        for device in &devices {
+            let errors = device.validate();
+            ensure!(
+                errors.is_empty(),
+                "invalid device {:?}: {}",
+                device.id,
+                errors.join(", ")
+            );
+            ensure!(ids.insert(&device.id), "duplicate device {:?}", device.id);
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        let settings = devices
+            .into_iter()
+            .map(|device| (device.id.clone(), Arc::new(device)))
+            .collect::<HashMap<_, _>>();
+        let mut integration = Self {
+            id: id.to_owned(),
+            client,
+            devices: Default::default(),
+            settings,
+            subscribers: Default::default(),
+            tasks: Vec::new(),
+        };
+
+        let pollers = integration
+            .settings
+            .values()
+            .map(|settings| integration.poller(settings))
+            .collect::<Vec<_>>();
+        join_all(pollers.iter().map(Poller::poll)).await;
+        integration.tasks = pollers
+            .into_iter()
+            .map(|poller| {
+                let interval = poller
+                    .settings
+                    .poll_interval
+                    .map(Duration::from_secs)
+                    .unwrap_or(poll_interval);
+                tokio::spawn(poller.run(interval))
+            })
+            .collect();
+        Ok(integration)
+    }
+
+    fn poller(&self, settings: &Arc<RestDeviceSettings>) -> Poller {
+        Poller {
+            integration_id: self.id.clone(),
+            client: self.client.clone(),
+            settings: Arc::clone(settings),
+            devices: Arc::clone(&self.devices),
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+impl Drop for RestIntegration {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl Integration for RestIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(self.devices.read().unwrap().values().cloned().collect())
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.devices.read().unwrap().get(id).cloned())
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let settings = self.settings.get(device_id).context("device not found")?;
+        let request = match command {
+            DeviceCommand::TurnOn => settings.turn_on.as_ref(),
+            DeviceCommand::TurnOff => settings.turn_off.as_ref(),
+            _ => None,
+        };
+        let Some(request) = request else {
+            bail!("{device_id} does not support {command:?}");
+        };
+
+        let method = Method::from_bytes(request.method.as_bytes())?;
+        let mut builder = self.client.request(method, &request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+        builder
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("failed to command {device_id}"))?;
+
+        // Read the new state right away instead of waiting for the next poll
+        self.poller(settings).poll().await;
+        Ok(())
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Polls one device, updating its state and sending the events for its changes.
+struct Poller {
+    integration_id: String,
+    client: reqwest::Client,
+    settings: Arc<RestDeviceSettings>,
+    devices: Arc<RwLock<BTreeMap<String, Device>>>,
+    subscribers: Subscribers,
+}
+
+impl Poller {
+    async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            self.poll().await;
+        }
+    }
+
+    async fn poll(&self) {
+        let (state, attributes) = match self.fetch().await {
+            Ok(response) => self.read(&response),
+            Err(e) => {
+                warn!("Failed to poll {}: {e:#}", self.settings.url);
+                ("unavailable".to_owned(), None)
+            }
+        };
+        if let Some(event) = self.update(state, attributes) {
+            self.subscribers.send(event);
+        }
+    }
+
+    async fn fetch(&self) -> Result<Value> {
+        let mut request = self.client.get(&self.settings.url);
+        for (name, value) in &self.settings.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?.error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// Reads the state and the attributes from a response.
+    fn read(&self, response: &Value) -> (String, Option<Map<String, Value>>) {
+        let state = match response.pointer(&self.settings.state) {
+            Some(value) => self.settings.parse_state(value),
+            None => {
+                warn!(
+                    "{} has nothing at {:?}",
+                    self.settings.url, self.settings.state
+                );
+                "unknown".to_owned()
+            }
+        };
+        let attributes = self
+            .settings
+            .attributes
+            .iter()
+            .filter_map(|(name, pointer)| Some((name.clone(), response.pointer(pointer)?.clone())))
+            .collect();
+        (state, Some(attributes))
+    }
+
+    /// Stores the polled state, returning the event for the change. Polls that fail or
+    /// come back from a failure are not changes.
+    fn update(&self, state: String, attributes: Option<Map<String, Value>>) -> Option<Event> {
+        let settings = &self.settings;
+        let mut devices = self.devices.write().unwrap();
+        let device = devices.entry(settings.id.clone()).or_insert_with(|| {
+            let mut attributes = Map::new();
+            if let Some(unit) = &settings.unit_of_measurement {
+                attributes.insert("unit_of_measurement".into(), unit.clone().into());
+            }
+            Device {
+                integration: self.integration_id.clone(),
+                id: settings.id.clone(),
+                name: settings.name.clone(),
+                typ: settings.typ,
+                capabilities: settings.capability().into_iter().collect(),
+                area: None,
+                floor: None,
+                state: None,
+                attributes,
+            }
+        });
+        if let Some(attributes) = attributes {
+            device.attributes.extend(attributes);
+        }
+        let old_state = device.state.replace(state.clone())?;
+
+        let known = |state: &str| DeviceState::parse(state).is_known();
+        if !known(&old_state) || !known(&state) {
+            return None;
+        }
+        Event::state_change(device.clone(), &old_state, &state)
+    }
+}
@@ -0,0 +1,204 @@
+use super::scenario::{Curve, Scenario};
+use super::Home;
+use crate::runtime::device::Device;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::f64::consts::TAU;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Something that happens at a moment of the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Action {
+    /// A step of the timeline
+    Step(usize),
+    /// A reading of a curve
+    Reading(usize),
+    /// A random activity starts
+    Activate(usize),
+    /// A random activity ends, unless it was activated again meanwhile
+    Deactivate(usize),
+}
+
+/// Actions waiting to happen, ordered by simulated time and then by insertion.
+#[derive(Default)]
+struct Queue {
+    heap: BinaryHeap<Reverse<(Duration, u64, Action)>>,
+    next: u64,
+}
+
+impl Queue {
+    fn push(&mut self, at: f64, action: Action) {
+        self.heap
+            .push(Reverse((Duration::from_secs_f64(at), self.next, action)));
+        self.next += 1;
+    }
+
+    fn pop(&mut self) -> Option<(f64, Action)> {
+        let Reverse((at, _, action)) = self.heap.pop()?;
+        Some((at.as_secs_f64(), action))
+    }
+}
+
+/// Runs the timeline, the curves and the random activity of a scenario, changing the devices
+/// of the [`Home`]. The simulated time advances `speed` times faster than the real time.
+pub(super) struct Engine {
+    scenario: Arc<Scenario>,
+    home: Arc<Home>,
+    rng: Rng,
+    /// For each random activity that is active, until when and the state it interrupted
+    active: Vec<Option<(f64, String)>>,
+}
+
+impl Engine {
+    pub fn new(scenario: Arc<Scenario>, home: Arc<Home>, seed: u64) -> Self {
+        Self {
+            active: vec![None; scenario.random.len()],
+            scenario,
+            home,
+            rng: Rng(seed),
+        }
+    }
+
+    /// Takes the first reading of every curve, so their devices start with a value.
+    pub fn take_first_readings(&mut self) {
+        for i in 0..self.scenario.curves.len() {
+            self.read(i, 0.0, false);
+        }
+    }
+
+    pub async fn run(mut self) {
+        let start = Instant::now();
+        let scenario = Arc::clone(&self.scenario);
+        let mut queue = Queue::default();
+        for (i, step) in scenario.timeline.iter().enumerate() {
+            queue.push(step.at, Action::Step(i));
+        }
+        for (i, curve) in scenario.curves.iter().enumerate() {
+            queue.push(curve.interval, Action::Reading(i));
+        }
+        for (i, random) in scenario.random.iter().enumerate() {
+            queue.push(self.rng.exponential(random.every), Action::Activate(i));
+        }
+
+        while let Some((at, action)) = queue.pop() {
+            tokio::time::sleep_until(start + Duration::from_secs_f64(at / scenario.speed)).await;
+            self.apply(at, action, &mut queue);
+        }
+    }
+
+    fn apply(&mut self, at: f64, action: Action, queue: &mut Queue) {
+        let scenario = Arc::clone(&self.scenario);
+        let result = match action {
+            Action::Step(i) => {
+                let step = &scenario.timeline[i];
+                if let Some(period) = scenario.timeline_period {
+                    queue.push(at + period, action);
+                }
+                self.home.change(&step.device, |device| {
+                    device.state = Some(step.state.clone());
+                    device.attributes.extend(step.attributes.clone());
+                })
+            }
+            Action::Reading(i) => {
+                queue.push(at + scenario.curves[i].interval, action);
+                self.read(i, at, true);
+                Ok(())
+            }
+            Action::Activate(i) => {
+                let random = &scenario.random[i];
+                queue.push(at + self.rng.exponential(random.every), action);
+                let activate = |device: &mut Device| device.state = Some(random.state.clone());
+                let Some(duration) = random.duration else {
+                    self.report(self.home.change(&random.device, activate));
+                    return;
+                };
+                queue.push(at + duration, Action::Deactivate(i));
+                match &mut self.active[i] {
+                    // Activating again extends the activity, like motion does
+                    Some((until, _)) => {
+                        *until = at + duration;
+                        Ok(())
+                    }
+                    active @ None => {
+                        let previous = self.home.state(&random.device).unwrap_or_default();
+                        *active = Some((at + duration, previous));
+                        self.home.change(&random.device, activate)
+                    }
+                }
+            }
+            Action::Deactivate(i) => match self.active[i].take() {
+                Some((until, previous)) if until <= at => {
+                    self.home.change(&scenario.random[i].device, |device| {
+                        device.state = Some(previous);
+                    })
+                }
+                active => {
+                    self.active[i] = active;
+                    Ok(())
+                }
+            },
+        };
+        self.report(result);
+    }
+
+    fn report(&self, result: anyhow::Result<()>) {
+        if let Err(e) = result {
+            warn!("Simulation failed to change a device: {e:#}");
+        }
+    }
+
+    /// Sets the device of a curve to its value at the simulated time `at`.
+    fn read(&mut self, i: usize, at: f64, notify: bool) {
+        let curve = &self.scenario.curves[i];
+        let value = curve_value(curve, at, self.rng.next_f64());
+        let change = |device: &mut Device| match &curve.attribute {
+            Some(attribute) => {
+                device.attributes.insert(attribute.clone(), value.into());
+            }
+            None => device.state = Some(value.to_string()),
+        };
+        let result = if notify {
+            self.home.change(&curve.device, change)
+        } else {
+            self.home.change_silently(&curve.device, change)
+        };
+        self.report(result);
+    }
+}
+
+/// The value of a curve at the simulated time `at`. `random` is between 0 and 1, and picks
+/// the noise.
+fn curve_value(curve: &Curve, at: f64, random: f64) -> f64 {
+    let middle = (curve.min + curve.max) / 2.0;
+    let amplitude = (curve.max - curve.min) / 2.0;
+    let phase = TAU * (at - curve.peak_at) / curve.period;
+    let noise = curve.noise * (2.0 * random - 1.0);
+    let scale = 10f64.powi(curve.precision.into());
+    ((middle + amplitude * phase.cos() + noise) * scale).round() / scale
+}
+
+/// A small seedable generator (SplitMix64), so scenarios with a seed always play the same.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform between 0 (inclusive) and 1 (exclusive)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Time until the next occurrence of something that happens every `mean` on average
+    fn exponential(&mut self, mean: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() * mean
+    }
+}
@@ -0,0 +1,309 @@
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, Integration};
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceState};
+use crate::runtime::event::{Event, EventType, ZONE_PARAMETER};
+use crate::runtime::value::Value;
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use engine::Engine;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+
+mod engine;
+mod scenario;
+
+pub use scenario::{Curve, RandomActivity, Room, Scenario, SimulatedDevice, TimelineStep};
+
+/// A simulated home for demos, workshops and tests, described by a [`Scenario`]. Devices
+/// keep the state they are commanded into, and change by themselves following the timeline,
+/// the curves and the random activity of the scenario.
+pub struct SimulationIntegration {
+    id: String,
+    home: Arc<Home>,
+    task: JoinHandle<()>,
+}
+
+/// The devices of the simulation and who is following their changes.
+pub(super) struct Home {
+    devices: RwLock<BTreeMap<String, Device>>,
+    subscribers: Subscribers,
+}
+
+impl Home {
+    fn state(&self, id: &str) -> Option<String> {
+        self.devices.read().unwrap().get(id)?.state.clone()
+    }
+
+    /// Changes a device and sends the event for the change, if any.
+    fn change(&self, id: &str, change: impl FnOnce(&mut Device)) -> Result<()> {
+        let event = {
+            let mut devices = self.devices.write().unwrap();
+            let device = devices
+                .get_mut(id)
+                .with_context(|| format!("device {id} not found"))?;
+            let old = device.clone();
+            change(device);
+            change_event(&old, device)
+        };
+        for event in event.into_iter().flat_map(Event::split_zone_change) {
+            self.subscribers.send(event);
+        }
+        Ok(())
+    }
+
+    fn change_silently(&self, id: &str, change: impl FnOnce(&mut Device)) -> Result<()> {
+        let mut devices = self.devices.write().unwrap();
+        let device = devices
+            .get_mut(id)
+            .with_context(|| format!("device {id} not found"))?;
+        change(device);
+        Ok(())
+    }
+}
+
+impl SimulationIntegration {
+    /// Starts simulating the scenario. The simulated time starts now.
+    pub fn new(id: &str, scenario: Scenario) -> Result<Self> {
+        ensure!(
+            is_valid_integration_id(id),
+            "invalid integration id: {id:?}"
+        );
+        let errors = scenario.validate();
+        ensure!(errors.is_empty(), "{}", errors.join("\n"));
+
+        let floors = scenario
+            .rooms
+            .iter()
+            .map(|room| (room.name.as_str(), room.floor.clone()))
+            .collect::<HashMap<_, _>>();
+        let devices = scenario
+            .devices
+            .iter()
+            .map(|settings| {
+                let mut attributes = settings.attributes.clone();
+                if let Some(unit) = &settings.unit_of_measurement {
+                    attributes.insert("unit_of_measurement".into(), unit.clone().into());
+                }
+                let device = Device {
+                    integration: id.to_owned(),
+                    id: settings.id.clone(),
+                    name: settings.name.clone(),
+                    typ: settings.typ,
+                    capabilities: settings.capabilities(),
+                    area: settings.room.clone(),
+                    floor: settings
+                        .room
+                        .as_deref()
+                        .and_then(|room| floors.get(room).cloned().flatten()),
+                    state: Some(settings.initial_state()),
+                    attributes,
+                };
+                (settings.id.clone(), device)
+            })
+            .collect();
+        let home = Arc::new(Home {
+            devices: RwLock::new(devices),
+            subscribers: Default::default(),
+        });
+
+        let seed = scenario.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        let mut engine = Engine::new(Arc::new(scenario), Arc::clone(&home), seed);
+        engine.take_first_readings();
+        let task = tokio::spawn(engine.run());
+
+        Ok(Self {
+            id: id.to_owned(),
+            home,
+            task,
+        })
+    }
+
+    /// Loads a scenario file and starts simulating it, optionally at another speed.
+    pub fn from_file(id: &str, path: &Path, speed: Option<f64>) -> Result<Self> {
+        let mut scenario = Scenario::load(path)?;
+        if let Some(speed) = speed {
+            scenario.speed = speed;
+        }
+        Self::new(id, scenario)
+    }
+}
+
+impl Drop for SimulationIntegration {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl Integration for SimulationIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(self
+            .home
+            .devices
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.home.devices.read().unwrap().get(id).cloned())
+    }
+
+    async fn turn_on_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOn).await
+    }
+
+    async fn turn_off_device(&self, device_id: &str) -> Result<()> {
+        self.execute(device_id, DeviceCommand::TurnOff).await
+    }
+
+    async fn set_light_color_rgb(&self, device_id: &str, color: [u8; 3]) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetColorRgb(color))
+            .await
+    }
+
+    async fn set_light_brightness(&self, device_id: &str, brightness: u8) -> Result<()> {
+        self.execute(device_id, DeviceCommand::SetBrightness(brightness))
+            .await
+    }
+
+    async fn execute(&self, device_id: &str, command: DeviceCommand) -> Result<()> {
+        let capability = command.required_capability();
+        let capable = self
+            .home
+            .devices
+            .read()
+            .unwrap()
+            .get(device_id)
+            .with_context(|| format!("device {device_id} not found"))?
+            .has_capability(capability);
+        ensure!(
+            capable,
+            "{device_id} does not have the {capability:?} capability"
+        );
+        self.home
+            .change(device_id, |device| apply_command(device, command))
+    }
+
+    fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        self.home.subscribers.subscribe(capacity)
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Changes a device the way the real device would change when commanded.
+fn apply_command(device: &mut Device, command: DeviceCommand) {
+    let dimmable = device.has_capability(Capability::Brightness);
+    let attributes = &mut device.attributes;
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    let state = match command {
+        DeviceCommand::TurnOn => {
+            let dark = attributes.get("brightness").is_none_or(|b| b == 0);
+            if dimmable && dark {
+                attributes.insert("brightness".into(), 255.into());
+            }
+            "on"
+        }
+        DeviceCommand::TurnOff => "off",
+        DeviceCommand::SetBrightness(brightness) => {
+            attributes.insert("brightness".into(), brightness.into());
+            on_off(brightness > 0)
+        }
+        DeviceCommand::SetColorRgb(color) => {
+            attributes.insert("rgb_color".into(), json!(color));
+            "on"
+        }
+        DeviceCommand::SetColorTemp(kelvin) => {
+            attributes.insert("color_temp_kelvin".into(), kelvin.into());
+            "on"
+        }
+        DeviceCommand::SetPosition(position) => {
+            attributes.insert("position".into(), position.into());
+            if position > 0 {
+                "open"
+            } else {
+                "closed"
+            }
+        }
+        DeviceCommand::Open => {
+            attributes.insert("position".into(), 100.into());
+            "open"
+        }
+        DeviceCommand::Close => {
+            attributes.insert("position".into(), 0.into());
+            "closed"
+        }
+        DeviceCommand::Lock => "locked",
+        DeviceCommand::Unlock => "unlocked",
+        DeviceCommand::SetTargetTemperature(temperature) => {
+            // Thermostats keep their mode as the state
+            attributes.insert("temperature".into(), temperature.into());
+            return;
+        }
+        DeviceCommand::SetFanSpeed(percentage) => {
+            attributes.insert("percentage".into(), percentage.into());
+            on_off(percentage > 0)
+        }
+        DeviceCommand::MediaPlay => "playing",
+        DeviceCommand::MediaPause => "paused",
+        DeviceCommand::MediaStop => "idle",
+    };
+    device.state = Some(state.to_owned());
+}
+
+/// The event for the change of a device from `old` to `new`, if any.
+fn change_event(old: &Device, new: &Device) -> Option<Event> {
+    let old_state = old.state.as_deref().unwrap_or("unknown");
+    let new_state = new.state.as_deref().unwrap_or("unknown");
+    // Every press is an event, even when it repeats the last action
+    let compared_state = if new.has_capability(Capability::Press) {
+        ""
+    } else {
+        old_state
+    };
+    let state_value = |state: &str| DeviceState::parse(state).to_value();
+
+    let (typ, old_value, new_value) = match new.get_state_change_event(compared_state, new_state) {
+        Some(typ) => (typ, state_value(old_state), state_value(new_state)),
+        None if new.has_capability(Capability::TemperatureSetpoint) => {
+            let old_target = old.attributes.get("temperature");
+            let new_target = new.attributes.get("temperature")?;
+            if old_target == Some(new_target) {
+                return None;
+            }
+            (
+                EventType::TargetTemperatureChangedEvent,
+                old_target.cloned().map_or(Value::Null, Value::from_json),
+                Value::from_json(new_target.clone()),
+            )
+        }
+        None => return None,
+    };
+
+    let mut event = Event::with_states(typ, new.clone(), old_value, new_value);
+    let zone = match event.typ {
+        EventType::PersonArrivedEvent => Some(new_state),
+        EventType::PersonLeftEvent => Some(old_state),
+        _ => None,
+    };
+    if let Some(zone) = zone {
+        event
+            .parameters
+            .insert(ZONE_PARAMETER.to_owned(), state_value(zone));
+    }
+    Some(event)
+}
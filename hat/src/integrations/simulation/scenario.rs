@@ -0,0 +1,324 @@
+use crate::runtime::device::{Capability, DeviceType, HOME_STATE};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// A simulated home, read from a TOML file:
+///
+/// ```toml
+/// speed = 60   # simulated seconds per real second
+/// seed = 42    # makes the random activity repeatable
+///
+/// [[rooms]]
+/// name = "Living room"
+/// floor = "Ground floor"
+///
+/// [[devices]]
+/// id = "living_light"
+/// type = "Light"
+/// room = "Living room"
+///
+/// [[devices]]
+/// id = "living_temperature"
+/// type = "Sensor"
+/// room = "Living room"
+/// unit_of_measurement = "°C"
+///
+/// [[devices]]
+/// id = "living_motion"
+/// type = "MotionSensor"
+/// room = "Living room"
+///
+/// [[devices]]
+/// id = "front_door"
+/// type = "DoorSensor"
+///
+/// [[timeline]]
+/// at = 30
+/// device = "front_door"
+/// state = "on"
+///
+/// [[curves]]
+/// device = "living_temperature"
+/// min = 18
+/// max = 24
+///
+/// [[random]]
+/// device = "living_motion"
+/// every = 600
+/// duration = 60
+/// ```
+///
+/// Times are simulated seconds since the simulation started.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Simulated seconds per real second
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// Seed of the random activity and noise. Random when missing.
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub rooms: Vec<Room>,
+    #[serde(default)]
+    pub devices: Vec<SimulatedDevice>,
+    /// Scripted changes, in any order
+    #[serde(default)]
+    pub timeline: Vec<TimelineStep>,
+    /// Simulated seconds after which the timeline starts again. It runs once when missing.
+    pub timeline_period: Option<f64>,
+    /// Values that follow a daily cycle, like temperatures
+    #[serde(default)]
+    pub curves: Vec<Curve>,
+    /// Devices that turn on at random moments, like motion sensors
+    #[serde(default)]
+    pub random: Vec<RandomActivity>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Room {
+    pub name: String,
+    pub floor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedDevice {
+    pub id: String,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub typ: DeviceType,
+    /// Name of one of the rooms
+    pub room: Option<String>,
+    /// The default capabilities of the type when missing
+    pub capabilities: Option<Vec<Capability>>,
+    /// State at the start of the simulation. Switches start `off`, covers `closed`, locks
+    /// `locked` and people at home.
+    pub state: Option<String>,
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+    pub unit_of_measurement: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimelineStep {
+    pub at: f64,
+    pub device: String,
+    pub state: String,
+    /// Attributes changed together with the state
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+}
+
+/// A value that follows a cosine between `min` and `max`, with some noise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Curve {
+    pub device: String,
+    /// Attribute that follows the curve, like the `current_temperature` of a thermostat.
+    /// The state when missing, which needs a sensor.
+    pub attribute: Option<String>,
+    pub min: f64,
+    pub max: f64,
+    /// Length of a cycle, a day by default
+    #[serde(default = "default_period")]
+    pub period: f64,
+    /// When the value is at `max` in the first cycle
+    #[serde(default)]
+    pub peak_at: f64,
+    /// Time between readings
+    #[serde(default = "default_interval")]
+    pub interval: f64,
+    /// Largest random change added to a reading, in either direction
+    #[serde(default)]
+    pub noise: f64,
+    /// Decimal places of the readings
+    #[serde(default = "default_precision")]
+    pub precision: u8,
+}
+
+/// A device that changes to `state` at random moments, `every` seconds on average, and goes
+/// back to its previous state after `duration`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomActivity {
+    pub device: String,
+    pub every: f64,
+    /// `on` by default. Buttons need the action, like `single`.
+    #[serde(default = "default_active_state")]
+    pub state: String,
+    /// Stays in `state` when missing, which suits buttons
+    pub duration: Option<f64>,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+fn default_period() -> f64 {
+    86400.0
+}
+
+fn default_interval() -> f64 {
+    300.0
+}
+
+fn default_precision() -> u8 {
+    1
+}
+
+fn default_active_state() -> String {
+    "on".into()
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read scenario {path:?}"))?;
+        Self::parse(&source).with_context(|| format!("invalid scenario {path:?}"))
+    }
+
+    /// Parses and validates a scenario.
+    pub fn parse(source: &str) -> Result<Self> {
+        let deserializer = toml::Deserializer::new(source);
+        let scenario: Scenario = serde_path_to_error::deserialize(deserializer)
+            .map_err(|e| anyhow!("{}: {}", e.path(), e.inner().message()))?;
+        let errors = scenario.validate();
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(scenario)
+    }
+
+    /// Problems with this scenario, as `field: message`.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.speed <= 0.0 {
+            errors.push("speed: must be greater than zero".to_owned());
+        }
+        if self.timeline_period.is_some_and(|p| p <= 0.0) {
+            errors.push("timeline_period: must be greater than zero".to_owned());
+        }
+
+        let mut rooms = HashSet::new();
+        for (i, room) in self.rooms.iter().enumerate() {
+            if !rooms.insert(room.name.as_str()) {
+                errors.push(format!("rooms[{i}].name: {:?} is repeated", room.name));
+            }
+        }
+        let mut devices = HashMap::new();
+        for (i, device) in self.devices.iter().enumerate() {
+            if device.id.trim().is_empty() || device.id.contains('@') {
+                errors.push(format!(
+                    "devices[{i}].id: {:?} must not be empty or contain `@`",
+                    device.id
+                ));
+            } else if devices.insert(device.id.as_str(), device).is_some() {
+                errors.push(format!("devices[{i}].id: {:?} is repeated", device.id));
+            }
+            if let Some(room) = &device.room {
+                if !rooms.contains(room.as_str()) {
+                    errors.push(format!(
+                        "devices[{i}].room: {room:?} is not one of the rooms"
+                    ));
+                }
+            }
+        }
+
+        let references = self
+            .timeline
+            .iter()
+            .enumerate()
+            .map(|(i, step)| (format!("timeline[{i}]"), &step.device))
+            .chain(
+                self.curves
+                    .iter()
+                    .enumerate()
+                    .map(|(i, curve)| (format!("curves[{i}]"), &curve.device)),
+            )
+            .chain(
+                self.random
+                    .iter()
+                    .enumerate()
+                    .map(|(i, random)| (format!("random[{i}]"), &random.device)),
+            );
+        for (field, id) in references {
+            if !devices.contains_key(id.as_str()) {
+                errors.push(format!("{field}.device: {id:?} is not one of the devices"));
+            }
+        }
+
+        for (i, step) in self.timeline.iter().enumerate() {
+            if step.at < 0.0 {
+                errors.push(format!("timeline[{i}].at: must not be negative"));
+            }
+        }
+        for (i, curve) in self.curves.iter().enumerate() {
+            let measures = devices
+                .get(curve.device.as_str())
+                .map(|d| d.capabilities().contains(&Capability::Measurement));
+            if curve.attribute.is_none() && measures == Some(false) {
+                errors.push(format!(
+                    "curves[{i}].attribute: must be set for devices that are not sensors"
+                ));
+            }
+            if curve.min > curve.max {
+                errors.push(format!(
+                    "curves[{i}].max: {} must not be less than min {}",
+                    curve.max, curve.min
+                ));
+            }
+            for (field, value) in [("period", curve.period), ("interval", curve.interval)] {
+                if value <= 0.0 {
+                    errors.push(format!("curves[{i}].{field}: must be greater than zero"));
+                }
+            }
+            if curve.noise < 0.0 {
+                errors.push(format!("curves[{i}].noise: must not be negative"));
+            }
+        }
+        for (i, random) in self.random.iter().enumerate() {
+            if random.every <= 0.0 {
+                errors.push(format!("random[{i}].every: must be greater than zero"));
+            }
+            if random.duration.is_some_and(|d| d <= 0.0) {
+                errors.push(format!("random[{i}].duration: must be greater than zero"));
+            }
+        }
+        errors
+    }
+}
+
+impl SimulatedDevice {
+    pub(super) fn capabilities(&self) -> Vec<Capability> {
+        match &self.capabilities {
+            Some(capabilities) => capabilities.clone(),
+            None => self.typ.default_capabilities().to_vec(),
+        }
+    }
+
+    pub(super) fn initial_state(&self) -> String {
+        if let Some(state) = &self.state {
+            return state.clone();
+        }
+        let capabilities = self.capabilities();
+        let state = [
+            (Capability::OnOff, "off"),
+            (Capability::Contact, "off"),
+            (Capability::Motion, "off"),
+            (Capability::Position, "closed"),
+            (Capability::Lock, "locked"),
+            (Capability::Presence, HOME_STATE),
+            (Capability::TemperatureSetpoint, "heat"),
+            (Capability::MediaPlayback, "idle"),
+        ]
+        .into_iter()
+        .find(|(capability, _)| capabilities.contains(capability))
+        .map_or("unknown", |(_, state)| state);
+        state.to_owned()
+    }
+}
@@ -0,0 +1,41 @@
+//! Reading of server-sent events, for integrations that follow an event stream.
+
+use anyhow::{anyhow, bail, Result};
+use std::convert::Infallible;
+use std::time::Duration;
+
+/// Reads the server-sent events of `response` until the stream closes, calling `handle` with
+/// the data of each one. Comments and events without data, like keep-alives, are skipped.
+/// With an `idle_timeout`, fails when nothing arrives for that long, so half-open connections
+/// of servers that send keep-alives are noticed.
+pub async fn read_events(
+    mut response: reqwest::Response,
+    idle_timeout: Option<Duration>,
+    mut handle: impl FnMut(&str),
+) -> Result<Infallible> {
+    let mut buffer = Vec::new();
+    loop {
+        let chunk = match idle_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response.chunk())
+                .await
+                .map_err(|_| anyhow!("nothing received for {timeout:?}"))??,
+            None => response.chunk().await?,
+        };
+        let Some(chunk) = chunk else {
+            bail!("event stream closed");
+        };
+        buffer.extend(chunk.iter().filter(|b| **b != b'\r'));
+        while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+            let message = buffer.drain(..end + 2).collect::<Vec<_>>();
+            let data = String::from_utf8_lossy(&message)
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if !data.is_empty() {
+                handle(&data);
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Channels of the subscribers of an integration, shared with its background tasks.
+
+use crate::runtime::event::Event;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::warn;
+
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<Sender<Event>>>>);
+
+impl Subscribers {
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel(capacity);
+        self.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Sends `event` to every subscriber, forgetting the ones that are gone.
+    pub fn send(&self, event: Event) {
+        let mut subscribers = self.0.lock().unwrap();
+        subscribers.retain(|tx| send(tx, event.clone()));
+    }
+}
+
+/// Sends `event` without waiting, dropping it when the channel is full. Returns whether the
+/// receiver is still there.
+pub fn send(tx: &Sender<Event>, event: Event) -> bool {
+    match tx.try_send(event) {
+        Ok(()) => true,
+        Err(TrySendError::Full(event)) => {
+            warn!(
+                "Dropping {:?} of {}, too many events are waiting",
+                event.typ,
+                event.device.full_id()
+            );
+            true
+        }
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
@@ -1,4 +1,6 @@
+pub mod config;
 pub mod integrations;
+pub mod notifiers;
 pub mod runtime;
 pub mod server;
 #[cfg(test)]
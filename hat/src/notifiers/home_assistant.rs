@@ -0,0 +1,49 @@
+use super::{Notification, Notifier};
+use crate::runtime::context::ExpressionContext;
+use crate::runtime::value::Value;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Calls a `notify.*` service of a Home Assistant integration, like
+/// `notify.mobile_app_phone`.
+#[derive(Debug, Clone)]
+pub struct HassNotifier {
+    /// ID of the Home Assistant integration
+    pub integration: String,
+    /// Name of the service, without the `notify.` domain
+    pub service: String,
+    /// Extra service data, like `{ "priority": "high" }` for the companion app
+    pub data: BTreeMap<String, Value>,
+}
+
+#[async_trait]
+impl Notifier for HassNotifier {
+    async fn notify(&self, ctx: Arc<ExpressionContext>, notification: &Notification) -> Result<()> {
+        let call_service = ctx
+            .runtime
+            .get_function(&format!("{}.ha_call_service", self.integration))
+            .with_context(|| format!("{} is not a Home Assistant integration", self.integration))?;
+        let mut data = BTreeMap::from([
+            (
+                "title".to_owned(),
+                Value::String(notification.title.clone()),
+            ),
+            (
+                "message".to_owned(),
+                Value::String(notification.message.clone()),
+            ),
+        ]);
+        if !self.data.is_empty() {
+            data.insert("data".to_owned(), Value::Map(self.data.clone()));
+        }
+        let arguments = vec![
+            Value::String(format!("notify.{}", self.service)),
+            Value::Null,
+            Value::Map(data),
+        ];
+        call_service.call(ctx, arguments).await?;
+        Ok(())
+    }
+}
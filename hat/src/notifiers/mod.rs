@@ -0,0 +1,135 @@
+//! Notifications sent by automations with `notify(target, title, message)`. Targets are
+//! configured by name in `[notifiers.<name>]`, so the source code never contains credentials.
+
+pub mod home_assistant;
+pub mod ntfy;
+pub mod smtp;
+pub mod webhook;
+
+use crate::runtime::context::ExpressionContext;
+use crate::runtime::function::{Function, FunctionCategory, FunctionParameter};
+use crate::runtime::value::{Value, ValueType};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures_util::future::join_all;
+use lazy_static::lazy_static;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub use home_assistant::HassNotifier;
+pub use ntfy::NtfyNotifier;
+pub use smtp::{SmtpNotifier, SmtpSecurity};
+pub use webhook::WebhookNotifier;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+}
+
+/// Delivers notifications to people, like a push to their phone or an email.
+#[async_trait]
+pub trait Notifier: Debug + Send + Sync {
+    async fn notify(&self, ctx: Arc<ExpressionContext>, notification: &Notification) -> Result<()>;
+}
+
+pub fn functions() -> Vec<Function> {
+    vec![Function {
+        name: "notify".to_owned(),
+        description: "Envia uma notificação a um destino configurado, ou a uma lista deles",
+        category: FunctionCategory::Action,
+        parameters: vec![
+            FunctionParameter::required("target", ValueType::Any),
+            FunctionParameter::required("title", ValueType::String),
+            FunctionParameter::required("message", ValueType::String),
+        ],
+        returns: ValueType::Null,
+        fun: Arc::new(|ctx, args| {
+            Box::pin(async move {
+                let mut args = args.into_iter();
+                let targets = match args.next() {
+                    Some(Value::String(target)) => vec![target],
+                    Some(Value::List(targets)) => targets
+                        .into_iter()
+                        .map(|target| match target {
+                            Value::String(target) => Ok(target),
+                            target => bail!("target {target} is not the name of a notifier"),
+                        })
+                        .collect::<Result<_>>()?,
+                    _ => bail!("first argument must be a notifier or a list of notifiers"),
+                };
+                let notification = Notification {
+                    title: text(args.next()),
+                    message: text(args.next()),
+                };
+
+                let notifiers = targets
+                    .iter()
+                    .map(|target| {
+                        ctx.runtime
+                            .settings()
+                            .notifiers
+                            .get(target)
+                            .map(|notifier| (target, notifier))
+                            .with_context(|| format!("notifier {target:?} is not configured"))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                // Every target is tried, so one broken notifier does not silence the others
+                let results = join_all(notifiers.into_iter().map(|(target, notifier)| {
+                    let ctx = Arc::clone(&ctx);
+                    let notification = &notification;
+                    async move {
+                        notifier
+                            .notify(ctx, notification)
+                            .await
+                            .map_err(|e| format!("failed to notify {target}: {e:#}"))
+                    }
+                }))
+                .await;
+                let errors = results
+                    .into_iter()
+                    .filter_map(Result::err)
+                    .collect::<Vec<_>>();
+                if !errors.is_empty() {
+                    bail!("{}", errors.join("; "));
+                }
+                Ok(Value::Null)
+            })
+        }),
+    }]
+}
+
+/// Strings are used as they are, other values as they are shown.
+fn text(value: Option<Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text,
+        Some(Value::Null) | None => String::new(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Fails with the status and the body of an unsuccessful response.
+async fn check_response(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("server replied {status}: {body}");
+    }
+    Ok(())
+}
+
+/// Encodes text for a header as in RFC 2047 when it is not printable ASCII. Line breaks are
+/// removed, so they cannot start another header.
+fn encode_header(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    if text.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+        text
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64_STANDARD.encode(text))
+    }
+}
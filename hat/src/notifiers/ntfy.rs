@@ -0,0 +1,45 @@
+use super::{check_response, encode_header, Notification, Notifier, CLIENT};
+use crate::runtime::context::ExpressionContext;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Publishes to a topic of an [ntfy](https://ntfy.sh) server, or any server with the same API:
+/// the message is the body and the title a header.
+#[derive(Debug, Clone)]
+pub struct NtfyNotifier {
+    /// Address of the server, like `https://ntfy.sh`
+    pub url: String,
+    pub topic: String,
+    /// Access token of protected topics
+    pub token: Option<String>,
+    /// From 1 (min) to 5 (max), 3 by default
+    pub priority: Option<u8>,
+    /// Tags, or emoji shortcodes like `warning`, shown with the notification
+    pub tags: Vec<String>,
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    async fn notify(&self, ctx: Arc<ExpressionContext>, notification: &Notification) -> Result<()> {
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), self.topic);
+        let mut request = CLIENT
+            .post(url)
+            .timeout(ctx.runtime.settings().http.timeout)
+            .body(notification.message.clone());
+        if !notification.title.is_empty() {
+            request = request.header("Title", encode_header(&notification.title));
+        }
+        if let Some(priority) = self.priority {
+            request = request.header("Priority", priority.to_string());
+        }
+        if !self.tags.is_empty() {
+            request = request.header("Tags", self.tags.join(","));
+        }
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.context("request failed")?;
+        check_response(response).await
+    }
+}
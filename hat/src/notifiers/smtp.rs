@@ -0,0 +1,202 @@
+//! A minimal SMTP client: enough to hand a plain text email to a mail server, optionally over
+//! TLS and with a username and password.
+//!
+//! A crate like `lettre` would bring MIME building, connection pools and several transports
+//! for a single short message. This client reuses the `native-tls` stack that the Home
+//! Assistant websocket already depends on, so email adds no TLS implementation to the build.
+
+use super::{encode_header, Notification, Notifier};
+use crate::runtime::context::ExpressionContext;
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::Local;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+/// Longest time to deliver an email, from connecting to the reply to `QUIT`
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, only for servers on the local network
+    None,
+    /// Plain text upgraded with `STARTTLS`, usually on port 587
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+/// Sends an email, with the title as subject, to every address of `to`.
+#[derive(Debug, Clone)]
+pub struct SmtpNotifier {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn notify(
+        &self,
+        _ctx: Arc<ExpressionContext>,
+        notification: &Notification,
+    ) -> Result<()> {
+        tokio::time::timeout(TIMEOUT, self.send(notification))
+            .await
+            .with_context(|| format!("{}:{} did not answer in time", self.host, self.port))?
+    }
+}
+
+impl SmtpNotifier {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("failed to connect to {}:{}", self.host, self.port))?;
+        match self.security {
+            SmtpSecurity::None => {
+                let mut connection = Connection::new(stream);
+                connection.reply(220).await?;
+                self.deliver(connection, notification).await
+            }
+            SmtpSecurity::StartTls => {
+                let mut connection = Connection::new(stream);
+                connection.reply(220).await?;
+                connection.command("EHLO localhost", 250).await?;
+                connection.command("STARTTLS", 220).await?;
+                let stream = self.tls(connection.into_inner()).await?;
+                self.deliver(Connection::new(stream), notification).await
+            }
+            SmtpSecurity::Tls => {
+                let mut connection = Connection::new(self.tls(stream).await?);
+                connection.reply(220).await?;
+                self.deliver(connection, notification).await
+            }
+        }
+    }
+
+    async fn tls(&self, stream: TcpStream) -> Result<tokio_native_tls::TlsStream<TcpStream>> {
+        let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+        connector
+            .connect(&self.host, stream)
+            .await
+            .with_context(|| format!("failed to start TLS with {}", self.host))
+    }
+
+    /// Sends the email through a connection whose greeting was already read.
+    async fn deliver<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut connection: Connection<S>,
+        notification: &Notification,
+    ) -> Result<()> {
+        connection.command("EHLO localhost", 250).await?;
+        if let Some(username) = &self.username {
+            let password = self.password.as_deref().unwrap_or_default();
+            let credentials = BASE64_STANDARD.encode(format!("\0{username}\0{password}"));
+            connection
+                .send(&format!("AUTH PLAIN {credentials}"))
+                .await?;
+            connection
+                .reply(235)
+                .await
+                .context("authentication failed")?;
+        }
+        connection
+            .command(&format!("MAIL FROM:<{}>", self.from), 250)
+            .await?;
+        for to in &self.to {
+            connection.command(&format!("RCPT TO:<{to}>"), 250).await?;
+        }
+        connection.command("DATA", 354).await?;
+        connection.send(&self.message(notification)).await?;
+        connection
+            .reply(250)
+            .await
+            .context("the email was not accepted")?;
+        connection.command("QUIT", 221).await?;
+        Ok(())
+    }
+
+    /// The email, ending with the `.` line that finishes `DATA`.
+    fn message(&self, notification: &Notification) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+            Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            encode_header(&notification.title),
+            Local::now().to_rfc2822(),
+        );
+        for line in notification.message.lines() {
+            // Lines starting with `.` get another one, otherwise a `.` line would end the email
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+        message
+    }
+}
+
+struct Connection<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        self.stream.write_all(line.as_bytes()).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Reads a reply, which may span several lines, and checks that it is in the same class
+    /// (like 2xx) as `expected`.
+    async fn reply(&mut self, expected: u16) -> Result<()> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            let read = self.stream.read_line(&mut line).await?;
+            ensure!(read > 0, "the server closed the connection");
+            let line = line.trim_end();
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .with_context(|| format!("invalid reply from the server: {line:?}"))?;
+            text.push_str(line.get(4..).unwrap_or_default());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                ensure!(code / 100 == expected / 100, "server replied {code} {text}");
+                return Ok(());
+            }
+            text.push(' ');
+        }
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<()> {
+        self.send(command).await?;
+        self.reply(expected)
+            .await
+            .with_context(|| format!("{command} failed"))
+    }
+}
@@ -0,0 +1,32 @@
+use super::{check_response, Notification, Notifier, CLIENT};
+use crate::runtime::context::ExpressionContext;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Posts `{"title": ..., "message": ...}` to a URL, like a chat bot endpoint.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, ctx: Arc<ExpressionContext>, notification: &Notification) -> Result<()> {
+        let mut request = CLIENT
+            .post(&self.url)
+            .timeout(ctx.runtime.settings().http.timeout)
+            .json(&json!({
+                "title": notification.title,
+                "message": notification.message,
+            }));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.context("request failed")?;
+        check_response(response).await
+    }
+}
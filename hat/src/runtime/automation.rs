@@ -1,6 +1,7 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use super::event::Event;
+use super::event::{Event, EventType};
 use crate::runtime::context::ExpressionContext;
 use crate::runtime::parser::expression::Expression;
 
@@ -9,19 +10,59 @@ use anyhow::{Context, Result};
 #[derive(Debug)]
 pub struct Automation {
     pub name: String,
-    pub triggers: Vec<String>,
+    pub triggers: Vec<AutomationTrigger>,
     pub conditions: Vec<Expression>,
     pub actions: Vec<Expression>,
 }
 
-impl Automation {
-    pub fn should_be_triggered_by(&self, event: &Event) -> bool {
-        for trigger in &self.triggers {
-            if event.typ.as_str().to_lowercase() == trigger.to_lowercase() {
-                return true;
-            }
+/// An event that runs an automation, like `DoorOpenEvent` or `Webhook "doorbell"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutomationTrigger {
+    /// Name of the event type. The `Event` suffix of built-in events can be left out.
+    pub event: String,
+    /// The device (`id` or `integration@id`) or webhook the event must come from
+    pub source: Option<String>,
+}
+
+impl AutomationTrigger {
+    pub fn event_type(&self) -> EventType {
+        match EventType::from_name(&self.event) {
+            EventType::Custom(_) => EventType::BUILT_IN
+                .iter()
+                .find(|e| {
+                    e.as_str()
+                        .strip_suffix("Event")
+                        .is_some_and(|name| name.eq_ignore_ascii_case(&self.event))
+                })
+                .cloned()
+                .unwrap_or_else(|| EventType::custom(&self.event)),
+            typ => typ,
         }
-        false
+    }
+
+    /// Whether `event` runs the automation. `aliases` maps alternative integration IDs to the
+    /// real ones, so sources like `HassIntegration0@lock.front` keep working.
+    pub fn matches(&self, event: &Event, aliases: &HashMap<String, String>) -> bool {
+        let source_matches = self.source.as_ref().is_none_or(|source| {
+            *source == event.device.id
+                || source.split_once('@').is_some_and(|(integration, id)| {
+                    let integration = aliases.get(integration).map_or(integration, String::as_str);
+                    integration == event.device.integration && id == event.device.id
+                })
+        });
+        source_matches
+            && event
+                .typ
+                .as_str()
+                .eq_ignore_ascii_case(self.event_type().as_str())
+    }
+}
+
+impl Automation {
+    pub fn should_be_triggered_by(&self, event: &Event, aliases: &HashMap<String, String>) -> bool {
+        self.triggers
+            .iter()
+            .any(|trigger| trigger.matches(event, aliases))
     }
 
     pub async fn trigger(&self, ctx: Arc<ExpressionContext>) -> Result<()> {
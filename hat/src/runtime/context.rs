@@ -9,8 +9,11 @@ use super::scheduler::TaskID;
 pub struct ExpressionContext {
     pub trigger: Trigger,
     pub runtime: Arc<HatRuntime>,
+    /// Name of the automation being executed, if the expression belongs to one
+    pub automation: Option<String>,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Trigger {
     Event(Event),
@@ -21,17 +24,13 @@ impl Debug for ExpressionContext {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AutomationContext")
             .field("trigger", &self.trigger)
+            .field("automation", &self.automation)
             .finish()
     }
 }
 
 impl ExpressionContext {
     pub fn get_function(&self, name: &str) -> Option<Arc<Function>> {
-        self.runtime
-            .functions
-            .read()
-            .unwrap()
-            .get(name)
-            .map(Arc::clone)
+        self.runtime.get_function(name)
     }
 }
@@ -1,6 +1,17 @@
 use serde::{Deserialize, Serialize};
 use strum::VariantArray;
 
+use super::event::EventType;
+use super::value::Value;
+
+/// State of a device with the [`Capability::Presence`] capability when its person is at home
+pub const HOME_STATE: &str = "home";
+/// State of a device with the [`Capability::Presence`] capability when its person is not in
+/// any known zone
+pub const AWAY_STATE: &str = "not_home";
+
+/// The kind of a device, used by editors to group and present devices. What a device can do is
+/// described by its [`Capability`] list.
 #[derive(Hash, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, VariantArray)]
 pub enum DeviceType {
     Dummy,
@@ -10,7 +21,183 @@ pub enum DeviceType {
     Switch,
     MotionSensor,
     Button,
+    Thermostat,
+    Cover,
+    Lock,
+    Fan,
+    Person,
+    Unknown,
+}
+
+impl DeviceType {
+    /// Capabilities of a typical device of this type, for integrations that do not know better.
+    pub const fn default_capabilities(&self) -> &'static [Capability] {
+        use Capability::*;
+        match self {
+            DeviceType::DoorSensor => &[Contact],
+            DeviceType::Light => &[OnOff, Brightness, ColorRgb],
+            DeviceType::Sensor => &[Measurement],
+            DeviceType::Switch => &[OnOff],
+            DeviceType::MotionSensor => &[Motion],
+            DeviceType::Button => &[Press],
+            DeviceType::Thermostat => &[TemperatureSetpoint],
+            DeviceType::Cover => &[Position],
+            DeviceType::Lock => &[Lock],
+            DeviceType::Fan => &[OnOff, FanSpeed],
+            DeviceType::Person => &[Presence],
+            DeviceType::Dummy | DeviceType::Unknown => &[],
+        }
+    }
+}
+
+/// Something a device can do or sense.
+#[derive(Hash, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, VariantArray)]
+pub enum Capability {
+    OnOff,
+    Brightness,
+    ColorRgb,
+    ColorTemp,
+    Position,
+    Lock,
+    TemperatureSetpoint,
+    FanSpeed,
+    MediaPlayback,
+    /// Binary sensor for doors and windows
+    Contact,
+    Motion,
+    /// Sensor that reports a value, like temperature or humidity
+    Measurement,
+    Press,
+    /// Location of a person, as a zone name or [`AWAY_STATE`]
+    Presence,
+}
+
+impl Capability {
+    /// Events emitted when the state of this capability changes. For on/off capabilities the
+    /// first event is the "on" one and the second is the "off" one.
+    pub const fn get_related_events(&self, device_type: DeviceType) -> &'static [EventType] {
+        use EventType::*;
+        match self {
+            Capability::OnOff => match device_type {
+                DeviceType::Light => &[LightOnEvent, LightOffEvent],
+                DeviceType::Fan => &[FanTurnedOnEvent, FanTurnedOffEvent],
+                _ => &[SwitchTurnedOnEvent, SwitchTurnedOffEvent],
+            },
+            Capability::Position => &[CoverOpenedEvent, CoverClosedEvent],
+            Capability::Lock => &[LockLockedEvent, LockUnlockedEvent],
+            Capability::TemperatureSetpoint => &[TargetTemperatureChangedEvent],
+            Capability::Contact => &[DoorOpenEvent, DoorCloseEvent],
+            Capability::Motion => &[MotionSensorOnEvent, MotionSensorOffEvent],
+            Capability::Measurement => &[SensorValueChangeEvent],
+            Capability::Press => &[ButtonPressedEvent],
+            Capability::Presence => &[PersonArrivedEvent, PersonLeftEvent],
+            Capability::Brightness
+            | Capability::ColorRgb
+            | Capability::ColorTemp
+            | Capability::FanSpeed
+            | Capability::MediaPlayback => &[],
+        }
+    }
+    /// For capabilities with two main states, returns the states that emit the first and the
+    /// second related events, respectively.
+    const fn get_binary_states(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Capability::OnOff | Capability::Contact | Capability::Motion => Some(("on", "off")),
+            Capability::Position => Some(("open", "closed")),
+            Capability::Lock => Some(("locked", "unlocked")),
+            _ => None,
+        }
+    }
+}
+
+/// A command that can be executed on a device through [`crate::integrations::Integration::execute`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeviceCommand {
+    TurnOn,
+    TurnOff,
+    SetBrightness(u8),
+    SetColorRgb([u8; 3]),
+    /// Color temperature in kelvin
+    SetColorTemp(u16),
+    /// Position in percent, where 0 is closed and 100 is fully open
+    SetPosition(u8),
+    Open,
+    Close,
+    Lock,
+    Unlock,
+    SetTargetTemperature(f64),
+    /// Fan speed in percent
+    SetFanSpeed(u8),
+    MediaPlay,
+    MediaPause,
+    MediaStop,
+}
+
+impl DeviceCommand {
+    pub const fn required_capability(&self) -> Capability {
+        match self {
+            DeviceCommand::TurnOn | DeviceCommand::TurnOff => Capability::OnOff,
+            DeviceCommand::SetBrightness(_) => Capability::Brightness,
+            DeviceCommand::SetColorRgb(_) => Capability::ColorRgb,
+            DeviceCommand::SetColorTemp(_) => Capability::ColorTemp,
+            DeviceCommand::SetPosition(_) | DeviceCommand::Open | DeviceCommand::Close => {
+                Capability::Position
+            }
+            DeviceCommand::Lock | DeviceCommand::Unlock => Capability::Lock,
+            DeviceCommand::SetTargetTemperature(_) => Capability::TemperatureSetpoint,
+            DeviceCommand::SetFanSpeed(_) => Capability::FanSpeed,
+            DeviceCommand::MediaPlay | DeviceCommand::MediaPause | DeviceCommand::MediaStop => {
+                Capability::MediaPlayback
+            }
+        }
+    }
+}
+
+/// Normalized view of the state of a device, see [`Device::typed_state`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceState {
+    On,
+    Off,
+    Numeric(f64),
+    /// The integration cannot reach the device
+    Unavailable,
+    /// The device is reachable but did not report its state yet
     Unknown,
+    /// Any other state, like `open` or a zone name
+    Raw(String),
+}
+
+impl DeviceState {
+    pub fn parse(state: &str) -> Self {
+        match state {
+            "on" => Self::On,
+            "off" => Self::Off,
+            "unavailable" => Self::Unavailable,
+            "unknown" | "" => Self::Unknown,
+            state => match state.parse::<f64>() {
+                Ok(number) if number.is_finite() => Self::Numeric(number),
+                _ => Self::Raw(state.to_owned()),
+            },
+        }
+    }
+
+    /// Returns false for [`DeviceState::Unavailable`] and [`DeviceState::Unknown`].
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Self::Unavailable | Self::Unknown)
+    }
+
+    /// Converts the state into a runtime value. Numeric states become numbers, so they can be
+    /// compared and subtracted in Hat code.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Self::On => Value::String("on".into()),
+            Self::Off => Value::String("off".into()),
+            Self::Numeric(number) => Value::Number(*number),
+            Self::Unavailable => Value::String("unavailable".into()),
+            Self::Unknown => Value::String("unknown".into()),
+            Self::Raw(state) => Value::String(state.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -19,6 +206,14 @@ pub struct Device {
     pub id: String,
     pub name: Option<String>,
     pub typ: DeviceType,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// Area (room) the device is in, when the integration knows it
+    #[serde(default)]
+    pub area: Option<String>,
+    /// Floor of the area the device is in
+    #[serde(default)]
+    pub floor: Option<String>,
     pub state: Option<String>,
     pub attributes: serde_json::Map<String, serde_json::Value>,
 }
@@ -27,4 +222,77 @@ impl Device {
     pub fn full_id(&self) -> String {
         format!("{}@{}", self.integration, self.id)
     }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Returns every event this device can emit, based on its capabilities. Dummy devices only
+    /// emit [`EventType::Dummy`].
+    pub fn get_related_events(&self) -> Vec<EventType> {
+        if self.typ == DeviceType::Dummy {
+            return vec![EventType::Dummy];
+        }
+        let mut events = Vec::new();
+        for capability in &self.capabilities {
+            for event in capability.get_related_events(self.typ) {
+                if !events.contains(event) {
+                    events.push(event.clone());
+                }
+            }
+        }
+        events
+    }
+
+    /// Whether the device is in the area with this name, ignoring case.
+    pub fn is_in_area(&self, area: &str) -> bool {
+        self.area
+            .as_deref()
+            .is_some_and(|a| a.eq_ignore_ascii_case(area))
+    }
+
+    /// The state of this device, or [`DeviceState::Unknown`] when it has none.
+    pub fn typed_state(&self) -> DeviceState {
+        self.state
+            .as_deref()
+            .map(DeviceState::parse)
+            .unwrap_or(DeviceState::Unknown)
+    }
+
+    /// Whether the person tracked by this device is at home.
+    pub fn is_home(&self) -> bool {
+        self.has_capability(Capability::Presence) && self.state.as_deref() == Some(HOME_STATE)
+    }
+
+    /// Returns the event that represents the change of this device from `old_state` to
+    /// `new_state`, if any of its capabilities emits one. Capabilities with two main states
+    /// ignore changes from `unavailable` or `unknown`, which happen when a device reconnects.
+    /// Presence emits an arrival when the new state is a zone, or a departure when the person
+    /// left a zone and is now away.
+    pub fn get_state_change_event(&self, old_state: &str, new_state: &str) -> Option<EventType> {
+        if old_state == new_state {
+            return None;
+        }
+        let reconnected = !DeviceState::parse(old_state).is_known();
+        self.capabilities.iter().find_map(|capability| {
+            let events = capability.get_related_events(self.typ);
+            match capability.get_binary_states() {
+                Some(_) if reconnected => None,
+                Some((first, _)) if new_state == first => events.first().cloned(),
+                Some((_, second)) if new_state == second => events.get(1).cloned(),
+                Some(_) => None,
+                None => match capability {
+                    Capability::Presence
+                        if reconnected || !DeviceState::parse(new_state).is_known() =>
+                    {
+                        None
+                    }
+                    Capability::Presence if new_state != AWAY_STATE => events.first().cloned(),
+                    Capability::Presence => events.get(1).cloned(),
+                    Capability::Measurement | Capability::Press => events.first().cloned(),
+                    _ => None,
+                },
+            }
+        })
+    }
 }
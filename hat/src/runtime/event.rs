@@ -1,12 +1,12 @@
-use crate::runtime::device::Device;
+use crate::runtime::device::{Capability, Device, DeviceState, AWAY_STATE};
+use crate::runtime::value::Value;
 use chrono::Local;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use strum::VariantArray;
 
 use super::device::DeviceType;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EventType {
     Dummy,
     DoorOpenEvent,
@@ -20,34 +20,89 @@ pub enum EventType {
     SensorValueChangeEvent,
     ClockTickEvent,
     ButtonPressedEvent,
+    LockLockedEvent,
+    LockUnlockedEvent,
+    CoverOpenedEvent,
+    CoverClosedEvent,
+    TargetTemperatureChangedEvent,
+    FanTurnedOnEvent,
+    FanTurnedOffEvent,
+    PersonArrivedEvent,
+    PersonLeftEvent,
+    IntegrationConnectedEvent,
+    IntegrationDisconnectedEvent,
+    /// An external system called `POST /webhook/:name`
+    WebhookEvent,
+    /// Events that are not known by the runtime, like the ones provided by integrations
+    #[serde(untagged)]
+    Custom(String),
 }
 
 impl EventType {
-    pub const fn get_events_related_to(device_type: DeviceType) -> &'static [EventType] {
+    pub const BUILT_IN: &'static [EventType] = {
         use EventType::*;
-        match device_type {
-            DeviceType::Dummy => &[Dummy],
-            DeviceType::DoorSensor => &[DoorOpenEvent, DoorCloseEvent],
-            DeviceType::Light => &[LightOnEvent, LightOffEvent],
-            DeviceType::Sensor => &[SensorValueChangeEvent],
-            DeviceType::Switch => &[SwitchTurnedOnEvent, SwitchTurnedOffEvent],
-            DeviceType::MotionSensor => &[MotionSensorOnEvent, MotionSensorOffEvent],
-            DeviceType::Button => &[ButtonPressedEvent],
-            DeviceType::Unknown => &[],
-        }
+        &[
+            Dummy,
+            DoorOpenEvent,
+            DoorCloseEvent,
+            LightOnEvent,
+            LightOffEvent,
+            SwitchTurnedOnEvent,
+            SwitchTurnedOffEvent,
+            MotionSensorOnEvent,
+            MotionSensorOffEvent,
+            SensorValueChangeEvent,
+            ClockTickEvent,
+            ButtonPressedEvent,
+            LockLockedEvent,
+            LockUnlockedEvent,
+            CoverOpenedEvent,
+            CoverClosedEvent,
+            TargetTemperatureChangedEvent,
+            FanTurnedOnEvent,
+            FanTurnedOffEvent,
+            PersonArrivedEvent,
+            PersonLeftEvent,
+            IntegrationConnectedEvent,
+            IntegrationDisconnectedEvent,
+            WebhookEvent,
+        ]
+    };
+    pub fn custom(name: impl Into<String>) -> Self {
+        Self::Custom(name.into())
     }
-    pub fn get_related_device_type(&self) -> Option<DeviceType> {
-        for dt in DeviceType::VARIANTS {
-            let events = Self::get_events_related_to(*dt);
-            for e in events {
-                if e == self {
-                    return Some(*dt);
-                }
-            }
+    /// Returns the built-in event type with this name (ignoring case), or a custom event type.
+    pub fn from_name(name: &str) -> Self {
+        Self::BUILT_IN
+            .iter()
+            .find(|e| e.as_str().eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| Self::custom(name))
+    }
+    /// The device type editors should associate with this event.
+    pub const fn get_related_device_type(&self) -> Option<DeviceType> {
+        use EventType::*;
+        match self {
+            Dummy => Some(DeviceType::Dummy),
+            DoorOpenEvent | DoorCloseEvent => Some(DeviceType::DoorSensor),
+            LightOnEvent | LightOffEvent => Some(DeviceType::Light),
+            SwitchTurnedOnEvent | SwitchTurnedOffEvent => Some(DeviceType::Switch),
+            MotionSensorOnEvent | MotionSensorOffEvent => Some(DeviceType::MotionSensor),
+            SensorValueChangeEvent => Some(DeviceType::Sensor),
+            ButtonPressedEvent => Some(DeviceType::Button),
+            LockLockedEvent | LockUnlockedEvent => Some(DeviceType::Lock),
+            CoverOpenedEvent | CoverClosedEvent => Some(DeviceType::Cover),
+            TargetTemperatureChangedEvent => Some(DeviceType::Thermostat),
+            FanTurnedOnEvent | FanTurnedOffEvent => Some(DeviceType::Fan),
+            PersonArrivedEvent | PersonLeftEvent => Some(DeviceType::Person),
+            ClockTickEvent
+            | IntegrationConnectedEvent
+            | IntegrationDisconnectedEvent
+            | WebhookEvent
+            | Custom(_) => None,
         }
-        None
     }
-    pub const fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         use EventType::*;
         match self {
             Dummy => "Dummy",
@@ -62,6 +117,19 @@ impl EventType {
             SensorValueChangeEvent => "SensorValueChangeEvent",
             ClockTickEvent => "ClockTickEvent",
             ButtonPressedEvent => "ButtonPressedEvent",
+            LockLockedEvent => "LockLockedEvent",
+            LockUnlockedEvent => "LockUnlockedEvent",
+            CoverOpenedEvent => "CoverOpenedEvent",
+            CoverClosedEvent => "CoverClosedEvent",
+            TargetTemperatureChangedEvent => "TargetTemperatureChangedEvent",
+            FanTurnedOnEvent => "FanTurnedOnEvent",
+            FanTurnedOffEvent => "FanTurnedOffEvent",
+            PersonArrivedEvent => "PersonArrivedEvent",
+            PersonLeftEvent => "PersonLeftEvent",
+            IntegrationConnectedEvent => "IntegrationConnectedEvent",
+            IntegrationDisconnectedEvent => "IntegrationDisconnectedEvent",
+            WebhookEvent => "WebhookEvent",
+            Custom(name) => name,
         }
     }
     pub const fn get_description(&self) -> &'static str {
@@ -79,14 +147,171 @@ impl EventType {
             SensorValueChangeEvent => "Valor de sensor mudou",
             ClockTickEvent => "Run every second",
             ButtonPressedEvent => "Botão foi apertado",
+            LockLockedEvent => "Fechadura foi trancada",
+            LockUnlockedEvent => "Fechadura foi destrancada",
+            CoverOpenedEvent => "Cortina/persiana foi aberta",
+            CoverClosedEvent => "Cortina/persiana foi fechada",
+            TargetTemperatureChangedEvent => "Temperatura desejada do termostato mudou",
+            FanTurnedOnEvent => "Ventilador foi ligado",
+            FanTurnedOffEvent => "Ventilador foi desligado",
+            PersonArrivedEvent => "Pessoa chegou em um local",
+            PersonLeftEvent => "Pessoa saiu de um local",
+            IntegrationConnectedEvent => "Conexão com uma integração foi restabelecida",
+            IntegrationDisconnectedEvent => "Conexão com uma integração foi perdida",
+            WebhookEvent => "Webhook foi chamado por um sistema externo",
+            Custom(_) => "Evento personalizado",
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Parameter holding the state of the device before the event, when known
+pub const OLD_STATE_PARAMETER: &str = "old_state";
+/// Parameter holding the state of the device after the event, when known
+pub const NEW_STATE_PARAMETER: &str = "new_state";
+/// Parameter holding the measured value after the event, for devices with
+/// [`Capability::Measurement`]
+pub const VALUE_PARAMETER: &str = "value";
+/// Parameter holding the zone a person arrived at or left, like `home`
+pub const ZONE_PARAMETER: &str = "zone";
+/// Parameter holding the ID of the integration an integration event is about
+pub const INTEGRATION_PARAMETER: &str = "integration";
+/// Parameter holding the name of the webhook of a [`EventType::WebhookEvent`]
+pub const WEBHOOK_PARAMETER: &str = "webhook";
+/// Parameter holding the JSON body sent to a webhook, `null` when empty
+pub const BODY_PARAMETER: &str = "body";
+
+/// Integration of the device of the events of webhooks, see [`Event::from_webhook`]
+pub const WEBHOOK_INTEGRATION_ID: &str = "webhook";
+/// Integration of the device of the events dispatched with `emit`
+pub const RUNTIME_INTEGRATION_ID: &str = "HatRuntime";
+
+/// Describes an event type that an integration can emit, so editors can offer it as a trigger.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventDescriptor {
+    pub event: EventType,
+    pub description: &'static str,
+    #[serde(rename = "relatedDeviceType")]
+    pub related_device_type: Option<DeviceType>,
+}
+
+/// Something that happened, triggering automations. Serializable so other Hat instances can
+/// follow it through `GET /event_stream`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub typ: EventType,
     pub datetime: chrono::DateTime<Local>,
     pub device: Device,
-    pub parameters: HashMap<String, String>,
+    pub parameters: HashMap<String, Value>,
+}
+
+impl Event {
+    /// Creates an event about an integration itself instead of one of its devices, like
+    /// [`EventType::IntegrationDisconnectedEvent`].
+    pub fn from_integration(
+        integration: &str,
+        typ: EventType,
+        mut parameters: HashMap<String, Value>,
+    ) -> Self {
+        parameters.insert(
+            INTEGRATION_PARAMETER.to_owned(),
+            Value::String(integration.to_owned()),
+        );
+        Self {
+            typ,
+            datetime: Local::now(),
+            device: Device {
+                integration: integration.to_owned(),
+                id: "integration".to_owned(),
+                name: None,
+                typ: DeviceType::Unknown,
+                capabilities: Vec::new(),
+                area: None,
+                floor: None,
+                state: None,
+                attributes: Default::default(),
+            },
+            parameters,
+        }
+    }
+    /// Creates the event for `device` changing its state from `old_state` to `new_state`, when
+    /// one of its capabilities has an event for the change.
+    pub fn state_change(device: Device, old_state: &str, new_state: &str) -> Option<Self> {
+        let typ = device.get_state_change_event(old_state, new_state)?;
+        let old_value = DeviceState::parse(old_state).to_value();
+        let new_value = DeviceState::parse(new_state).to_value();
+        Some(Self::with_states(typ, device, old_value, new_value))
+    }
+    /// Creates an event of `typ` for `device` changing from `old_value` to `new_value`, which
+    /// is also the [`VALUE_PARAMETER`] of measurements.
+    pub fn with_states(typ: EventType, device: Device, old_value: Value, new_value: Value) -> Self {
+        let mut parameters = HashMap::from([(OLD_STATE_PARAMETER.to_owned(), old_value)]);
+        if device.has_capability(Capability::Measurement) {
+            parameters.insert(VALUE_PARAMETER.to_owned(), new_value.clone());
+        }
+        parameters.insert(NEW_STATE_PARAMETER.to_owned(), new_value);
+        Self {
+            typ,
+            datetime: Local::now(),
+            device,
+            parameters,
+        }
+    }
+    /// Splits the arrival of a person straight from another zone, like `work` to `home`, into
+    /// the departure from the old zone followed by the arrival. Other events stay as they are.
+    pub fn split_zone_change(self) -> Vec<Self> {
+        let from_zone = match (&self.typ, self.old_state()) {
+            (EventType::PersonArrivedEvent, Some(Value::String(zone))) if zone != AWAY_STATE => {
+                zone.clone()
+            }
+            _ => return vec![self],
+        };
+        let mut departure = self.clone();
+        departure.typ = EventType::PersonLeftEvent;
+        departure
+            .parameters
+            .insert(ZONE_PARAMETER.to_owned(), Value::String(from_zone));
+        vec![departure, self]
+    }
+    /// Creates the [`EventType::WebhookEvent`] of a call to the webhook `name`. The fields of a
+    /// JSON object body are also parameters of their own.
+    pub fn from_webhook(name: &str, body: serde_json::Value) -> Self {
+        let mut parameters = match &body {
+            serde_json::Value::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| (key.clone(), Value::from_json(value.clone())))
+                .collect(),
+            _ => HashMap::new(),
+        };
+        parameters.insert(WEBHOOK_PARAMETER.to_owned(), Value::String(name.to_owned()));
+        parameters.insert(BODY_PARAMETER.to_owned(), Value::from_json(body));
+        Self {
+            typ: EventType::WebhookEvent,
+            datetime: Local::now(),
+            device: Device {
+                integration: WEBHOOK_INTEGRATION_ID.to_owned(),
+                id: name.to_owned(),
+                name: None,
+                typ: DeviceType::Unknown,
+                capabilities: Vec::new(),
+                area: None,
+                floor: None,
+                state: None,
+                attributes: Default::default(),
+            },
+            parameters,
+        }
+    }
+    pub fn get_parameter(&self, name: &str) -> Option<&Value> {
+        self.parameters.get(name)
+    }
+    pub fn old_state(&self) -> Option<&Value> {
+        self.get_parameter(OLD_STATE_PARAMETER)
+    }
+    /// Returns the state after the event. Falls back to the state of the device when the
+    /// integration did not provide it as a parameter.
+    pub fn new_state(&self) -> Option<Value> {
+        self.get_parameter(NEW_STATE_PARAMETER)
+            .cloned()
+            .or_else(|| self.device.state.clone().map(Value::String))
+    }
 }
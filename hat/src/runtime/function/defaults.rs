@@ -1,10 +1,12 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 use crate::runtime::context::Trigger;
+use crate::runtime::device::{Capability, DeviceCommand, DeviceState};
+use crate::runtime::function::{FunctionCategory, FunctionParameter};
 use crate::runtime::value::time::coerce_to_time;
-use crate::runtime::value::Value;
-use crate::runtime::HatRuntime;
+use crate::runtime::value::{Value, ValueType};
 use crate::runtime::{function::Function, value::time::Time};
 use anyhow::{anyhow, bail, ensure, Context};
 use lazy_static::lazy_static;
@@ -15,7 +17,13 @@ lazy_static! {
         vec![
             Function {
                 name: "echo".to_owned(),
-                fun: |_ctx, args| {
+                description: "Escreve os valores no log do Hat",
+                category: FunctionCategory::Utility,
+                parameters: vec![
+                    FunctionParameter::variadic("values", ValueType::Any),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|_ctx, args| {
                     Box::pin(async move {
                         let args = args
                             .into_iter()
@@ -25,11 +33,15 @@ lazy_static! {
                         info!("[ECHO] {args}");
                         Ok(Value::Null)
                     })
-                },
+                }),
             },
             Function {
                 name: "get_device".to_owned(),
-                fun: (|ctx, _args| Box::pin(async move {
+                description: "Dispositivo que gerou o evento",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| Box::pin(async move {
                     match &ctx.trigger {
                         Trigger::Event(e) => Ok(e.device.full_id().into()),
                         _ => Ok(Value::Null),
@@ -38,7 +50,11 @@ lazy_static! {
             },
             Function {
                 name: "get_integration".to_owned(),
-                fun: (|ctx, _args| {
+                description: "Integração do dispositivo que gerou o evento",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
                     Box::pin(async move {
                         match &ctx.trigger {
                             Trigger::Event(e) => Ok(e.device.integration.clone().into()),
@@ -49,7 +65,11 @@ lazy_static! {
             },
             Function {
                 name: "event_date".to_owned(),
-                fun: (|ctx, _args| {
+                description: "Data e hora em que o evento aconteceu",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
                     Box::pin(async move {
                         match &ctx.trigger {
                             Trigger::Event(e) => Ok(e.datetime.to_rfc3339().into()),
@@ -60,7 +80,11 @@ lazy_static! {
             },
             Function {
                 name: "event_time".to_owned(),
-                fun: (|ctx, _args| {
+                description: "Horário em que o evento aconteceu",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::Time,
+                fun: Arc::new(|ctx, _args| {
                     Box::pin(async move {
                         match &ctx.trigger {
                             Trigger::Event(e) => Ok(Time::from(e.datetime).into()),
@@ -69,9 +93,124 @@ lazy_static! {
                     })
                 }),
             },
+            Function {
+                name: "event_param".to_owned(),
+                description: "Valor de um parâmetro do evento, como o valor lido por um sensor",
+                category: FunctionCategory::Event,
+                parameters: vec![FunctionParameter::required("name", ValueType::String)],
+                returns: ValueType::Any,
+                fun: Arc::new(|ctx, args| {
+                    Box::pin(async move {
+                        let name = match args.first() {
+                            Some(Value::String(name)) => name,
+                            _ => bail!("first argument must be the parameter name"),
+                        };
+                        match &ctx.trigger {
+                            Trigger::Event(e) => Ok(e.get_parameter(name).cloned().into()),
+                            _ => Ok(Value::Null),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "event_old_state".to_owned(),
+                description: "Estado do dispositivo antes do evento",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::Any,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        match &ctx.trigger {
+                            Trigger::Event(e) => Ok(e.old_state().cloned().into()),
+                            _ => Ok(Value::Null),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "event_new_state".to_owned(),
+                description: "Estado do dispositivo depois do evento",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::Any,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        match &ctx.trigger {
+                            Trigger::Event(e) => Ok(e.new_state().into()),
+                            _ => Ok(Value::Null),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "event_type".to_owned(),
+                description: "Tipo do evento que iniciou a automação",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        match &ctx.trigger {
+                            Trigger::Event(e) => Ok(e.typ.as_str().to_owned().into()),
+                            _ => Ok(Value::Null),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "trigger_kind".to_owned(),
+                description: "O que iniciou a execução: \"event\" ou \"schedule\"",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        match &ctx.trigger {
+                            Trigger::Event(_) => Ok(Value::String("event".into())),
+                            Trigger::Task(_) => Ok(Value::String("schedule".into())),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "task_name".to_owned(),
+                description: "Nome da rotina agendada em execução",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        match &ctx.trigger {
+                            Trigger::Task(tid) => Ok(ctx
+                                .runtime
+                                .get_task(tid)
+                                .await
+                                .map(|task| task.name.clone())
+                                .into()),
+                            _ => Ok(Value::Null),
+                        }
+                    })
+                }),
+            },
+            Function {
+                name: "automation_name".to_owned(),
+                description: "Nome da automação em execução",
+                category: FunctionCategory::Event,
+                parameters: vec![],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move { Ok(ctx.automation.clone().into()) })
+                }),
+            },
             Function {
                 name: "time".to_owned(),
-                fun: (|_ctx, args| {
+                description: "Converte um texto no formato HH:MM:SS em horário. Sem argumentos, retorna o horário atual",
+                category: FunctionCategory::Conversion,
+                parameters: vec![
+                    FunctionParameter::optional("time", ValueType::String),
+                ],
+                returns: ValueType::Time,
+                fun: Arc::new(|_ctx, args| {
                     Box::pin(async move {
                         let arg = args.first();
                         Ok(Value::Time(coerce_to_time(arg)?))
@@ -80,7 +219,13 @@ lazy_static! {
             },
             Function {
                 name: "turn_off_device".to_owned(),
-                fun: (|ctx, args| {
+                description: "Desliga o dispositivo",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         let full_device_id = {
                             let first = args
@@ -96,27 +241,8 @@ lazy_static! {
                         let runtime = Arc::clone(&ctx.runtime);
 
                         tokio::spawn(async move {
-                            let (integration, device_id) =
-                                HatRuntime::parse_full_device_id(&full_device_id);
-
-                            if let Some(integration) = integration {
-                                match runtime.get_integration(integration).await {
-                                    Some(integration) => {
-                                        if let Err(e) = integration.turn_off_device(device_id).await
-                                        {
-                                            error!(
-                                                "failed to turn off device {full_device_id}: {e:?}"
-                                            );
-                                        }
-                                    }
-                                    None => {
-                                        error!(
-                                            "failed to find integration of device {full_device_id}"
-                                        );
-                                    }
-                                }
-                            } else {
-                                todo!()
+                            if let Err(e) = runtime.execute(&full_device_id, DeviceCommand::TurnOff).await {
+                                error!("failed to turn off device {full_device_id}: {e:?}");
                             }
                         });
 
@@ -126,7 +252,13 @@ lazy_static! {
             },
             Function {
                 name: "turn_on_device".to_owned(),
-                fun: (|ctx, args| {
+                description: "Liga o dispositivo",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         let full_device_id = {
                             let first = args
@@ -142,27 +274,8 @@ lazy_static! {
                         let runtime = Arc::clone(&ctx.runtime);
 
                         tokio::spawn(async move {
-                            let (integration, device_id) =
-                                HatRuntime::parse_full_device_id(&full_device_id);
-
-                            if let Some(integration) = integration {
-                                match runtime.get_integration(integration).await {
-                                    Some(integration) => {
-                                        if let Err(e) = integration.turn_on_device(device_id).await
-                                        {
-                                            error!(
-                                                "failed to turn on device {full_device_id}: {e:?}"
-                                            );
-                                        }
-                                    }
-                                    None => {
-                                        error!(
-                                            "failed to find integration of device {full_device_id}"
-                                        );
-                                    }
-                                }
-                            } else {
-                                todo!()
+                            if let Err(e) = runtime.execute(&full_device_id, DeviceCommand::TurnOn).await {
+                                error!("failed to turn on device {full_device_id}: {e:?}");
                             }
                         });
 
@@ -172,7 +285,14 @@ lazy_static! {
             },
             Function {
                 name: "set_light_color".to_owned(),
-                fun: (|ctx, args| {
+                description: "Configura a cor de uma lâmpada no formato #RRGGBB",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                    FunctionParameter::required("color", ValueType::String),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         let full_device_id = {
                             let first = args.first().ok_or(anyhow!("missing device_id"))?;
@@ -203,26 +323,8 @@ lazy_static! {
                         let runtime = Arc::clone(&ctx.runtime);
 
                         tokio::spawn(async move {
-                            let (integration, device_id) =
-                                HatRuntime::parse_full_device_id(&full_device_id);
-
-                            if let Some(integration) = integration {
-                                match runtime.get_integration(integration).await {
-                                    Some(integration) => {
-                                        if let Err(e) =
-                                            integration.set_light_color_rgb(device_id, color).await
-                                        {
-                                            error!("failed to set color on device {full_device_id}: {e:?}");
-                                        }
-                                    }
-                                    None => {
-                                        error!(
-                                            "failed to find integration of device {full_device_id}"
-                                        );
-                                    }
-                                }
-                            } else {
-                                todo!()
+                            if let Err(e) = runtime.execute(&full_device_id, DeviceCommand::SetColorRgb(color)).await {
+                                error!("failed to set color on device {full_device_id}: {e:?}");
                             }
                         });
 
@@ -232,7 +334,14 @@ lazy_static! {
             },
             Function {
                 name: "set_light_brightness".to_owned(),
-                fun: (|ctx, args| {
+                description: "Configura o brilho de uma lâmpada (0 a 255)",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                    FunctionParameter::required("brightness", ValueType::Number),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         let full_device_id = {
                             let first = args.first().ok_or(anyhow!("missing device_id"))?;
@@ -254,27 +363,8 @@ lazy_static! {
                         let runtime = Arc::clone(&ctx.runtime);
 
                         tokio::spawn(async move {
-                            let (integration, device_id) =
-                                HatRuntime::parse_full_device_id(&full_device_id);
-
-                            if let Some(integration) = integration {
-                                match runtime.get_integration(integration).await {
-                                    Some(integration) => {
-                                        if let Err(e) = integration
-                                            .set_light_brightness(device_id, brightness)
-                                            .await
-                                        {
-                                            error!("failed to set brightness on device {full_device_id}: {e:?}");
-                                        }
-                                    }
-                                    None => {
-                                        error!(
-                                            "failed to find integration of device {full_device_id}"
-                                        );
-                                    }
-                                }
-                            } else {
-                                todo!()
+                            if let Err(e) = runtime.execute(&full_device_id, DeviceCommand::SetBrightness(brightness)).await {
+                                error!("failed to set brightness on device {full_device_id}: {e:?}");
                             }
                         });
 
@@ -282,13 +372,61 @@ lazy_static! {
                     })
                 }),
             },
+            device_command_function(
+                "set_temperature",
+                "Configura a temperatura alvo de um termostato",
+                vec![FunctionParameter::required("temperature", ValueType::Number)],
+                |args| Ok(DeviceCommand::SetTargetTemperature(number_arg(args, "temperature")?)),
+            ),
+            device_command_function(
+                "open_cover",
+                "Abre uma cortina ou persiana",
+                vec![],
+                |_| Ok(DeviceCommand::Open),
+            ),
+            device_command_function(
+                "close_cover",
+                "Fecha uma cortina ou persiana",
+                vec![],
+                |_| Ok(DeviceCommand::Close),
+            ),
+            device_command_function(
+                "set_cover_position",
+                "Move uma cortina ou persiana para uma posição (0 fechada a 100 aberta)",
+                vec![FunctionParameter::required("position", ValueType::Number)],
+                |args| Ok(DeviceCommand::SetPosition(percent_arg(args, "position")?)),
+            ),
+            device_command_function(
+                "lock",
+                "Tranca uma fechadura",
+                vec![],
+                |_| Ok(DeviceCommand::Lock),
+            ),
+            device_command_function(
+                "unlock",
+                "Destranca uma fechadura",
+                vec![],
+                |_| Ok(DeviceCommand::Unlock),
+            ),
+            device_command_function(
+                "set_fan_speed",
+                "Configura a velocidade de um ventilador (0 a 100%)",
+                vec![FunctionParameter::required("percentage", ValueType::Number)],
+                |args| Ok(DeviceCommand::SetFanSpeed(percent_arg(args, "percentage")?)),
+            ),
             Function {
                 name: "is_device_on".to_owned(),
-                fun: (|ctx, args| {
+                description: "Verifica se o dispositivo está ligado",
+                category: FunctionCategory::Condition,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         if let Some(Value::String(arg)) = args.first() {
                             if let Some(dev) = ctx.runtime.get_device(arg).await? {
-                                if matches!(dev.state.as_deref(), Some("on")) {
+                                if dev.typed_state() == DeviceState::On {
                                     Ok(Value::Boolean(true))
                                 } else {
                                     Ok(Value::Boolean(false))
@@ -302,13 +440,106 @@ lazy_static! {
                     })
                 }),
             },
+            Function {
+                name: "is_home".to_owned(),
+                description: "Verifica se uma pessoa está em casa",
+                category: FunctionCategory::Condition,
+                parameters: vec![
+                    FunctionParameter::required("person", ValueType::String),
+                ],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, args| {
+                    Box::pin(async move {
+                        let Some(Value::String(arg)) = args.first() else {
+                            bail!("first argument must be the person device id")
+                        };
+                        let dev = ctx.runtime.get_device(arg).await?.with_context(|| format!("person {arg} not found!"))?;
+                        ensure!(dev.has_capability(Capability::Presence), "device {arg} does not track a person");
+                        Ok(Value::Boolean(dev.is_home()))
+                    })
+                }),
+            },
+            Function {
+                name: "anyone_home".to_owned(),
+                description: "Verifica se pelo menos uma pessoa está em casa",
+                category: FunctionCategory::Condition,
+                parameters: vec![],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        let people = ctx.runtime.list_people().await;
+                        Ok(Value::Boolean(people.iter().any(|dev| dev.is_home())))
+                    })
+                }),
+            },
+            Function {
+                name: "everyone_away".to_owned(),
+                description: "Verifica se todas as pessoas estão fora de casa",
+                category: FunctionCategory::Condition,
+                parameters: vec![],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, _args| {
+                    Box::pin(async move {
+                        let people = ctx.runtime.list_people().await;
+                        Ok(Value::Boolean(!people.iter().any(|dev| dev.is_home())))
+                    })
+                }),
+            },
+            Function {
+                name: "devices_in_area".to_owned(),
+                description: "Lista os dispositivos de um cômodo",
+                category: FunctionCategory::Device,
+                parameters: vec![
+                    FunctionParameter::required("area", ValueType::String),
+                ],
+                returns: ValueType::List,
+                fun: Arc::new(|ctx, args| {
+                    Box::pin(async move {
+                        let Some(Value::String(area)) = args.first() else {
+                            bail!("first argument must be the area name")
+                        };
+                        let devices = ctx.runtime.list_devices().await?;
+                        Ok(Value::List(
+                            devices
+                                .iter()
+                                .filter(|dev| dev.is_in_area(area))
+                                .map(|dev| Value::String(dev.full_id()))
+                                .collect(),
+                        ))
+                    })
+                }),
+            },
+            Function {
+                name: "area_of".to_owned(),
+                description: "Cômodo em que o dispositivo está",
+                category: FunctionCategory::Device,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, args| {
+                    Box::pin(async move {
+                        let Some(Value::String(arg)) = args.first() else {
+                            bail!("first argument must be the device id")
+                        };
+                        let dev = ctx.runtime.get_device(arg).await?.with_context(|| format!("device {arg} not found!"))?;
+                        Ok(dev.area.map(Value::String).unwrap_or(Value::Null))
+                    })
+                }),
+            },
             Function {
                 name: "is_device_off".to_owned(),
-                fun: (|ctx, args| {
+                description: "Verifica se o dispositivo está desligado",
+                category: FunctionCategory::Condition,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         if let Some(Value::String(arg)) = args.first() {
                             if let Some(dev) = ctx.runtime.get_device(arg).await? {
-                                if matches!(dev.state.as_deref(), Some("off")) {
+                                if dev.typed_state() == DeviceState::Off {
                                     Ok(Value::Boolean(true))
                                 } else {
                                     Ok(Value::Boolean(false))
@@ -324,7 +555,13 @@ lazy_static! {
             },
             Function {
                 name: "wait".to_owned(),
-                fun: (|_ctx, args| {
+                description: "Espera alguns segundos antes de continuar",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("seconds", ValueType::Number),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|_ctx, args| {
                     Box::pin(async move {
                         if let Some(Value::Number(seconds)) = args.first() {
                             let millis = (seconds * 1000.0) as u64;
@@ -339,7 +576,13 @@ lazy_static! {
             },
             Function {
                 name: "get_device_state".to_owned(),
-                fun: (|ctx, args| {
+                description: "Estado atual do dispositivo",
+                category: FunctionCategory::Device,
+                parameters: vec![
+                    FunctionParameter::required("device", ValueType::String),
+                ],
+                returns: ValueType::String,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         if let Some(Value::String(arg)) = args.first() {
                             if let Some(dev) = ctx.runtime.get_device(arg).await? {
@@ -358,7 +601,13 @@ lazy_static! {
             },
             Function {
                 name: "number".to_owned(),
-                fun: (|_, args| {
+                description: "Converte um valor em número",
+                category: FunctionCategory::Conversion,
+                parameters: vec![
+                    FunctionParameter::required("value", ValueType::Any),
+                ],
+                returns: ValueType::Number,
+                fun: Arc::new(|_, args| {
                     Box::pin(async move {
                         if let Some(arg) = args.first() {
                             match arg {
@@ -366,6 +615,8 @@ lazy_static! {
                                 Value::Boolean(arg) => Ok(Value::Number(if *arg { 1f64 } else { 0f64 })),
                                 Value::Number(n) => Ok(Value::Number(*n)),
                                 Value::Time(_) => bail!("cannot convert time into a number"),
+                                Value::List(_) => bail!("cannot convert a list into a number"),
+                                Value::Map(_) => bail!("cannot convert a map into a number"),
                                 Value::Null => bail!("cannot convert null into a number"),
                             }
                         } else {
@@ -376,7 +627,13 @@ lazy_static! {
             },
             Function {
                 name: "string".to_owned(),
-                fun: (|_, args| {
+                description: "Converte um valor em texto",
+                category: FunctionCategory::Conversion,
+                parameters: vec![
+                    FunctionParameter::required("value", ValueType::Any),
+                ],
+                returns: ValueType::String,
+                fun: Arc::new(|_, args| {
                     Box::pin(async move {
                         if let Some(arg) = args.into_iter().next() {
                             match arg {
@@ -384,6 +641,9 @@ lazy_static! {
                                 Value::Boolean(arg) => Ok(Value::String(arg.to_string())),
                                 Value::Number(n) => Ok(Value::String(n.to_string())),
                                 Value::Time(t) => Ok(Value::String(t.to_string())),
+                                value @ (Value::List(_) | Value::Map(_)) => {
+                                    Ok(Value::String(value.to_string()))
+                                }
                                 Value::Null => Ok(Value::String("null".into())),
                             }
                         } else {
@@ -392,9 +652,50 @@ lazy_static! {
                     })
                 }),
             },
+            Function {
+                name: "get".to_owned(),
+                description: "Valor dentro de um mapa ou lista, seguindo as chaves e posições dadas",
+                category: FunctionCategory::Conversion,
+                parameters: vec![
+                    FunctionParameter::required("value", ValueType::Any),
+                    FunctionParameter::variadic("keys", ValueType::Any),
+                ],
+                returns: ValueType::Any,
+                fun: Arc::new(|_, args| {
+                    Box::pin(async move {
+                        let mut args = args.into_iter();
+                        let mut value = args.next().context("first argument is missing")?;
+                        // Missing keys and positions give null, like missing JSON fields
+                        for key in args {
+                            value = match (value, key) {
+                                (Value::Map(mut map), Value::String(key)) => {
+                                    map.remove(&key).unwrap_or(Value::Null)
+                                }
+                                (Value::List(list), Value::Number(i)) if i >= 0.0 && i.fract() == 0.0 => {
+                                    list.into_iter().nth(i as usize).unwrap_or(Value::Null)
+                                }
+                                (Value::Null, _) => Value::Null,
+                                (Value::Map(_), key) => bail!("maps are indexed by text, not {key}"),
+                                (Value::List(_), key) => {
+                                    bail!("lists are indexed by positions from 0, not {key}")
+                                }
+                                (value, _) => bail!("cannot get a value inside {value}"),
+                            };
+                        }
+                        Ok(value)
+                    })
+                }),
+            },
             Function {
                 name: "event_time_between".to_owned(),
-                fun: (|ctx, args| {
+                description: "Verifica se o evento aconteceu entre dois horários",
+                category: FunctionCategory::Condition,
+                parameters: vec![
+                    FunctionParameter::required("start", ValueType::String),
+                    FunctionParameter::required("end", ValueType::String),
+                ],
+                returns: ValueType::Boolean,
+                fun: Arc::new(|ctx, args| {
                     Box::pin(async move {
                         let event = match &ctx.trigger {
                             Trigger::Event(e) => e,
@@ -425,10 +726,42 @@ lazy_static! {
                     })
                 }),
             },
+            Function {
+                name: "emit".to_owned(),
+                description: "Dispara um evento personalizado, com dados opcionais",
+                category: FunctionCategory::Action,
+                parameters: vec![
+                    FunctionParameter::required("event", ValueType::String),
+                    FunctionParameter::optional("payload", ValueType::Map),
+                ],
+                returns: ValueType::Null,
+                fun: Arc::new(|ctx, args| {
+                    Box::pin(async move {
+                        let mut args = args.into_iter();
+                        let name = match args.next() {
+                            Some(Value::String(name)) => name,
+                            _ => bail!("first argument must be the event name"),
+                        };
+                        let parameters = match args.next() {
+                            Some(Value::Map(payload)) => payload.into_iter().collect(),
+                            Some(Value::Null) | None => HashMap::new(),
+                            Some(_) => bail!("event payload must be a map"),
+                        };
+                        ctx.runtime
+                            .emit_event_from(&ctx.trigger, &name, parameters)
+                            .await?;
+                        Ok(Value::Null)
+                    })
+                }),
+            },
             // This function simulates a call to a service
             Function {
                 name: "benchmark_simulation".to_owned(),
-                fun: (|ctx, args| {
+                description: "Simula uma chamada de serviço de 100ms",
+                category: FunctionCategory::Utility,
+                parameters: vec![],
+                returns: ValueType::Null,
+                fun: Arc::new(|_ctx, _args| {
                     Box::pin(async move {
                         tokio::time::sleep(Duration::from_millis(100)).await;
                         Ok(Value::Null)
@@ -438,3 +771,62 @@ lazy_static! {
         ]
     };
 }
+
+/// Builds an action function that runs a [`DeviceCommand`] on the device given as the first
+/// argument. `make_command` receives the remaining arguments.
+fn device_command_function(
+    name: &str,
+    description: &'static str,
+    parameters: Vec<FunctionParameter>,
+    make_command: fn(&[Value]) -> anyhow::Result<DeviceCommand>,
+) -> Function {
+    let mut all_parameters = vec![FunctionParameter::required("device", ValueType::String)];
+    all_parameters.extend(parameters);
+    let name = name.to_owned();
+    let function_name = name.clone();
+    Function {
+        name,
+        description,
+        category: FunctionCategory::Action,
+        parameters: all_parameters,
+        returns: ValueType::Null,
+        fun: Arc::new(move |ctx, args| {
+            let function_name = function_name.clone();
+            Box::pin(async move {
+                let full_device_id = match args.first() {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(_) => bail!("device id must be a string"),
+                    None => bail!("missing device_id"),
+                };
+                let command = make_command(&args[1..])?;
+
+                let runtime = Arc::clone(&ctx.runtime);
+
+                tokio::spawn(async move {
+                    if let Err(e) = runtime.execute(&full_device_id, command).await {
+                        error!("failed to {function_name} on device {full_device_id}: {e:?}");
+                    }
+                });
+
+                Ok(Value::Null)
+            })
+        }),
+    }
+}
+
+fn number_arg(args: &[Value], name: &str) -> anyhow::Result<f64> {
+    match args.first() {
+        Some(Value::Number(number)) => Ok(*number),
+        Some(_) => bail!("{name} must be a number"),
+        None => bail!("missing {name} argument"),
+    }
+}
+
+fn percent_arg(args: &[Value], name: &str) -> anyhow::Result<u8> {
+    let number = number_arg(args, name)?;
+    ensure!(
+        (0.0..=100.0).contains(&number),
+        "{name} must be between 0 and 100"
+    );
+    Ok(number.round() as u8)
+}
@@ -0,0 +1,215 @@
+//! Functions that call external services: `http_get`, `http_post` and `webhook`. They return
+//! the response as a map with `status`, `ok`, `body` and `json`, the body parsed as JSON when
+//! it is JSON. Redirects are returned instead of followed, and bodies larger than
+//! [`MAX_BODY_SIZE`] are refused.
+
+use crate::runtime::function::{Function, FunctionCategory, FunctionParameter};
+use crate::runtime::value::{Value, ValueType};
+use anyhow::{bail, ensure, Context, Result};
+use lazy_static::lazy_static;
+use reqwest::{Method, RequestBuilder};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Largest response body read, so a service cannot fill the memory of Hat
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+lazy_static! {
+    /// Redirects are not followed, as they could lead to URLs that are not allowed
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+}
+
+/// What automations may call with `http_get`, `http_post` and `webhook`.
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// URL prefixes, like `https://api.example.com/v1/`, that `http_get` and `http_post` may
+    /// call. Nothing may be called when empty.
+    pub allowed_urls: Vec<String>,
+    /// Longest time to wait for a response
+    pub timeout: Duration,
+    /// Requests called by name with `webhook`. Their URLs do not need to be allowed.
+    pub webhooks: BTreeMap<String, Webhook>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            allowed_urls: Vec::new(),
+            timeout: Duration::from_secs(10),
+            webhooks: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub url: String,
+    pub method: Method,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl HttpSettings {
+    /// Whether `url` starts with one of the allowed URLs. Scheme, host and port must be the
+    /// same, and the path must continue the allowed path.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        self.allowed_urls.iter().any(|allowed| {
+            let Ok(allowed) = Url::parse(allowed) else {
+                return false;
+            };
+            let path = allowed.path();
+            url.scheme() == allowed.scheme()
+                && url.host_str() == allowed.host_str()
+                && url.port_or_known_default() == allowed.port_or_known_default()
+                && url.path().strip_prefix(path).is_some_and(|rest| {
+                    path.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+                })
+        })
+    }
+}
+
+pub fn functions() -> Vec<Function> {
+    vec![
+        Function {
+            name: "http_get".to_owned(),
+            description: "Faz uma requisição GET a um serviço externo e retorna a resposta",
+            category: FunctionCategory::Action,
+            parameters: vec![
+                FunctionParameter::required("url", ValueType::String),
+                FunctionParameter::optional("headers", ValueType::Map),
+            ],
+            returns: ValueType::Map,
+            fun: Arc::new(|ctx, args| {
+                Box::pin(async move {
+                    let settings = &ctx.runtime.settings().http;
+                    let url = allowed_url(settings, args.first())?;
+                    let request = CLIENT.get(url);
+                    let request = with_headers(request, args.get(1))?;
+                    send(request, settings.timeout).await
+                })
+            }),
+        },
+        Function {
+            name: "http_post".to_owned(),
+            description: "Faz uma requisição POST a um serviço externo e retorna a resposta. \
+                Listas e mapas são enviados como JSON",
+            category: FunctionCategory::Action,
+            parameters: vec![
+                FunctionParameter::required("url", ValueType::String),
+                FunctionParameter::optional("body", ValueType::Any),
+                FunctionParameter::optional("headers", ValueType::Map),
+            ],
+            returns: ValueType::Map,
+            fun: Arc::new(|ctx, args| {
+                Box::pin(async move {
+                    let settings = &ctx.runtime.settings().http;
+                    let url = allowed_url(settings, args.first())?;
+                    let request = with_body(CLIENT.post(url), args.get(1));
+                    let request = with_headers(request, args.get(2))?;
+                    send(request, settings.timeout).await
+                })
+            }),
+        },
+        Function {
+            name: "webhook".to_owned(),
+            description: "Chama um webhook configurado, enviando os dados como JSON",
+            category: FunctionCategory::Action,
+            parameters: vec![
+                FunctionParameter::required("name", ValueType::String),
+                FunctionParameter::optional("payload", ValueType::Any),
+            ],
+            returns: ValueType::Map,
+            fun: Arc::new(|ctx, args| {
+                Box::pin(async move {
+                    let settings = &ctx.runtime.settings().http;
+                    let name = match args.first() {
+                        Some(Value::String(name)) => name,
+                        _ => bail!("first argument must be the name of the webhook"),
+                    };
+                    let webhook = settings
+                        .webhooks
+                        .get(name)
+                        .with_context(|| format!("webhook {name:?} is not configured"))?;
+                    let mut request = CLIENT.request(webhook.method.clone(), &webhook.url);
+                    if let Some(payload) = args.get(1).filter(|p| **p != Value::Null) {
+                        request = request.json(&payload.to_json());
+                    }
+                    for (name, value) in &webhook.headers {
+                        request = request.header(name, value);
+                    }
+                    send(request, settings.timeout).await
+                })
+            }),
+        },
+    ]
+}
+
+fn allowed_url(settings: &HttpSettings, url: Option<&Value>) -> Result<Url> {
+    let Some(Value::String(url)) = url else {
+        bail!("first argument must be the URL");
+    };
+    let url = Url::parse(url).with_context(|| format!("invalid URL {url:?}"))?;
+    ensure!(
+        settings.is_allowed(&url),
+        "{url} is not allowed, add it to http.allowed_urls"
+    );
+    Ok(url)
+}
+
+fn with_headers(mut request: RequestBuilder, headers: Option<&Value>) -> Result<RequestBuilder> {
+    match headers {
+        Some(Value::Map(headers)) => {
+            for (name, value) in headers {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                request = request.header(name, value);
+            }
+        }
+        Some(Value::Null) | None => {}
+        Some(_) => bail!("headers must be a map"),
+    }
+    Ok(request)
+}
+
+fn with_body(request: RequestBuilder, body: Option<&Value>) -> RequestBuilder {
+    match body {
+        Some(Value::String(body)) => request.body(body.clone()),
+        Some(Value::Null) | None => request,
+        Some(body) => request.json(&body.to_json()),
+    }
+}
+
+async fn send(request: RequestBuilder, timeout: Duration) -> Result<Value> {
+    let mut response = request
+        .timeout(timeout)
+        .send()
+        .await
+        .context("request failed")?;
+    let status = response.status();
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("failed to read the response")?
+    {
+        ensure!(
+            body.len() + chunk.len() <= MAX_BODY_SIZE,
+            "the response is larger than {MAX_BODY_SIZE} bytes"
+        );
+        body.extend_from_slice(&chunk);
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+    let json = serde_json::from_str(&body).map_or(Value::Null, Value::from_json);
+    Ok(Value::Map(BTreeMap::from([
+        ("status".to_owned(), Value::Number(status.as_u16().into())),
+        ("ok".to_owned(), Value::Boolean(status.is_success())),
+        ("body".to_owned(), Value::String(body)),
+        ("json".to_owned(), json),
+    ])))
+}
@@ -1,20 +1,98 @@
 pub mod defaults;
+pub mod http;
 
-use std::{fmt::Display, future::Future, pin::Pin, sync::Arc};
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
 
 use crate::runtime::context::ExpressionContext;
 use crate::runtime::parser::expression::Expression;
-use crate::runtime::value::Value;
+use crate::runtime::value::{Value, ValueType};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 
-pub(crate) type NativeFunctionType =
-    fn(Arc<ExpressionContext>, Vec<Value>) -> Pin<Box<dyn Future<Output = Result<Value>> + Send>>;
+pub type FunctionFuture = Pin<Box<dyn Future<Output = Result<Value>> + Send>>;
 
-#[derive(Debug, Clone)]
+/// The implementation of a function. It is reference counted so integrations can register
+/// closures that capture their own state.
+pub type FunctionBody =
+    Arc<dyn Fn(Arc<ExpressionContext>, Vec<Value>) -> FunctionFuture + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FunctionCategory {
+    /// Functions that change something outside of the runtime, like turning on a device
+    Action,
+    /// Functions that answer a yes/no question, meant to be used in conditions
+    Condition,
+    /// Functions that read information from the event that triggered the automation
+    Event,
+    /// Functions that read information about devices
+    Device,
+    /// Functions that convert values between types
+    Conversion,
+    Utility,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionParameter {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub typ: ValueType,
+    pub optional: bool,
+    /// A variadic parameter accepts any number of arguments and must be the last one
+    pub variadic: bool,
+}
+
+impl FunctionParameter {
+    pub const fn required(name: &'static str, typ: ValueType) -> Self {
+        Self {
+            name,
+            typ,
+            optional: false,
+            variadic: false,
+        }
+    }
+    pub const fn optional(name: &'static str, typ: ValueType) -> Self {
+        Self {
+            name,
+            typ,
+            optional: true,
+            variadic: false,
+        }
+    }
+    pub const fn variadic(name: &'static str, typ: ValueType) -> Self {
+        Self {
+            name,
+            typ,
+            optional: true,
+            variadic: true,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Function {
     pub name: String,
-    pub fun: NativeFunctionType,
+    pub description: &'static str,
+    pub category: FunctionCategory,
+    pub parameters: Vec<FunctionParameter>,
+    pub returns: ValueType,
+    pub fun: FunctionBody,
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("category", &self.category)
+            .field("parameters", &self.parameters)
+            .field("returns", &self.returns)
+            .finish()
+    }
 }
 
 impl Function {
@@ -48,12 +126,8 @@ impl FunctionCall {
                 arguments.push(result);
             }
 
-            let fun = ctx.get_function(&self.name);
-
-            match fun {
-                Some(fun) => fun.call(ctx, arguments).await,
-                None => bail!("function {} not found!", self.name),
-            }
+            let fun = ctx.runtime.find_function(&self.name)?;
+            fun.call(ctx, arguments).await
         })
     }
 }
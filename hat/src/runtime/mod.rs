@@ -7,23 +7,35 @@ pub mod parser;
 pub mod scheduler;
 pub mod value;
 
-use self::event::Event;
+use self::event::{Event, EventDescriptor, EventType};
 use crate::integrations::clock::ClockIntegration;
+use crate::integrations::helpers::HelperIntegration;
 use crate::integrations::Integration;
+use crate::notifiers::Notifier;
 use crate::runtime::automation::Automation;
 use crate::runtime::context::ExpressionContext;
+use crate::runtime::function::http::HttpSettings;
 use crate::runtime::function::Function;
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
+use chrono::Local;
 use context::Trigger;
-use device::Device;
-use futures_util::FutureExt;
+use device::{Device, DeviceCommand, DeviceType};
 use scheduler::{ScheduleTask, Scheduler, TaskID};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
-use tokio::sync::{mpsc, oneshot, Mutex as TokioMutex, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex as TokioMutex, RwLock};
 use tokio::task::JoinHandle;
 use tracing::{error, trace, warn};
+use value::Value;
+
+/// Longest chain of events emitted by automations triggered by emitted events
+pub const MAX_EMIT_DEPTH: u64 = 8;
+/// ID of the device of emitted events
+const EMIT_DEVICE_ID: &str = "emit";
+/// Attribute of emitted events with their position in the chain of emitted events
+const EMIT_DEPTH_ATTRIBUTE: &str = "depth";
 
 #[derive(Error, Debug)]
 pub enum RuntimeError {
@@ -45,6 +57,38 @@ pub enum RuntimeError {
     },
     #[error("Scheduler error: {inner}")]
     SchedulerError { inner: anyhow::Error },
+    #[error("Helper error: {inner}")]
+    HelperError { inner: anyhow::Error },
+}
+
+/// Tunables of a [`HatRuntime`].
+#[derive(Debug, Clone)]
+pub struct RuntimeSettings {
+    /// Capacity of the queue of events and scheduled tasks waiting to be executed. Integrations
+    /// wait for space when it is full.
+    pub event_channel_size: usize,
+    /// Capacity of the queue of events of each integration, waiting to join the queue of
+    /// `event_channel_size`. Integrations drop the events that do not fit.
+    pub integration_channel_size: usize,
+    /// File where helpers and their values are saved. Without it they are kept in memory. The
+    /// runtime fails to start if it exists but cannot be read.
+    pub helpers_file: Option<PathBuf>,
+    /// What automations may call with `http_get`, `http_post` and `webhook`
+    pub http: HttpSettings,
+    /// Targets of `notify`, by name
+    pub notifiers: BTreeMap<String, Arc<dyn Notifier>>,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            event_channel_size: 128,
+            integration_channel_size: 128,
+            helpers_file: None,
+            http: HttpSettings::default(),
+            notifiers: BTreeMap::new(),
+        }
+    }
 }
 
 type IntegrationAndStopChannel = (Arc<dyn Integration>, oneshot::Sender<()>);
@@ -57,11 +101,28 @@ pub struct HatRuntime {
     executor_channel: mpsc::Sender<ExecutorMessage>,
     executor_handle: tokio::sync::Mutex<Option<JoinHandle<()>>>,
     functions: std::sync::RwLock<HashMap<String, Arc<Function>>>,
+    integration_events: std::sync::RwLock<HashMap<String, Vec<EventDescriptor>>>,
+    custom_events: std::sync::RwLock<HashSet<String>>,
+    /// Alternative IDs of integrations, mapped to their real IDs
+    integration_aliases: std::sync::RwLock<HashMap<String, String>>,
+    /// Every dispatched event, for observers like `GET /event_stream`
+    events: broadcast::Sender<Event>,
+    helpers: HelperIntegration,
+    settings: RuntimeSettings,
 }
 
 impl HatRuntime {
     pub async fn new() -> Arc<Self> {
-        let (tx, mut rx) = mpsc::channel(128);
+        Self::with_settings(RuntimeSettings::default())
+            .await
+            .expect("default settings have no helpers file")
+    }
+
+    /// Fails if the helpers file cannot be read.
+    pub async fn with_settings(settings: RuntimeSettings) -> Result<Arc<Self>> {
+        let helpers = HelperIntegration::new(settings.helpers_file.clone())?;
+        let (tx, mut rx) = mpsc::channel(settings.event_channel_size);
+        let (events, _) = broadcast::channel(settings.event_channel_size);
 
         let runtime = Arc::new(Self {
             scheduler: Scheduler::new(tx.clone()).await.unwrap(),
@@ -71,6 +132,12 @@ impl HatRuntime {
             executor_channel: tx,
             executor_handle: Default::default(),
             functions: Default::default(),
+            integration_events: Default::default(),
+            custom_events: Default::default(),
+            integration_aliases: Default::default(),
+            events,
+            helpers,
+            settings,
         });
 
         runtime.register_default_functions();
@@ -81,12 +148,14 @@ impl HatRuntime {
                 let rt = Arc::clone(&runtime_clone);
                 match message {
                     ExecutorMessage::Event(event) => {
+                        rt.events.send(event.clone()).ok();
                         tokio::spawn(async move {
                             let filtered_automations = {
+                                let aliases = rt.integration_aliases.read().unwrap();
                                 let automations = rt.automations.lock().unwrap();
                                 automations
                                     .values()
-                                    .filter(|a| a.should_be_triggered_by(&event))
+                                    .filter(|a| a.should_be_triggered_by(&event, &aliases))
                                     .map(Arc::clone)
                                     .collect::<Vec<_>>()
                             };
@@ -95,6 +164,7 @@ impl HatRuntime {
                                 let context = Arc::new(ExpressionContext {
                                     trigger: Trigger::Event(event.clone()),
                                     runtime: Arc::clone(&rt),
+                                    automation: Some(automation.name.clone()),
                                 });
                                 if let Err(e) = automation.trigger(context).await {
                                     error!("Failed to run automation {}: {e:?}", automation.name);
@@ -107,6 +177,7 @@ impl HatRuntime {
                             let ctx = Arc::new(ExpressionContext {
                                 trigger: Trigger::Task(task_id),
                                 runtime: Arc::clone(&rt),
+                                automation: None,
                             });
                             if let Err(e) = task.execute(ctx).await {
                                 error!("Failed to run scheduled task {}: {e:?}", task.name);
@@ -122,13 +193,37 @@ impl HatRuntime {
             *executor_handle = Some(handle);
         }
 
-        runtime.integrate(ClockIntegration).await;
+        runtime.integrate(ClockIntegration).await.unwrap();
+        runtime.integrate(runtime.helpers.clone()).await.unwrap();
+
+        Ok(runtime)
+    }
+
+    pub fn settings(&self) -> &RuntimeSettings {
+        &self.settings
+    }
 
-        runtime
+    /// The helpers owned by this runtime, also integrated as [`HelperIntegration`].
+    pub fn helpers(&self) -> &HelperIntegration {
+        &self.helpers
     }
 
-    pub async fn integrate<T: 'static + Integration>(&self, integration: T) {
-        let mut integration_events = integration.subscribe();
+    /// Receives every event dispatched from now on. Slow receivers miss the oldest events
+    /// once more than `event_channel_size` are waiting.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Starts receiving events from the integration and registers what it contributes to the
+    /// runtime. Fails if another integration already has its ID.
+    pub async fn integrate<T: 'static + Integration>(&self, integration: T) -> Result<()> {
+        let mut integrations = self.integrations.write().await;
+        ensure!(
+            !integrations.contains_key(integration.get_id()),
+            "an integration with the ID {} already exists",
+            integration.get_id()
+        );
+        let mut integration_events = integration.subscribe(self.settings.integration_channel_size);
         let executor_channel = self.executor_channel.clone();
         let (stop_signal_tx, mut stop_signal_rx) = oneshot::channel::<()>();
         let integration_id = integration.get_id().to_owned();
@@ -154,12 +249,57 @@ impl HatRuntime {
                 }
             }
         });
+        let integration_id = integration.get_id().to_owned();
+        for mut fun in integration.get_functions() {
+            let name = fun.name.clone();
+            fun.name = format!("{integration_id}.{name}");
+            self.register_function(fun);
+            let functions = self.functions.read().unwrap();
+            let candidates = integration_functions(&functions, &name);
+            if candidates.len() > 1 {
+                warn!(
+                    "Function {name} is now provided by several integrations, so calls without \
+                    the integration fail. Call one of {} instead",
+                    function_names(&candidates)
+                );
+            }
+        }
+        {
+            let mut events = self.integration_events.write().unwrap();
+            events.insert(integration_id, integration.get_event_descriptors());
+        }
         let integration_arc: Arc<dyn Integration> = Arc::new(integration);
-        let mut integrations = self.integrations.write().await;
         integrations.insert(
             integration_arc.get_id().to_owned(),
             (integration_arc, stop_signal_tx),
         );
+        Ok(())
+    }
+
+    /// Stops receiving events from the integration and unregisters everything it contributed
+    /// to the runtime. Returns the removed integration, if it existed.
+    pub async fn remove_integration(&self, integration_id: &str) -> Option<Arc<dyn Integration>> {
+        let (integration, stop_signal) = {
+            let mut integrations = self.integrations.write().await;
+            integrations.remove(integration_id)?
+        };
+        let _ = stop_signal.send(());
+
+        {
+            let prefix = format!("{integration_id}.");
+            let mut functions = self.functions.write().unwrap();
+            functions.retain(|name, _| !name.starts_with(&prefix));
+        }
+        {
+            let mut events = self.integration_events.write().unwrap();
+            events.remove(integration_id);
+        }
+        {
+            let mut aliases = self.integration_aliases.write().unwrap();
+            aliases.retain(|_, id| id != integration_id);
+        }
+
+        Some(integration)
     }
 
     pub async fn dispatch_event(&self, event: Event) -> Result<()> {
@@ -169,6 +309,93 @@ impl HatRuntime {
         Ok(())
     }
 
+    /// Dispatches an event by name. Custom events must be declared in the source code or
+    /// provided by an integration.
+    pub async fn emit_event(&self, name: &str, parameters: HashMap<String, Value>) -> Result<()> {
+        self.emit_event_with_depth(name, parameters, 1).await
+    }
+
+    /// Like [`Self::emit_event`], for an event emitted by an automation started by `trigger`.
+    /// Fails when the trigger is the end of a chain of [`MAX_EMIT_DEPTH`] emitted events, like
+    /// an automation that emits the event that triggers it.
+    pub async fn emit_event_from(
+        &self,
+        trigger: &Trigger,
+        name: &str,
+        parameters: HashMap<String, Value>,
+    ) -> Result<()> {
+        let depth = match trigger {
+            Trigger::Event(event)
+                if event.device.integration == event::RUNTIME_INTEGRATION_ID
+                    && event.device.id == EMIT_DEVICE_ID =>
+            {
+                event
+                    .device
+                    .attributes
+                    .get(EMIT_DEPTH_ATTRIBUTE)
+                    .and_then(|depth| depth.as_u64())
+                    .unwrap_or(1)
+            }
+            _ => 0,
+        };
+        ensure!(
+            depth < MAX_EMIT_DEPTH,
+            "event {name} not emitted, {MAX_EMIT_DEPTH} events were already emitted in a chain"
+        );
+        self.emit_event_with_depth(name, parameters, depth + 1)
+            .await
+    }
+
+    async fn emit_event_with_depth(
+        &self,
+        name: &str,
+        parameters: HashMap<String, Value>,
+        depth: u64,
+    ) -> Result<()> {
+        let typ = EventType::from_name(name);
+        ensure!(self.is_event_known(&typ), "event {name} is not declared");
+
+        self.dispatch_event(Event {
+            typ,
+            datetime: Local::now(),
+            device: Device {
+                integration: event::RUNTIME_INTEGRATION_ID.to_owned(),
+                id: EMIT_DEVICE_ID.to_owned(),
+                name: None,
+                typ: DeviceType::Unknown,
+                capabilities: Vec::new(),
+                area: None,
+                floor: None,
+                state: None,
+                attributes: serde_json::Map::from_iter([(
+                    EMIT_DEPTH_ATTRIBUTE.to_owned(),
+                    depth.into(),
+                )]),
+            },
+            parameters,
+        })
+        .await
+    }
+
+    /// Returns true if the event type is built-in, declared in the source code or provided by
+    /// an integration.
+    pub fn is_event_known(&self, typ: &EventType) -> bool {
+        match typ {
+            EventType::Custom(name) => {
+                let declared = self.custom_events.read().unwrap();
+                if declared.iter().any(|e| e.eq_ignore_ascii_case(name)) {
+                    return true;
+                }
+                let provided = self.integration_events.read().unwrap();
+                provided
+                    .values()
+                    .flatten()
+                    .any(|d| d.event.as_str().eq_ignore_ascii_case(name))
+            }
+            _ => true,
+        }
+    }
+
     pub async fn join(&self) {
         let mut handle_lock = self.executor_handle.lock().await;
         let handle = &mut *handle_lock;
@@ -182,11 +409,46 @@ impl HatRuntime {
         filename: String,
         code: &str,
     ) -> std::result::Result<(), RuntimeError> {
-        let (automations, scheduler_tasks) = parser::parse(filename, code)?;
+        self.load(parser::parse(filename, code)?, false).await
+    }
+
+    /// Adds a parsed program to the runtime. When `replace` is true, the helpers it declares
+    /// replace the ones declared by previous programs.
+    async fn load(
+        &self,
+        program: parser::Program,
+        replace: bool,
+    ) -> std::result::Result<(), RuntimeError> {
+        let parser::Program {
+            automations,
+            scheduler_tasks,
+            events,
+            helpers,
+        } = program;
+
+        self.helpers
+            .declare(helpers, replace)
+            .map_err(|inner| RuntimeError::HelperError { inner })?;
+
+        {
+            let mut custom_events = self.custom_events.write().unwrap();
+            custom_events.extend(events);
+        }
 
         {
             let mut automations_lock = self.automations.lock().unwrap();
 
+            for automation in &automations {
+                for trigger in &automation.triggers {
+                    if !self.is_event_known(&trigger.event_type()) {
+                        warn!(
+                            "Automation {} is triggered by unknown event {}, it will only run if the event is dispatched by name",
+                            automation.name, trigger.event
+                        );
+                    }
+                }
+            }
+
             for automation in automations {
                 let name = automation.name.clone();
                 automations_lock.insert(name, Arc::new(automation));
@@ -216,7 +478,8 @@ impl HatRuntime {
     ) -> std::result::Result<(), RuntimeError> {
         self.clear_automations();
         self.clear_scheduler_tasks().await;
-        self.parse(filename, code).await
+        self.clear_custom_events();
+        self.load(parser::parse(filename, code)?, true).await
     }
 
     pub fn clear_automations(&self) {
@@ -224,6 +487,11 @@ impl HatRuntime {
         lock.clear();
     }
 
+    pub fn clear_custom_events(&self) {
+        let mut lock = self.custom_events.write().unwrap();
+        lock.clear();
+    }
+
     pub async fn clear_scheduler_tasks(&self) {
         let mut lock = self.scheduler_tasks.lock().await;
         lock.clear();
@@ -234,8 +502,95 @@ impl HatRuntime {
         lock.insert(fun.name.clone(), Arc::new(fun));
     }
 
+    /// Returns every function registered in the runtime, sorted by name.
+    pub fn get_functions(&self) -> Vec<Arc<Function>> {
+        let lock = self.functions.read().unwrap();
+        let mut functions = lock.values().map(Arc::clone).collect::<Vec<_>>();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
+
+    /// Returns the function registered with `name`. Functions of integrations can also be called
+    /// through an alias of the integration. If there is none, a function provided by an
+    /// integration (`{INTEGRATION_ID}.{NAME}`) is returned, as long as only one integration
+    /// provides a function with that name.
+    pub fn get_function(&self, name: &str) -> Option<Arc<Function>> {
+        self.find_function(name).ok()
+    }
+
+    /// Like [`Self::get_function`], but explains why no function was found.
+    pub fn find_function(&self, name: &str) -> Result<Arc<Function>> {
+        let lock = self.functions.read().unwrap();
+        if let Some(fun) = lock.get(name) {
+            return Ok(Arc::clone(fun));
+        }
+        if let Some((alias, fun_name)) = name.split_once('.') {
+            let aliases = self.integration_aliases.read().unwrap();
+            if let Some(fun) = aliases
+                .get(alias)
+                .and_then(|id| lock.get(&format!("{id}.{fun_name}")))
+            {
+                return Ok(Arc::clone(fun));
+            }
+        }
+        let candidates = integration_functions(&lock, name);
+        match candidates.as_slice() {
+            [fun] => Ok(Arc::clone(fun)),
+            [] => bail!("function {name} not found!"),
+            _ => bail!(
+                "function {name} is provided by several integrations, call one of {}",
+                function_names(&candidates)
+            ),
+        }
+    }
+
+    /// Returns the custom event types, both declared in the source code and contributed by the
+    /// integrations.
+    pub fn get_event_descriptors(&self) -> Vec<EventDescriptor> {
+        let declared = self.custom_events.read().unwrap();
+        let provided = self.integration_events.read().unwrap();
+        declared
+            .iter()
+            .map(|name| EventDescriptor {
+                event: EventType::custom(name.as_str()),
+                description: EventType::custom(name.as_str()).get_description(),
+                related_device_type: None,
+            })
+            .chain(provided.values().flatten().cloned())
+            .collect()
+    }
+
+    /// Makes `alias` refer to the integration with the ID `integration_id` wherever an
+    /// integration ID is accepted, like in full device IDs. Real IDs take precedence over aliases.
+    pub fn add_integration_alias(&self, alias: &str, integration_id: &str) {
+        let mut aliases = self.integration_aliases.write().unwrap();
+        aliases.insert(alias.to_owned(), integration_id.to_owned());
+    }
+
+    /// Replaces the integration alias at the start of a full device ID (`{ALIAS}@{ID}`) with the
+    /// integration ID, so it compares equal to the IDs returned by `get_device()`. Any other value
+    /// is returned unchanged.
+    pub fn canonicalize_device_id(&self, value: Value) -> Value {
+        let Value::String(full_id) = &value else {
+            return value;
+        };
+        let Some((alias, id)) = full_id.split_once('@') else {
+            return value;
+        };
+        let aliases = self.integration_aliases.read().unwrap();
+        match aliases.get(alias) {
+            Some(integration) => Value::String(format!("{integration}@{id}")),
+            None => value,
+        }
+    }
+
     pub async fn get_integration(&self, integration: &str) -> Option<Arc<dyn Integration>> {
         let lock = self.integrations.read().await;
+        if let Some((i, _)) = lock.get(integration) {
+            return Some(Arc::clone(i));
+        }
+        let aliases = self.integration_aliases.read().unwrap();
+        let integration = aliases.get(integration)?;
         lock.get(integration).map(|(i, _)| Arc::clone(i))
     }
 
@@ -278,15 +633,86 @@ impl HatRuntime {
         }
     }
 
+    /// Lists the devices of every integration, querying them concurrently.
+    pub async fn list_devices(&self) -> Result<Vec<Device>> {
+        let futures = self
+            .get_integrations()
+            .await
+            .into_iter()
+            .map(|i| tokio::spawn(async move { i.list_devices().await }))
+            .collect::<Vec<_>>();
+
+        let mut devices = Vec::new();
+        for future in futures {
+            devices.append(&mut future.await??);
+        }
+        Ok(devices)
+    }
+
+    /// Lists the [`DeviceType::Person`] devices, skipping the integrations that fail to list
+    /// their devices.
+    pub async fn list_people(&self) -> Vec<Device> {
+        let futures = self
+            .get_integrations()
+            .await
+            .into_iter()
+            .map(|i| tokio::spawn(async move { (i.get_id().to_owned(), i.list_devices().await) }))
+            .collect::<Vec<_>>();
+
+        let mut people = Vec::new();
+        for future in futures {
+            match future.await {
+                Ok((_, Ok(devices))) => people.extend(
+                    devices
+                        .into_iter()
+                        .filter(|device| device.typ == DeviceType::Person),
+                ),
+                Ok((id, Err(e))) => warn!("Failed to list the devices of {id}: {e:#}"),
+                Err(e) => warn!("Failed to list devices: {e}"),
+            }
+        }
+        people
+    }
+
+    /// Executes a command on a device, after checking that the device has the capability
+    /// required by the command. The `full_device_id` has the same format accepted by
+    /// [`Self::get_device`].
+    pub async fn execute(&self, full_device_id: &str, command: DeviceCommand) -> Result<()> {
+        let device = self
+            .get_device(full_device_id)
+            .await?
+            .with_context(|| format!("device {full_device_id} not found"))?;
+
+        // Devices that report no capabilities are left for their integration to handle
+        let capability = command.required_capability();
+        ensure!(
+            device.capabilities.is_empty() || device.has_capability(capability),
+            "device {full_device_id} does not have the {capability:?} capability required by {command:?}"
+        );
+
+        let integration = self
+            .get_integration(&device.integration)
+            .await
+            .with_context(|| format!("failed to find integration of device {full_device_id}"))?;
+
+        integration.execute(&device.id, command).await
+    }
+
     fn register_default_functions(&self) {
         let mut lock = self.functions.write().unwrap();
 
         for fun in function::defaults::DEFAULT_FUNCTIONS.iter() {
             lock.insert(fun.name.clone(), Arc::new(fun.clone()));
         }
+        for fun in function::http::functions()
+            .into_iter()
+            .chain(crate::notifiers::functions())
+        {
+            lock.insert(fun.name.clone(), Arc::new(fun));
+        }
     }
 
-    async fn get_task(&self, tid: &TaskID) -> Option<Arc<ScheduleTask>> {
+    pub(crate) async fn get_task(&self, tid: &TaskID) -> Option<Arc<ScheduleTask>> {
         let tasks_lock = self.scheduler_tasks.lock().await;
 
         tasks_lock.get(tid).map(Arc::clone)
@@ -299,8 +725,35 @@ impl Drop for HatRuntime {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum ExecutorMessage {
     Event(Event),
     TaskRun(TaskID),
 }
+
+/// Functions of integrations named `name` once their integration is left out.
+fn integration_functions(
+    functions: &HashMap<String, Arc<Function>>,
+    name: &str,
+) -> Vec<Arc<Function>> {
+    let mut candidates = functions
+        .iter()
+        .filter(|(full_name, _)| {
+            full_name
+                .split_once('.')
+                .is_some_and(|(_, fun_name)| fun_name == name)
+        })
+        .map(|(_, fun)| Arc::clone(fun))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+    candidates
+}
+
+fn function_names(functions: &[Arc<Function>]) -> String {
+    functions
+        .iter()
+        .map(|fun| fun.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::future::Future;
 use std::pin::Pin;
@@ -15,6 +16,8 @@ use anyhow::Result;
 pub enum Expression {
     Constant(Value),
     Function(FunctionCall),
+    List(Vec<Expression>),
+    Map(Vec<(String, Expression)>),
     BinaryOperation {
         lhs: Box<Expression>,
         op: Operation,
@@ -31,17 +34,40 @@ impl Expression {
             match self {
                 Expression::Constant(value) => Ok(value.clone()),
                 Expression::Function(function) => function.evaluate(ctx).await,
+                Expression::List(items) => {
+                    let mut values = Vec::with_capacity(items.len());
+                    for item in items {
+                        values.push(item.evaluate(Arc::clone(&ctx)).await?);
+                    }
+                    Ok(Value::List(values))
+                }
+                Expression::Map(entries) => {
+                    let mut values = BTreeMap::new();
+                    for (key, entry) in entries {
+                        values.insert(key.clone(), entry.evaluate(Arc::clone(&ctx)).await?);
+                    }
+                    Ok(Value::Map(values))
+                }
                 Expression::BinaryOperation { lhs, op, rhs } => {
                     // Box recursive calls
                     let lh_value = lhs.evaluate(Arc::clone(&ctx)).await?;
-                    let rh_value = rhs.evaluate(ctx).await?;
+                    let rh_value = rhs.evaluate(Arc::clone(&ctx)).await?;
+                    let (lh_value, rh_value) = match op {
+                        Operation::Equals | Operation::NotEquals => (
+                            ctx.runtime.canonicalize_device_id(lh_value),
+                            ctx.runtime.canonicalize_device_id(rh_value),
+                        ),
+                        _ => (lh_value, rh_value),
+                    };
                     match op {
                         Operation::Add => lh_value.try_add(rh_value),
                         Operation::Subtract => lh_value.try_sub(rh_value),
                         Operation::Multiply => lh_value.try_mul(rh_value),
                         Operation::Divide => lh_value.try_div(rh_value),
-                        Operation::Equals => Ok(Value::Boolean(lh_value == rh_value)),
-                        Operation::NotEquals => Ok(Value::Boolean(lh_value != rh_value)),
+                        Operation::Equals => Ok(Value::Boolean(lh_value.loosely_equals(&rh_value))),
+                        Operation::NotEquals => {
+                            Ok(Value::Boolean(!lh_value.loosely_equals(&rh_value)))
+                        }
                         Operation::And => {
                             Ok(Value::Boolean(lh_value.as_bool() && rh_value.as_bool()))
                         }
@@ -64,6 +90,22 @@ impl Display for Expression {
         match self {
             Self::Constant(c) => write!(f, "{c}"),
             Self::Function(fun) => write!(f, "{fun}"),
+            Self::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "[{items}]")
+            }
+            Self::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, entry)| format!("\"{key}\": {entry}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{{{entries}}}")
+            }
             Self::BinaryOperation { lhs, op, rhs } => {
                 write!(f, "{} {} {}", lhs, op, rhs)
             }
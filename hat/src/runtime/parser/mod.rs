@@ -1,4 +1,5 @@
-use crate::runtime::automation::Automation;
+use crate::integrations::helpers::HelperSettings;
+use crate::runtime::automation::{Automation, AutomationTrigger};
 use crate::runtime::function::FunctionCall;
 use crate::runtime::scheduler::Weekday;
 use crate::runtime::value::Value;
@@ -39,10 +40,18 @@ lazy_static::lazy_static! {
     };
 }
 
-pub fn parse(
-    filename: String,
-    code: &str,
-) -> std::result::Result<(Vec<Automation>, Vec<ScheduleTask>), RuntimeError> {
+/// Everything declared in a Hat source file.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub automations: Vec<Automation>,
+    pub scheduler_tasks: Vec<ScheduleTask>,
+    /// Names of the custom events declared with `event Name`
+    pub events: Vec<String>,
+    /// Helpers declared with `helper id kind(options)`
+    pub helpers: Vec<HelperSettings>,
+}
+
+pub fn parse(filename: String, code: &str) -> std::result::Result<Program, RuntimeError> {
     // TODO: stop panicking
     let code_program = HatParser::parse(Rule::program, code);
 
@@ -90,10 +99,12 @@ pub fn parse(
                             Rule::stmt => "statement",
                             Rule::program => "program",
                             Rule::automation_triggers => "automation triggers",
+                            Rule::automation_trigger => "automation trigger",
                             Rule::automation_action => "automation action",
                             Rule::const_atom => "constant",
                             Rule::bool => "boolean",
                             Rule::function => "function",
+                            Rule::function_name => "function name",
                             Rule::function_parameters => "function parameters",
                             Rule::atom => "value (atom)",
                             Rule::bin_op => "binary operation",
@@ -118,6 +129,17 @@ pub fn parse(
                                 "weekday of the schedule interval"
                             }
                             Rule::schedule_interval_cron => "schedule interval cron",
+                            Rule::event_declaration => "event declaration",
+                            Rule::list => "list",
+                            Rule::map => "map",
+                            Rule::map_entry => "map entry",
+                            Rule::helper_declaration => "helper declaration",
+                            Rule::helper_kind => {
+                                "helper kind (toggle, number, select, text or timer)"
+                            }
+                            Rule::helper_options => "helper options",
+                            Rule::helper_option => "helper option",
+                            Rule::helper_option_list => "list of constants",
                         })
                         .collect(),
                     ErrorVariant::CustomError { .. } => todo!(),
@@ -128,6 +150,8 @@ pub fn parse(
 
     let mut automations = Vec::new();
     let mut scheduler_tasks = Vec::new();
+    let mut events = Vec::new();
+    let mut helpers = Vec::new();
 
     for rule in program {
         match rule.as_rule() {
@@ -145,7 +169,16 @@ pub fn parse(
                     .next()
                     .expect("missing the automation triggers")
                     .into_inner()
-                    .map(|trigger| trigger.as_span().as_str().to_owned())
+                    .map(|trigger| {
+                        let mut inner = trigger.into_inner();
+                        let event = inner.next().expect("missing the trigger event");
+                        AutomationTrigger {
+                            event: event.as_span().as_str().to_owned(),
+                            source: inner.next().map(|source| {
+                                parse_string(source).expect("failed to parse string")
+                            }),
+                        }
+                    })
                     .collect();
 
                 let mut conditions = Vec::new();
@@ -208,7 +241,7 @@ pub fn parse(
 
                         let time_span = inner.next().unwrap().as_span().as_str();
                         let time = parse_time(time_span)
-                            .expect(&format!("invalid time format: {time_span}"));
+                            .unwrap_or_else(|_| panic!("invalid time format: {time_span}"));
 
                         ScheduleInterval::Time { weekday, at: time }
                     }
@@ -239,12 +272,50 @@ pub fn parse(
 
                 scheduler_tasks.push(schedule_task);
             }
+            Rule::event_declaration => {
+                let name = rule
+                    .into_inner()
+                    .next()
+                    .expect("missing name of the event")
+                    .as_span()
+                    .as_str()
+                    .to_owned();
+                events.push(name);
+            }
+            Rule::helper_declaration => {
+                let mut inner = rule.into_inner();
+                let id = inner.next().expect("missing id of the helper").as_str();
+                let kind = inner.next().expect("missing kind of the helper").as_str();
+
+                let mut settings = serde_json::Map::new();
+                settings.insert("id".into(), id.into());
+                settings.insert("kind".into(), kind.into());
+                for option in inner.flat_map(Pair::into_inner) {
+                    let mut option = option.into_inner();
+                    let name = option.next().expect("missing option name").as_str();
+                    let value = option.next().expect("missing option value");
+                    let value = parse_json_constant(value)
+                        .map_err(|inner| RuntimeError::HelperError { inner })?;
+                    settings.insert(name.to_owned(), value);
+                }
+                let settings = serde_json::from_value(settings.into()).map_err(|e| {
+                    RuntimeError::HelperError {
+                        inner: anyhow::anyhow!("invalid helper {id}: {e}"),
+                    }
+                })?;
+                helpers.push(settings);
+            }
             Rule::EOI => {}
             _ => unreachable!("top level rule not implemented {rule:?}"),
         }
     }
 
-    Ok((automations, scheduler_tasks))
+    Ok(Program {
+        automations,
+        scheduler_tasks,
+        events,
+        helpers,
+    })
 }
 
 pub fn parse_time(span: &str) -> Result<Time> {
@@ -264,7 +335,7 @@ pub fn parse_time(span: &str) -> Result<Time> {
         .map(|s| s.parse::<u32>())
         .unwrap_or(Ok(0))
         .context("failed to parse seconds")?;
-    Ok(Time::from_hms_opt(hours, mins, secs).context("invalid time provided")?)
+    Time::from_hms_opt(hours, mins, secs).context("invalid time provided")
 }
 
 fn parse_string(rule: Pair<Rule>) -> Result<String> {
@@ -277,28 +348,40 @@ fn parse_string(rule: Pair<Rule>) -> Result<String> {
     }
 }
 
+fn parse_constant(rule: Pair<Rule>) -> Result<Value> {
+    let span = rule.as_span().as_str();
+    match rule.as_rule() {
+        Rule::null => Ok(Value::Null),
+        Rule::bool => Ok((span == "true").into()),
+        Rule::string => parse_string(rule).map(Value::from),
+        Rule::time => parse_time(span).map(Value::from),
+        Rule::decimal => Ok(f64::from_str(span)?.into()),
+        Rule::integer => Ok((i64::from_str(span)? as f64).into()),
+        _ => bail!("rule is not a constant: {rule:?}"),
+    }
+}
+
+/// Converts a constant or a list of constants into JSON. Integers stay integers, so they can
+/// be read into integer fields.
+fn parse_json_constant(rule: Pair<Rule>) -> Result<serde_json::Value> {
+    match rule.as_rule() {
+        Rule::integer => Ok(i64::from_str(rule.as_str())?.into()),
+        Rule::helper_option_list => rule.into_inner().map(parse_json_constant).collect(),
+        _ => Ok(parse_constant(rule)?.to_json()),
+    }
+}
+
 fn parse_atom(rule: Pair<Rule>) -> Result<Expression> {
     match rule.as_rule() {
         Rule::atom => {
             let inner = rule.into_inner().next().context("empty atom")?;
             match inner.as_rule() {
-                Rule::null => Ok(Expression::Constant(Value::Null)),
-                Rule::bool => match inner.as_span().as_str() {
-                    "true" => Ok(Expression::Constant(true.into())),
-                    "false" => Ok(Expression::Constant(false.into())),
-                    _ => unreachable!(),
-                },
-                Rule::string => parse_string(inner).map(|s| Expression::Constant(s.into())),
-                Rule::time => parse_time(inner.as_span().as_str())
-                    .map(|time| Expression::Constant(time.into())),
-                Rule::decimal => {
-                    let inner = inner.as_span().as_str();
-                    Ok(Expression::Constant(f64::from_str(inner)?.into()))
-                }
-                Rule::integer => {
-                    let inner = inner.as_span().as_str();
-                    Ok(Expression::Constant((i64::from_str(inner)? as f64).into()))
-                }
+                Rule::null
+                | Rule::bool
+                | Rule::string
+                | Rule::time
+                | Rule::decimal
+                | Rule::integer => parse_constant(inner).map(Expression::Constant),
                 Rule::function => {
                     let mut inner = inner.into_inner();
                     let name = inner
@@ -317,6 +400,29 @@ fn parse_atom(rule: Pair<Rule>) -> Result<Expression> {
                         arguments: parameters,
                     }))
                 }
+                Rule::list => {
+                    let items = inner
+                        .into_inner()
+                        .map(|rule| parse_expression(rule.into_inner()))
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(Expression::List(items))
+                }
+                Rule::map => {
+                    let entries = inner
+                        .into_inner()
+                        .map(|entry| {
+                            let mut entry = entry.into_inner();
+                            let key = entry.next().context("map entry without key")?;
+                            let key = match key.as_rule() {
+                                Rule::string => parse_string(key)?,
+                                _ => key.as_span().as_str().to_owned(),
+                            };
+                            let value = entry.next().context("map entry without value")?;
+                            Ok((key, parse_expression(value.into_inner())?))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(Expression::Map(entries))
+                }
                 Rule::expr => parse_expression(inner.into_inner()),
                 _ => bail!("unknown atom rule: {inner:?}"),
             }
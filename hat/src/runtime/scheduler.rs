@@ -37,7 +37,7 @@ impl Scheduler {
             .inner_scheduler
             .add(
                 Job::new_async_tz(&cron_expr, chrono::Local, move |tid, _l| {
-                    let tx = (&executor_tx).clone();
+                    let tx = executor_tx.clone();
                     Box::pin(async move {
                         tx.send(ExecutorMessage::TaskRun(TaskID(tid)))
                             .await
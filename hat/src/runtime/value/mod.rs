@@ -4,6 +4,7 @@ pub mod time;
 use anyhow::{bail, Context};
 use chrono::Timelike;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use time::Time;
 
@@ -13,9 +14,24 @@ pub enum Value {
     Boolean(bool),
     Number(f64),
     Time(Time),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
     Null,
 }
 
+/// The type of a [`Value`], used to describe function signatures to editors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueType {
+    String,
+    Boolean,
+    Number,
+    Time,
+    List,
+    Map,
+    Null,
+    Any,
+}
+
 impl Value {
     pub fn as_bool(&self) -> bool {
         match self {
@@ -23,9 +39,57 @@ impl Value {
             Value::Boolean(b) => *b,
             Value::Number(n) => *n != 0.0,
             Value::Time(t) => Some(t) != Time::from_hms_opt(0, 0, 0).as_ref(),
+            Value::List(l) => !l.is_empty(),
+            Value::Map(m) => !m.is_empty(),
             Value::Null => false,
         }
     }
+
+    /// Equality of `==` and `!=`. A number also equals a string with the same number, so
+    /// comparisons written when sensor values were strings, like `value == "21.5"`, still hold.
+    pub fn loosely_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(n), Value::String(s)) | (Value::String(s), Value::Number(n)) => {
+                s.trim().parse::<f64>().is_ok_and(|s| s == *n)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Converts a JSON value into a Hat value. Objects become maps and arrays become lists.
+    pub fn from_json(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Boolean(b),
+            serde_json::Value::Number(n) => n.as_f64().into(),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(a) => {
+                Value::List(a.into_iter().map(Value::from_json).collect())
+            }
+            serde_json::Value::Object(o) => Value::Map(
+                o.into_iter()
+                    .map(|(k, v)| (k, Value::from_json(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Converts this value into JSON. Times are represented as `HH:MM:SS` strings.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::String(s) => s.clone().into(),
+            Value::Boolean(b) => (*b).into(),
+            Value::Number(n) => (*n).into(),
+            Value::Time(t) => t.to_string().into(),
+            Value::List(l) => l.iter().map(Value::to_json).collect(),
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_json()))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            Value::Null => serde_json::Value::Null,
+        }
+    }
 }
 
 impl Display for Value {
@@ -35,6 +99,20 @@ impl Display for Value {
             Value::Boolean(b) => b.to_string(),
             Value::Number(n) => n.to_string(),
             Value::Time(t) => t.to_string(),
+            Value::List(l) => format!(
+                "[{}]",
+                l.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Map(m) => format!(
+                "{{{}}}",
+                m.iter()
+                    .map(|(k, v)| format!("\"{k}\": {v}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Value::Null => return write!(f, "null"),
         };
         write!(f, "{}", str)
@@ -65,6 +143,18 @@ impl From<Time> for Value {
     }
 }
 
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Self::List(value)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(value: BTreeMap<String, Value>) -> Self {
+        Self::Map(value)
+    }
+}
+
 impl<T: Into<Value>> From<Option<T>> for Value {
     fn from(value: Option<T>) -> Self {
         match value {
@@ -77,11 +167,13 @@ impl<T: Into<Value>> From<Option<T>> for Value {
 impl operations::TryAdd for Value {
     fn try_add(self, rhs: Self) -> anyhow::Result<Self> {
         Ok(match self {
+            Value::List(_) | Value::Map(_) => bail!("cannot add to a list or map"),
             Value::String(lhs) => match rhs {
                 Value::String(rhs) => format!("{lhs}{rhs}").into(),
                 Value::Boolean(rhs) => format!("{lhs}{rhs}").into(),
                 Value::Number(rhs) => format!("{lhs}{rhs}").into(),
                 Value::Time(rhs) => format!("{lhs}{rhs}").into(),
+                Value::List(_) | Value::Map(_) => format!("{lhs}{rhs}").into(),
                 Value::Null => format!("{lhs}null").into(),
             },
             Value::Boolean(lhs) => match rhs {
@@ -89,6 +181,7 @@ impl operations::TryAdd for Value {
                 Value::Boolean(rhs) => ((lhs as u8 + rhs as u8) as f64).into(),
                 Value::Number(rhs) => ((lhs as u8) as f64 + rhs).into(),
                 Value::Time(_) => bail!("cannot add boolean and time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot add a list or map"),
                 Value::Null => Value::Boolean(lhs),
             },
             Value::Number(lhs) => match rhs {
@@ -96,6 +189,7 @@ impl operations::TryAdd for Value {
                 Value::Boolean(rhs) => (lhs + (rhs as u8) as f64).into(),
                 Value::Number(rhs) => (lhs + rhs).into(),
                 Value::Time(_) => bail!("cannot add time and number"),
+                Value::List(_) | Value::Map(_) => bail!("cannot add a list or map"),
                 Value::Null => Value::Number(lhs),
             },
             Value::Null => match rhs {
@@ -103,6 +197,7 @@ impl operations::TryAdd for Value {
                 Value::Boolean(rhs) => Value::Boolean(rhs),
                 Value::Number(rhs) => Value::Number(rhs),
                 Value::Time(rhs) => Value::Time(rhs),
+                Value::List(_) | Value::Map(_) => bail!("cannot add a list or map"),
                 Value::Null => Value::Null,
             },
             Value::Time(lhs) => match rhs {
@@ -117,6 +212,7 @@ impl operations::TryAdd for Value {
                     )
                     .context("failed to add times together")?,
                 ),
+                Value::List(_) | Value::Map(_) => bail!("cannot add a list or map"),
                 Value::Null => bail!("cannot add null to a time"),
             },
         })
@@ -126,11 +222,13 @@ impl operations::TryAdd for Value {
 impl operations::TrySub for Value {
     fn try_sub(self, rhs: Self) -> anyhow::Result<Self> {
         Ok(match self {
+            Value::List(_) | Value::Map(_) => bail!("cannot subtract from a list or map"),
             Value::String(_) => match rhs {
                 Value::String(_) => bail!("cannot subtract two strings"),
                 Value::Boolean(_) => bail!("cannot subtract a boolean from a string"),
                 Value::Number(_) => bail!("cannot subtract a number from a string"),
                 Value::Time(_) => bail!("cannot subtract time from a string"),
+                Value::List(_) | Value::Map(_) => bail!("cannot subtract a list or map"),
                 Value::Null => bail!("cannot subtract null from a string"),
             },
             Value::Boolean(lhs) => match rhs {
@@ -138,6 +236,7 @@ impl operations::TrySub for Value {
                 Value::Boolean(rhs) => (((lhs as u8) as f64) - ((rhs as u8) as f64)).into(),
                 Value::Number(rhs) => ((lhs as u8) as f64 - rhs).into(),
                 Value::Time(_) => bail!("cannot subtract time from a boolean"),
+                Value::List(_) | Value::Map(_) => bail!("cannot subtract a list or map"),
                 Value::Null => Value::Boolean(lhs),
             },
             Value::Number(lhs) => match rhs {
@@ -145,6 +244,7 @@ impl operations::TrySub for Value {
                 Value::Boolean(rhs) => (lhs - ((rhs as u8) as f64)).into(),
                 Value::Number(rhs) => (lhs - rhs).into(),
                 Value::Time(_) => bail!("cannot subtract time from a number"),
+                Value::List(_) | Value::Map(_) => bail!("cannot subtract a list or map"),
                 Value::Null => Value::Number(lhs),
             },
             Value::Null => match rhs {
@@ -152,6 +252,7 @@ impl operations::TrySub for Value {
                 Value::Boolean(_) => bail!("cannot subtract boolean from null"),
                 Value::Number(_) => bail!("cannot subtract number from null"),
                 Value::Time(_) => bail!("cannot subtract time from null"),
+                Value::List(_) | Value::Map(_) => bail!("cannot subtract a list or map"),
                 Value::Null => Value::Null,
             },
             Value::Time(lhs) => match rhs {
@@ -166,6 +267,7 @@ impl operations::TrySub for Value {
                     )
                     .context("failed to add times together")?,
                 ),
+                Value::List(_) | Value::Map(_) => bail!("cannot subtract a list or map"),
                 Value::Null => bail!("cannot subtract null from a time"),
             },
         })
@@ -175,11 +277,13 @@ impl operations::TrySub for Value {
 impl operations::TryMul for Value {
     fn try_mul(self, rhs: Self) -> anyhow::Result<Self> {
         Ok(match self {
+            Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
             Value::String(_) => match rhs {
                 Value::String(_) => bail!("cannot multiply two strings"),
                 Value::Boolean(_) => bail!("cannot multiply a string and a boolean"),
                 Value::Number(_) => bail!("cannot multiply a string and a number"),
                 Value::Time(_) => bail!("cannot multiply a string and a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
                 Value::Null => bail!("cannot multiply a string and null"),
             },
             Value::Boolean(lhs) => match rhs {
@@ -187,6 +291,7 @@ impl operations::TryMul for Value {
                 Value::Boolean(rhs) => (((lhs as u8) as f64) * ((rhs as u8) as f64)).into(),
                 Value::Number(rhs) => ((lhs as u8) as f64 * rhs).into(),
                 Value::Time(_) => bail!("cannot multiply a boolean and a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
                 Value::Null => bail!("cannot multiply a boolean and null"),
             },
             Value::Number(lhs) => match rhs {
@@ -201,6 +306,7 @@ impl operations::TryMul for Value {
                     )
                     .context("failed to add times together")?,
                 ),
+                Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
                 Value::Null => bail!("cannot multiply a number and null"),
             },
             Value::Null => match rhs {
@@ -208,6 +314,7 @@ impl operations::TryMul for Value {
                 Value::Boolean(_) => bail!("cannot multiply a boolean and null"),
                 Value::Number(_) => bail!("cannot multiply a number and null"),
                 Value::Time(_) => bail!("cannot multiply null and a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
                 Value::Null => Value::Null,
             },
             Value::Time(lhs) => match rhs {
@@ -222,6 +329,7 @@ impl operations::TryMul for Value {
                     .context("failed to add times together")?,
                 ),
                 Value::Time(_) => bail!("cannot multiply two times"),
+                Value::List(_) | Value::Map(_) => bail!("cannot multiply a list or map"),
                 Value::Null => bail!("cannot subtract null from a time"),
             },
         })
@@ -231,11 +339,13 @@ impl operations::TryMul for Value {
 impl operations::TryDiv for Value {
     fn try_div(self, rhs: Self) -> anyhow::Result<Self> {
         Ok(match self {
+            Value::List(_) | Value::Map(_) => bail!("cannot divide a list or map"),
             Value::String(_) => match rhs {
                 Value::String(_) => bail!("cannot divide two strings"),
                 Value::Boolean(_) => bail!("cannot divide a string and a boolean"),
                 Value::Number(_) => bail!("cannot divide a string and a number"),
                 Value::Time(_) => bail!("cannot divide a string by a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot divide by a list or map"),
                 Value::Null => bail!("cannot divide a string and null"),
             },
             Value::Boolean(lhs) => match rhs {
@@ -243,6 +353,7 @@ impl operations::TryDiv for Value {
                 Value::Boolean(rhs) => (((lhs as u8) as f64) / ((rhs as u8) as f64)).into(),
                 Value::Number(rhs) => ((lhs as u8) as f64 / rhs).into(),
                 Value::Time(_) => bail!("cannot divide a boolean by a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot divide by a list or map"),
                 Value::Null => bail!("cannot divide a boolean and null"),
             },
             Value::Number(lhs) => match rhs {
@@ -250,6 +361,7 @@ impl operations::TryDiv for Value {
                 Value::Boolean(rhs) => (lhs / ((rhs as u8) as f64)).into(),
                 Value::Number(rhs) => (lhs / rhs).into(),
                 Value::Time(_) => bail!("cannot divide a number by a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot divide by a list or map"),
                 Value::Null => bail!("cannot divide a number and null"),
             },
             Value::Null => match rhs {
@@ -257,6 +369,7 @@ impl operations::TryDiv for Value {
                 Value::Boolean(_) => bail!("cannot divide a boolean and null"),
                 Value::Number(_) => bail!("cannot divide a number and null"),
                 Value::Time(_) => bail!("cannot divide null by a time"),
+                Value::List(_) | Value::Map(_) => bail!("cannot divide by a list or map"),
                 Value::Null => Value::Null,
             },
             Value::Time(lhs) => match rhs {
@@ -271,6 +384,7 @@ impl operations::TryDiv for Value {
                     .context("failed to add times together")?,
                 ),
                 Value::Time(_) => bail!("cannot divide two times"),
+                Value::List(_) | Value::Map(_) => bail!("cannot divide by a list or map"),
                 Value::Null => bail!("cannot divide a time by null"),
             },
         })
@@ -75,7 +75,7 @@ pub fn coerce_to_time(arg: Option<&Value>) -> anyhow::Result<Time> {
     match arg {
         Some(arg) => {
             if let Value::String(s) = arg {
-                parse_time(&s)
+                parse_time(s)
             } else {
                 bail!("time function only accepts strings");
             }
@@ -54,6 +54,7 @@ impl IntoResponse for ApiError {
     }
 }
 
+#[allow(dead_code)]
 pub trait MapErrToApi<T> {
     fn map_err_to_api(self, code: StatusCode) -> Result<T, ApiError>;
 }
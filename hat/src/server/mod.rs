@@ -1,33 +1,101 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use axum::{
-    routing::{get, post},
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Router,
 };
-use http::header::CONTENT_TYPE;
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    StatusCode,
+};
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::config::{Features, ServerConfig};
 use crate::runtime::HatRuntime;
+use error::ApiError;
 
 mod error;
 mod routes;
 
+pub use routes::events::KEEP_ALIVE_INTERVAL;
+
 #[derive(Clone)]
 struct AppState {
     pub runtime: Arc<HatRuntime>,
+    /// Secrets of the accepted webhooks, by name
+    pub webhooks: Arc<BTreeMap<String, String>>,
 }
 
 pub fn make_router(runtime: Arc<HatRuntime>) -> Router {
+    make_router_with_config(runtime, &ServerConfig::default(), &Features::default())
+}
+
+/// Builds the router without the routes of disabled features. When the server has a token,
+/// every request but the webhooks must send it as `Authorization: Bearer {TOKEN}`.
+pub fn make_router_with_config(
+    runtime: Arc<HatRuntime>,
+    server: &ServerConfig,
+    features: &Features,
+) -> Router {
     let cors = CorsLayer::new()
         .allow_methods(Any)
         .allow_origin(Any)
-        .allow_headers([CONTENT_TYPE]);
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION]);
 
-    Router::new()
+    let mut router = Router::new()
         .route("/devices", get(routes::devices::get_devices))
         .route("/device", get(routes::devices::get_device))
         .route("/possible_events", get(routes::events::get_possible_events))
-        .route("/update_code", post(routes::update_code::update_code))
+        .route("/functions", get(routes::functions::get_functions))
+        .route("/helpers", get(routes::helpers::get_helpers));
+    if features.emit_events {
+        router = router.route("/events/:name", post(routes::events::emit_event));
+    }
+    if features.update_code {
+        router = router.route("/update_code", post(routes::update_code::update_code));
+    }
+    if features.helpers {
+        router = router
+            .route("/helpers", post(routes::helpers::create_helper))
+            .route("/helpers/:id", delete(routes::helpers::delete_helper))
+            .route(
+                "/helpers/:id/value",
+                post(routes::helpers::set_helper_value),
+            );
+    }
+    if features.federation {
+        router = router
+            .route("/event_stream", get(routes::events::event_stream))
+            .route("/device/command", post(routes::devices::execute_command));
+    }
+    if let Some(token) = &server.token {
+        let token: Arc<str> = Arc::from(token.as_str());
+        router = router.layer(middleware::from_fn_with_state(token, require_token));
+    }
+    // Added after the token layer, as webhooks are protected by their own secrets
+    if features.webhooks {
+        router = router.route("/webhook/:name", post(routes::webhooks::receive_webhook));
+    }
+
+    let webhooks = Arc::new(server.webhooks.clone());
+    router
         .layer(cors)
-        .with_state(AppState { runtime })
+        .with_state(AppState { runtime, webhooks })
+}
+
+async fn require_token(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| value == &*token);
+    if !authorized {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "missing or invalid token").into_response();
+    }
+    next.run(request).await
 }
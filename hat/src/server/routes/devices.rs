@@ -1,39 +1,37 @@
-use std::sync::Arc;
-
 use axum::{
     extract::{Query, State},
     Json,
 };
 use serde::Deserialize;
+use serde_json::json;
 
 use crate::{
-    runtime::device::Device,
+    runtime::device::{Device, DeviceCommand},
     server::{
-        error::{ApiResult, RaiseInternalError},
+        error::{ApiError, ApiResult, RaiseInternalError},
         AppState,
     },
 };
 
+#[derive(Deserialize)]
+pub struct GetDevicesQuery {
+    /// Only lists the devices in this area
+    area: Option<String>,
+}
+
 #[axum::debug_handler]
-pub async fn get_devices(State(state): State<AppState>) -> ApiResult<Json<Vec<Device>>> {
-    let integrations = state.runtime.get_integrations().await;
-
-    let futures = integrations
-        .iter()
-        .map(|i| {
-            let i = Arc::clone(i);
-            tokio::spawn(async move { i.list_devices().await })
-        })
-        .collect::<Vec<_>>();
-
-    let mut devices = Vec::new();
-
-    for future in futures {
-        let mut result = future
-            .await
-            .raise_internal_error(Some("failed to list devices"))?
-            .raise_internal_error(Some("failed to list devices"))?;
-        devices.append(&mut result);
+pub async fn get_devices(
+    Query(query): Query<GetDevicesQuery>,
+    State(state): State<AppState>,
+) -> ApiResult<Json<Vec<Device>>> {
+    let mut devices = state
+        .runtime
+        .list_devices()
+        .await
+        .raise_internal_error(Some("failed to list devices"))?;
+
+    if let Some(area) = &query.area {
+        devices.retain(|dev| dev.is_in_area(area));
     }
 
     Ok(Json(devices))
@@ -56,3 +54,25 @@ pub async fn get_device(
 
     Ok(Json(dev))
 }
+
+#[derive(Deserialize)]
+pub struct ExecuteCommandBody {
+    /// Full ID of the device, like `home@light.kitchen`
+    id: String,
+    command: DeviceCommand,
+}
+
+/// Executes a command on a device, used by other Hat instances that import its devices.
+#[axum::debug_handler]
+pub async fn execute_command(
+    State(state): State<AppState>,
+    Json(body): Json<ExecuteCommandBody>,
+) -> ApiResult<Json<serde_json::Value>> {
+    state
+        .runtime
+        .execute(&body.id, body.command)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+
+    Ok(Json(json!({"ok": true})))
+}
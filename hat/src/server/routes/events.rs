@@ -1,12 +1,21 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, convert::Infallible, sync::Arc, time::Duration};
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+    Json,
+};
+use futures_util::Stream;
+use http::StatusCode;
 use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
 
 use crate::{
-    runtime::{device::DeviceType, event::EventType},
+    runtime::{device::DeviceType, event::EventType, value::Value},
     server::{
-        error::{ApiResult, RaiseInternalError},
+        error::{ApiError, ApiResult, RaiseInternalError},
         AppState,
     },
 };
@@ -44,18 +53,94 @@ pub async fn get_possible_events(
     }
 
     let types = devices
-        .into_iter()
-        .map(|d| d.typ)
+        .iter()
+        .flat_map(|d| d.get_related_events())
         .collect::<HashSet<_>>()
         .into_iter()
-        .map(|device_type| EventType::get_events_related_to(device_type))
-        .flat_map(|slice| slice.iter().cloned())
         .map(|event| EventInfo {
-            event,
             description: event.get_description(),
             related_device_type: event.get_related_device_type(),
+            event,
         })
+        .chain(
+            state
+                .runtime
+                .get_event_descriptors()
+                .into_iter()
+                .map(|descriptor| EventInfo {
+                    event: descriptor.event,
+                    description: descriptor.description,
+                    related_device_type: descriptor.related_device_type,
+                }),
+        )
         .collect::<Vec<_>>();
 
     Ok(Json(types))
 }
+
+/// Dispatches an event by name. The body, if present, must be a JSON object and its fields
+/// become the event parameters.
+#[axum::debug_handler]
+pub async fn emit_event(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: String,
+) -> ApiResult<Json<serde_json::Value>> {
+    let parameters = if body.trim().is_empty() {
+        Default::default()
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(serde_json::Value::Object(fields)) => fields
+                .into_iter()
+                .map(|(key, value)| (key, Value::from_json(value)))
+                .collect(),
+            Ok(_) => return Err(ApiError::bad_request("event payload must be a JSON object")),
+            Err(e) => {
+                return Err(ApiError::bad_request(format!(
+                    "failed to parse event payload: {e}"
+                )))
+            }
+        }
+    };
+
+    if !state.runtime.is_event_known(&EventType::from_name(&name)) {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("event {name} is not declared"),
+        ));
+    }
+
+    state
+        .runtime
+        .emit_event(&name, parameters)
+        .await
+        .raise_internal_error(Some("failed to dispatch event"))?;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+/// How often `GET /event_stream` sends a keep-alive comment when there are no events
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Streams every event dispatched by the runtime as server-sent events with JSON data, so
+/// other Hat instances can follow them.
+pub async fn event_stream(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let receiver = state.runtime.subscribe_events();
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => match SseEvent::default().json_data(&event) {
+                    Ok(message) => return Some((Ok(message), receiver)),
+                    Err(e) => warn!("Failed to serialize event {:?}: {e}", event.typ),
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event stream is too slow, {missed} events were dropped");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
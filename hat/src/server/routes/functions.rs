@@ -0,0 +1,38 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::{
+    runtime::{
+        function::{FunctionCategory, FunctionParameter},
+        value::ValueType,
+    },
+    server::{error::ApiResult, AppState},
+};
+
+#[derive(Serialize)]
+pub struct FunctionInfo {
+    name: String,
+    description: &'static str,
+    category: FunctionCategory,
+    parameters: Vec<FunctionParameter>,
+    #[serde(rename = "returnType")]
+    return_type: ValueType,
+}
+
+#[axum::debug_handler]
+pub async fn get_functions(State(state): State<AppState>) -> ApiResult<Json<Vec<FunctionInfo>>> {
+    let functions = state
+        .runtime
+        .get_functions()
+        .into_iter()
+        .map(|f| FunctionInfo {
+            name: f.name.clone(),
+            description: f.description,
+            category: f.category,
+            parameters: f.parameters.clone(),
+            return_type: f.returns,
+        })
+        .collect();
+
+    Ok(Json(functions))
+}
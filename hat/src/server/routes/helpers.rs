@@ -0,0 +1,70 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    integrations::helpers::{HelperEntry, HelperSettings},
+    runtime::value::Value,
+    server::{
+        error::{ApiError, ApiResult},
+        AppState,
+    },
+};
+
+#[axum::debug_handler]
+pub async fn get_helpers(State(state): State<AppState>) -> Json<Vec<HelperEntry>> {
+    Json(state.runtime.helpers().list())
+}
+
+#[axum::debug_handler]
+pub async fn create_helper(
+    State(state): State<AppState>,
+    Json(settings): Json<HelperSettings>,
+) -> ApiResult<Json<serde_json::Value>> {
+    state
+        .runtime
+        .helpers()
+        .create(settings)
+        .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+#[axum::debug_handler]
+pub async fn delete_helper(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    state
+        .runtime
+        .helpers()
+        .remove(&id)
+        .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct SetValueBody {
+    value: serde_json::Value,
+}
+
+/// Changes the value of a helper. Timers are started by `true` or a number of seconds, and
+/// cancelled by `false`.
+#[axum::debug_handler]
+pub async fn set_helper_value(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<SetValueBody>,
+) -> ApiResult<Json<serde_json::Value>> {
+    state
+        .runtime
+        .helpers()
+        .set_value(&id, &Value::from_json(body.value))
+        .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+
+    Ok(Json(json!({"ok": true})))
+}
@@ -1,3 +1,6 @@
 pub mod devices;
 pub mod events;
+pub mod functions;
+pub mod helpers;
 pub mod update_code;
+pub mod webhooks;
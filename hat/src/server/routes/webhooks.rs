@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use http::{HeaderMap, StatusCode};
+use serde_json::json;
+
+use crate::{
+    runtime::event::Event,
+    server::{
+        error::{ApiError, ApiResult, RaiseInternalError},
+        AppState,
+    },
+};
+
+/// Header with the secret of a webhook. Clients that cannot set headers use `?secret=`.
+const SECRET_HEADER: &str = "X-Webhook-Secret";
+
+/// Dispatches a `WebhookEvent` carrying the JSON body, if the secret of the webhook is sent.
+#[axum::debug_handler]
+pub async fn receive_webhook(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<Json<serde_json::Value>> {
+    let Some(expected) = state.webhooks.get(&name) else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("webhook {name} is not configured"),
+        ));
+    };
+    let secret = headers
+        .get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or(query.get("secret").map(String::as_str));
+    if !secret.is_some_and(|secret| constant_time_eq(secret.as_bytes(), expected.as_bytes())) {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid webhook secret",
+        ));
+    }
+
+    let body = if body.trim().is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_str(&body)
+            .map_err(|e| ApiError::bad_request(format!("failed to parse webhook body: {e}")))?
+    };
+    state
+        .runtime
+        .dispatch_event(Event::from_webhook(&name, body))
+        .await
+        .raise_internal_error(Some("failed to dispatch event"))?;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Compares secrets in a time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
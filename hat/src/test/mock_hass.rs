@@ -0,0 +1,597 @@
+//! A local stand-in for Home Assistant, speaking enough of its websocket and REST APIs to test
+//! [`crate::integrations::home_assistant::HassIntegration`] without a real instance.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as WebSocketMessage;
+
+pub const MOCK_HA_VERSION: &str = "2024.10.0";
+
+/// A service call received by the mock, through the websocket or REST.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceCall {
+    pub domain: String,
+    pub service: String,
+    pub target: Value,
+    pub data: Value,
+}
+
+#[derive(Clone)]
+enum Broadcast {
+    Event { event_type: String, event: Value },
+    Disconnect,
+}
+
+#[derive(Default)]
+struct MockState {
+    entities: BTreeMap<String, Map<String, Value>>,
+    service_calls: Vec<ServiceCall>,
+    /// Errors returned by services, as `(code, message)`
+    failures: HashMap<String, (String, String)>,
+    /// Responses of the services that return data
+    responses: HashMap<String, Value>,
+    areas: Vec<Value>,
+    floors: Vec<Value>,
+    devices: Vec<Value>,
+    registry_entities: Vec<Value>,
+    last_updated: DateTime<Utc>,
+    subscriptions: usize,
+    /// State changes sent before the next reply to `get_states`
+    flood: usize,
+}
+
+impl MockState {
+    /// Sets the state of an entity, returning the `state_changed` event of the change.
+    fn change_state(&mut self, entity_id: &str, new_state: &str, attributes: Value) -> Value {
+        let timestamp = self.next_timestamp();
+        let Value::Object(new_state) = json!({
+            "entity_id": entity_id,
+            "state": new_state,
+            "attributes": attributes,
+            "last_changed": timestamp,
+            "last_updated": timestamp,
+            "context": { "id": "mock" },
+        }) else {
+            unreachable!()
+        };
+        let old_state = self
+            .entities
+            .insert(entity_id.to_owned(), new_state.clone());
+        json!({
+            "event_type": "state_changed",
+            "time_fired": timestamp,
+            "origin": "LOCAL",
+            "context": { "id": "mock" },
+            "data": {
+                "entity_id": entity_id,
+                "old_state": old_state,
+                "new_state": new_state,
+            },
+        })
+    }
+
+    /// A timestamp that is always newer than the previous one, like `last_updated` in HA.
+    fn next_timestamp(&mut self) -> String {
+        let now = Utc::now().max(self.last_updated + ChronoDuration::microseconds(1));
+        self.last_updated = now;
+        now.to_rfc3339()
+    }
+}
+
+pub struct MockHass {
+    addr: SocketAddr,
+    token: String,
+    state: Arc<Mutex<MockState>>,
+    broadcast: broadcast::Sender<Broadcast>,
+    server: JoinHandle<()>,
+}
+
+impl MockHass {
+    pub async fn start(token: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let (broadcast, _) = broadcast::channel(64);
+
+        let server = {
+            let state = Arc::clone(&state);
+            let broadcast = broadcast.clone();
+            let token = token.to_owned();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let connection = Connection {
+                        state: Arc::clone(&state),
+                        broadcast: broadcast.clone(),
+                        token: token.clone(),
+                    };
+                    tokio::spawn(connection.handle(stream));
+                }
+            })
+        };
+
+        Self {
+            addr,
+            token: token.to_owned(),
+            state,
+            broadcast,
+            server,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Sets the state of an entity, creating it if needed, and fires `state_changed`.
+    pub fn set_state(&self, entity_id: &str, state: &str, attributes: Value) {
+        set_state(&self.state, &self.broadcast, entity_id, state, attributes);
+    }
+
+    /// Changes the state of an entity without firing `state_changed`, like a change that
+    /// happened while Hat was disconnected.
+    pub fn set_state_silently(&self, entity_id: &str, state: &str) {
+        let mut lock = self.state.lock().unwrap();
+        let timestamp = lock.next_timestamp();
+        let entity = lock
+            .entities
+            .get_mut(entity_id)
+            .expect("entity should exist");
+        entity.insert("state".into(), state.into());
+        entity.insert("last_updated".into(), timestamp.into());
+    }
+
+    /// Sends `count` state changes of `sensor.flood` right before the next reply to
+    /// `get_states`, like a busy Home Assistant.
+    pub fn flood_before_states(&self, count: usize) {
+        self.state.lock().unwrap().flood = count;
+    }
+
+    pub fn get_state(&self, entity_id: &str) -> Option<String> {
+        let lock = self.state.lock().unwrap();
+        let entity = lock.entities.get(entity_id)?;
+        entity.get("state")?.as_str().map(|s| s.to_owned())
+    }
+
+    /// Waits until `count` subscriptions to state changes were made since the mock started.
+    pub async fn wait_for_subscriptions(&self, count: usize) {
+        for _ in 0..500 {
+            if self.state.lock().unwrap().subscriptions >= count {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("home assistant mock received no subscription");
+    }
+
+    /// Sends an event with any type and payload to the subscribers.
+    pub fn fire_event(&self, event_type: &str, event: Value) {
+        self.broadcast
+            .send(Broadcast::Event {
+                event_type: event_type.to_owned(),
+                event,
+            })
+            .ok();
+    }
+
+    /// Closes every websocket connection.
+    pub fn disconnect_all(&self) {
+        self.broadcast.send(Broadcast::Disconnect).ok();
+    }
+
+    /// Makes every call to `domain.service` fail with the given error code.
+    pub fn fail_service(&self, service: &str, code: &str, message: &str) {
+        let mut lock = self.state.lock().unwrap();
+        lock.failures
+            .insert(service.to_owned(), (code.to_owned(), message.to_owned()));
+    }
+
+    /// Makes `domain.service` return `response` to calls with `return_response`.
+    pub fn set_service_response(&self, service: &str, response: Value) {
+        let mut lock = self.state.lock().unwrap();
+        lock.responses.insert(service.to_owned(), response);
+    }
+
+    pub fn service_calls(&self) -> Vec<ServiceCall> {
+        self.state.lock().unwrap().service_calls.clone()
+    }
+
+    pub fn add_floor(&self, floor_id: &str, name: &str) {
+        let mut lock = self.state.lock().unwrap();
+        lock.floors
+            .push(json!({ "floor_id": floor_id, "name": name, "level": 0 }));
+    }
+
+    pub fn add_area(&self, area_id: &str, name: &str, floor_id: Option<&str>) {
+        let mut lock = self.state.lock().unwrap();
+        lock.areas
+            .push(json!({ "area_id": area_id, "name": name, "floor_id": floor_id }));
+    }
+
+    /// Registers a device in an area and its entities, which inherit the area.
+    /// Moves a device to another area, announcing it like Home Assistant does.
+    pub fn set_device_area(&self, device_id: &str, area_id: Option<&str>) {
+        {
+            let mut lock = self.state.lock().unwrap();
+            for device in &mut lock.devices {
+                if device["id"] == device_id {
+                    device["area_id"] = json!(area_id);
+                }
+            }
+        }
+        self.fire_event(
+            "device_registry_updated",
+            json!({
+                "event_type": "device_registry_updated",
+                "time_fired": Utc::now().to_rfc3339(),
+                "origin": "LOCAL",
+                "context": {},
+                "data": { "action": "update", "device_id": device_id },
+            }),
+        );
+    }
+
+    pub fn add_device(&self, device_id: &str, area_id: Option<&str>, entities: &[&str]) {
+        let mut lock = self.state.lock().unwrap();
+        lock.devices
+            .push(json!({ "id": device_id, "area_id": area_id }));
+        for entity_id in entities {
+            lock.registry_entities.push(json!({
+                "entity_id": entity_id,
+                "device_id": device_id,
+                "area_id": null,
+            }));
+        }
+    }
+}
+
+impl Drop for MockHass {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.disconnect_all();
+    }
+}
+
+struct Connection {
+    state: Arc<Mutex<MockState>>,
+    broadcast: broadcast::Sender<Broadcast>,
+    token: String,
+}
+
+impl Connection {
+    async fn handle(self, stream: TcpStream) {
+        let mut buf = [0; 64];
+        let Ok(n) = stream.peek(&mut buf).await else {
+            return;
+        };
+        if buf[..n].starts_with(b"GET /api/websocket") {
+            self.handle_websocket(stream).await;
+        } else {
+            self.handle_rest(stream).await;
+        }
+    }
+
+    async fn handle_websocket(self, stream: TcpStream) {
+        let Ok(ws) = tokio_tungstenite::accept_async(stream).await else {
+            return;
+        };
+        let (mut tx, mut rx) = ws.split();
+        let send = |msg: Value| WebSocketMessage::Text(msg.to_string());
+
+        if tx
+            .send(send(
+                json!({ "type": "auth_required", "ha_version": MOCK_HA_VERSION }),
+            ))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let auth = match rx.next().await {
+            Some(Ok(WebSocketMessage::Text(auth))) => auth,
+            _ => return,
+        };
+        let auth: Value = serde_json::from_str(&auth).unwrap_or_default();
+        if auth["type"] != "auth" || auth["access_token"] != self.token.as_str() {
+            tx.send(send(
+                json!({ "type": "auth_invalid", "message": "Invalid access token" }),
+            ))
+            .await
+            .ok();
+            return;
+        }
+        if tx
+            .send(send(
+                json!({ "type": "auth_ok", "ha_version": MOCK_HA_VERSION }),
+            ))
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        let mut broadcast = self.broadcast.subscribe();
+        // Subscription ID and event type filter
+        let mut subscriptions: Vec<(u64, Option<String>)> = Vec::new();
+
+        loop {
+            tokio::select! {
+                msg = rx.next() => {
+                    let msg = match msg {
+                        Some(Ok(WebSocketMessage::Text(msg))) => msg,
+                        Some(Ok(_)) => continue,
+                        _ => break,
+                    };
+                    let Ok(msg) = serde_json::from_str::<Value>(&msg) else {
+                        continue;
+                    };
+                    let id = msg["id"].as_u64().unwrap_or_default();
+                    let response = match msg["type"].as_str().unwrap_or_default() {
+                        "ping" => json!({ "id": id, "type": "pong" }),
+                        "subscribe_events" => {
+                            let event_type = msg["event_type"].as_str().map(|t| t.to_owned());
+                            if event_type.as_deref() == Some("state_changed") {
+                                self.state.lock().unwrap().subscriptions += 1;
+                            }
+                            subscriptions.push((id, event_type));
+                            success(id, Value::Null)
+                        }
+                        "get_states" => {
+                            let flood = {
+                                let mut lock = self.state.lock().unwrap();
+                                let count = std::mem::take(&mut lock.flood);
+                                (0..count)
+                                    .map(|i| lock.change_state("sensor.flood", &i.to_string(), json!({})))
+                                    .collect::<Vec<_>>()
+                            };
+                            for event in flood {
+                                for (id, filter) in &subscriptions {
+                                    if filter.as_deref() == Some("state_changed") {
+                                        let msg = json!({ "id": id, "type": "event", "event": event });
+                                        if tx.send(send(msg)).await.is_err() {
+                                            return;
+                                        }
+                                    }
+                                }
+                            }
+                            let lock = self.state.lock().unwrap();
+                            success(id, json!(lock.entities.values().collect::<Vec<_>>()))
+                        }
+                        "call_service" => {
+                            let call = ServiceCall {
+                                domain: msg["domain"].as_str().unwrap_or_default().to_owned(),
+                                service: msg["service"].as_str().unwrap_or_default().to_owned(),
+                                target: msg["target"].clone(),
+                                data: msg["service_data"].clone(),
+                            };
+                            let name = format!("{}.{}", call.domain, call.service);
+                            let response = match msg["return_response"].as_bool() {
+                                Some(true) => self.state.lock().unwrap().responses.get(&name).cloned(),
+                                _ => None,
+                            };
+                            match self.call_service(call) {
+                                Ok(()) => success(id, json!({ "context": {}, "response": response })),
+                                Err((code, message)) => failure(id, &code, &message),
+                            }
+                        }
+                        msg_type @ ("config/area_registry/list"
+                        | "config/floor_registry/list"
+                        | "config/device_registry/list"
+                        | "config/entity_registry/list") => {
+                            let lock = self.state.lock().unwrap();
+                            let list = match msg_type {
+                                "config/area_registry/list" => &lock.areas,
+                                "config/floor_registry/list" => &lock.floors,
+                                "config/device_registry/list" => &lock.devices,
+                                _ => &lock.registry_entities,
+                            };
+                            success(id, json!(list))
+                        }
+                        msg_type => failure(id, "unknown_command", &format!("Unknown command {msg_type}")),
+                    };
+                    if tx.send(send(response)).await.is_err() {
+                        break;
+                    }
+                }
+                broadcast = broadcast.recv() => {
+                    let (event_type, event) = match broadcast {
+                        Ok(Broadcast::Event { event_type, event }) => (event_type, event),
+                        Ok(Broadcast::Disconnect) | Err(_) => break,
+                    };
+                    for (id, filter) in &subscriptions {
+                        if filter.as_ref().is_some_and(|f| f != &event_type) {
+                            continue;
+                        }
+                        let msg = json!({ "id": id, "type": "event", "event": event });
+                        if tx.send(send(msg)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        tx.close().await.ok();
+    }
+
+    async fn handle_rest(self, mut stream: TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        let header_end = loop {
+            let Ok(n) = stream.read(&mut buf).await else {
+                return;
+            };
+            if n == 0 {
+                return;
+            }
+            request.extend_from_slice(&buf[..n]);
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&request[..header_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_owned();
+        let path = request_line.next().unwrap_or_default().to_owned();
+        let headers = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_owned()))
+            .collect::<HashMap<_, _>>();
+        let content_length = headers
+            .get("content-length")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or_default();
+        while request.len() < header_end + content_length {
+            let Ok(n) = stream.read(&mut buf).await else {
+                return;
+            };
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        let body: Value = serde_json::from_slice(&request[header_end..]).unwrap_or_default();
+
+        let (status, response) =
+            if headers.get("authorization") != Some(&format!("Bearer {}", self.token)) {
+                (401, json!({ "message": "Unauthorized" }))
+            } else {
+                self.route(&method, &path, body)
+            };
+
+        let response = response.to_string();
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            _ => "Not Found",
+        };
+        let http = format!(
+            "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+            response.len()
+        );
+        stream.write_all(http.as_bytes()).await.ok();
+    }
+
+    fn route(&self, method: &str, path: &str, body: Value) -> (u16, Value) {
+        let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        match (method, segments.as_slice()) {
+            ("GET", ["api", "states"]) => {
+                let lock = self.state.lock().unwrap();
+                (200, json!(lock.entities.values().collect::<Vec<_>>()))
+            }
+            ("GET", ["api", "states", entity_id]) => {
+                let lock = self.state.lock().unwrap();
+                match lock.entities.get(*entity_id) {
+                    Some(entity) => (200, json!(entity)),
+                    None => (404, json!({ "message": "Entity not found." })),
+                }
+            }
+            ("POST", ["api", "services", domain, service]) => {
+                let mut data = match body {
+                    Value::Object(data) => data,
+                    _ => Map::new(),
+                };
+                let target = match data.remove("entity_id") {
+                    Some(entity_id) => json!({ "entity_id": entity_id }),
+                    None => Value::Null,
+                };
+                let call = ServiceCall {
+                    domain: domain.to_string(),
+                    service: service.to_string(),
+                    target,
+                    data: Value::Object(data),
+                };
+                match self.call_service(call) {
+                    Ok(()) => (200, json!([])),
+                    Err((_, message)) => (400, json!({ "message": message })),
+                }
+            }
+            _ => (404, json!({ "message": "Not found" })),
+        }
+    }
+
+    /// Records a service call and applies its effect on the state of simple entities.
+    fn call_service(&self, call: ServiceCall) -> Result<(), (String, String)> {
+        let new_state = {
+            let mut lock = self.state.lock().unwrap();
+            let name = format!("{}.{}", call.domain, call.service);
+            lock.service_calls.push(call.clone());
+            if let Some(failure) = lock.failures.get(&name) {
+                return Err(failure.clone());
+            }
+            let entity_id = call.target["entity_id"].as_str().map(|e| e.to_owned());
+            if let Some(entity_id) = &entity_id {
+                if !lock.entities.contains_key(entity_id) {
+                    return Err(("not_found".into(), format!("Entity {entity_id} not found")));
+                }
+            }
+            let new_state = match call.service.as_str() {
+                "turn_on" => Some("on"),
+                "turn_off" => Some("off"),
+                "lock" => Some("locked"),
+                "unlock" => Some("unlocked"),
+                "open_cover" => Some("open"),
+                "close_cover" => Some("closed"),
+                _ => None,
+            };
+            entity_id.zip(new_state).map(|(entity_id, new_state)| {
+                let attributes = lock.entities[&entity_id]["attributes"].clone();
+                (entity_id, new_state, attributes)
+            })
+        };
+        if let Some((entity_id, new_state, attributes)) = new_state {
+            set_state(
+                &self.state,
+                &self.broadcast,
+                &entity_id,
+                new_state,
+                attributes,
+            );
+        }
+        Ok(())
+    }
+}
+
+fn set_state(
+    state: &Mutex<MockState>,
+    broadcast: &broadcast::Sender<Broadcast>,
+    entity_id: &str,
+    new_state: &str,
+    attributes: Value,
+) {
+    let event = state
+        .lock()
+        .unwrap()
+        .change_state(entity_id, new_state, attributes);
+    broadcast
+        .send(Broadcast::Event {
+            event_type: "state_changed".into(),
+            event,
+        })
+        .ok();
+}
+
+fn success(id: u64, result: Value) -> Value {
+    json!({ "id": id, "type": "result", "success": true, "result": result })
+}
+
+fn failure(id: u64, code: &str, message: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "result",
+        "success": false,
+        "error": { "code": code, "message": message },
+    })
+}
@@ -0,0 +1,241 @@
+//! A local stand-in for a Philips Hue bridge, serving enough of the v2 API and its event
+//! stream to test [`crate::integrations::hue::HueIntegration`] without a bridge.
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event as SseEvent, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// A `PUT` received by the mock, as `(resource type, id, body)`.
+pub type PutRequest = (String, String, Value);
+
+#[derive(Clone)]
+enum Broadcast {
+    Message(Value),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct MockState {
+    resources: BTreeMap<String, Value>,
+    put_requests: Vec<PutRequest>,
+    streams: usize,
+}
+
+#[derive(Clone)]
+struct Shared {
+    application_key: String,
+    state: Arc<Mutex<MockState>>,
+    broadcast: broadcast::Sender<Broadcast>,
+}
+
+impl Shared {
+    /// The error response for requests without the application key, if any.
+    fn unauthorized(&self, headers: &HeaderMap) -> Option<Response> {
+        match headers.get("hue-application-key") {
+            Some(key) if key == self.application_key.as_str() => None,
+            _ => Some(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "errors": [{ "description": "unauthorized user" }], "data": [] })),
+                )
+                    .into_response(),
+            ),
+        }
+    }
+
+    /// Merges `update` into a resource and sends it through the event stream.
+    fn update(&self, id: &str, update: Value) {
+        let mut state = self.state.lock().unwrap();
+        let resource = state.resources.get_mut(id).expect("resource should exist");
+        merge(resource, &update);
+        let mut data = update;
+        data["id"] = json!(id);
+        data["type"] = resource["type"].clone();
+        self.broadcast
+            .send(Broadcast::Message(
+                json!([{ "type": "update", "data": [data] }]),
+            ))
+            .ok();
+    }
+}
+
+pub struct MockHueBridge {
+    addr: SocketAddr,
+    shared: Shared,
+    server: JoinHandle<()>,
+}
+
+impl MockHueBridge {
+    pub async fn start(application_key: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (broadcast, _) = broadcast::channel(64);
+        let shared = Shared {
+            application_key: application_key.to_owned(),
+            state: Arc::default(),
+            broadcast,
+        };
+
+        let router = Router::new()
+            .route("/clip/v2/resource", get(get_resources))
+            .route(
+                "/clip/v2/resource/:rtype/:id",
+                axum::routing::put(put_resource),
+            )
+            .route("/eventstream/clip/v2", get(event_stream))
+            .with_state(shared.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        Self {
+            addr,
+            shared,
+            server,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Adds a resource, like `{"id": "...", "type": "light", ...}`, without notifying.
+    pub fn add_resource(&self, resource: Value) {
+        let id = resource["id"].as_str().unwrap().to_owned();
+        let mut state = self.shared.state.lock().unwrap();
+        state.resources.insert(id, resource);
+    }
+
+    /// Changes a resource and sends the change through the event stream, like the bridge
+    /// does when a device reports.
+    pub fn update(&self, id: &str, update: Value) {
+        self.shared.update(id, update);
+    }
+
+    /// Changes a resource without notifying, like a change that happened while Hat was
+    /// disconnected.
+    pub fn update_silently(&self, id: &str, update: Value) {
+        let mut state = self.shared.state.lock().unwrap();
+        merge(state.resources.get_mut(id).unwrap(), &update);
+    }
+
+    pub fn put_requests(&self) -> Vec<PutRequest> {
+        self.shared.state.lock().unwrap().put_requests.clone()
+    }
+
+    /// Waits until `count` event streams were opened since the mock started.
+    pub async fn wait_for_streams(&self, count: usize) {
+        for _ in 0..500 {
+            if self.shared.state.lock().unwrap().streams >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("hue bridge mock received no event stream connection");
+    }
+
+    /// Closes every event stream.
+    pub fn disconnect_all(&self) {
+        self.shared.broadcast.send(Broadcast::Disconnect).ok();
+    }
+}
+
+impl Drop for MockHueBridge {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.disconnect_all();
+    }
+}
+
+async fn get_resources(State(shared): State<Shared>, headers: HeaderMap) -> Response {
+    if let Some(response) = shared.unauthorized(&headers) {
+        return response;
+    }
+    let state = shared.state.lock().unwrap();
+    let data = state.resources.values().cloned().collect::<Vec<_>>();
+    Json(json!({ "errors": [], "data": data })).into_response()
+}
+
+async fn put_resource(
+    State(shared): State<Shared>,
+    Path((rtype, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    if let Some(response) = shared.unauthorized(&headers) {
+        return response;
+    }
+    {
+        let mut state = shared.state.lock().unwrap();
+        let exists = state
+            .resources
+            .get(&id)
+            .is_some_and(|resource| resource["type"] == rtype.as_str());
+        if !exists {
+            let errors = json!([{ "description": format!("Not found: /{rtype}/{id}") }]);
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "errors": errors, "data": [] })),
+            )
+                .into_response();
+        }
+        state
+            .put_requests
+            .push((rtype.clone(), id.clone(), body.clone()));
+    }
+    shared.update(&id, body);
+    Json(json!({ "errors": [], "data": [{ "rid": id, "rtype": rtype }] })).into_response()
+}
+
+async fn event_stream(State(shared): State<Shared>, headers: HeaderMap) -> Response {
+    if let Some(response) = shared.unauthorized(&headers) {
+        return response;
+    }
+    let receiver = shared.broadcast.subscribe();
+    shared.state.lock().unwrap().streams += 1;
+    // The bridge starts every stream with a comment
+    let hello = futures_util::stream::once(async { Ok(SseEvent::default().comment("hi")) });
+    let messages = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(Broadcast::Message(message)) => {
+                    let event = SseEvent::default().data(message.to_string());
+                    return Some((Ok::<_, Infallible>(event), receiver));
+                }
+                Ok(Broadcast::Disconnect) | Err(broadcast::error::RecvError::Closed) => {
+                    return None
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+            }
+        }
+    });
+    Sse::new(futures_util::StreamExt::chain(hello, messages)).into_response()
+}
+
+fn merge(target: &mut Value, update: &Value) {
+    match (target, update) {
+        (Value::Object(target), Value::Object(update)) => {
+            for (key, value) in update {
+                match target.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, update) => *target = update.clone(),
+    }
+}
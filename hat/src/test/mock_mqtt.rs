@@ -0,0 +1,225 @@
+//! A local stand-in for an MQTT broker, speaking enough of MQTT 3.1.1 to test
+//! [`crate::integrations::mqtt::MqttIntegration`] without mosquitto.
+
+use bytes::BytesMut;
+use rumqttc::mqttbytes::Error as MqttBytesError;
+use rumqttc::{
+    matches, ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, Publish, QoS, SubAck,
+    SubscribeReasonCode,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Default)]
+struct BrokerState {
+    retained: BTreeMap<String, String>,
+    /// Messages published by clients, in order
+    received: Vec<(String, String)>,
+    clients: HashMap<usize, Client>,
+    next_client_id: usize,
+}
+
+struct Client {
+    filters: Vec<String>,
+    tx: mpsc::UnboundedSender<Packet>,
+}
+
+impl BrokerState {
+    fn route(&mut self, topic: &str, payload: &str, retain: bool) {
+        if retain {
+            self.retained.insert(topic.to_owned(), payload.to_owned());
+        }
+        for client in self.clients.values() {
+            if client.filters.iter().any(|f| matches(topic, f)) {
+                let publish = Publish::new(topic, QoS::AtMostOnce, payload);
+                client.tx.send(Packet::Publish(publish)).ok();
+            }
+        }
+    }
+}
+
+pub struct MockBroker {
+    addr: SocketAddr,
+    state: Arc<Mutex<BrokerState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockBroker {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+
+        let server = {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle_connection(Arc::clone(&state), stream));
+                }
+            })
+        };
+
+        Self {
+            addr,
+            state,
+            server,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Publishes a message to the subscribed clients, like a device would.
+    pub fn publish(&self, topic: &str, payload: &str, retain: bool) {
+        self.state.lock().unwrap().route(topic, payload, retain);
+    }
+
+    /// Waits until a client subscribes to `filter`.
+    pub async fn wait_for_subscription(&self, filter: &str) {
+        wait_until(|| {
+            let state = self.state.lock().unwrap();
+            state
+                .clients
+                .values()
+                .any(|c| c.filters.iter().any(|f| f == filter))
+        })
+        .await;
+    }
+
+    /// Waits for a client to publish on `topic`, returning the payload.
+    pub async fn next_message(&self, topic: &str) -> String {
+        let mut payload = None;
+        wait_until(|| {
+            let mut state = self.state.lock().unwrap();
+            let position = state.received.iter().position(|(t, _)| t == topic);
+            payload = position.map(|i| state.received.remove(i).1);
+            payload.is_some()
+        })
+        .await;
+        payload.unwrap()
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn wait_until(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the mock broker");
+}
+
+async fn handle_connection(state: Arc<Mutex<BrokerState>>, stream: TcpStream) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+    let client_id = {
+        let mut state = state.lock().unwrap();
+        state.next_client_id += 1;
+        let id = state.next_client_id;
+        state.clients.insert(
+            id,
+            Client {
+                filters: Vec::new(),
+                tx: tx.clone(),
+            },
+        );
+        id
+    };
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            let mut buffer = BytesMut::new();
+            let written = match packet {
+                Packet::ConnAck(p) => p.write(&mut buffer),
+                Packet::SubAck(p) => p.write(&mut buffer),
+                Packet::PubAck(p) => p.write(&mut buffer),
+                Packet::Publish(p) => p.write(&mut buffer),
+                Packet::PingResp => PingResp.write(&mut buffer),
+                packet => panic!("mock broker cannot send {packet:?}"),
+            };
+            written.unwrap();
+            if writer.write_all(&buffer).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut buffer = BytesMut::new();
+    'connection: loop {
+        let packet = loop {
+            match rumqttc::mqttbytes::v4::read(&mut buffer, MAX_PACKET_SIZE) {
+                Ok(packet) => break packet,
+                Err(MqttBytesError::InsufficientBytes(_)) => {
+                    match reader.read_buf(&mut buffer).await {
+                        Ok(0) | Err(_) => break 'connection,
+                        Ok(_) => {}
+                    }
+                }
+                Err(e) => panic!("mock broker received an invalid packet: {e:?}"),
+            }
+        };
+
+        match packet {
+            Packet::Connect(_) => {
+                let connack = ConnAck::new(ConnectReturnCode::Success, false);
+                tx.send(Packet::ConnAck(connack)).ok();
+            }
+            Packet::Subscribe(subscribe) => {
+                let codes = subscribe
+                    .filters
+                    .iter()
+                    .map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce))
+                    .collect();
+                tx.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)))
+                    .ok();
+                let mut state = state.lock().unwrap();
+                for filter in subscribe.filters {
+                    for (topic, payload) in &state.retained {
+                        if matches(topic, &filter.path) {
+                            let publish = Publish::new(topic, QoS::AtMostOnce, payload.as_str());
+                            tx.send(Packet::Publish(publish)).ok();
+                        }
+                    }
+                    if let Some(client) = state.clients.get_mut(&client_id) {
+                        client.filters.push(filter.path);
+                    }
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.qos != QoS::AtMostOnce {
+                    tx.send(Packet::PubAck(PubAck::new(publish.pkid))).ok();
+                }
+                let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                let mut state = state.lock().unwrap();
+                state
+                    .received
+                    .push((publish.topic.clone(), payload.clone()));
+                state.route(&publish.topic, &payload, publish.retain);
+            }
+            Packet::PingReq => {
+                tx.send(Packet::PingResp).ok();
+            }
+            Packet::Disconnect => break,
+            _ => {}
+        }
+    }
+
+    state.lock().unwrap().clients.remove(&client_id);
+    writer_task.abort();
+}
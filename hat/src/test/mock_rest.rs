@@ -0,0 +1,131 @@
+//! A local stand-in for gadgets with a JSON HTTP endpoint, to test
+//! [`crate::integrations::rest::RestIntegration`].
+
+use axum::extract::State;
+use axum::http::header::LOCATION;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A request received by the mock, as `(method, path, body)`.
+pub type Request = (String, String, String);
+
+#[derive(Default)]
+struct MockState {
+    documents: HashMap<String, Value>,
+    failing: HashSet<String>,
+    redirects: HashMap<String, String>,
+    requests: Vec<Request>,
+    headers: Vec<HeaderMap>,
+}
+
+type Shared = Arc<Mutex<MockState>>;
+
+pub struct MockRestServer {
+    addr: SocketAddr,
+    state: Shared,
+    server: JoinHandle<()>,
+}
+
+impl MockRestServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Shared::default();
+        let router = Router::new()
+            .fallback(handle)
+            .with_state(Arc::clone(&state));
+        let server = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        Self {
+            addr,
+            state,
+            server,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// Sets the document returned by `GET path`.
+    pub fn set_json(&self, path: &str, document: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.documents.insert(path.to_owned(), document);
+    }
+
+    /// Makes every request to `path` fail with a 500, or succeed again.
+    pub fn set_failing(&self, path: &str, failing: bool) {
+        let mut state = self.state.lock().unwrap();
+        if failing {
+            state.failing.insert(path.to_owned());
+        } else {
+            state.failing.remove(path);
+        }
+    }
+
+    /// Makes every request to `path` redirect to `location`.
+    pub fn set_redirect(&self, path: &str, location: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.redirects.insert(path.to_owned(), location.to_owned());
+    }
+
+    /// Requests other than `GET`, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Headers of the requests returned by [`Self::requests`], in the same order.
+    pub fn request_headers(&self) -> Vec<HeaderMap> {
+        self.state.lock().unwrap().headers.clone()
+    }
+}
+
+impl Drop for MockRestServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// `GET` returns the document of the path. Other methods are recorded, and a JSON object
+/// in their body is merged into the document, like a gadget applying a command.
+async fn handle(
+    State(state): State<Shared>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let path = uri.path().to_owned();
+    if state.failing.contains(&path) {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    if let Some(location) = state.redirects.get(&path) {
+        return (StatusCode::FOUND, [(LOCATION, location.clone())]).into_response();
+    }
+    if method == Method::GET {
+        return match state.documents.get(&path) {
+            Some(document) => Json(document.clone()).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        };
+    }
+    state
+        .requests
+        .push((method.to_string(), path.clone(), body.clone()));
+    state.headers.push(headers);
+    if let (Ok(Value::Object(update)), Some(Value::Object(document))) = (
+        serde_json::from_str::<Value>(&body),
+        state.documents.get_mut(&path),
+    ) {
+        document.extend(update);
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
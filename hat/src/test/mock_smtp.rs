@@ -0,0 +1,104 @@
+//! A local SMTP sink that accepts every email, to test
+//! [`crate::notifiers::SmtpNotifier`].
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// An email received by the sink.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Email {
+    /// Credentials of `AUTH PLAIN`, still base64 encoded
+    pub auth: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Everything sent after `DATA`, without the final `.` line
+    pub data: String,
+}
+
+type Shared = Arc<Mutex<Vec<Email>>>;
+
+pub struct MockSmtpServer {
+    addr: SocketAddr,
+    emails: Shared,
+    server: JoinHandle<()>,
+}
+
+impl MockSmtpServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let emails = Shared::default();
+        let server = tokio::spawn({
+            let emails = Arc::clone(&emails);
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle(stream, Arc::clone(&emails)));
+                }
+            }
+        });
+        Self {
+            addr,
+            emails,
+            server,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Emails received, in order.
+    pub fn emails(&self) -> Vec<Email> {
+        self.emails.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockSmtpServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn handle(stream: TcpStream, emails: Shared) {
+    let mut stream = BufReader::new(stream);
+    let mut email = Email::default();
+    stream.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+    let mut line = String::new();
+    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+        let command = line.trim_end().to_owned();
+        line.clear();
+        let reply: &[u8] = if command.starts_with("EHLO") {
+            b"250-mock\r\n250 AUTH PLAIN\r\n"
+        } else if let Some(credentials) = command.strip_prefix("AUTH PLAIN ") {
+            email.auth = Some(credentials.to_owned());
+            b"235 OK\r\n"
+        } else if let Some(from) = command.strip_prefix("MAIL FROM:") {
+            email.from = from.trim_matches(['<', '>']).to_owned();
+            b"250 OK\r\n"
+        } else if let Some(to) = command.strip_prefix("RCPT TO:") {
+            email.to.push(to.trim_matches(['<', '>']).to_owned());
+            b"250 OK\r\n"
+        } else if command == "DATA" {
+            stream.write_all(b"354 go ahead\r\n").await.unwrap();
+            while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                if line == ".\r\n" {
+                    break;
+                }
+                email.data.push_str(&line);
+                line.clear();
+            }
+            line.clear();
+            emails.lock().unwrap().push(std::mem::take(&mut email));
+            b"250 OK\r\n"
+        } else if command == "QUIT" {
+            stream.write_all(b"221 bye\r\n").await.unwrap();
+            return;
+        } else {
+            b"502 not implemented\r\n"
+        };
+        stream.write_all(reply).await.unwrap();
+    }
+}
@@ -1,13 +1,47 @@
+mod mock_hass;
+mod mock_hue;
+mod mock_mqtt;
+mod mock_rest;
+mod mock_smtp;
+
+use crate::config::{Config, Features, ServerConfig};
+use crate::integrations::calendar::{Calendar, CalendarIntegration};
+use crate::integrations::dummy::DummyIntegration;
+use crate::integrations::home_assistant::command::HassError;
+use crate::integrations::home_assistant::HassIntegration;
+use crate::integrations::hue::HueIntegration;
+use crate::integrations::mqtt::{MqttIntegration, MqttSettings};
+use crate::integrations::remote::RemoteHatIntegration;
+use crate::integrations::rest::{RestDeviceSettings, RestIntegration};
+use crate::integrations::simulation::{Scenario, SimulationIntegration};
+use crate::integrations::subscribers::Subscribers;
+use crate::integrations::{is_valid_integration_id, Integration};
 use crate::runtime::context::{ExpressionContext, Trigger};
-use crate::runtime::device::{Device, DeviceType};
-use crate::runtime::event::{Event, EventType};
-use crate::runtime::function::FunctionCall;
+use crate::runtime::device::{Capability, Device, DeviceCommand, DeviceState, DeviceType};
+use crate::runtime::event::{
+    Event, EventDescriptor, EventType, NEW_STATE_PARAMETER, OLD_STATE_PARAMETER, ZONE_PARAMETER,
+};
+use crate::runtime::function::{Function as HatFunction, FunctionCall, FunctionCategory};
 use crate::runtime::parser::expression::Expression;
 use crate::runtime::parser::expression::Expression::{BinaryOperation, Constant, Function};
 use crate::runtime::parser::operation::Operation;
-use crate::runtime::value::Value;
-use crate::runtime::HatRuntime;
+use crate::runtime::value::{Value, ValueType};
+use crate::runtime::{HatRuntime, RuntimeError, RuntimeSettings, MAX_EMIT_DEPTH};
+use crate::server::make_router_with_config;
+use anyhow::{ensure, Result};
+use mock_hass::{MockHass, ServiceCall};
+use mock_hue::MockHueBridge;
+use mock_mqtt::MockBroker;
+use mock_rest::MockRestServer;
+use mock_smtp::{Email, MockSmtpServer};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
 #[tokio::test]
 pub async fn test_parse_sample() {
@@ -33,12 +67,16 @@ pub async fn test_function_call() {
                 id: "test_dev".to_string(),
                 name: None,
                 typ: DeviceType::Dummy,
+                capabilities: DeviceType::Dummy.default_capabilities().to_vec(),
+                area: None,
+                floor: None,
                 state: None,
                 attributes: Default::default(),
             },
             parameters: Default::default(),
         }),
         runtime: Arc::clone(&runtime),
+        automation: None,
     };
 
     let expression: Expression = BinaryOperation {
@@ -54,3 +92,2995 @@ pub async fn test_function_call() {
 
     assert_eq!(result, Value::String("Example-test@test_dev".into()));
 }
+
+#[tokio::test]
+pub async fn test_function_catalog() {
+    let runtime = HatRuntime::new().await;
+
+    let functions = runtime.get_functions();
+
+    let names = functions
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect::<Vec<_>>();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    let turn_on = functions
+        .iter()
+        .find(|f| f.name == "turn_on_device")
+        .expect("turn_on_device should be registered");
+    assert_eq!(turn_on.category, FunctionCategory::Action);
+    assert_eq!(turn_on.parameters.len(), 1);
+    assert_eq!(turn_on.parameters[0].typ, ValueType::String);
+
+    assert!(functions.iter().all(|f| !f.description.is_empty()));
+
+    let set_temperature = functions
+        .iter()
+        .find(|f| f.name == "set_temperature")
+        .expect("set_temperature should be registered");
+    assert_eq!(set_temperature.parameters.len(), 2);
+    assert_eq!(set_temperature.parameters[1].typ, ValueType::Number);
+}
+
+/// Provides `greet`, which answers with the ID of the integration
+struct FunctionProviderIntegration(&'static str);
+
+#[async_trait::async_trait]
+impl Integration for FunctionProviderIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        Ok(Vec::new())
+    }
+    async fn get_device(&self, _: &str) -> Result<Option<Device>> {
+        Ok(None)
+    }
+    async fn turn_on_device(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+    async fn turn_off_device(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+    async fn set_light_color_rgb(&self, _: &str, _: [u8; 3]) -> Result<()> {
+        Ok(())
+    }
+    async fn set_light_brightness(&self, _: &str, _: u8) -> Result<()> {
+        Ok(())
+    }
+    fn subscribe(&self, capacity: usize) -> mpsc::Receiver<Event> {
+        let (_tx, rx) = mpsc::channel(capacity);
+        rx
+    }
+    fn get_id(&self) -> &str {
+        self.0
+    }
+    fn get_functions(&self) -> Vec<HatFunction> {
+        let greeting = Arc::new(format!("hello from {}", self.0));
+        vec![HatFunction {
+            name: "greet".into(),
+            description: "Greets",
+            category: FunctionCategory::Utility,
+            parameters: vec![],
+            returns: ValueType::String,
+            fun: Arc::new(move |_ctx, _args| {
+                let greeting = Arc::clone(&greeting);
+                Box::pin(async move { Ok(Value::String(greeting.to_string())) })
+            }),
+        }]
+    }
+    fn get_event_descriptors(&self) -> Vec<EventDescriptor> {
+        vec![EventDescriptor {
+            event: EventType::custom("ProviderEvent"),
+            description: "Provider event",
+            related_device_type: None,
+        }]
+    }
+}
+
+#[tokio::test]
+pub async fn test_integration_functions() {
+    let runtime = HatRuntime::new().await;
+    runtime
+        .integrate(FunctionProviderIntegration("provider"))
+        .await
+        .unwrap();
+
+    assert!(runtime.get_function("provider.greet").is_some());
+    assert!(runtime
+        .get_event_descriptors()
+        .iter()
+        .any(|d| d.event == EventType::custom("ProviderEvent")));
+
+    let context = Arc::new(ExpressionContext {
+        trigger: Trigger::Task(crate::runtime::scheduler::TaskID(Default::default())),
+        runtime: Arc::clone(&runtime),
+        automation: None,
+    });
+    for name in ["provider.greet", "greet"] {
+        let expression = Function(FunctionCall {
+            name: name.into(),
+            arguments: vec![],
+        });
+        let result = expression.evaluate(Arc::clone(&context)).await.unwrap();
+        assert_eq!(result, Value::String("hello from provider".into()));
+    }
+
+    runtime
+        .parse(
+            "test.hat".into(),
+            "automation a (ProviderEvent) { run provider.greet() }",
+        )
+        .await
+        .unwrap();
+
+    // A second provider makes the unqualified name ambiguous instead of picking one
+    runtime
+        .integrate(FunctionProviderIntegration("other"))
+        .await
+        .unwrap();
+    let call = |name: &str| {
+        Function(FunctionCall {
+            name: name.into(),
+            arguments: vec![],
+        })
+    };
+    let error = call("greet")
+        .evaluate(Arc::clone(&context))
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{error:#}").contains("call one of other.greet, provider.greet"),
+        "{error:#}"
+    );
+    assert_eq!(
+        call("other.greet")
+            .evaluate(Arc::clone(&context))
+            .await
+            .unwrap(),
+        Value::String("hello from other".into())
+    );
+    assert!(runtime.remove_integration("other").await.is_some());
+
+    assert!(runtime.remove_integration("provider").await.is_some());
+    assert!(runtime.get_function("provider.greet").is_none());
+    assert!(runtime.get_event_descriptors().is_empty());
+}
+
+#[tokio::test]
+pub async fn test_custom_events() {
+    let runtime = HatRuntime::new().await;
+
+    runtime
+        .parse(
+            "test.hat".into(),
+            r#"
+            event GoodNight
+            event Received
+
+            automation "Relay" (GoodNight) {
+                run emit("Received", {"room": "bedroom", "level": 2, "tags": ["a", "b"]})
+            }
+            "#,
+        )
+        .await
+        .unwrap();
+
+    assert!(runtime.is_event_known(&EventType::from_name("goodnight")));
+    assert!(!runtime.is_event_known(&EventType::from_name("MotionDetectEvent")));
+    assert_eq!(
+        EventType::from_name("LightOnEvent"),
+        EventType::LightOnEvent
+    );
+    assert!(runtime
+        .emit_event("MotionDetectEvent", Default::default())
+        .await
+        .is_err());
+
+    runtime.replace_source("test.hat".into(), "").await.unwrap();
+    assert!(!runtime.is_event_known(&EventType::from_name("GoodNight")));
+
+    // An automation emitting its own trigger stops after a few rounds
+    runtime
+        .replace_source(
+            "test.hat".into(),
+            r#"
+            event Ping
+            automation "Echo" (Ping) { run emit("Ping") }
+            "#,
+        )
+        .await
+        .unwrap();
+    let mut events = runtime.subscribe_events();
+    runtime
+        .emit_event("Ping", Default::default())
+        .await
+        .unwrap();
+    let mut depths = Vec::new();
+    // Other events, like clock ticks, are also dispatched
+    while let Ok(event) = tokio::time::timeout(Duration::from_millis(500), events.recv()).await {
+        let event = event.unwrap();
+        if event.typ == EventType::custom("Ping") {
+            depths.push(event.device.attributes["depth"].as_u64().unwrap());
+        }
+    }
+    assert_eq!(depths, (1..=MAX_EMIT_DEPTH).collect::<Vec<_>>());
+}
+
+/// Evaluates a single Hat expression with the given trigger
+async fn evaluate(runtime: &Arc<HatRuntime>, trigger: Trigger, expression: &str) -> Value {
+    try_evaluate(runtime, trigger, expression).await.unwrap()
+}
+
+async fn try_evaluate(
+    runtime: &Arc<HatRuntime>,
+    trigger: Trigger,
+    expression: &str,
+) -> Result<Value> {
+    let context = Arc::new(ExpressionContext {
+        trigger,
+        runtime: Arc::clone(runtime),
+        automation: Some("test automation".into()),
+    });
+    let program = crate::runtime::parser::parse(
+        "test.hat".into(),
+        &format!("automation a (Dummy) {{ run {expression} }}"),
+    )
+    .unwrap();
+    program.automations[0].actions[0].evaluate(context).await
+}
+
+fn sensor_event(old_state: f64, new_state: f64) -> Event {
+    Event {
+        typ: EventType::SensorValueChangeEvent,
+        datetime: Default::default(),
+        device: Device {
+            integration: "test".to_string(),
+            id: "sensor.temperature".to_string(),
+            name: None,
+            typ: DeviceType::Sensor,
+            capabilities: DeviceType::Sensor.default_capabilities().to_vec(),
+            area: None,
+            floor: None,
+            state: Some(new_state.to_string()),
+            attributes: Default::default(),
+        },
+        parameters: HashMap::from([
+            (OLD_STATE_PARAMETER.to_owned(), old_state.into()),
+            (NEW_STATE_PARAMETER.to_owned(), new_state.into()),
+            ("value".to_owned(), new_state.into()),
+        ]),
+    }
+}
+
+#[tokio::test]
+pub async fn test_collection_literals() {
+    let runtime = HatRuntime::new().await;
+    let trigger = Trigger::Task(crate::runtime::scheduler::TaskID(Default::default()));
+
+    let result = evaluate(
+        &runtime,
+        trigger,
+        r#"{"room": "bedroom", level: 1 + 1, "tags": ["a", null]}"#,
+    )
+    .await;
+
+    assert_eq!(
+        result.to_json(),
+        serde_json::json!({"room": "bedroom", "level": 2.0, "tags": ["a", null]})
+    );
+}
+
+#[tokio::test]
+pub async fn test_event_introspection() {
+    let runtime = HatRuntime::new().await;
+    let event = || Trigger::Event(sensor_event(20.0, 23.5));
+
+    let cases = [
+        ("event_param(\"value\")", Value::Number(23.5)),
+        // Sensor values used to be strings
+        ("event_param(\"value\") == \"23.5\"", Value::Boolean(true)),
+        ("event_param(\"value\") != \"23.50\"", Value::Boolean(false)),
+        ("event_param(\"value\") == \"on\"", Value::Boolean(false)),
+        ("event_param(\"missing\")", Value::Null),
+        (
+            "event_new_state() - event_old_state() > 2",
+            Value::Boolean(true),
+        ),
+        (
+            "event_type()",
+            Value::String("SensorValueChangeEvent".into()),
+        ),
+        ("trigger_kind()", Value::String("event".into())),
+        ("task_name()", Value::Null),
+        ("automation_name()", Value::String("test automation".into())),
+    ];
+    for (expression, expected) in cases {
+        assert_eq!(
+            evaluate(&runtime, event(), expression).await,
+            expected,
+            "{expression}"
+        );
+    }
+
+    let task = Trigger::Task(crate::runtime::scheduler::TaskID(Default::default()));
+    assert_eq!(
+        evaluate(&runtime, task, "trigger_kind()").await,
+        Value::String("schedule".into())
+    );
+
+    let disconnected = Trigger::Event(Event::from_integration(
+        "hass",
+        EventType::IntegrationDisconnectedEvent,
+        HashMap::from([("reason".to_owned(), Value::String("timeout".into()))]),
+    ));
+    assert_eq!(
+        evaluate(
+            &runtime,
+            disconnected,
+            "event_param(\"integration\") + \": \" + event_param(\"reason\")"
+        )
+        .await,
+        Value::String("hass: timeout".into())
+    );
+}
+
+#[test]
+pub fn test_capability_events() {
+    let device = |typ: DeviceType| Device {
+        integration: "test".to_string(),
+        id: "device".to_string(),
+        name: None,
+        typ,
+        capabilities: typ.default_capabilities().to_vec(),
+        area: None,
+        floor: None,
+        state: None,
+        attributes: Default::default(),
+    };
+
+    let light = device(DeviceType::Light);
+    assert_eq!(
+        light.get_state_change_event("off", "on"),
+        Some(EventType::LightOnEvent)
+    );
+    assert_eq!(
+        light.get_state_change_event("on", "off"),
+        Some(EventType::LightOffEvent)
+    );
+    assert_eq!(light.get_state_change_event("on", "on"), None);
+    assert_eq!(
+        device(DeviceType::Switch).get_state_change_event("off", "on"),
+        Some(EventType::SwitchTurnedOnEvent)
+    );
+    assert_eq!(
+        device(DeviceType::Sensor).get_state_change_event("20", "21"),
+        Some(EventType::SensorValueChangeEvent)
+    );
+    assert_eq!(
+        device(DeviceType::DoorSensor).get_related_events(),
+        vec![EventType::DoorOpenEvent, EventType::DoorCloseEvent]
+    );
+    assert!(device(DeviceType::Unknown).get_related_events().is_empty());
+    assert_eq!(
+        device(DeviceType::Lock).get_state_change_event("unlocked", "locked"),
+        Some(EventType::LockLockedEvent)
+    );
+    assert_eq!(
+        device(DeviceType::Cover).get_state_change_event("opening", "open"),
+        Some(EventType::CoverOpenedEvent)
+    );
+    assert_eq!(
+        device(DeviceType::Fan).get_state_change_event("off", "on"),
+        Some(EventType::FanTurnedOnEvent)
+    );
+    assert_eq!(light.get_state_change_event("unavailable", "on"), None);
+
+    let person = device(DeviceType::Person);
+    assert_eq!(
+        person.get_state_change_event("not_home", "home"),
+        Some(EventType::PersonArrivedEvent)
+    );
+    assert_eq!(
+        person.get_state_change_event("Work", "not_home"),
+        Some(EventType::PersonLeftEvent)
+    );
+    assert_eq!(person.get_state_change_event("home", "unavailable"), None);
+}
+
+#[test]
+pub fn test_device_state() {
+    assert_eq!(DeviceState::parse("on"), DeviceState::On);
+    assert_eq!(DeviceState::parse("off"), DeviceState::Off);
+    assert_eq!(DeviceState::parse("21.5"), DeviceState::Numeric(21.5));
+    assert_eq!(DeviceState::parse("unavailable"), DeviceState::Unavailable);
+    assert_eq!(DeviceState::parse("unknown"), DeviceState::Unknown);
+    assert_eq!(DeviceState::parse("NaN"), DeviceState::Raw("NaN".into()));
+    assert_eq!(
+        DeviceState::parse("not_home"),
+        DeviceState::Raw("not_home".into())
+    );
+    assert!(!DeviceState::Unavailable.is_known());
+    assert_eq!(DeviceState::parse("3").to_value(), Value::Number(3.0));
+
+    let device = Device {
+        integration: "test".to_string(),
+        id: "light.kitchen".to_string(),
+        name: None,
+        typ: DeviceType::Light,
+        capabilities: DeviceType::Light.default_capabilities().to_vec(),
+        area: None,
+        floor: None,
+        state: None,
+        attributes: Default::default(),
+    };
+    assert_eq!(device.typed_state(), DeviceState::Unknown);
+}
+
+/// Integration with a fixed list of devices
+struct StaticIntegration {
+    id: &'static str,
+    devices: Vec<Device>,
+    failing: bool,
+}
+
+impl StaticIntegration {
+    fn new(id: &'static str, devices: &[(&str, DeviceType, &str)]) -> Self {
+        let devices = devices
+            .iter()
+            .map(|(device_id, typ, state)| Device {
+                integration: id.to_owned(),
+                id: device_id.to_string(),
+                name: None,
+                typ: *typ,
+                capabilities: typ.default_capabilities().to_vec(),
+                area: None,
+                floor: None,
+                state: Some(state.to_string()),
+                attributes: Default::default(),
+            })
+            .collect();
+        Self {
+            id,
+            devices,
+            failing: false,
+        }
+    }
+
+    /// An integration that fails to list its devices
+    fn failing(id: &'static str) -> Self {
+        Self {
+            id,
+            devices: Vec::new(),
+            failing: true,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Integration for StaticIntegration {
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        ensure!(!self.failing, "connection lost");
+        Ok(self.devices.clone())
+    }
+    async fn get_device(&self, id: &str) -> Result<Option<Device>> {
+        Ok(self.devices.iter().find(|d| d.id == id).cloned())
+    }
+    async fn turn_on_device(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+    async fn turn_off_device(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+    async fn set_light_color_rgb(&self, _: &str, _: [u8; 3]) -> Result<()> {
+        Ok(())
+    }
+    async fn set_light_brightness(&self, _: &str, _: u8) -> Result<()> {
+        Ok(())
+    }
+    fn subscribe(&self, capacity: usize) -> mpsc::Receiver<Event> {
+        let (_tx, rx) = mpsc::channel(capacity);
+        rx
+    }
+    fn get_id(&self) -> &str {
+        self.id
+    }
+}
+
+#[tokio::test]
+pub async fn test_presence_functions() {
+    let runtime = HatRuntime::new().await;
+    runtime
+        .integrate(StaticIntegration::new(
+            "people",
+            &[
+                ("person.alice", DeviceType::Person, "home"),
+                ("person.bob", DeviceType::Person, "Work"),
+                ("light.kitchen", DeviceType::Light, "on"),
+            ],
+        ))
+        .await
+        .unwrap();
+
+    let task = || Trigger::Task(crate::runtime::scheduler::TaskID(Default::default()));
+    for (expr, expected) in [
+        ("is_home(\"person.alice\")", true),
+        ("is_home(\"people@person.bob\")", false),
+        ("anyone_home()", true),
+        ("everyone_away()", false),
+    ] {
+        assert_eq!(
+            evaluate(&runtime, task(), expr).await,
+            Value::Boolean(expected),
+            "{expr}"
+        );
+    }
+
+    // Only people count, and integrations that fail are skipped
+    let runtime = HatRuntime::new().await;
+    runtime
+        .integrate(StaticIntegration::new(
+            "people",
+            &[
+                ("person.bob", DeviceType::Person, "Work"),
+                ("device_tracker.tv", DeviceType::Unknown, "home"),
+            ],
+        ))
+        .await
+        .unwrap();
+    runtime
+        .integrate(StaticIntegration::failing("broken"))
+        .await
+        .unwrap();
+    for (expr, expected) in [("anyone_home()", false), ("everyone_away()", true)] {
+        assert_eq!(
+            evaluate(&runtime, task(), expr).await,
+            Value::Boolean(expected),
+            "{expr}"
+        );
+    }
+}
+
+#[tokio::test]
+pub async fn test_execute_checks_capabilities() {
+    let runtime = HatRuntime::new().await;
+    let integration = DummyIntegration::new("dummy").unwrap();
+    let device_id = format!("{}@dummy-device-2707", integration.get_id());
+    runtime.integrate(integration).await.unwrap();
+
+    runtime
+        .execute(&device_id, DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    runtime
+        .execute("dummy-device-2707", DeviceCommand::TurnOff)
+        .await
+        .unwrap();
+    assert!(runtime
+        .execute(&device_id, DeviceCommand::SetBrightness(10))
+        .await
+        .is_err());
+    assert!(runtime
+        .execute("missing-device", DeviceCommand::TurnOn)
+        .await
+        .is_err());
+
+    // Dummy devices accept commands, but only emit the Dummy event
+    let device = runtime.get_device(&device_id).await.unwrap().unwrap();
+    assert_eq!(device.get_related_events(), [EventType::Dummy]);
+}
+
+#[tokio::test]
+pub async fn test_integration_aliases() {
+    let runtime = HatRuntime::new().await;
+    runtime
+        .integrate(DummyIntegration::new("dummy").unwrap())
+        .await
+        .unwrap();
+    runtime.add_integration_alias("DummyIntegration0", "dummy");
+
+    let device = runtime
+        .get_device("DummyIntegration0@dummy-device-2707")
+        .await
+        .unwrap()
+        .expect("alias should resolve to the dummy integration");
+    assert_eq!(device.full_id(), "dummy@dummy-device-2707");
+    runtime
+        .execute("DummyIntegration0@dummy-device-2707", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    assert!(runtime
+        .get_device("unknown@dummy-device-2707")
+        .await
+        .unwrap()
+        .is_none());
+
+    // Triggers and functions also accept aliases
+    runtime
+        .parse(
+            "test.hat".into(),
+            r#"
+            helper fired toggle
+
+            automation "Aliased" (Dummy "DummyIntegration0@dummy-device-2707") {
+                run helpers.set_value("fired", true)
+            }
+            "#,
+        )
+        .await
+        .unwrap();
+    runtime.add_integration_alias("aides", "helpers");
+    assert!(runtime.get_function("aides.set_value").is_some());
+    runtime
+        .dispatch_event(Event {
+            typ: EventType::Dummy,
+            datetime: Default::default(),
+            device: runtime
+                .get_device("dummy@dummy-device-2707")
+                .await
+                .unwrap()
+                .unwrap(),
+            parameters: HashMap::new(),
+        })
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while runtime.helpers().get("fired").unwrap().value() != Value::Boolean(true) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the automation with an aliased trigger did not run");
+
+    // Scripts written with the alias keep comparing device IDs correctly
+    let trigger = Event {
+        typ: EventType::Dummy,
+        datetime: Default::default(),
+        device: device.clone(),
+        parameters: HashMap::new(),
+    };
+    for (expression, expected) in [
+        (
+            r#"get_device() == "DummyIntegration0@dummy-device-2707""#,
+            true,
+        ),
+        (
+            r#"get_device() != "DummyIntegration0@dummy-device-2707""#,
+            false,
+        ),
+        (
+            r#""DummyIntegration0@dummy-device-2707" == get_device()"#,
+            true,
+        ),
+        (r#"get_device() == "dummy@dummy-device-2707""#, true),
+        (r#"get_device() == "DummyIntegration0@other""#, false),
+        (r#""DummyIntegration0@x" == "dummy@x""#, true),
+    ] {
+        assert_eq!(
+            evaluate(&runtime, Trigger::Event(trigger.clone()), expression).await,
+            Value::Boolean(expected),
+            "{expression}"
+        );
+    }
+
+    let error = runtime
+        .integrate(DummyIntegration::new("dummy").unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "an integration with the ID dummy already exists"
+    );
+    assert!(DummyIntegration::new("dummy@home").is_err());
+
+    // Removing an integration also removes its aliases
+    runtime.remove_integration("dummy").await.unwrap();
+    assert!(runtime.get_integration("DummyIntegration0").await.is_none());
+    assert_eq!(
+        runtime.canonicalize_device_id(Value::String("DummyIntegration0@x".into())),
+        Value::String("DummyIntegration0@x".into())
+    );
+    assert!(runtime.get_function("aides.set_value").is_some());
+
+    assert!(is_valid_integration_id("home"));
+    assert!(is_valid_integration_id("cabin_2"));
+    assert!(!is_valid_integration_id("home@cabin"));
+    assert!(!is_valid_integration_id("home.cabin"));
+    assert!(!is_valid_integration_id(""));
+}
+
+#[tokio::test]
+pub async fn test_areas() {
+    let runtime = HatRuntime::new().await;
+    let mut integration = StaticIntegration::new(
+        "house",
+        &[
+            ("light.kitchen", DeviceType::Light, "on"),
+            ("switch.kettle", DeviceType::Switch, "off"),
+            ("light.bedroom", DeviceType::Light, "off"),
+        ],
+    );
+    integration.devices[0].area = Some("Kitchen".into());
+    integration.devices[1].area = Some("Kitchen".into());
+    runtime.integrate(integration).await.unwrap();
+
+    let task = || Trigger::Task(crate::runtime::scheduler::TaskID(Default::default()));
+    assert_eq!(
+        evaluate(&runtime, task(), "devices_in_area(\"kitchen\")").await,
+        Value::List(vec![
+            Value::String("house@light.kitchen".into()),
+            Value::String("house@switch.kettle".into()),
+        ])
+    );
+    assert_eq!(
+        evaluate(&runtime, task(), "area_of(\"light.kitchen\")").await,
+        Value::String("Kitchen".into())
+    );
+    assert_eq!(
+        evaluate(&runtime, task(), "area_of(\"light.bedroom\")").await,
+        Value::Null
+    );
+}
+
+/// Capacity of the event channels of integrations subscribed to by tests
+const CHANNEL_SIZE: usize = 128;
+
+async fn next_event(events: &mut mpsc::Receiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .expect("timed out waiting for event")
+        .expect("event channel closed")
+}
+
+#[tokio::test]
+pub async fn test_subscribers_drop_events_when_full() {
+    let subscribers = Subscribers::default();
+    let mut events = subscribers.subscribe(1);
+    subscribers.send(Event::from_webhook("first", json!({})));
+    subscribers.send(Event::from_webhook("second", json!({})));
+    assert_eq!(next_event(&mut events).await.device.id, "first");
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn test_hass_devices() {
+    let mock = MockHass::start("secret").await;
+    mock.add_floor("ground", "Ground floor");
+    mock.add_area("kitchen", "Kitchen", Some("ground"));
+    mock.add_device("bulb", Some("kitchen"), &["light.kitchen"]);
+    mock.set_state(
+        "light.kitchen",
+        "off",
+        json!({ "friendly_name": "Kitchen light", "supported_color_modes": ["brightness"] }),
+    );
+    mock.set_state("sensor.temperature", "21.5", json!({}));
+    mock.set_state("sun.sun", "above_horizon", json!({}));
+    mock.set_state("device_tracker.router", "home", json!({}));
+
+    assert!(HassIntegration::new("home", &mock.url(), "wrong")
+        .await
+        .is_err());
+    let hass = HassIntegration::new("home", &mock.url(), mock.token())
+        .await
+        .unwrap();
+
+    let mut devices = hass.list_devices().await.unwrap();
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(devices.len(), 3);
+    // Trackers follow presence, but are not people
+    let tracker = &devices[0];
+    assert_eq!(tracker.id, "device_tracker.router");
+    assert_eq!(tracker.typ, DeviceType::Unknown);
+    assert!(tracker.is_home());
+    let light = &devices[1];
+    assert_eq!(light.id, "light.kitchen");
+    assert_eq!(light.name.as_deref(), Some("Kitchen light"));
+    assert_eq!(light.area.as_deref(), Some("Kitchen"));
+    assert_eq!(light.floor.as_deref(), Some("Ground floor"));
+    assert_eq!(
+        light.capabilities,
+        vec![Capability::OnOff, Capability::Brightness]
+    );
+    assert_eq!(light.typed_state(), DeviceState::Off);
+
+    let sensor = hass
+        .get_device("sensor.temperature")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sensor.typed_state(), DeviceState::Numeric(21.5));
+    assert_eq!(sensor.area, None);
+    assert!(hass.get_device("light.missing").await.unwrap().is_none());
+
+    // Devices moved to another area are seen without reconnecting
+    let _events = hass.subscribe(CHANNEL_SIZE);
+    mock.wait_for_subscriptions(1).await;
+    mock.add_area("garage", "Garage", None);
+    // The first move may be read when subscribing, the second one only from the update
+    for (area_id, area) in [("garage", "Garage"), ("kitchen", "Kitchen")] {
+        mock.set_device_area("bulb", Some(area_id));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let light = hass.get_device("light.kitchen").await.unwrap().unwrap();
+                if light.area.as_deref() == Some(area) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for the area to be reloaded");
+    }
+}
+
+#[tokio::test]
+pub async fn test_hass_events_and_services() {
+    let mock = MockHass::start("secret").await;
+    mock.set_state("light.kitchen", "off", json!({}));
+    mock.set_state("lock.front_door", "locked", json!({}));
+    mock.set_state("person.alice", "not_home", json!({}));
+    mock.set_state("sensor.hall_temperature", "21.5", json!({ "battery": 90 }));
+    mock.set_state(
+        "binary_sensor.hall_motion",
+        "off",
+        json!({ "device_class": "motion" }),
+    );
+
+    let hass = HassIntegration::new("home", &mock.url(), mock.token())
+        .await
+        .unwrap();
+    // Changes before the subscription are not lost
+    mock.set_state(
+        "binary_sensor.hall_motion",
+        "on",
+        json!({ "device_class": "motion" }),
+    );
+    let mut events = hass.subscribe(CHANNEL_SIZE);
+    mock.wait_for_subscriptions(1).await;
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::MotionSensorOnEvent);
+
+    hass.execute("light.kitchen", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOnEvent);
+    assert_eq!(event.device.state.as_deref(), Some("on"));
+    assert_eq!(event.new_state(), Some(Value::String("on".into())));
+    assert_eq!(
+        mock.service_calls(),
+        vec![ServiceCall {
+            domain: "light".into(),
+            service: "turn_on".into(),
+            target: json!({ "entity_id": "light.kitchen" }),
+            data: json!({}),
+        }]
+    );
+
+    // Turning on a light that is already on does nothing
+    hass.execute("light.kitchen", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    assert_eq!(mock.service_calls().len(), 1);
+
+    hass.execute("light.kitchen", DeviceCommand::SetBrightness(128))
+        .await
+        .unwrap();
+    assert_eq!(mock.service_calls()[1].data, json!({ "brightness": 128 }));
+    assert_eq!(mock.get_state("light.kitchen").as_deref(), Some("on"));
+
+    mock.fail_service("lock.unlock", "service_validation_error", "Code required");
+    let error = hass
+        .execute("lock.front_door", DeviceCommand::Unlock)
+        .await
+        .unwrap_err();
+    assert!(
+        error.chain().any(|e| matches!(
+            e.downcast_ref::<HassError>(),
+            Some(HassError::ServiceValidation(message)) if message == "Code required"
+        )),
+        "{error:#}"
+    );
+
+    // Only state changes are subscribed to
+    mock.fire_event(
+        "automation_triggered",
+        json!({
+            "event_type": "automation_triggered",
+            "time_fired": "2024-10-01T10:00:00+00:00",
+            "origin": "LOCAL",
+            "context": {},
+            "data": { "entity_id": "automation.morning" },
+        }),
+    );
+    mock.set_state("lock.front_door", "unlocked", json!({}));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LockUnlockedEvent);
+    assert_eq!(event.old_state(), Some(&Value::String("locked".into())));
+
+    // Sensors report changes that only touch their attributes
+    mock.set_state("sensor.hall_temperature", "21.5", json!({ "battery": 80 }));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+    assert_eq!(event.device.attributes.get("battery"), Some(&json!(80)));
+
+    // Going straight from one zone to another leaves the first one
+    mock.set_state("person.alice", "work", json!({}));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::PersonArrivedEvent);
+    mock.set_state("person.alice", "home", json!({}));
+    let left = next_event(&mut events).await;
+    assert_eq!(left.typ, EventType::PersonLeftEvent);
+    assert_eq!(
+        left.get_parameter(ZONE_PARAMETER),
+        Some(&Value::String("work".into()))
+    );
+    let arrived = next_event(&mut events).await;
+    assert_eq!(arrived.typ, EventType::PersonArrivedEvent);
+    assert_eq!(
+        arrived.get_parameter(ZONE_PARAMETER),
+        Some(&Value::String("home".into()))
+    );
+}
+
+#[tokio::test]
+pub async fn test_hass_call_service_function() {
+    let mock = MockHass::start("secret").await;
+    mock.set_state("script.morning", "off", json!({}));
+    mock.set_state("weather.home", "sunny", json!({}));
+    mock.set_state("switch.smart_plug", "off", json!({}));
+    mock.set_state("media_player.tv", "off", json!({}));
+
+    let runtime = HatRuntime::new().await;
+    let hass = HassIntegration::new("home", &mock.url(), mock.token())
+        .await
+        .unwrap();
+    runtime.integrate(hass).await.unwrap();
+
+    let task = || Trigger::Task(crate::runtime::scheduler::TaskID(Default::default()));
+    evaluate(
+        &runtime,
+        task(),
+        "ha_call_service(\"script.run\", \"script.morning\", {speed: 2})",
+    )
+    .await;
+    assert_eq!(
+        mock.service_calls(),
+        vec![ServiceCall {
+            domain: "script".into(),
+            service: "run".into(),
+            target: json!({ "entity_id": "script.morning" }),
+            data: json!({ "speed": 2.0 }),
+        }]
+    );
+
+    // Services that return data only do it when asked to
+    let forecast = json!({ "weather.home": { "forecast": [{ "temperature": 25.0 }] } });
+    mock.set_service_response("weather.get_forecasts", forecast);
+    let call = |return_response: &str| {
+        format!(
+            "ha_call_service(\"weather.get_forecasts\", \"weather.home\", {{type: \"daily\"}}{return_response})"
+        )
+    };
+    assert_eq!(evaluate(&runtime, task(), &call("")).await, Value::Null);
+    let response = evaluate(&runtime, task(), &call(", true")).await;
+    assert_eq!(
+        response.to_json()["weather.home"]["forecast"][0]["temperature"],
+        json!(25.0)
+    );
+
+    // Switches without a device class, media players and entities without capabilities,
+    // like scripts, can still be turned on, as turn_on_device does
+    for device in ["switch.smart_plug", "media_player.tv", "script.morning"] {
+        runtime
+            .execute(&format!("home@{device}"), DeviceCommand::TurnOn)
+            .await
+            .unwrap();
+        let call = mock.service_calls().pop().unwrap();
+        assert_eq!(
+            (call.service.as_str(), call.target),
+            ("turn_on", json!({ "entity_id": device }))
+        );
+    }
+    assert!(runtime
+        .execute("home@switch.smart_plug", DeviceCommand::SetBrightness(10))
+        .await
+        .is_err());
+    let tv = runtime
+        .get_device("home@media_player.tv")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        tv.capabilities,
+        vec![Capability::OnOff, Capability::MediaPlayback]
+    );
+}
+
+#[tokio::test]
+pub async fn test_hass_reconnects() {
+    let mock = MockHass::start("secret").await;
+    mock.set_state("light.kitchen", "on", json!({}));
+
+    let hass = HassIntegration::new("home", &mock.url(), mock.token())
+        .await
+        .unwrap();
+    let mut events = hass.subscribe(CHANNEL_SIZE);
+    mock.wait_for_subscriptions(1).await;
+
+    mock.disconnect_all();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::IntegrationDisconnectedEvent);
+    // Changes while disconnected are emitted after reconnecting, even when events keep
+    // arriving while the states are read
+    mock.set_state_silently("light.kitchen", "off");
+    mock.flood_before_states(50);
+
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::IntegrationConnectedEvent);
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOffEvent);
+    assert_eq!(
+        hass.get_device("light.kitchen")
+            .await
+            .unwrap()
+            .unwrap()
+            .typed_state(),
+        DeviceState::Off
+    );
+
+    mock.wait_for_subscriptions(2).await;
+    mock.set_state("light.kitchen", "on", json!({}));
+    loop {
+        let event = next_event(&mut events).await;
+        if event.device.id != "sensor.flood" {
+            assert_eq!(event.typ, EventType::LightOnEvent);
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+pub async fn test_config_file() {
+    let mock = MockHass::start("secret").await;
+    mock.set_state("light.kitchen", "on", json!({}));
+
+    let source = r#"
+        [server]
+        address = "127.0.0.1:8080"
+
+        [runtime]
+        event_channel_size = 16
+        integration_channel_size = 8
+
+        [location]
+        timezone = "America/Sao_Paulo"
+
+        [features]
+        update_code = false
+
+        [[integrations]]
+        type = "home_assistant"
+        id = "home"
+        aliases = ["HassIntegration0"]
+        url = "${HASS_URL}"
+        token = "${HASS_TOKEN}"
+    "#;
+    let url = mock.url();
+    let config = Config::parse_with_env(source, |name| match name {
+        "HASS_URL" => Some(url.clone()),
+        "HASS_TOKEN" => Some("secret".into()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(config.server.address.to_string(), "127.0.0.1:8080");
+    assert_eq!(config.timezone(), Some("America/Sao_Paulo"));
+    assert!(config.features.server && !config.features.update_code);
+
+    let settings = config.runtime_settings();
+    assert_eq!(settings.event_channel_size, 16);
+    assert_eq!(settings.integration_channel_size, 8);
+
+    let runtime = HatRuntime::with_settings(settings).await.unwrap();
+    for integration in &config.integrations {
+        integration.integrate(&runtime).await.unwrap();
+    }
+    let device = runtime
+        .get_device("HassIntegration0@light.kitchen")
+        .await
+        .unwrap();
+    assert_eq!(device.unwrap().integration, "home");
+}
+
+#[test]
+pub fn test_config_errors() {
+    let error = |source: &str| {
+        let env = |name: &str| (name == "TOKEN").then(|| "abc".to_owned());
+        format!("{:#}", Config::parse_with_env(source, env).unwrap_err())
+    };
+
+    assert!(error("[server\n").contains("line 1"));
+    assert_eq!(
+        error("[runtime]\nevent_chanel_size = 1"),
+        "runtime.event_chanel_size: unknown field `event_chanel_size`, expected one of \
+        `event_channel_size`, `integration_channel_size`, `helpers_file`"
+    );
+    assert_eq!(
+        error("[[integrations]]\ntype = \"home_assistant\"\nid = \"home\"\nurl = \"http://hass\""),
+        "integrations[0]: missing field `token`"
+    );
+    assert_eq!(
+        error("[[integrations]]\ntype = \"dummy\"\nid = \"${MISSING}\""),
+        "integrations[0].id: environment variable MISSING is not set"
+    );
+    assert_eq!(
+        error(
+            r#"
+            runtime = { integration_channel_size = 0 }
+
+            [[integrations]]
+            type = "dummy"
+            id = "dummy"
+
+            [[integrations]]
+            type = "home_assistant"
+            id = "home"
+            aliases = ["dummy", "1home", "my-home"]
+            url = "ws://hass"
+            token = "${TOKEN}"
+            "#
+        ),
+        "runtime.integration_channel_size: must be greater than zero\n\
+        integrations[1].aliases: \"dummy\" is already used by another integration\n\
+        integrations[1].aliases: \"1home\" must start with a letter and contain only \
+        letters, digits and `_`\n\
+        integrations[1].aliases: \"my-home\" must start with a letter and contain only \
+        letters, digits and `_`\n\
+        integrations[1].url: \"ws://hass\" must be an http or https URL"
+    );
+    assert_eq!(
+        error("[location]\ntimezone = \"America/Sao_Pulo\""),
+        "location.timezone: \"America/Sao_Pulo\" is not in the tz database, use a name like \
+        \"Europe/Lisbon\""
+    );
+    assert_eq!(
+        error("[[integrations]]\ntype = \"hue\"\nid = \"hue\"\nurl = \"https://10.0.0.2\"\napplication_key = \"\""),
+        "integrations[0].application_key: must not be empty"
+    );
+    assert_eq!(
+        error(
+            r#"
+            [[integrations]]
+            type = "rest"
+            id = "gadgets"
+            poll_interval = 0
+
+            [[integrations.devices]]
+            id = "pump"
+            type = "Switch"
+            url = "ftp://pump"
+            state = "relay"
+            "#
+        ),
+        "integrations[0].poll_interval: must be greater than zero\n\
+        integrations[0].devices[0].url: \"ftp://pump\" must be an http or https URL\n\
+        integrations[0].devices[0].state: \"relay\" must be a JSON pointer, starting with `/`"
+    );
+
+    let config = Config::parse_with_env("", |_| None).unwrap();
+    assert!(config.integrations.is_empty());
+    assert_eq!(config.server.address.to_string(), "0.0.0.0:5000");
+}
+
+async fn mqtt_integration(broker: &MockBroker) -> MqttIntegration {
+    let settings = MqttSettings {
+        host: "127.0.0.1".into(),
+        port: broker.port(),
+        ..Default::default()
+    };
+    MqttIntegration::new("mqtt", settings).await.unwrap()
+}
+
+/// Waits until the integration discovers a device, returning it.
+async fn wait_for_device(integration: &impl Integration, id: &str) -> Device {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(device) = integration.get_device(id).await.unwrap() {
+                return device;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for device")
+}
+
+#[tokio::test]
+pub async fn test_mqtt_zigbee2mqtt() {
+    let broker = MockBroker::start().await;
+    let devices = json!([
+        { "ieee_address": "0x00", "friendly_name": "Coordinator", "type": "Coordinator" },
+        {
+            "ieee_address": "0x01",
+            "friendly_name": "Kitchen light",
+            "type": "Router",
+            "definition": {
+                "model": "LED1545G12",
+                "vendor": "IKEA",
+                "exposes": [
+                    {
+                        "type": "light",
+                        "features": [
+                            { "type": "binary", "name": "state", "property": "state", "value_on": "ON", "value_off": "OFF" },
+                            { "type": "numeric", "name": "brightness", "property": "brightness" },
+                            { "type": "composite", "name": "color_xy", "property": "color" }
+                        ]
+                    },
+                    { "type": "numeric", "name": "linkquality", "property": "linkquality" }
+                ]
+            }
+        },
+        {
+            "ieee_address": "0x02",
+            "friendly_name": "Hall motion",
+            "type": "EndDevice",
+            "definition": {
+                "exposes": [
+                    { "type": "binary", "name": "occupancy", "property": "occupancy", "value_on": true, "value_off": false },
+                    { "type": "numeric", "name": "illuminance_lux", "property": "illuminance_lux", "unit": "lx" }
+                ]
+            }
+        },
+        {
+            "ieee_address": "0x03",
+            "friendly_name": "Remote",
+            "type": "EndDevice",
+            "definition": {
+                "exposes": [{ "type": "enum", "name": "action", "property": "action" }]
+            }
+        }
+    ]);
+    broker.publish("zigbee2mqtt/bridge/devices", &devices.to_string(), true);
+    // States published before the device list are applied once the device is known
+    broker.publish(
+        "zigbee2mqtt/Kitchen light",
+        r#"{"state":"ON","brightness":120}"#,
+        true,
+    );
+
+    let mqtt = mqtt_integration(&broker).await;
+    let mut events = mqtt.subscribe(CHANNEL_SIZE);
+
+    let light = wait_for_device(&mqtt, "Kitchen light").await;
+    assert_eq!(light.typ, DeviceType::Light);
+    assert_eq!(
+        light.capabilities,
+        vec![
+            Capability::OnOff,
+            Capability::Brightness,
+            Capability::ColorRgb
+        ]
+    );
+    assert_eq!(light.state.as_deref(), Some("on"));
+    assert_eq!(light.attributes.get("brightness"), Some(&json!(120)));
+    let motion = wait_for_device(&mqtt, "Hall motion").await;
+    assert_eq!(motion.typ, DeviceType::MotionSensor);
+    assert!(mqtt.get_device("Coordinator").await.unwrap().is_none());
+
+    broker.publish("zigbee2mqtt/Kitchen light", r#"{"state":"OFF"}"#, false);
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOffEvent);
+    assert_eq!(event.device.full_id(), "mqtt@Kitchen light");
+
+    broker.publish("zigbee2mqtt/Hall motion", r#"{"occupancy":false}"#, false);
+    broker.publish("zigbee2mqtt/Hall motion", r#"{"occupancy":true}"#, false);
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::MotionSensorOnEvent
+    );
+
+    // Repeated actions are separate presses
+    for _ in 0..2 {
+        broker.publish("zigbee2mqtt/Remote", r#"{"action":"single"}"#, false);
+        let event = next_event(&mut events).await;
+        assert_eq!(event.typ, EventType::ButtonPressedEvent);
+        assert_eq!(
+            event.parameters.get(NEW_STATE_PARAMETER),
+            Some(&Value::String("single".into()))
+        );
+    }
+
+    // Going offline and back is not a state change, the last known state is restored
+    broker.publish(
+        "zigbee2mqtt/Kitchen light/availability",
+        r#"{"state":"offline"}"#,
+        false,
+    );
+    wait_until_state(&mqtt, "Kitchen light", "unavailable").await;
+    broker.publish(
+        "zigbee2mqtt/Kitchen light/availability",
+        r#"{"state":"online"}"#,
+        false,
+    );
+    wait_until_state(&mqtt, "Kitchen light", "off").await;
+    broker.publish("zigbee2mqtt/Kitchen light", r#"{"state":"ON"}"#, false);
+    assert_eq!(next_event(&mut events).await.typ, EventType::LightOnEvent);
+
+    mqtt.execute("Kitchen light", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("zigbee2mqtt/Kitchen light/set").await,
+        r#"{"state":"ON"}"#
+    );
+    mqtt.execute("Kitchen light", DeviceCommand::SetBrightness(255))
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("zigbee2mqtt/Kitchen light/set").await,
+        r#"{"brightness":254}"#
+    );
+    mqtt.execute("Kitchen light", DeviceCommand::SetColorRgb([255, 0, 10]))
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("zigbee2mqtt/Kitchen light/set").await,
+        r#"{"color":{"b":10,"g":0,"r":255}}"#
+    );
+    assert!(mqtt
+        .execute("Hall motion", DeviceCommand::TurnOn)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+pub async fn test_mqtt_tasmota() {
+    let broker = MockBroker::start().await;
+    let config = json!({
+        "ip": "192.168.1.20",
+        "dn": "Living room",
+        "fn": ["Living room lamp", null],
+        "hn": "tasmota-A1B2C3",
+        "mac": "A1B2C3D4E5F6",
+        "md": "Sonoff B1",
+        "ty": 0,
+        "ofln": "Offline",
+        "onln": "Online",
+        "state": ["OFF", "ON", "TOGGLE", "HOLD"],
+        "sw": "13.1.0",
+        "t": "tasmota_A1B2C3",
+        "ft": "%prefix%/%topic%/",
+        "tp": ["cmnd", "stat", "tele"],
+        "rl": [2, 0, 0, 0, 0, 0, 0, 0],
+        "lt_st": 3
+    });
+    let sensors = json!({
+        "sn": { "Time": "2024-10-01T10:00:00", "AM2301": { "Temperature": 21.5, "Humidity": 40 }, "TempUnit": "C" },
+        "ver": 1
+    });
+    broker.publish(
+        "tasmota/discovery/A1B2C3D4E5F6/config",
+        &config.to_string(),
+        true,
+    );
+    broker.publish(
+        "tasmota/discovery/A1B2C3D4E5F6/sensors",
+        &sensors.to_string(),
+        true,
+    );
+
+    let mqtt = mqtt_integration(&broker).await;
+    let mut events = mqtt.subscribe(CHANNEL_SIZE);
+
+    let lamp = wait_for_device(&mqtt, "tasmota_A1B2C3").await;
+    assert_eq!(lamp.name.as_deref(), Some("Living room lamp"));
+    assert_eq!(lamp.typ, DeviceType::Light);
+    assert_eq!(
+        lamp.capabilities,
+        vec![
+            Capability::OnOff,
+            Capability::Brightness,
+            Capability::ColorRgb
+        ]
+    );
+    let temperature = wait_for_device(&mqtt, "tasmota_A1B2C3.AM2301.Temperature").await;
+    assert_eq!(temperature.state.as_deref(), Some("21.5"));
+    assert_eq!(
+        temperature.attributes.get("unit_of_measurement"),
+        Some(&json!("°C"))
+    );
+
+    broker.wait_for_subscription("stat/tasmota_A1B2C3/+").await;
+    broker.wait_for_subscription("tele/tasmota_A1B2C3/+").await;
+    broker.publish("stat/tasmota_A1B2C3/POWER", "OFF", false);
+    broker.publish(
+        "stat/tasmota_A1B2C3/RESULT",
+        r#"{"POWER":"ON","Dimmer":40}"#,
+        false,
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOnEvent);
+    assert_eq!(event.device.attributes.get("Dimmer"), Some(&json!(40)));
+
+    broker.publish(
+        "tele/tasmota_A1B2C3/SENSOR",
+        r#"{"AM2301":{"Temperature":22.0,"Humidity":41}}"#,
+        false,
+    );
+    let mut measured = Vec::new();
+    for _ in 0..2 {
+        let event = next_event(&mut events).await;
+        assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+        measured.push((event.device.id, event.parameters["value"].clone()));
+    }
+    measured.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        measured,
+        vec![
+            ("tasmota_A1B2C3.AM2301.Humidity".into(), Value::Number(41.0)),
+            (
+                "tasmota_A1B2C3.AM2301.Temperature".into(),
+                Value::Number(22.0)
+            ),
+        ]
+    );
+
+    broker.publish("tele/tasmota_A1B2C3/LWT", "Offline", false);
+    wait_until_state(&mqtt, "tasmota_A1B2C3", "unavailable").await;
+
+    mqtt.execute("tasmota_A1B2C3", DeviceCommand::TurnOff)
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("cmnd/tasmota_A1B2C3/POWER").await,
+        "OFF"
+    );
+    mqtt.execute("tasmota_A1B2C3", DeviceCommand::SetBrightness(128))
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("cmnd/tasmota_A1B2C3/Dimmer").await,
+        "50"
+    );
+    mqtt.execute("tasmota_A1B2C3", DeviceCommand::SetColorRgb([1, 2, 3]))
+        .await
+        .unwrap();
+    assert_eq!(
+        broker.next_message("cmnd/tasmota_A1B2C3/Color").await,
+        "1,2,3"
+    );
+
+    // Clearing the retained configuration removes the device
+    broker.publish("tasmota/discovery/A1B2C3D4E5F6/config", "", true);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !mqtt.list_devices().await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the device to be removed");
+
+    // A burst of configurations subscribes to the topics of every device
+    for i in 0..200 {
+        let mut config = config.clone();
+        config["mac"] = json!(format!("{i:012X}"));
+        config["t"] = json!(format!("tasmota_{i}"));
+        broker.publish(
+            &format!("tasmota/discovery/{i:012X}/config"),
+            &config.to_string(),
+            true,
+        );
+    }
+    broker.wait_for_subscription("stat/tasmota_199/+").await;
+}
+
+async fn wait_until_state(integration: &impl Integration, id: &str, state: &str) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let device = integration.get_device(id).await.unwrap();
+            if device.and_then(|d| d.state).as_deref() == Some(state) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for state");
+}
+
+/// A bridge with a color bulb in the kitchen, a dimmer switch and a motion sensor.
+async fn hue_bridge() -> MockHueBridge {
+    let bridge = MockHueBridge::start("hue-key").await;
+    for resource in [
+        json!({
+            "id": "bulb", "type": "device",
+            "metadata": { "name": "Kitchen bulb" },
+            "product_data": { "product_name": "Hue color lamp" },
+            "services": [{ "rid": "light-1", "rtype": "light" }, { "rid": "zigbee-1", "rtype": "zigbee_connectivity" }]
+        }),
+        json!({
+            "id": "light-1", "type": "light",
+            "owner": { "rid": "bulb", "rtype": "device" },
+            "metadata": { "name": "Kitchen bulb" },
+            "on": { "on": false },
+            "dimming": { "brightness": 50.0 },
+            "color_temperature": { "mirek": 250 },
+            "color": { "xy": { "x": 0.3, "y": 0.3 } }
+        }),
+        json!({
+            "id": "zigbee-1", "type": "zigbee_connectivity",
+            "owner": { "rid": "bulb", "rtype": "device" },
+            "status": "connected"
+        }),
+        json!({
+            "id": "kitchen", "type": "room",
+            "metadata": { "name": "Kitchen" },
+            "children": [{ "rid": "bulb", "rtype": "device" }],
+            "services": [{ "rid": "group-1", "rtype": "grouped_light" }]
+        }),
+        json!({
+            "id": "group-1", "type": "grouped_light",
+            "owner": { "rid": "kitchen", "rtype": "room" },
+            "on": { "on": false },
+            "dimming": { "brightness": 0.0 }
+        }),
+        json!({
+            "id": "group-all", "type": "grouped_light",
+            "owner": { "rid": "home", "rtype": "bridge_home" },
+            "on": { "on": false }
+        }),
+        json!({
+            "id": "dimmer", "type": "device",
+            "metadata": { "name": "Hall dimmer" },
+            "services": [{ "rid": "button-1", "rtype": "button" }]
+        }),
+        json!({
+            "id": "button-1", "type": "button",
+            "owner": { "rid": "dimmer", "rtype": "device" },
+            "metadata": { "control_id": 1 },
+            "button": {}
+        }),
+        json!({
+            "id": "sensor", "type": "device",
+            "metadata": { "name": "Hall sensor" },
+            "services": [{ "rid": "motion-1", "rtype": "motion" }]
+        }),
+        json!({
+            "id": "motion-1", "type": "motion",
+            "owner": { "rid": "sensor", "rtype": "device" },
+            "enabled": true,
+            "motion": { "motion": false, "motion_valid": true }
+        }),
+    ] {
+        bridge.add_resource(resource);
+    }
+    bridge
+}
+
+#[tokio::test]
+pub async fn test_hue_devices_and_events() {
+    let bridge = hue_bridge().await;
+    assert!(
+        HueIntegration::new("hue", &bridge.url(), "wrong-key", false)
+            .await
+            .is_err()
+    );
+
+    let hue = HueIntegration::new("hue", &bridge.url(), "hue-key", false)
+        .await
+        .unwrap();
+    let mut events = hue.subscribe(CHANNEL_SIZE);
+    bridge.wait_for_streams(1).await;
+
+    let devices = hue.list_devices().await.unwrap();
+    let names = devices
+        .iter()
+        .map(|d| d.name.as_deref().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["Hall dimmer 1", "Hall sensor", "Kitchen", "Kitchen bulb"]
+    );
+    let light = hue.get_device("light-1").await.unwrap().unwrap();
+    assert_eq!(light.typ, DeviceType::Light);
+    assert_eq!(
+        light.capabilities,
+        vec![
+            Capability::OnOff,
+            Capability::Brightness,
+            Capability::ColorTemp,
+            Capability::ColorRgb
+        ]
+    );
+    assert_eq!(light.area.as_deref(), Some("Kitchen"));
+    assert_eq!(light.state.as_deref(), Some("off"));
+    assert_eq!(light.attributes.get("brightness"), Some(&json!(128)));
+    assert_eq!(
+        light.attributes.get("color_temp_kelvin"),
+        Some(&json!(4000))
+    );
+    assert_eq!(
+        light.attributes.get("model"),
+        Some(&json!("Hue color lamp"))
+    );
+    let room = hue.get_device("group-1").await.unwrap().unwrap();
+    assert_eq!(room.typ, DeviceType::Light);
+    assert_eq!(room.area.as_deref(), Some("Kitchen"));
+    assert_eq!(
+        room.capabilities,
+        vec![Capability::OnOff, Capability::Brightness]
+    );
+
+    bridge.update("light-1", json!({ "on": { "on": true } }));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOnEvent);
+    assert_eq!(event.device.full_id(), "hue@light-1");
+
+    bridge.update("motion-1", json!({ "motion": { "motion": true } }));
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::MotionSensorOnEvent
+    );
+
+    // Only the release of a press is an event, and repeated presses are separate events
+    for report in [
+        "initial_press",
+        "short_release",
+        "initial_press",
+        "short_release",
+    ] {
+        bridge.update(
+            "button-1",
+            json!({ "button": { "button_report": { "event": report } } }),
+        );
+    }
+    for _ in 0..2 {
+        let event = next_event(&mut events).await;
+        assert_eq!(event.typ, EventType::ButtonPressedEvent);
+        assert_eq!(
+            event.parameters.get(NEW_STATE_PARAMETER),
+            Some(&Value::String("short_release".into()))
+        );
+    }
+
+    // Losing and regaining the connection to a bulb is not a state change
+    bridge.update("zigbee-1", json!({ "status": "connectivity_issue" }));
+    wait_until_state(&hue, "light-1", "unavailable").await;
+    bridge.update("zigbee-1", json!({ "status": "connected" }));
+    wait_until_state(&hue, "light-1", "on").await;
+
+    hue.turn_off_device("light-1").await.unwrap();
+    assert_eq!(next_event(&mut events).await.typ, EventType::LightOffEvent);
+    hue.set_light_brightness("light-1", 255).await.unwrap();
+    hue.set_light_color_rgb("light-1", [255, 0, 0])
+        .await
+        .unwrap();
+    hue.execute("light-1", DeviceCommand::SetColorTemp(2700))
+        .await
+        .unwrap();
+    hue.turn_on_device("group-1").await.unwrap();
+    let requests = bridge.put_requests();
+    let request =
+        |rtype: &str, id: &str, body: serde_json::Value| (rtype.to_owned(), id.to_owned(), body);
+    assert_eq!(
+        requests,
+        vec![
+            request("light", "light-1", json!({ "on": { "on": false } })),
+            request(
+                "light",
+                "light-1",
+                json!({ "dimming": { "brightness": 100.0 } })
+            ),
+            request(
+                "light",
+                "light-1",
+                json!({ "color": { "xy": { "x": 0.6401, "y": 0.33 } } })
+            ),
+            request(
+                "light",
+                "light-1",
+                json!({ "color_temperature": { "mirek": 370 } })
+            ),
+            request("grouped_light", "group-1", json!({ "on": { "on": true } })),
+        ]
+    );
+    assert!(hue
+        .execute("motion-1", DeviceCommand::TurnOn)
+        .await
+        .is_err());
+    assert!(hue
+        .set_light_color_rgb("group-1", [0, 0, 255])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+pub async fn test_hue_reconnect() {
+    let bridge = hue_bridge().await;
+    let hue = HueIntegration::new("hue", &bridge.url(), "hue-key", false)
+        .await
+        .unwrap();
+    let mut events = hue.subscribe(CHANNEL_SIZE);
+    bridge.wait_for_streams(1).await;
+
+    bridge.disconnect_all();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::IntegrationDisconnectedEvent);
+
+    // Changes missed while disconnected are events after reconnecting
+    bridge.update_silently("light-1", json!({ "on": { "on": true } }));
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::IntegrationConnectedEvent
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::LightOnEvent);
+    assert_eq!(event.device.full_id(), "hue@light-1");
+
+    bridge.update("light-1", json!({ "on": { "on": false } }));
+    assert_eq!(next_event(&mut events).await.typ, EventType::LightOffEvent);
+}
+
+#[tokio::test]
+pub async fn test_rest_polling() {
+    let server = MockRestServer::start().await;
+    server.set_json(
+        "/inverter",
+        json!({ "power": { "current": 1200 }, "energy": { "today": 5.5 } }),
+    );
+    server.set_json("/pump", json!({ "relay": false, "rpm": 0 }));
+    let devices: Vec<RestDeviceSettings> = serde_json::from_value(json!([
+        {
+            "id": "inverter",
+            "name": "Solar inverter",
+            "type": "Sensor",
+            "url": server.url("/inverter"),
+            "state": "/power/current",
+            "attributes": { "energy_today": "/energy/today" },
+            "unit_of_measurement": "W"
+        },
+        {
+            "id": "pump",
+            "type": "Switch",
+            "url": server.url("/pump"),
+            "state": "/relay",
+            "attributes": { "rpm": "/rpm" },
+            "turn_on": { "url": server.url("/pump"), "body": "{\"relay\":true}" },
+            "turn_off": { "url": server.url("/pump"), "method": "PUT", "body": "{\"relay\":false}" }
+        }
+    ]))
+    .unwrap();
+
+    let mut invalid = devices[1].clone();
+    invalid.typ = DeviceType::Button;
+    assert!(
+        RestIntegration::new("gadgets", Duration::from_millis(50), vec![invalid])
+            .await
+            .is_err()
+    );
+
+    let rest = RestIntegration::new("gadgets", Duration::from_millis(50), devices)
+        .await
+        .unwrap();
+    let mut events = rest.subscribe(CHANNEL_SIZE);
+
+    let inverter = rest.get_device("inverter").await.unwrap().unwrap();
+    assert_eq!(inverter.typ, DeviceType::Sensor);
+    assert_eq!(inverter.capabilities, vec![Capability::Measurement]);
+    assert_eq!(inverter.state.as_deref(), Some("1200"));
+    assert_eq!(inverter.attributes.get("energy_today"), Some(&json!(5.5)));
+    assert_eq!(
+        inverter.attributes.get("unit_of_measurement"),
+        Some(&json!("W"))
+    );
+    let pump = rest.get_device("pump").await.unwrap().unwrap();
+    assert_eq!(pump.state.as_deref(), Some("off"));
+
+    server.set_json("/inverter", json!({ "power": { "current": 1350 } }));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+    assert_eq!(event.device.full_id(), "gadgets@inverter");
+    assert_eq!(event.parameters.get("value"), Some(&Value::Number(1350.0)));
+
+    // Commands read the new state right away
+    rest.turn_on_device("pump").await.unwrap();
+    assert_eq!(
+        rest.get_device("pump")
+            .await
+            .unwrap()
+            .unwrap()
+            .state
+            .as_deref(),
+        Some("on")
+    );
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::SwitchTurnedOnEvent
+    );
+    rest.turn_off_device("pump").await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::SwitchTurnedOffEvent
+    );
+    assert_eq!(
+        server.requests(),
+        vec![
+            ("POST".into(), "/pump".into(), r#"{"relay":true}"#.into()),
+            ("PUT".into(), "/pump".into(), r#"{"relay":false}"#.into()),
+        ]
+    );
+    assert!(rest.set_light_brightness("pump", 100).await.is_err());
+    assert!(rest.turn_on_device("inverter").await.is_err());
+
+    // Failed polls make the device unavailable, and recovering from them is not a change
+    server.set_failing("/inverter", true);
+    wait_until_state(&rest, "inverter", "unavailable").await;
+    server.set_json("/inverter", json!({ "power": { "current": 1400 } }));
+    server.set_failing("/inverter", false);
+    wait_until_state(&rest, "inverter", "1400").await;
+    server.set_json("/inverter", json!({ "power": { "current": 1500 } }));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+    assert_eq!(event.old_state(), Some(&Value::Number(1400.0)));
+}
+
+/// Forwards TCP connections to another address, and can drop them to simulate network
+/// failures.
+struct TcpProxy {
+    addr: SocketAddr,
+    connections: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
+    server: JoinHandle<()>,
+}
+
+impl TcpProxy {
+    async fn start(target: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let server = {
+            let connections = Arc::clone(&connections);
+            tokio::spawn(async move {
+                while let Ok((mut inbound, _)) = listener.accept().await {
+                    let connection = tokio::spawn(async move {
+                        if let Ok(mut outbound) = TcpStream::connect(target).await {
+                            tokio::io::copy_bidirectional(&mut inbound, &mut outbound)
+                                .await
+                                .ok();
+                        }
+                    });
+                    connections.lock().unwrap().push(connection);
+                }
+            })
+        };
+        Self {
+            addr,
+            connections,
+            server,
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    fn disconnect_all(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+impl Drop for TcpProxy {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.disconnect_all();
+    }
+}
+
+/// Serves the HTTP API of a runtime on a local port.
+async fn serve(runtime: Arc<HatRuntime>, server: &ServerConfig) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = make_router_with_config(runtime, server, &Features::default());
+    let handle = tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    (addr, handle)
+}
+
+#[tokio::test]
+pub async fn test_remote_hat() {
+    // The remote instance controls a pool pump through the REST integration
+    let gadget = MockRestServer::start().await;
+    gadget.set_json("/pump", json!({ "relay": false }));
+    let devices = serde_json::from_value(json!([{
+        "id": "pump",
+        "type": "Switch",
+        "url": gadget.url("/pump"),
+        "state": "/relay",
+        "turn_on": { "url": gadget.url("/pump"), "body": "{\"relay\":true}" },
+        "turn_off": { "url": gadget.url("/pump"), "body": "{\"relay\":false}" }
+    }]))
+    .unwrap();
+    let remote = HatRuntime::new().await;
+    let rest = RestIntegration::new("gadgets", Duration::from_millis(50), devices)
+        .await
+        .unwrap();
+    remote.integrate(rest).await.unwrap();
+    let server_config = ServerConfig {
+        token: Some("secret".into()),
+        ..Default::default()
+    };
+    let (remote_addr, remote_server) = serve(Arc::clone(&remote), &server_config).await;
+    let proxy = TcpProxy::start(remote_addr).await;
+
+    let error = RemoteHatIntegration::new("garage", &proxy.url(), None)
+        .await
+        .err()
+        .unwrap();
+    assert!(format!("{error:#}").contains("401"));
+
+    let garage = RemoteHatIntegration::new("garage", &proxy.url(), Some("secret"))
+        .await
+        .unwrap();
+    let mut events = garage.subscribe(CHANNEL_SIZE);
+    let runtime = HatRuntime::new().await;
+    runtime.integrate(garage).await.unwrap();
+
+    let pump = runtime
+        .get_device("garage@gadgets@pump")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pump.full_id(), "garage@gadgets@pump");
+    assert_eq!(pump.typ, DeviceType::Switch);
+    assert_eq!(pump.state.as_deref(), Some("off"));
+
+    runtime
+        .execute("garage@gadgets@pump", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SwitchTurnedOnEvent);
+    assert_eq!(event.device.full_id(), "garage@gadgets@pump");
+    assert_eq!(event.old_state(), Some(&Value::String("off".into())));
+    assert_eq!(
+        gadget.requests(),
+        vec![("POST".into(), "/pump".into(), r#"{"relay":true}"#.into())]
+    );
+    assert_eq!(
+        runtime
+            .get_device("garage@gadgets@pump")
+            .await
+            .unwrap()
+            .and_then(|d| d.state),
+        Some("on".into())
+    );
+
+    // Events of pseudo-integrations like webhooks are not devices of the remote instance
+    remote
+        .dispatch_event(Event::from_webhook("doorbell", json!({})))
+        .await
+        .unwrap();
+    runtime
+        .execute("garage@gadgets@pump", DeviceCommand::TurnOff)
+        .await
+        .unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SwitchTurnedOffEvent);
+    let devices = runtime.list_devices().await.unwrap();
+    assert_eq!(
+        devices.iter().map(Device::full_id).collect::<Vec<_>>(),
+        vec!["garage@gadgets@pump"]
+    );
+    runtime
+        .execute("garage@gadgets@pump", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    next_event(&mut events).await;
+
+    // Changes missed while disconnected are events after reconnecting
+    proxy.disconnect_all();
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::IntegrationDisconnectedEvent
+    );
+    gadget.set_json("/pump", json!({ "relay": false }));
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::IntegrationConnectedEvent
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SwitchTurnedOffEvent);
+    assert_eq!(event.device.full_id(), "garage@gadgets@pump");
+
+    runtime.remove_integration("garage").await;
+    remote.remove_integration("gadgets").await;
+    remote_server.abort();
+}
+
+#[tokio::test]
+pub async fn test_helpers() {
+    let file = std::env::temp_dir().join(format!("hat-helpers-{}.json", std::process::id()));
+    std::fs::remove_file(&file).ok();
+    let settings = RuntimeSettings {
+        helpers_file: Some(file.clone()),
+        ..Default::default()
+    };
+    let source = r#"
+        helper guest_mode toggle
+        helper target number(min: 16, max: 28, step: 0.5, initial: 21, unit: "°C")
+        helper house_mode select(name: "Modo da casa", options: ["home", "away", "night"])
+        helper laundry timer(duration: 3600)
+
+        automation "Guests" (SwitchTurnedOnEvent) {
+            if get_device() == "helpers@guest_mode"
+            run helpers.set_value("target", 23.5)
+        }
+    "#;
+
+    let runtime = HatRuntime::with_settings(settings.clone()).await.unwrap();
+    let mut events = runtime.helpers().subscribe(CHANNEL_SIZE);
+    runtime.parse("test.hat".into(), source).await.unwrap();
+
+    let target = runtime.get_device("helpers@target").await.unwrap().unwrap();
+    assert_eq!(target.typ, DeviceType::Sensor);
+    assert_eq!(target.state.as_deref(), Some("21"));
+    assert_eq!(target.attributes["unit_of_measurement"], "°C");
+    let house_mode = runtime.get_device("house_mode").await.unwrap().unwrap();
+    assert_eq!(house_mode.name.as_deref(), Some("Modo da casa"));
+    assert_eq!(house_mode.state.as_deref(), Some("home"));
+
+    // Helpers emit the events of real devices, triggering automations
+    runtime
+        .execute("helpers@guest_mode", DeviceCommand::TurnOn)
+        .await
+        .unwrap();
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::SwitchTurnedOnEvent
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+    assert_eq!(event.get_parameter("value"), Some(&Value::Number(23.5)));
+
+    let helpers = runtime.helpers();
+    assert!(helpers
+        .set_value("house_mode", &Value::String("vacation".into()))
+        .is_err());
+    assert!(helpers.set_value("target", &Value::Number(40.0)).is_err());
+    helpers
+        .set_value("house_mode", &Value::String("away".into()))
+        .unwrap();
+    assert_eq!(
+        next_event(&mut events).await.get_parameter("value"),
+        Some(&Value::String("away".into()))
+    );
+    let trigger = || Trigger::Event(sensor_event(1.0, 2.0));
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"helpers.value("guest_mode")"#).await,
+        Value::Boolean(true)
+    );
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"value("target") + 1"#).await,
+        Value::Number(24.5)
+    );
+
+    // Timers are switches that turn off by themselves
+    helpers
+        .start_timer("laundry", Some(Duration::from_millis(50)))
+        .unwrap();
+    assert_eq!(
+        next_event(&mut events).await.typ,
+        EventType::SwitchTurnedOnEvent
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::SwitchTurnedOffEvent);
+    assert_eq!(event.get_parameter("finished"), Some(&Value::Boolean(true)));
+
+    // Helpers can be created through the API, but not the ones of the source code deleted
+    let (addr, server) = serve(Arc::clone(&runtime), &ServerConfig::default()).await;
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{addr}/helpers"))
+        .json(&json!({ "id": "vacation", "kind": "toggle" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .post(format!("http://{addr}/helpers/vacation/value"))
+        .json(&json!({ "value": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .delete(format!("http://{addr}/helpers/guest_mode"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let devices: Vec<Device> = client
+        .get(format!("http://{addr}/devices"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let helper_devices = devices
+        .iter()
+        .filter(|d| d.integration == "helpers")
+        .count();
+    assert_eq!(helper_devices, 5);
+    server.abort();
+
+    // Values survive restarts, and helpers of the source code come back when declared again
+    runtime.helpers().saved().await;
+    let restarted = HatRuntime::with_settings(settings.clone()).await.unwrap();
+    let state = |id: &str| restarted.helpers().get(id).map(|h| h.state);
+    assert_eq!(state("vacation").as_deref(), Some("on"));
+    assert_eq!(state("target"), None);
+    restarted.parse("test.hat".into(), source).await.unwrap();
+    assert_eq!(state("target").as_deref(), Some("23.5"));
+    assert_eq!(state("house_mode").as_deref(), Some("away"));
+    assert_eq!(state("laundry").as_deref(), Some("off"));
+
+    restarted
+        .replace_source("test.hat".into(), "helper guest_mode toggle")
+        .await
+        .unwrap();
+    assert_eq!(state("target"), None);
+    assert_eq!(state("vacation").as_deref(), Some("on"));
+
+    for invalid in [
+        "helper range number(min: 5, max: 1)",
+        "helper flag toggle(maximum: 3)",
+        "helper vacation toggle",
+    ] {
+        assert!(
+            matches!(
+                restarted.parse("test.hat".into(), invalid).await,
+                Err(RuntimeError::HelperError { .. })
+            ),
+            "{invalid} should be invalid"
+        );
+    }
+
+    // A file that cannot be read stops the runtime instead of being overwritten
+    restarted.helpers().saved().await;
+    std::fs::write(&file, "not json").unwrap();
+    assert!(HatRuntime::with_settings(settings).await.is_err());
+
+    std::fs::remove_file(&file).ok();
+}
+
+#[tokio::test]
+pub async fn test_simulation() {
+    let scenario = Scenario::parse(
+        r#"
+        speed = 1000
+        seed = 7
+
+        [[rooms]]
+        name = "Sala"
+        floor = "Térreo"
+
+        [[devices]]
+        id = "lamp"
+        type = "Light"
+        room = "Sala"
+
+        [[devices]]
+        id = "thermostat"
+        type = "Thermostat"
+        room = "Sala"
+
+        [[devices]]
+        id = "temperature"
+        type = "Sensor"
+        unit_of_measurement = "°C"
+
+        [[devices]]
+        id = "door"
+        type = "DoorSensor"
+
+        [[devices]]
+        id = "motion"
+        type = "MotionSensor"
+
+        [[timeline]]
+        at = 60
+        device = "door"
+        state = "off"
+
+        [[timeline]]
+        at = 30
+        device = "door"
+        state = "on"
+
+        [[curves]]
+        device = "temperature"
+        min = 18
+        max = 24
+        interval = 20
+        noise = 0.5
+
+        [[random]]
+        device = "motion"
+        every = 50
+        duration = 10
+        "#,
+    )
+    .unwrap();
+    let simulation = SimulationIntegration::new("demo", scenario).unwrap();
+    let mut events = simulation.subscribe(CHANNEL_SIZE);
+
+    let lamp = simulation.get_device("lamp").await.unwrap().unwrap();
+    assert_eq!(lamp.area.as_deref(), Some("Sala"));
+    assert_eq!(lamp.floor.as_deref(), Some("Térreo"));
+    assert_eq!(lamp.state.as_deref(), Some("off"));
+    let temperature = simulation.get_device("temperature").await.unwrap().unwrap();
+    assert_eq!(temperature.attributes["unit_of_measurement"], "°C");
+    let reading = DeviceState::parse(temperature.state.as_deref().unwrap()).to_value();
+    assert!(matches!(reading, Value::Number(n) if (17.5..=24.5).contains(&n)));
+
+    // Events of a device, skipping the activity of the others
+    async fn next_event_of(events: &mut mpsc::Receiver<Event>, id: &str) -> Event {
+        loop {
+            let event = next_event(events).await;
+            if event.device.id == id {
+                return event;
+            }
+        }
+    }
+
+    simulation
+        .execute("lamp", DeviceCommand::SetBrightness(120))
+        .await
+        .unwrap();
+    assert_eq!(
+        next_event_of(&mut events, "lamp").await.typ,
+        EventType::LightOnEvent
+    );
+    let lamp = simulation.get_device("lamp").await.unwrap().unwrap();
+    assert_eq!(lamp.attributes["brightness"], 120);
+    assert!(simulation
+        .execute("lamp", DeviceCommand::Lock)
+        .await
+        .is_err());
+
+    simulation
+        .execute("thermostat", DeviceCommand::SetTargetTemperature(22.5))
+        .await
+        .unwrap();
+    let event = next_event_of(&mut events, "thermostat").await;
+    assert_eq!(event.typ, EventType::TargetTemperatureChangedEvent);
+    assert_eq!(
+        event.get_parameter(NEW_STATE_PARAMETER),
+        Some(&Value::Number(22.5))
+    );
+
+    // The timeline plays in order of time, not of declaration
+    assert_eq!(
+        next_event_of(&mut events, "door").await.typ,
+        EventType::DoorOpenEvent
+    );
+    assert_eq!(
+        next_event_of(&mut events, "door").await.typ,
+        EventType::DoorCloseEvent
+    );
+
+    for _ in 0..3 {
+        let event = next_event_of(&mut events, "temperature").await;
+        assert_eq!(event.typ, EventType::SensorValueChangeEvent);
+        let value = event.get_parameter("value").unwrap();
+        assert!(matches!(value, Value::Number(n) if (17.5..=24.5).contains(n)));
+    }
+
+    assert_eq!(
+        next_event_of(&mut events, "motion").await.typ,
+        EventType::MotionSensorOnEvent
+    );
+    assert_eq!(
+        next_event_of(&mut events, "motion").await.typ,
+        EventType::MotionSensorOffEvent
+    );
+
+    for (source, error) in [
+        ("speed = 0", "speed: must be greater than zero"),
+        (
+            "[[devices]]\nid = \"a\"\ntype = \"Light\"\nroom = \"Cozinha\"",
+            "devices[0].room: \"Cozinha\" is not one of the rooms",
+        ),
+        (
+            "[[random]]\ndevice = \"ghost\"\nevery = 10",
+            "random[0].device: \"ghost\" is not one of the devices",
+        ),
+        (
+            "[[curves]]\ndevice = \"x\"",
+            "curves[0]: missing field `min`",
+        ),
+    ] {
+        let e = Scenario::parse(source).unwrap_err();
+        assert!(e.to_string().contains(error), "{e} should contain {error}");
+    }
+}
+
+#[tokio::test]
+pub async fn test_calendar() {
+    use chrono::{Local, TimeDelta, TimeZone, Utc};
+
+    let calendar = Calendar::parse(
+        "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:gym\r
+SUMMARY:Academia\r
+DTSTART:20300107T070000\r
+DURATION:PT1H\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4\r
+EXDATE:20300109T070000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:gym\r
+RECURRENCE-ID:20300114T070000\r
+SUMMARY:Academia (mais tarde)\r
+DTSTART:20300114T090000\r
+DTEND:20300114T100000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:birthday\r
+SUMMARY:Aniversário\\, Ana\r
+DESCRIPTION:Comprar bolo\\ne \r
+ velas\r
+DTSTART;VALUE=DATE:20300110\r
+RRULE:FREQ=YEARLY\r
+BEGIN:VALARM\r
+DESCRIPTION:Lembrete\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:cancelled\r
+SUMMARY:Reunião\r
+STATUS:CANCELLED\r
+DTSTART:20300108T100000\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+    )
+    .unwrap();
+    let local = |d, h| Local.with_ymd_and_hms(2030, 1, d, h, 0, 0).unwrap();
+    let occurrences = calendar.occurrences(local(1, 0), local(31, 0));
+    let summaries = occurrences
+        .iter()
+        .map(|o| (o.summary.as_str(), o.start))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summaries,
+        [
+            ("Academia", local(7, 7)),
+            ("Aniversário, Ana", local(10, 0)),
+            ("Academia (mais tarde)", local(14, 9)),
+            ("Academia", local(16, 7)),
+        ]
+    );
+    assert_eq!(occurrences[0].end, local(7, 8));
+    assert!(occurrences[1].all_day);
+    assert_eq!(occurrences[1].end, local(11, 0));
+    assert_eq!(
+        occurrences[1].description.as_deref(),
+        Some("Comprar bolo\ne velas")
+    );
+    let next_year = Local.with_ymd_and_hms(2031, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(
+        calendar.occurrences(next_year, next_year + TimeDelta::days(31))[0].summary,
+        "Aniversário, Ana"
+    );
+    // Times with a TZID are in that timezone, even with the prefix some applications add
+    let calendar = Calendar::parse(
+        "BEGIN:VCALENDAR
+BEGIN:VEVENT
+UID:tokyo
+DTSTART;TZID=Asia/Tokyo:20300107T090000
+DTEND;TZID=Asia/Tokyo:20300107T100000
+END:VEVENT
+BEGIN:VEVENT
+UID:new-york
+DTSTART;TZID=/mozilla.org/20070129_1/America/New_York:20300107T090000
+DURATION:PT1H
+END:VEVENT
+END:VCALENDAR",
+    )
+    .unwrap();
+    let starts = calendar
+        .occurrences(local(1, 0), local(31, 0))
+        .iter()
+        .map(|o| (o.uid.clone(), o.start.with_timezone(&Utc)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        starts,
+        [
+            (
+                "tokyo".to_owned(),
+                Utc.with_ymd_and_hms(2030, 1, 7, 0, 0, 0).unwrap()
+            ),
+            (
+                "new-york".to_owned(),
+                Utc.with_ymd_and_hms(2030, 1, 7, 14, 0, 0).unwrap()
+            ),
+        ]
+    );
+    assert!(Calendar::parse("BEGIN:VEVENT\nEND:VEVENT").is_err());
+    assert!(Calendar::parse("BEGIN:VCALENDAR\nBEGIN:VEVENT\nSUMMARY:x\nEND:VEVENT").is_err());
+
+    // Events relative to now, to see them start and end
+    let ics = |events: &[(&str, i64, i64)]| {
+        let now = Utc::now();
+        let mut source = "BEGIN:VCALENDAR\n".to_owned();
+        for (summary, start, end) in events {
+            let at = |seconds| (now + TimeDelta::seconds(seconds)).format("%Y%m%dT%H%M%SZ");
+            source += &format!(
+                "BEGIN:VEVENT\nUID:{summary}\nSUMMARY:{summary}\nLOCATION:Quarto de hóspedes\n\
+                DTSTART:{}\nDTEND:{}\nEND:VEVENT\n",
+                at(*start),
+                at(*end)
+            );
+        }
+        source + "END:VCALENDAR\n"
+    };
+    let file = std::env::temp_dir().join(format!("hat-calendar-{}.ics", std::process::id()));
+    std::fs::write(&file, ics(&[("Visita dos avós", 2, 4)])).unwrap();
+    let calendars = [("family".to_owned(), file.clone())].into();
+    let integration =
+        CalendarIntegration::new("calendar", calendars, Duration::from_secs(1)).unwrap();
+    let mut events = integration.subscribe(CHANNEL_SIZE);
+    let runtime = HatRuntime::new().await;
+    runtime.integrate(integration).await.unwrap();
+    let trigger = || Trigger::Event(sensor_event(1.0, 2.0));
+
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"calendar_busy("family")"#).await,
+        Value::Boolean(false)
+    );
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            r#"next_calendar_event("family", "summary")"#
+        )
+        .await,
+        Value::String("Visita dos avós".into())
+    );
+    assert!(runtime
+        .get_event_descriptors()
+        .iter()
+        .any(|d| d.event == EventType::custom("CalendarEventStarted")));
+
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::custom("CalendarEventStarted"));
+    assert_eq!(
+        event.get_parameter("location"),
+        Some(&Value::String("Quarto de hóspedes".into()))
+    );
+    assert_eq!(event.device.state.as_deref(), Some("on"));
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"calendar_busy("family")"#).await,
+        Value::Boolean(true)
+    );
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::custom("CalendarEventEnded"));
+    assert_eq!(
+        event.get_parameter("summary"),
+        Some(&Value::String("Visita dos avós".into()))
+    );
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"next_calendar_event("family")"#).await,
+        Value::Null
+    );
+
+    // Changes to the file are picked up
+    std::fs::write(&file, ics(&[("Jantar", 2, 3)])).unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::custom("CalendarEventStarted"));
+    assert_eq!(
+        event.get_parameter("summary"),
+        Some(&Value::String("Jantar".into()))
+    );
+
+    // An event without length starts before it ends
+    std::fs::write(&file, ics(&[("Lembrete", 2, 2)])).unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::custom("CalendarEventStarted"));
+    let event = next_event(&mut events).await;
+    assert_eq!(event.typ, EventType::custom("CalendarEventEnded"));
+    assert_eq!(
+        event.get_parameter("summary"),
+        Some(&Value::String("Lembrete".into()))
+    );
+
+    std::fs::remove_file(&file).ok();
+}
+
+#[tokio::test]
+pub async fn test_http_functions() {
+    let server = MockRestServer::start().await;
+    server.set_json("/api/weather", json!({"current": {"temperature": 21.5}}));
+    server.set_failing("/api/broken", true);
+    let config = Config::parse(&format!(
+        r#"
+        [http]
+        timeout = 5
+        allowed_urls = ["{}"]
+
+        [http.webhooks.alert]
+        url = "{}"
+        headers = {{ Authorization = "Bearer secret" }}
+        "#,
+        server.url("/api"),
+        server.url("/hooks/alert"),
+    ))
+    .unwrap();
+    let settings = config.runtime_settings();
+    assert_eq!(settings.http.timeout, Duration::from_secs(5));
+    assert_eq!(
+        settings.http.webhooks["alert"].method,
+        reqwest::Method::POST
+    );
+    let runtime = HatRuntime::with_settings(settings).await.unwrap();
+    let trigger = || Trigger::Event(sensor_event(1.0, 2.0));
+    let weather = server.url("/api/weather");
+
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            &format!(r#"get(http_get("{weather}"), "json", "current", "temperature")"#)
+        )
+        .await,
+        Value::Number(21.5)
+    );
+    let response = evaluate(&runtime, trigger(), &format!(r#"http_get("{weather}")"#)).await;
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            &format!(r#"get(http_get("{weather}"), "status")"#)
+        )
+        .await,
+        Value::Number(200.0)
+    );
+    let Value::Map(response) = response else {
+        panic!("{response:?} is not a map");
+    };
+    assert_eq!(response["ok"], Value::Boolean(true));
+    assert_eq!(
+        response["body"],
+        Value::String(r#"{"current":{"temperature":21.5}}"#.into())
+    );
+
+    let broken = server.url("/api/broken");
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            &format!(r#"get(http_get("{broken}"), "ok")"#)
+        )
+        .await,
+        Value::Boolean(false)
+    );
+    // Only URLs under an allowed one can be called
+    for url in [
+        server.url("/apix"),
+        server.url("/other"),
+        "http://example.com/api".into(),
+    ] {
+        let error = try_evaluate(&runtime, trigger(), &format!(r#"http_get("{url}")"#))
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("is not allowed"), "{error:#}");
+    }
+
+    evaluate(
+        &runtime,
+        trigger(),
+        &format!(
+            r#"http_post("{}", {{ state: "on", level: 3 }}, {{ "X-Source": "hat" }})"#,
+            server.url("/api/relay")
+        ),
+    )
+    .await;
+    evaluate(
+        &runtime,
+        trigger(),
+        r#"webhook("alert", { message: "Porta aberta" })"#,
+    )
+    .await;
+    assert_eq!(
+        server.requests(),
+        [
+            (
+                "POST".to_owned(),
+                "/api/relay".to_owned(),
+                r#"{"level":3.0,"state":"on"}"#.to_owned()
+            ),
+            (
+                "POST".to_owned(),
+                "/hooks/alert".to_owned(),
+                r#"{"message":"Porta aberta"}"#.to_owned()
+            ),
+        ]
+    );
+    assert!(try_evaluate(&runtime, trigger(), r#"webhook("missing")"#)
+        .await
+        .is_err());
+
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"get([1, {a: "b"}], 1, "a")"#).await,
+        Value::String("b".into())
+    );
+    assert_eq!(
+        evaluate(&runtime, trigger(), r#"get({a: 1}, "b", "c")"#).await,
+        Value::Null
+    );
+
+    // Redirects could leave the allowed URLs, so they are returned instead of followed
+    server.set_json("/private", json!({"secret": true}));
+    server.set_redirect("/api/moved", "/private");
+    let moved = server.url("/api/moved");
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            &format!(r#"get(http_get("{moved}"), "status")"#)
+        )
+        .await,
+        Value::Number(302.0)
+    );
+    server.set_json(
+        "/api/huge",
+        json!("x".repeat(crate::runtime::function::http::MAX_BODY_SIZE)),
+    );
+    let huge = server.url("/api/huge");
+    let error = try_evaluate(&runtime, trigger(), &format!(r#"http_get("{huge}")"#))
+        .await
+        .unwrap_err();
+    assert!(format!("{error:#}").contains("is larger than"), "{error:#}");
+
+    let error = Config::parse(
+        r#"
+        [http]
+        timeout = 0
+        allowed_urls = ["ftp://example.com"]
+
+        [http.webhooks.alert]
+        url = "https://example.com"
+        method = "SEND"
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        format!("{error:#}"),
+        "http.timeout: must be greater than zero\n\
+        http.allowed_urls[0]: \"ftp://example.com\" must be an http or https URL\n\
+        http.webhooks.alert.method: \"SEND\" is not GET, POST, PUT, PATCH or DELETE"
+    );
+}
+
+#[tokio::test]
+pub async fn test_webhooks() {
+    let runtime = HatRuntime::new().await;
+    runtime
+        .parse(
+            "test.hat".into(),
+            r#"
+            helper rang toggle
+            helper garage_opened toggle
+
+            automation "Doorbell" (Webhook "doorbell") {
+                if event_param("visitor") == "Ana"
+                run helpers.set_value("rang", true)
+            }
+
+            automation "Garage" (WebhookEvent "garage") {
+                run helpers.set_value("garage_opened", true)
+            }
+            "#,
+        )
+        .await
+        .unwrap();
+    let mut events = runtime.subscribe_events();
+    let server_config = ServerConfig {
+        token: Some("token".into()),
+        webhooks: [("doorbell".to_owned(), "s3cret".to_owned())].into(),
+        ..Default::default()
+    };
+    let (addr, server) = serve(Arc::clone(&runtime), &server_config).await;
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://{addr}{path}");
+
+    // Webhooks need their secret, not the token of the server
+    for (path, secret, status) in [
+        ("/webhook/doorbell", None, 401),
+        ("/webhook/doorbell", Some("token"), 401),
+        ("/webhook/garage", Some("s3cret"), 404),
+    ] {
+        let mut request = client.post(url(path)).body("{}");
+        if let Some(secret) = secret {
+            request = request.header("X-Webhook-Secret", secret);
+        }
+        assert_eq!(request.send().await.unwrap().status(), status, "{path}");
+    }
+    let response = client
+        .post(url("/webhook/doorbell?secret=s3cret"))
+        .body("not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .post(url("/webhook/doorbell"))
+        .header("X-Webhook-Secret", "s3cret")
+        .json(&json!({"visitor": "Ana"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let event = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let event = events.recv().await.unwrap();
+            if event.typ == EventType::WebhookEvent {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for the webhook event");
+    assert_eq!(
+        event.get_parameter("webhook"),
+        Some(&Value::String("doorbell".into()))
+    );
+    assert_eq!(
+        event.get_parameter("visitor"),
+        Some(&Value::String("Ana".into()))
+    );
+    assert_eq!(
+        event.get_parameter("body").map(Value::to_json),
+        Some(json!({"visitor": "Ana"}))
+    );
+    let state = |id: &str| runtime.helpers().get(id).unwrap().value();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while state("rang") != Value::Boolean(true) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the automation of the webhook did not run");
+    assert_eq!(state("garage_opened"), Value::Boolean(false));
+
+    // Other triggers can also name the device the event must come from
+    let program = crate::runtime::parser::parse(
+        "test.hat".into(),
+        r#"automation a (DoorOpen "front", LightOnEvent "HueBridge0@kitchen") {}"#,
+    )
+    .unwrap();
+    let automation = &program.automations[0];
+    let aliases = HashMap::from([("HueBridge0".to_owned(), "hue".to_owned())]);
+    let event = |typ: EventType, integration: &str, id: &str| {
+        let mut event = Event::from_integration(integration, typ, HashMap::new());
+        event.device.id = id.to_owned();
+        event
+    };
+    assert!(automation
+        .should_be_triggered_by(&event(EventType::DoorOpenEvent, "home", "front"), &aliases));
+    assert!(!automation
+        .should_be_triggered_by(&event(EventType::DoorOpenEvent, "home", "back"), &aliases));
+    assert!(automation
+        .should_be_triggered_by(&event(EventType::LightOnEvent, "hue", "kitchen"), &aliases));
+    assert!(!automation
+        .should_be_triggered_by(&event(EventType::LightOnEvent, "home", "kitchen"), &aliases));
+
+    server.abort();
+}
+
+#[tokio::test]
+pub async fn test_notify() {
+    let hass = MockHass::start("secret").await;
+    let server = MockRestServer::start().await;
+    let smtp = MockSmtpServer::start().await;
+    let config = Config::parse(&format!(
+        r#"
+        [[integrations]]
+        type = "home_assistant"
+        id = "home"
+        aliases = ["hass"]
+        url = "{}"
+        token = "secret"
+
+        [notifiers.phone]
+        type = "home_assistant"
+        integration = "hass"
+        service = "mobile_app_phone"
+        data = {{ priority = "high" }}
+
+        [notifiers.chat]
+        type = "webhook"
+        url = "{}"
+        headers = {{ Authorization = "Bearer chat" }}
+
+        [notifiers.family]
+        type = "ntfy"
+        url = "{}"
+        topic = "casa"
+        token = "family"
+        priority = 4
+        tags = ["warning"]
+
+        [notifiers.email]
+        type = "smtp"
+        host = "127.0.0.1"
+        port = {}
+        security = "none"
+        username = "hat"
+        password = "mail"
+        from = "hat@example.com"
+        to = ["ana@example.com", "bia@example.com"]
+        "#,
+        hass.url(),
+        server.url("/hooks/chat"),
+        server.url(""),
+        smtp.port(),
+    ))
+    .unwrap();
+    let runtime = HatRuntime::with_settings(config.runtime_settings())
+        .await
+        .unwrap();
+    for integration in &config.integrations {
+        integration.integrate(&runtime).await.unwrap();
+    }
+    let trigger = || Trigger::Event(sensor_event(1.0, 2.0));
+
+    assert_eq!(
+        evaluate(
+            &runtime,
+            trigger(),
+            r#"notify(["phone", "chat", "family", "email"], "Atenção", "Porta aberta
+.às 22h")"#
+        )
+        .await,
+        Value::Null
+    );
+
+    assert_eq!(
+        hass.service_calls(),
+        [ServiceCall {
+            domain: "notify".into(),
+            service: "mobile_app_phone".into(),
+            target: serde_json::Value::Null,
+            data: json!({
+                "title": "Atenção",
+                "message": "Porta aberta\n.às 22h",
+                "data": { "priority": "high" },
+            }),
+        }]
+    );
+
+    // Targets are notified at the same time, so requests arrive in any order
+    let mut requests = server
+        .requests()
+        .into_iter()
+        .zip(server.request_headers())
+        .collect::<Vec<_>>();
+    requests.sort_by(|a, b| b.0 .1.cmp(&a.0 .1));
+    let (requests, headers): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+    assert_eq!(
+        requests,
+        [
+            (
+                "POST".to_owned(),
+                "/hooks/chat".to_owned(),
+                r#"{"message":"Porta aberta\n.às 22h","title":"Atenção"}"#.to_owned()
+            ),
+            (
+                "POST".to_owned(),
+                "/casa".to_owned(),
+                "Porta aberta\n.às 22h".to_owned()
+            ),
+        ]
+    );
+    assert_eq!(headers[0]["authorization"], "Bearer chat");
+    assert_eq!(headers[1]["title"], "=?UTF-8?B?QXRlbsOnw6Nv?=");
+    assert_eq!(headers[1]["priority"], "4");
+    assert_eq!(headers[1]["tags"], "warning");
+    assert_eq!(headers[1]["authorization"], "Bearer family");
+
+    let emails = smtp.emails();
+    assert_eq!(emails.len(), 1);
+    let Email {
+        auth,
+        from,
+        to,
+        data,
+    } = &emails[0];
+    assert_eq!(auth.as_deref(), Some("AGhhdABtYWls"));
+    assert_eq!(from, "hat@example.com");
+    assert_eq!(to, &["ana@example.com", "bia@example.com"]);
+    assert!(data.starts_with(
+        "From: hat@example.com\r\nTo: ana@example.com, bia@example.com\r\n\
+        Subject: =?UTF-8?B?QXRlbsOnw6Nv?=\r\n"
+    ));
+    assert!(data.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+    assert!(
+        data.ends_with("\r\n\r\nPorta aberta\r\n..às 22h\r\n"),
+        "{data}"
+    );
+
+    let error = try_evaluate(&runtime, trigger(), r#"notify("pager", "a", "b")"#)
+        .await
+        .unwrap_err();
+    assert!(format!("{error:#}").contains(r#"notifier "pager" is not configured"#));
+    // Nothing is sent when one of the targets is unknown
+    assert!(try_evaluate(
+        &runtime,
+        trigger(),
+        r#"notify(["chat", "pager"], "a", "b")"#
+    )
+    .await
+    .is_err());
+    assert_eq!(server.requests().len(), 2);
+
+    // A failing target does not keep the notification from the others
+    server.set_failing("/hooks/chat", true);
+    let error = try_evaluate(
+        &runtime,
+        trigger(),
+        r#"notify(["chat", "family", "email"], "Vazamento", "Água na cozinha")"#,
+    )
+    .await
+    .unwrap_err();
+    assert!(format!("{error:#}").contains("failed to notify chat"));
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(smtp.emails().len(), 2);
+
+    let errors = Config::parse(
+        r#"
+        [notifiers.phone]
+        type = "home_assistant"
+        integration = "home"
+        service = ""
+
+        [notifiers.family]
+        type = "ntfy"
+        url = "ftp://example.com"
+        topic = "a/b"
+        priority = 7
+
+        [notifiers.email]
+        type = "smtp"
+        host = "smtp.example.com"
+        password = "mail"
+        from = "hat@example.com"
+        to = []
+        "#,
+    )
+    .unwrap_err()
+    .to_string();
+    assert_eq!(
+        errors,
+        [
+            r#"notifiers.email.to: must have at least one address"#,
+            r#"notifiers.email.username: is required with a password"#,
+            r#"notifiers.family.url: "ftp://example.com" must be an http or https URL"#,
+            r#"notifiers.family.topic: "a/b" must not be empty or contain `/`"#,
+            r#"notifiers.family.priority: must be between 1 and 5"#,
+            r#"notifiers.phone.integration: "home" is not a home_assistant integration"#,
+            r#"notifiers.phone.service: must not be empty"#,
+        ]
+        .join("\n")
+    );
+    assert!(Config::parse("[notifiers.email]\ntype = \"smtp\"\nhost = \"a\"\nfrom = \"b\"\nto = [\"c\"]\nsecurity = \"ssl\"")
+        .unwrap_err()
+        .to_string()
+        .contains("notifiers.email"));
+}
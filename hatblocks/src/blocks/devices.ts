@@ -21,6 +21,16 @@ export function getIconFor(typ: DeviceType): string {
       return "🎚️";
     case 'Button':
       return "🔴";
+    case 'Thermostat':
+      return "🌡️";
+    case 'Cover':
+      return "🪟";
+    case 'Lock':
+      return "🔒";
+    case 'Fan':
+      return "🌀";
+    case 'Person':
+      return "🧍";
     case 'Unknown':
       return "❔";
     default:
@@ -44,6 +54,16 @@ export function getLabelFor(typ: DeviceType): string {
       return "Interruptor";
     case 'Button':
       return "Botão";
+    case 'Thermostat':
+      return "Termostato";
+    case 'Cover':
+      return "Cortina";
+    case 'Lock':
+      return "Fechadura";
+    case 'Fan':
+      return "Ventilador";
+    case 'Person':
+      return "Pessoa";
     case 'Unknown':
       return "Desconhecido";
     default:
@@ -7,6 +7,11 @@ export const DeviceTypes = [
   "Switch",
   "MotionSensor",
   "Button",
+  "Thermostat",
+  "Cover",
+  "Lock",
+  "Fan",
+  "Person",
   "Unknown",
 ] as const;
 
@@ -17,17 +22,41 @@ export const deviceTypePriorities: Record<DeviceType, number> = {
   Switch: 3,
   MotionSensor: 3,
   Button: 3,
+  Lock: 3,
+  Person: 3,
   Light: 2,
+  Thermostat: 2,
+  Cover: 2,
+  Fan: 2,
   Sensor: 1,
   Dummy: 0,
   Unknown: 0
 };
 
+export type Capability =
+  | "OnOff"
+  | "Brightness"
+  | "ColorRgb"
+  | "ColorTemp"
+  | "Position"
+  | "Lock"
+  | "TemperatureSetpoint"
+  | "FanSpeed"
+  | "MediaPlayback"
+  | "Contact"
+  | "Motion"
+  | "Measurement"
+  | "Press"
+  | "Presence";
+
 export interface Device {
   integration: string;
   id: string;
   name: string | null;
   typ: DeviceType;
+  capabilities: Capability[];
+  area: string | null;
+  floor: string | null;
   state: string | null;
 }
 
@@ -37,6 +66,25 @@ export interface RuntimeEvent {
   relatedDeviceType: DeviceType,
 };
 
+export type ValueType = "String" | "Boolean" | "Number" | "Time" | "List" | "Map" | "Null" | "Any";
+
+export type FunctionCategory = "Action" | "Condition" | "Event" | "Device" | "Conversion" | "Utility";
+
+export interface FunctionParameter {
+  name: string;
+  type: ValueType;
+  optional: boolean;
+  variadic: boolean;
+}
+
+export interface HatFunction {
+  name: string;
+  description: string;
+  category: FunctionCategory;
+  parameters: FunctionParameter[];
+  returnType: ValueType;
+}
+
 export interface ApiError {
   code: number,
   errors: {
@@ -110,8 +158,9 @@ export class HatApi {
     return this.request(endpoint, 'DELETE', undefined, headers);
   }
 
-  async listDevices(): Promise<Device[]> {
-    const devices = await this.get("/devices");
+  async listDevices(area?: string): Promise<Device[]> {
+    const query = area ? "?area=" + encodeURIComponent(area) : "";
+    const devices = await this.get("/devices" + query);
     const filteredDevices = (devices as Device[]).filter((dev) => !isDeviceBlacklisted(dev));
     return filteredDevices;
   }
@@ -126,6 +175,10 @@ export class HatApi {
     return (events as RuntimeEvent[]).filter(e => e.event != "Dummy");
   }
 
+  async listFunctions(): Promise<HatFunction[]> {
+    return await this.get("/functions");
+  }
+
   async updateSource(source: string): Promise<void> {
     return this.post('/update_code', source);
   }
@@ -140,6 +193,6 @@ function isDeviceBlacklisted(device: Device) {
   if (device.id.includes("a35_isabel")) {
     return true;
   }
-  if (device.integration.includes("DummyIntegration")) return true;
+  if (device.typ == "Dummy") return true;
   return false;
 }